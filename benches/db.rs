@@ -0,0 +1,168 @@
+//! Throughput of the shared `Db` as the number of concurrent clients grows.
+//!
+//! Every client is a task on a multi-threaded runtime issuing a mix of SET and
+//! GET calls against the same store. A single shard behaves like the old
+//! `Arc<Mutex<HashMap<..>>>`; comparing it with sharded stores shows how much
+//! of the time is spent waiting on the lock.
+//!
+//! `server_clients` runs the same mix through the server, so commands also
+//! go through parsing, the `Db` gate and the connection. Each client pipelines
+//! its commands in batches so the time is not dominated by round trips.
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use my_redis::{server, Connection, Db, Frame};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Commands a client sends before waiting for their responses.
+const BATCH: usize = 64;
+
+/// Operations issued per benchmark iteration, split evenly between clients.
+const OPS: usize = 64 * 1024;
+
+async fn run_clients(db: &Db, clients: usize) {
+    let per_client = OPS / clients;
+
+    let handles: Vec<_> = (0..clients)
+        .map(|client| {
+            let db = db.clone();
+
+            tokio::spawn(async move {
+                for i in 0..per_client {
+                    let key = format!("key:{}:{}", client, i % 1024);
+
+                    if i % 2 == 0 {
+                        db.set(key, Bytes::from_static(b"value"), None);
+                    } else {
+                        std::hint::black_box(db.get(&key));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+/// Build a command frame out of bulk strings.
+fn command(args: &[&str]) -> Frame {
+    Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    )
+}
+
+/// Issue `OPS` commands over `connections`, returning them once every
+/// response has been read.
+async fn run_server_clients(connections: Vec<Connection>) -> Vec<Connection> {
+    let per_client = OPS / connections.len();
+
+    let handles: Vec<_> = connections
+        .into_iter()
+        .enumerate()
+        .map(|(client, mut connection)| {
+            tokio::spawn(async move {
+                for batch in 0..per_client / BATCH {
+                    for i in batch * BATCH..(batch + 1) * BATCH {
+                        let key = format!("key:{}:{}", client, i % 1024);
+
+                        let frame = if i % 2 == 0 {
+                            command(&["SET", &key, "value"])
+                        } else {
+                            command(&["GET", &key])
+                        };
+                        connection.buffer_frame(&frame).await.unwrap();
+                    }
+                    connection.flush().await.unwrap();
+
+                    for _ in 0..BATCH {
+                        let response = connection.read_frame().await.unwrap();
+                        std::hint::black_box(response);
+                    }
+                }
+
+                connection
+            })
+        })
+        .collect();
+
+    let mut connections = vec![];
+    for handle in handles {
+        connections.push(handle.await.unwrap());
+    }
+    connections
+}
+
+fn concurrent_clients(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // `Db::new` spawns the expiration task, which needs a runtime context
+    let _guard = rt.enter();
+
+    let mut group = c.benchmark_group("concurrent_clients");
+    group.throughput(Throughput::Elements(OPS as u64));
+
+    for shards in [1, 16, 64] {
+        let db = Db::new(shards);
+
+        for clients in [1, 4, 16, 64] {
+            let id = BenchmarkId::new(format!("{}_shards", shards), clients);
+
+            group.bench_with_input(id, &clients, |b, &clients| {
+                b.to_async(&rt).iter(|| run_clients(&db, clients));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn server_clients(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("server_clients");
+    group.throughput(Throughput::Elements(OPS as u64));
+
+    for shards in [1, 16, 64] {
+        let addr = rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let db = Db::new(shards);
+            tokio::spawn(server::run(listener, db, std::future::pending::<()>()));
+            addr
+        });
+
+        for clients in [1, 4, 16, 64] {
+            let id = BenchmarkId::new(format!("{}_shards", shards), clients);
+
+            let mut connections = rt.block_on(async {
+                let mut connections = vec![];
+                for _ in 0..clients {
+                    connections.push(Connection::new(TcpStream::connect(addr).await.unwrap()));
+                }
+                connections
+            });
+
+            group.bench_with_input(id, &clients, |b, _| {
+                b.iter(|| {
+                    connections = rt.block_on(run_server_clients(std::mem::take(&mut connections)));
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_clients, server_clients);
+criterion_main!(benches);
@@ -0,0 +1,124 @@
+//! Cost of parsing frames carrying large bulk strings.
+//!
+//! `parse_bulk` parses a `SET` command whose value is 1 MiB or larger, the
+//! way `Connection::parse_frame` does: the frame is split off the read buffer
+//! and bulk strings are split off the frame, without copying them. The
+//! `copy` variant then copies every bulk string into its own allocation, which
+//! is what the parser used to do.
+//!
+//! Time alone hides part of the difference, so the number of bytes allocated
+//! by a single parse is printed for each variant before measuring.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use my_redis::connection::Limits;
+use my_redis::Frame;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const VALUE_SIZES: [usize; 3] = [1 << 20, 4 << 20, 16 << 20];
+
+/// Counts the bytes allocated by the process.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// A read buffer holding `SET key <value>` with a `len` bytes long value.
+fn set_command(len: usize) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(len + 64);
+    buf.extend_from_slice(format!("*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n${}\r\n", len).as_bytes());
+    buf.extend_from_slice(&vec![b'x'; len]);
+    buf.extend_from_slice(b"\r\n");
+    buf
+}
+
+/// Parse the frame at the front of `buf` like `Connection::parse_frame`.
+fn parse(buf: &mut BytesMut) -> Frame {
+    let mut cursor = Cursor::new(&buf[..]);
+    Frame::check(&mut cursor, &Limits::default()).unwrap();
+    let len = cursor.position() as usize;
+
+    let mut src = buf.split_to(len).freeze();
+    Frame::parse(&mut src).unwrap()
+}
+
+/// Copy every bulk string of `frame` into its own allocation.
+fn copy_bulks(frame: Frame) -> Frame {
+    match frame {
+        Frame::Bulk(data) => Frame::Bulk(Bytes::copy_from_slice(&data)),
+        Frame::Array(parts) => Frame::Array(parts.into_iter().map(copy_bulks).collect()),
+        frame => frame,
+    }
+}
+
+/// Bytes allocated by `f`, not counting the allocations made by `setup`.
+fn allocated_by<T, R>(setup: impl FnOnce() -> T, f: impl FnOnce(T) -> R) -> usize {
+    let input = setup();
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let output = f(input);
+    let after = ALLOCATED.load(Ordering::Relaxed);
+    drop(output);
+    after - before
+}
+
+fn parse_bulk(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_bulk");
+
+    for len in VALUE_SIZES {
+        let command = set_command(len);
+
+        let zero_copy = allocated_by(|| command.clone(), |mut buf| parse(&mut buf));
+        let copy = allocated_by(|| command.clone(), |mut buf| copy_bulks(parse(&mut buf)));
+        println!(
+            "parse_bulk/{}: zero_copy allocates {} bytes, copy allocates {} bytes",
+            len, zero_copy, copy
+        );
+
+        group.throughput(Throughput::Bytes(command.len() as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("zero_copy", len),
+            &command,
+            |b, command| {
+                b.iter_batched(
+                    || command.clone(),
+                    |mut buf| parse(&mut buf),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+
+        group.bench_with_input(BenchmarkId::new("copy", len), &command, |b, command| {
+            b.iter_batched(
+                || command.clone(),
+                |mut buf| copy_bulks(parse(&mut buf)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(3));
+    targets = parse_bulk
+}
+criterion_main!(benches);
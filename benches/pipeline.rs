@@ -0,0 +1,144 @@
+//! Cost of answering pipelined commands.
+//!
+//! `write_responses` compares flushing after every response, which is what
+//! the server used to do, with buffering a whole batch and flushing once.
+//! Every flush is a `write` system call, so a batch of `n` responses costs `n`
+//! calls in the first case and one call per 8 KiB of output in the second.
+//! Both variants use the same connection, only the number of flushes differs.
+//!
+//! `pipelined_gets` measures the server end to end: a client sends a batch of
+//! GET commands with a single flush and waits for every response.
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use my_redis::{server, Connection, Db, Frame};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+const BATCH_SIZES: [usize; 3] = [1, 16, 128];
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// A `Connection` whose peer discards everything it receives.
+async fn sink() -> Connection {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 64 * 1024];
+        while socket.read(&mut buf).await.unwrap() > 0 {}
+    });
+
+    Connection::new(TcpStream::connect(addr).await.unwrap())
+}
+
+/// Write `n` responses, flushing after each of them or once at the end.
+async fn write_batch(connection: &mut Connection, response: &Frame, n: usize, per_frame: bool) {
+    for _ in 0..n {
+        if per_frame {
+            connection.write_frame(response).await.unwrap();
+        } else {
+            connection.buffer_frame(response).await.unwrap();
+        }
+    }
+
+    connection.flush().await.unwrap();
+}
+
+fn write_responses(c: &mut Criterion) {
+    let rt = runtime();
+    let response = Frame::Bulk(Bytes::from_static(b"value"));
+
+    let mut group = c.benchmark_group("write_responses");
+
+    for n in BATCH_SIZES {
+        group.throughput(Throughput::Elements(n as u64));
+
+        for (name, per_frame) in [("flush_per_frame", true), ("flush_per_batch", false)] {
+            let mut connection = rt.block_on(sink());
+
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let start = Instant::now();
+
+                        for _ in 0..iters {
+                            write_batch(&mut connection, &response, n, per_frame).await;
+                        }
+
+                        start.elapsed()
+                    })
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn pipelined_gets(c: &mut Criterion) {
+    let rt = runtime();
+
+    let addr = rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let db = Db::new(16);
+        db.set("key".to_string(), Bytes::from_static(b"value"), None);
+
+        tokio::spawn(server::run(listener, db, std::future::pending::<()>()));
+        addr
+    });
+
+    let get = Frame::Array(vec![
+        Frame::Bulk(Bytes::from_static(b"GET")),
+        Frame::Bulk(Bytes::from_static(b"key")),
+    ]);
+
+    let mut group = c.benchmark_group("pipelined_gets");
+
+    for n in BATCH_SIZES {
+        group.throughput(Throughput::Elements(n as u64));
+
+        let mut connection =
+            rt.block_on(async { Connection::new(TcpStream::connect(addr).await.unwrap()) });
+
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let start = Instant::now();
+
+                    for _ in 0..iters {
+                        for _ in 0..n {
+                            connection.buffer_frame(&get).await.unwrap();
+                        }
+                        connection.flush().await.unwrap();
+
+                        for _ in 0..n {
+                            connection.read_frame().await.unwrap().unwrap();
+                        }
+                    }
+
+                    start.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(3));
+    targets = write_responses, pipelined_gets
+}
+criterion_main!(benches);
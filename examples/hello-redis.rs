@@ -1,8 +1,8 @@
-use mini_redis::{client, Result};
+use my_redis::{client, Result};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Open a connection to the mini-redis address
+    // Open a connection to the my-redis address
     let mut client = client::connect("127.0.0.1:6379").await?;
 
     // Set the key "hello" with the value "world"
@@ -12,6 +12,6 @@ async fn main() -> Result<()> {
     let result = client.get("hello").await?;
 
     println!("got value from the server; result={:?}", result);
-    
+
     Ok(())
 }
@@ -0,0 +1,626 @@
+//! Append only file persistence.
+//!
+//! Every command changing the keyspace is appended to a file, encoded as RESP
+//! the same way clients send it. Replaying the file on startup rebuilds the
+//! keyspace as it was when the server stopped.
+//!
+//! The file only ever grows, so it can be rewritten from the current keyspace
+//! with `BGREWRITEAOF`, leaving one command per key.
+
+use crate::cmd::{expire, set, zset};
+use crate::connection::Limits;
+use crate::db::Value;
+use crate::frame::{self, Frame};
+use crate::{Command, Db};
+
+use bytes::Bytes;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::SystemTime;
+use tokio::time::{self, Duration};
+
+/// Most elements a rewrite adds to a collection with a single command, so
+/// that a large collection does not turn into one huge command.
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// Bytes of an invalid record shown in the error naming it
+const RECORD_PREVIEW_LEN: usize = 64;
+
+/// When the file is flushed to disk, named after the Redis `appendfsync`
+/// setting.
+///
+/// Writes always reach the OS before the client gets a response, so they
+/// survive the server crashing. The policy decides how many of them a power
+/// loss or an OS crash may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    /// After every write command, before it is answered. Nothing is lost, at
+    /// the cost of one disk flush per write.
+    Always,
+
+    /// Once per second, from a background task. At most about a second of
+    /// writes is lost.
+    EverySec,
+
+    /// Never explicitly, the OS flushes the file whenever it sees fit.
+    No,
+}
+
+/// Handle to the append only file of a server.
+///
+/// `Aof` is cheap to clone, every clone appends to the same file.
+#[derive(Clone)]
+pub struct Aof {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+
+    fsync: Fsync,
+
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The file commands are appended to. Not buffered: a command must reach
+    /// the OS before it is answered.
+    file: File,
+
+    /// Something was written since the last fsync
+    dirty: bool,
+
+    /// Number of writes made to the file, and how many of them were flushed
+    /// to disk by `flush_logged`.
+    written: u64,
+    synced: u64,
+
+    /// Commands logged since a rewrite started. `Some` while a rewrite is in
+    /// progress, they are appended to the new file once it is complete.
+    rewrite: Option<Vec<u8>>,
+}
+
+impl Aof {
+    /// Open the append only file at `path`, replaying it into `db` first.
+    ///
+    /// The file is created if it does not exist. A command cut short at the
+    /// end of the file, as left behind when the server dies in the middle of
+    /// a write, is discarded with a warning. Any other invalid content is an
+    /// error naming the offending record, the file has to be fixed by hand or
+    /// opened with [`Aof::open_truncating_invalid`].
+    ///
+    /// With [`Fsync::EverySec`], this spawns the task flushing the file, so
+    /// it must be called from within a Tokio runtime. The task exits once
+    /// every handle to the `Aof` has been dropped.
+    pub fn open(path: impl Into<PathBuf>, fsync: Fsync, db: &Db) -> crate::Result<Aof> {
+        Aof::open_with(path.into(), fsync, db, false)
+    }
+
+    /// Like [`Aof::open`], except that the file is cut off at the first
+    /// invalid record, with a warning, instead of failing. Every command
+    /// from that record on is lost.
+    pub fn open_truncating_invalid(
+        path: impl Into<PathBuf>,
+        fsync: Fsync,
+        db: &Db,
+    ) -> crate::Result<Aof> {
+        Aof::open_with(path.into(), fsync, db, true)
+    }
+
+    fn open_with(
+        path: PathBuf,
+        fsync: Fsync,
+        db: &Db,
+        truncate_invalid: bool,
+    ) -> crate::Result<Aof> {
+        replay(&path, db, truncate_invalid)?;
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let shared = Arc::new(Shared {
+            path,
+            fsync,
+            state: Mutex::new(State {
+                file,
+                dirty: false,
+                written: 0,
+                synced: 0,
+                rewrite: None,
+            }),
+        });
+
+        if fsync == Fsync::EverySec {
+            // The task only holds a weak reference, so it does not keep the
+            // file open after the last `Aof` is gone.
+            tokio::spawn(sync_every_second(Arc::downgrade(&shared)));
+        }
+
+        Ok(Aof { shared })
+    }
+
+    /// Path of the file.
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// Returns `true` while a rewrite started by [`rewrite`](Aof::rewrite)
+    /// has not completed yet.
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.shared.state.lock().unwrap().rewrite.is_some()
+    }
+
+    /// Flush everything written so far to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.shared.sync()
+    }
+
+    /// Start rewriting the file from the current contents of `db`.
+    ///
+    /// Returns right away, the keyspace is copied and the file written by a
+    /// blocking task. Commands keep being appended to the old file in the
+    /// meantime, and are also kept aside to be appended to the new one, which
+    /// then replaces the old file. If the rewrite fails, the old file stays
+    /// in place and the error is logged.
+    ///
+    /// Only one rewrite runs at a time, an error is returned if one is
+    /// already in progress.
+    pub fn rewrite(&self, db: &Db) -> crate::Result<()> {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+
+            if state.rewrite.is_some() {
+                return Err("ERR Background append only file rewriting already in progress".into());
+            }
+
+            state.rewrite = Some(vec![]);
+        }
+
+        let shared = self.shared.clone();
+        let db = db.clone();
+
+        // Copying takes time in proportion to the keyspace, keep it off the
+        // runtime threads
+        tokio::task::spawn_blocking(move || {
+            // With the gate held exclusively no command is running, so every
+            // command is either part of the copy or kept aside, never both
+            // nor neither.
+            let entries = {
+                let _gate = db.lock_exclusive();
+
+                shared.state.lock().unwrap().rewrite = Some(vec![]);
+                db.snapshot()
+            };
+
+            if let Err(err) = shared.rewrite(entries) {
+                eprintln!("failed to rewrite append only file; err = {}", err);
+                shared.state.lock().unwrap().rewrite = None;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Apply a command and append the records it returns to the file.
+    ///
+    /// `apply` returns its result along with the commands to write, usually
+    /// the command itself, none if it did not change the keyspace. Both
+    /// happen under the file lock, so commands are written in the order they
+    /// changed the keyspace.
+    ///
+    /// With [`Fsync::Always`], the records are not flushed to disk yet: this
+    /// runs with the gate held, and flushing may take a while. The caller
+    /// waits for [`flush_logged`](Aof::flush_logged) once the gate is
+    /// released, before answering.
+    ///
+    /// If the records cannot be written, an error is returned even though
+    /// the keyspace was changed.
+    pub(crate) fn log<T>(&self, apply: impl FnOnce() -> (T, Vec<Frame>)) -> crate::Result<T> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        let (ret, records) = apply();
+
+        if records.is_empty() {
+            return Ok(ret);
+        }
+
+        let mut buf = vec![];
+        for record in &records {
+            encode(record, &mut buf);
+        }
+
+        if let Some(pending) = &mut state.rewrite {
+            pending.extend_from_slice(&buf);
+        }
+
+        match state.file.write_all(&buf) {
+            Ok(()) => {
+                state.dirty = true;
+                state.written += 1;
+                Ok(ret)
+            }
+            Err(err) => {
+                eprintln!("failed to write to append only file; err = {}", err);
+                Err(format!("ERR failed to write to the append only file: {}", err).into())
+            }
+        }
+    }
+
+    /// With [`Fsync::Always`], wait for everything logged so far to be
+    /// flushed to disk. Does nothing with the other policies.
+    ///
+    /// Flushing happens on a blocking thread. Writes already flushed by
+    /// another caller are not flushed again.
+    pub(crate) async fn flush_logged(&self) -> crate::Result<()> {
+        if self.shared.fsync != Fsync::Always {
+            return Ok(());
+        }
+
+        let shared = self.shared.clone();
+        let res = tokio::task::spawn_blocking(move || shared.sync_written()).await?;
+
+        res.map_err(|err| {
+            eprintln!("failed to fsync append only file; err = {}", err);
+            format!("ERR failed to write to the append only file: {}", err).into()
+        })
+    }
+}
+
+impl fmt::Debug for Aof {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Aof")
+            .field("path", &self.shared.path)
+            .field("fsync", &self.shared.fsync)
+            .finish()
+    }
+}
+
+impl FromStr for Fsync {
+    type Err = crate::Error;
+
+    /// Parse the values accepted by the Redis `appendfsync` setting.
+    fn from_str(s: &str) -> crate::Result<Fsync> {
+        match &s.to_lowercase()[..] {
+            "always" => Ok(Fsync::Always),
+            "everysec" => Ok(Fsync::EverySec),
+            "no" => Ok(Fsync::No),
+            _ => Err(format!("invalid fsync policy `{}`", s).into()),
+        }
+    }
+}
+
+impl Shared {
+    fn sync(&self) -> io::Result<()> {
+        // Flush through a second handle to the file, so commands can keep
+        // being appended while the disk catches up.
+        let file = {
+            let mut state = self.state.lock().unwrap();
+
+            if !state.dirty {
+                return Ok(());
+            }
+
+            state.dirty = false;
+            state.file.try_clone()?
+        };
+
+        file.sync_data()
+    }
+
+    /// Flush every write made so far, unless another call already did.
+    fn sync_written(&self) -> io::Result<()> {
+        let (file, written) = {
+            let state = self.state.lock().unwrap();
+
+            if state.synced >= state.written {
+                return Ok(());
+            }
+
+            (state.file.try_clone()?, state.written)
+        };
+
+        file.sync_data()?;
+
+        let mut state = self.state.lock().unwrap();
+        state.synced = state.synced.max(written);
+
+        Ok(())
+    }
+
+    /// Write `entries` to a temporary file, then swap it with the current
+    /// file.
+    fn rewrite(&self, entries: Vec<(String, Value, Option<SystemTime>)>) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("rewrite.tmp");
+
+        let res = self.write_rewrite(&tmp_path, entries);
+
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+
+        res
+    }
+
+    fn write_rewrite(
+        &self,
+        tmp_path: &Path,
+        entries: Vec<(String, Value, Option<SystemTime>)>,
+    ) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(tmp_path)?);
+        let mut buf = vec![];
+
+        for (key, value, expires_at) in entries {
+            buf.clear();
+
+            for frame in rebuild(&key, &value, expires_at) {
+                encode(&frame, &mut buf);
+            }
+
+            out.write_all(&buf)?;
+        }
+
+        let mut file = out.into_inner().map_err(|err| err.into_error())?;
+        file.sync_data()?;
+
+        // From here on commands wait for the swap. Only what was logged
+        // during the rewrite is left to write, which is quick.
+        let mut state = self.state.lock().unwrap();
+
+        let pending = state.rewrite.take().unwrap_or_default();
+        file.write_all(&pending)?;
+        file.sync_data()?;
+
+        fs::rename(tmp_path, &self.path)?;
+
+        // The cursor of the new file is at its end, it can be written to
+        // directly.
+        state.file = file;
+        state.dirty = false;
+        state.synced = state.written;
+
+        Ok(())
+    }
+}
+
+/// Apply every command found in the file at `path` to `db`.
+///
+/// A missing file is an empty one. A truncated final command is cut off the
+/// file, so that the commands appended next start on a clean boundary. So is
+/// a final transaction missing its `EXEC`, none of its commands are applied.
+///
+/// An invalid record is an error naming it, unless `truncate_invalid` is
+/// set, in which case the file is cut off at that record instead.
+fn replay(path: &Path, db: &Db, truncate_invalid: bool) -> crate::Result<()> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+
+    // Nothing in a valid file can be longer than the file itself
+    let limits = Limits {
+        max_bulk_len: data.len(),
+        max_array_len: data.len(),
+        ..Limits::default()
+    };
+
+    let mut pos = 0;
+
+    // Commands of the transaction being read, applied once its `EXEC` is,
+    // along with where its `MULTI` starts
+    let mut transaction: Option<(usize, Vec<Command>)> = None;
+
+    // Why the record at `pos` could not be replayed
+    let mut invalid = None;
+
+    while pos < data.len() {
+        let (len, command) = match read_command(&data[pos..], &limits) {
+            Ok(Some(read)) => read,
+            Ok(None) => break,
+            Err(err) => {
+                invalid = Some(err);
+                break;
+            }
+        };
+
+        match (command, &mut transaction) {
+            (Command::Multi(_), None) => transaction = Some((pos, vec![])),
+            (Command::Exec(_), Some(_)) => {
+                let (_, commands) = transaction.take().unwrap();
+                for command in commands {
+                    command.apply(db);
+                }
+            }
+            (Command::Multi(_) | Command::Exec(_), _) => {
+                invalid = Some("unexpected transaction boundary".to_string());
+                break;
+            }
+            (command, Some((_, commands))) => commands.push(command),
+            (command, None) => {
+                command.apply(db);
+            }
+        }
+
+        pos += len;
+    }
+
+    let end = transaction.map_or(pos, |(start, _)| start);
+
+    match invalid {
+        Some(err) => {
+            let record = &data[pos..data.len().min(pos + RECORD_PREVIEW_LEN)];
+            let msg = format!(
+                "invalid append only file at byte {}, in the record starting with `{}`; {}",
+                pos,
+                record.escape_ascii(),
+                err
+            );
+
+            if !truncate_invalid {
+                return Err(msg.into());
+            }
+
+            eprintln!("{}; discarding its last {} bytes", msg, data.len() - end);
+        }
+        None if end < data.len() => eprintln!(
+            "append only file ends with a truncated command or transaction, discarding its last {} bytes",
+            data.len() - end
+        ),
+        None => return Ok(()),
+    }
+
+    file.set_len(end as u64)?;
+
+    Ok(())
+}
+
+/// Read the command at the start of `data`, along with the length of its
+/// record. `None` if the record is cut short.
+fn read_command(data: &[u8], limits: &Limits) -> Result<Option<(usize, Command)>, String> {
+    let mut cursor = Cursor::new(data);
+
+    match Frame::check(&mut cursor, limits) {
+        Ok(()) => {}
+        Err(frame::Error::Incomplete) => return Ok(None),
+        Err(err) => return Err(err.to_string()),
+    }
+
+    let len = cursor.position() as usize;
+
+    // Copied out of `data`, otherwise every value would keep the whole file
+    // alive, overwritten commands included.
+    let mut src = Bytes::copy_from_slice(&data[..len]);
+    let frame = Frame::parse(&mut src).map_err(|err| err.to_string())?;
+    let command = Command::from_frame(frame).map_err(|err| err.to_string())?;
+
+    Ok(Some((len, command)))
+}
+
+/// Commands recreating `key`, as written by a rewrite.
+fn rebuild(key: &str, value: &Value, expires_at: Option<SystemTime>) -> Vec<Frame> {
+    let mut frames = match value {
+        // A single `SET` covers the deadline as well
+        Value::String(data) => return vec![set::frame_at(key, data, expires_at)],
+        Value::List(list) => {
+            let elements: Vec<_> = list.iter().collect();
+
+            elements
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    let mut frame = Frame::array();
+                    frame.push_bulk(Bytes::from("rpush".as_bytes()));
+                    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+                    for element in chunk {
+                        frame.push_bulk((*element).clone());
+                    }
+                    frame
+                })
+                .collect::<Vec<_>>()
+        }
+        Value::Hash(hash) => {
+            let pairs: Vec<_> = hash.iter().collect();
+
+            pairs
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    let mut frame = Frame::array();
+                    frame.push_bulk(Bytes::from("hset".as_bytes()));
+                    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+                    for (field, value) in chunk {
+                        frame.push_bulk((*field).clone());
+                        frame.push_bulk((*value).clone());
+                    }
+                    frame
+                })
+                .collect::<Vec<_>>()
+        }
+        Value::Set(set) => {
+            let members: Vec<_> = set.iter().collect();
+
+            members
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| {
+                    let mut frame = Frame::array();
+                    frame.push_bulk(Bytes::from("sadd".as_bytes()));
+                    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+                    for member in chunk {
+                        frame.push_bulk((*member).clone());
+                    }
+                    frame
+                })
+                .collect::<Vec<_>>()
+        }
+        Value::SortedSet(set) => {
+            let members: Vec<_> = set.iter().collect();
+
+            members
+                .chunks(ITEMS_PER_COMMAND)
+                .map(|chunk| zset::zadd_frame(key, chunk.iter().copied()))
+                .collect::<Vec<_>>()
+        }
+    };
+
+    if let Some(when) = expires_at {
+        frames.push(expire::pexpireat_frame(key, expire::unix_millis(when)));
+    }
+
+    frames
+}
+
+/// Encode a command frame as RESP.
+///
+/// Commands are arrays of strings, integer arguments are written as strings
+/// like clients do.
+fn encode(frame: &Frame, dst: &mut Vec<u8>) {
+    match frame {
+        Frame::Array(args) => {
+            dst.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+
+            for arg in args {
+                match arg {
+                    Frame::Bulk(data) => encode_bulk(data, dst),
+                    Frame::Integer(value) => encode_bulk(value.to_string().as_bytes(), dst),
+                    _ => unreachable!("command argument is not a string: {:?}", arg),
+                }
+            }
+        }
+        _ => unreachable!("command is not an array: {:?}", frame),
+    }
+}
+
+fn encode_bulk(data: &[u8], dst: &mut Vec<u8>) {
+    dst.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+    dst.extend_from_slice(data);
+    dst.extend_from_slice(b"\r\n");
+}
+
+/// Background task flushing the file once per second. Exits once the `Aof`
+/// has been dropped.
+async fn sync_every_second(shared: Weak<Shared>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        // Only hold a strong reference while flushing, otherwise the `Aof`
+        // could never be dropped.
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        // Flushing blocks, keep it off the runtime threads
+        let res = tokio::task::spawn_blocking(move || shared.sync()).await;
+
+        if let Ok(Err(err)) = res {
+            eprintln!("failed to fsync append only file; err = {}", err);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use my_redis::cli::{format_frame, split_args};
+use my_redis::{Connection, Frame};
+
+use bytes::Bytes;
+use std::io::{IsTerminal, Write};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+
+const USAGE: &str = "\
+usage: client [-h <host>] [-p <port>] [command [arg ...]]
+
+Without a command, commands are read from stdin, one per line. When stdin is
+a terminal, an interactive prompt is shown.";
+
+/// Entry point for CLI tool.
+///
+/// The `[tokio::main]` annotation signals that the Tokio runtime should be
+/// started when the function is called. The body of the function is executed
+/// within the newly spawned runtime.
+#[tokio::main]
+async fn main() -> my_redis::Result<()> {
+    let mut host = "127.0.0.1".to_string();
+    let mut port: u16 = 6379;
+    let mut command = vec![];
+
+    let mut args = std::env::args().skip(1);
+
+    // Options come first, everything after them is the one-shot command
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-h" | "--host" if command.is_empty() => host = args.next().ok_or(USAGE)?,
+            "-p" | "--port" if command.is_empty() => port = args.next().ok_or(USAGE)?.parse()?,
+            "--help" if command.is_empty() => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => command.push(Bytes::from(arg)),
+        }
+    }
+
+    let socket = TcpStream::connect((&host[..], port)).await?;
+    let mut connection = Connection::new(socket);
+
+    // One-shot mode, `client GET foo`. The arguments were already split by
+    // the shell so they are sent as is.
+    if !command.is_empty() {
+        return run(&mut connection, command).await;
+    }
+
+    let interactive = std::io::stdin().is_terminal();
+    let prompt = format!("{}:{}> ", host, port);
+
+    let mut lines = BufReader::new(io::stdin()).lines();
+
+    loop {
+        if interactive {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+        }
+
+        let line = match lines.next_line().await? {
+            Some(line) => line,
+            // End of input
+            None => return Ok(()),
+        };
+
+        let command = match split_args(&line) {
+            Ok(command) => command,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+
+        match command.first() {
+            None => continue,
+            Some(name)
+                if name.eq_ignore_ascii_case(b"quit") || name.eq_ignore_ascii_case(b"exit") =>
+            {
+                return Ok(())
+            }
+            Some(_) => run(&mut connection, command).await?,
+        }
+    }
+}
+
+/// Send a single command and print the response.
+///
+/// After a successful `SUBSCRIBE`, messages are printed as they arrive until
+/// the server closes the connection.
+async fn run(connection: &mut Connection, command: Vec<Bytes>) -> my_redis::Result<()> {
+    let subscribe = command[0].eq_ignore_ascii_case(b"subscribe");
+
+    let frame = Frame::Array(command.into_iter().map(Frame::Bulk).collect());
+    connection.write_frame(&frame).await?;
+
+    let response = read_response(connection).await?;
+    println!("{}", format_frame(&response));
+
+    if subscribe && !matches!(response, Frame::Error(_)) {
+        println!("Reading messages... (press Ctrl-C to quit)");
+
+        loop {
+            let message = read_response(connection).await?;
+            println!("{}", format_frame(&message));
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the response to a command, treating a closed connection as an error.
+async fn read_response(connection: &mut Connection) -> my_redis::Result<Frame> {
+    match connection.read_frame().await? {
+        Some(frame) => Ok(frame),
+        None => Err("connection closed by the server".into()),
+    }
+}
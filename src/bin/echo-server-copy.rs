@@ -1,4 +1,4 @@
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::io;
 use tokio::net::TcpListener;
 
 #[tokio::main]
@@ -6,7 +6,7 @@ async fn main() -> io::Result<()> {
     let listener = TcpListener::bind("127.0.0.1:6412").await?;
 
     loop {
-        let (mut socket, _) = listener.accept().await?;
+        let (socket, _) = listener.accept().await?;
 
         tokio::spawn(async move {
             let (mut rd, mut wr) = io::split(socket);
@@ -16,6 +16,4 @@ async fn main() -> io::Result<()> {
             }
         });
     }
-
-    Ok(())
 }
@@ -1,63 +1,133 @@
-use bytes::Bytes;
-use mini_redis::{Connection, Frame};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::net::{TcpListener, TcpStream};
-
-type Db = Arc<Mutex<HashMap<String, Bytes>>>;
+use my_redis::aof::{Aof, Fsync};
+use my_redis::db::{Db, DEFAULT_SHARDS};
+use my_redis::server::{self, Config};
+use my_redis::snapshot::{self, SaveRule, Snapshots};
+use std::process;
+use std::str::FromStr;
+use tokio::net::TcpListener;
+use tokio::signal;
 
 #[tokio::main]
 async fn main() {
+    let mut num_shards = DEFAULT_SHARDS;
+    let mut config = Config::default();
+    let mut appendonly = false;
+    let mut appendfilename = "appendonly.aof".to_string();
+    let mut appendfsync = Fsync::EverySec;
+    let mut aof_truncate_invalid = false;
+    let mut dbfilename = "dump.rdb".to_string();
+    let mut save_rules = SaveRule::defaults();
+
+    // `--shards <n>`, `--max-clients <n>`, and the Redis named
+    // `--proto-max-bulk-len <bytes>`, `--client-query-buffer-limit <bytes>`,
+    // `--appendonly yes|no`, `--appendfilename <path>`,
+    // `--appendfsync always|everysec|no`, `--dbfilename <path>` and
+    // `--save "<seconds> <changes> ..."`, plus `--aof-truncate-invalid yes|no`
+    // to recover from a corrupt append only file. Anything else is ignored.
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--shards" => num_shards = parse_count(&arg, args.next()),
+            "--max-clients" => config.max_connections = parse_count(&arg, args.next()),
+            "--proto-max-bulk-len" => config.limits.max_bulk_len = parse_count(&arg, args.next()),
+            "--client-query-buffer-limit" => {
+                config.limits.max_buffered_bytes = parse_count(&arg, args.next())
+            }
+            "--appendonly" => appendonly = parse_value::<YesNo>(&arg, args.next()).0,
+            "--appendfilename" => appendfilename = parse_value(&arg, args.next()),
+            "--appendfsync" => appendfsync = parse_value(&arg, args.next()),
+            "--aof-truncate-invalid" => {
+                aof_truncate_invalid = parse_value::<YesNo>(&arg, args.next()).0
+            }
+            "--dbfilename" => dbfilename = parse_value(&arg, args.next()),
+            "--save" => {
+                let value: String = parse_value(&arg, args.next());
+                save_rules = SaveRule::parse_list(&value)
+                    .unwrap_or_else(|_| panic!("invalid value `{}` for `{}`", value, arg));
+            }
+            _ => {}
+        }
+    }
+
+    let db = Db::new(num_shards);
+
+    // Rebuild the keyspace before serving anyone. Like Redis, the append
+    // only file wins over the snapshot when both are enabled, as it is the
+    // more up to date of the two.
+    if appendonly {
+        let aof = if aof_truncate_invalid {
+            Aof::open_truncating_invalid(&appendfilename, appendfsync, &db)
+        } else {
+            Aof::open(&appendfilename, appendfsync, &db)
+        };
+
+        match aof {
+            Ok(aof) => config.aof = Some(aof),
+            Err(err) => exit_with_error(format!(
+                "failed to load `{}`: {}\n\
+                 Fix or remove the record by hand, or restart with \
+                 `--aof-truncate-invalid yes` to discard the file from that \
+                 record on.",
+                appendfilename, err
+            )),
+        }
+    } else if let Err(err) = snapshot::load(&dbfilename, &db) {
+        exit_with_error(format!("failed to load `{}`: {}", dbfilename, err));
+    }
+
+    config.snapshots = Some(Snapshots::new(dbfilename, save_rules, &db));
+
     // Bind to the listener address
     let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
 
-    println!("Listening...");
+    println!(
+        "Listening... ({} shards, at most {} clients)",
+        num_shards, config.max_connections
+    );
 
-    let db: Db = Arc::new(Mutex::new(HashMap::new()));
+    // Stop accepting connections on Ctrl-C and wait for the open ones to
+    // finish their current command.
+    server::run_with_config(listener, db, config, signal::ctrl_c()).await;
+}
 
-    loop {
-        // The second item contains the IP and port of the new connection
-        let (socket, _) = listener.accept().await.unwrap();
+/// Report an error the server cannot start with, and exit.
+fn exit_with_error(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
 
-        let db = db.clone();
+/// Parse the value of a flag expecting a positive number.
+fn parse_count(flag: &str, value: Option<String>) -> usize {
+    let value = value.unwrap_or_else(|| panic!("`{}` requires a value", flag));
 
-        tokio::spawn(async move {
-            process(socket, db).await;
-        });
+    match value.parse() {
+        Ok(n) if n > 0 => n,
+        _ => panic!("invalid value `{}` for `{}`", value, flag),
     }
 }
 
-async fn process(socket: TcpStream) {
-    use mini_redis::Command::{self, Get, Set};
-    use std::collections::HashMap;
+/// Parse the value of a flag with `FromStr`.
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_else(|| panic!("`{}` requires a value", flag));
 
-    // create the variable to store values
-    let mut db = HashMap::new();
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => panic!("invalid value `{}` for `{}`", value, flag),
+    }
+}
 
-    // The `Connection` lets us read/write redis **frames** instead
-    // of byte streams. The `Connection` type is defined by mini-redis.
-    let mut connection = Connection::new(socket);
+/// A `yes` or `no` flag value, as Redis settings take them.
+struct YesNo(bool);
 
-    // Use `read_frame()` to receive a command from the connection
-    while let Some(frame) = connection.read_frame().await.unwrap() {
-        let response = match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                // The value is stored as Vec<u8>
-                db.insert(cmd.key().to_string(), cmd.value().to_vec());
-                Frame::Simple("OK".to_string())
-            }
-            Get(cmd) => {
-                if let Some(value) = db.get(cmd.key()) {
-                    // `Frame::Bulk` expected data to be of a type `Bytes`
-                    // `&Vec<u8>` is converted into `Bytes` uding `.into`
-                    Frame::Bulk(value.clone().into())
-                } else {
-                    Frame::Null
-                }
-            }
-            cmd => panic!("unimplemented {:?}", cmd),
-        };
-        // Write the response to the client
-        connection.write_frame(&response).await.unwrap();
+impl FromStr for YesNo {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<YesNo, ()> {
+        match s {
+            "yes" => Ok(YesNo(true)),
+            "no" => Ok(YesNo(false)),
+            _ => Err(()),
+        }
     }
 }
@@ -0,0 +1,124 @@
+//! Helpers for the `client` command line tool.
+//!
+//! Typed commands are split into arguments the same way `redis-cli` does it,
+//! and responses are rendered in the same format.
+
+use crate::frame::format_double;
+use crate::Frame;
+
+pub use crate::inline::split_args;
+
+/// Render a response the way `redis-cli` does.
+///
+/// Nested arrays are indented under the index of the element containing
+/// them. The returned string does not end with a new line.
+///
+/// # Examples
+///
+/// ```
+/// use my_redis::cli::format_frame;
+/// use my_redis::Frame;
+///
+/// let frame = Frame::Array(vec![Frame::Integer(1), Frame::Null]);
+/// assert_eq!(format_frame(&frame), "1) (integer) 1\n2) (nil)");
+/// ```
+pub fn format_frame(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(value) => value.clone(),
+        Frame::Error(msg) => format!("(error) {}", msg),
+        Frame::Integer(num) => format!("(integer) {}", num),
+        Frame::Bulk(value) => quote(value),
+        Frame::Null => "(nil)".to_string(),
+        Frame::Double(num) => format!("(double) {}", format_double(*num)),
+        Frame::Boolean(value) => format!("({})", value),
+        Frame::BigNumber(num) => format!("(big number) {}", num),
+        // Verbatim strings are meant to be shown as they are
+        Frame::Verbatim(_, value) => String::from_utf8_lossy(value).into_owned(),
+        Frame::Array(parts) if parts.is_empty() => "(empty array)".to_string(),
+        Frame::Set(parts) if parts.is_empty() => "(empty set)".to_string(),
+        Frame::Push(parts) if parts.is_empty() => "(empty push)".to_string(),
+        Frame::Map(pairs) if pairs.is_empty() => "(empty hash)".to_string(),
+        Frame::Array(parts) | Frame::Push(parts) => {
+            format_elements(parts.iter().map(format_frame).collect(), ')')
+        }
+        Frame::Set(parts) => format_elements(parts.iter().map(format_frame).collect(), '~'),
+        Frame::Map(pairs) => {
+            let entries = pairs
+                .iter()
+                .map(|(key, value)| {
+                    // The value's following lines are aligned with its first
+                    // line.
+                    let key = format!("{} => ", format_frame(key));
+                    indent(&key, &format_frame(value))
+                })
+                .collect();
+
+            format_elements(entries, '#')
+        }
+        // Attributes are metadata, only the frame they describe is shown
+        Frame::Attribute(_, frame) => format_frame(frame),
+    }
+}
+
+/// Number the formatted elements of an aggregate, `1) `, `2) `, etc. using
+/// `marker` after the index.
+fn format_elements(elements: Vec<String>, marker: char) -> String {
+    // Indices are right aligned, so every element starts in the same column.
+    let width = elements.len().to_string().len();
+    let mut out = String::new();
+
+    for (i, element) in elements.iter().enumerate() {
+        let prefix = format!("{:>width$}{} ", i + 1, marker, width = width);
+
+        if !out.is_empty() {
+            out.push('\n');
+        }
+
+        out.push_str(&indent(&prefix, element));
+    }
+
+    out
+}
+
+/// Put `prefix` in front of the first line of `text`, and indent the other
+/// lines by as many spaces.
+fn indent(prefix: &str, text: &str) -> String {
+    let mut out = String::new();
+
+    // `split` rather than `lines`, so empty text still gets its prefix
+    for (i, line) in text.split('\n').enumerate() {
+        if i == 0 {
+            out.push_str(prefix);
+        } else {
+            out.push('\n');
+            out.extend(std::iter::repeat_n(' ', prefix.chars().count()));
+        }
+
+        out.push_str(line);
+    }
+
+    out
+}
+
+/// Quote a bulk string, escaping anything that is not printable ASCII.
+fn quote(value: &[u8]) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+
+    for &c in value {
+        match c {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            c if c.is_ascii_graphic() || c == b' ' => out.push(c as char),
+            c => out.push_str(&format!("\\x{:02x}", c)),
+        }
+    }
+
+    out.push('"');
+    out
+}
@@ -0,0 +1,563 @@
+//! Minimal Redis client implementation
+//!
+//! Provides an async connect and methods for issuing the supported commands.
+
+use crate::cmd::{Expire, Get, Persist, Ping, Publish, Set, Subscribe, Ttl, Unsubscribe};
+use crate::db;
+use crate::{Connection, Frame};
+
+use bytes::Bytes;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// Established connection with a Redis server.
+///
+/// Backed by a single `TcpStream`, `Client` provides basic network client
+/// functionality (no pooling, retrying, ...). Connections are established using
+/// the [`connect`](fn@connect) function.
+///
+/// Requests are issued using the various methods of `Client`. Errors returned
+/// by the server are turned into [`ServerError`] values carrying the error
+/// message.
+pub struct Client {
+    /// The TCP connection decorated with the redis protocol encoder / decoder
+    /// implemented using a buffered `TcpStream`.
+    ///
+    /// `Connection` lets the client operate at the "frame" level and keeps
+    /// the byte level protocol parsing details encapsulated.
+    connection: Connection,
+
+    /// A request was written and its reply not read yet. Stays set if the
+    /// future making the request is dropped, the next reply on the
+    /// connection would then belong to that request.
+    awaiting_reply: bool,
+}
+
+/// A client that has entered pub/sub mode.
+///
+/// Once clients subscribe to a channel, they may only perform pub/sub related
+/// commands. The `Client` type is transitioned to a `Subscriber` type in order
+/// to prevent non-pub/sub methods from being called.
+pub struct Subscriber {
+    /// The subscribed client.
+    client: Client,
+
+    /// The set of channels to which the `Subscriber` is currently subscribed.
+    subscribed_channels: Vec<String>,
+}
+
+/// Error reply sent by the server to a request, e.g. `ERR value is not an
+/// integer or out of range`.
+///
+/// It only concerns the request it answers. Any other error returned by a
+/// `Client` means the connection is broken, or out of step with the server
+/// after an unexpected reply.
+#[derive(Debug)]
+pub struct ServerError(String);
+
+/// A message received on a subscribed channel.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub channel: String,
+    pub content: Bytes,
+}
+
+/// Establish a connection with the Redis server located at `addr`.
+///
+/// `addr` may be any type that can be asynchronously converted to a
+/// `SocketAddr`. This includes `SocketAddr` and strings. The `ToSocketAddrs`
+/// trait is the Tokio version and not the `std` version.
+///
+/// # Examples
+///
+/// ```no_run
+/// use my_redis::client;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = match client::connect("localhost:6379").await {
+///         Ok(client) => client,
+///         Err(_) => panic!("failed to establish connection"),
+///     };
+/// # drop(client);
+/// }
+/// ```
+pub async fn connect<T: ToSocketAddrs>(addr: T) -> crate::Result<Client> {
+    // The `addr` argument is passed directly to `TcpStream::connect`. This
+    // performs any asynchronous DNS lookup and attempts to establish the TCP
+    // connection. An error at either step returns an error, which is then
+    // bubbled up to the caller of `my_redis` connect.
+    let socket = TcpStream::connect(addr).await?;
+
+    // Initialize the connection state. This allocates read/write buffers to
+    // perform redis protocol frame parsing.
+    let connection = Connection::new(socket);
+
+    Ok(Client {
+        connection,
+        awaiting_reply: false,
+    })
+}
+
+impl Client {
+    /// Ping to the server.
+    ///
+    /// Returns PONG if no argument is provided, otherwise
+    /// return a copy of the argument as a bulk.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use my_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let pong = client.ping(None).await.unwrap();
+    ///     assert_eq!(b"PONG", &pong[..]);
+    /// }
+    /// ```
+    pub async fn ping(&mut self, msg: Option<Bytes>) -> crate::Result<Bytes> {
+        let frame = Ping::new(msg).into_frame();
+        self.write_request(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(value) => Ok(value.into()),
+            Frame::Bulk(value) => Ok(value),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Get the value of key.
+    ///
+    /// If the key does not exist the special value `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use my_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let val = client.get("foo").await.unwrap();
+    ///     println!("Got = {:?}", val);
+    /// }
+    /// ```
+    pub async fn get(&mut self, key: &str) -> crate::Result<Option<Bytes>> {
+        // Create a `Get` command for the `key` and convert it to a frame.
+        let frame = Get::new(key).into_frame();
+
+        // Write the frame to the socket. This writes the full frame to the
+        // socket, waiting if necessary.
+        self.write_request(&frame).await?;
+
+        // Wait for the response from the server
+        //
+        // Both `Simple` and `Bulk` frames are accepted. `Null` represents the
+        // key not being present and `None` is returned.
+        match self.read_response().await? {
+            Frame::Simple(value) => Ok(Some(value.into())),
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Set `key` to hold the given `value`.
+    ///
+    /// The `value` is associated with `key` until it is overwritten by the next
+    /// call to `set` or it is removed.
+    ///
+    /// If key already holds a value, it is overwritten. Any previous time to
+    /// live associated with the key is discarded on successful SET operation.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use my_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set("foo", "bar".into()).await.unwrap();
+    ///
+    ///     // Getting the value immediately works
+    ///     let val = client.get("foo").await.unwrap().unwrap();
+    ///     assert_eq!(val, "bar");
+    /// }
+    /// ```
+    pub async fn set(&mut self, key: &str, value: Bytes) -> crate::Result<()> {
+        // Create a `Set` command and pass it to `set_cmd`. A separate method is
+        // used to set a value with an expiration. The common parts of both
+        // functions are implemented by `set_cmd`.
+        self.set_cmd(Set::new(key, value, None)).await
+    }
+
+    /// Set `key` to hold the given `value`. The value expires after
+    /// `expiration`.
+    ///
+    /// The `value` is associated with `key` until one of the following:
+    /// - it expires.
+    /// - it is overwritten by the next call to `set`.
+    /// - it is removed.
+    ///
+    /// If key already holds a value, it is overwritten. Any previous time to
+    /// live associated with the key is discarded on a successful SET operation.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use my_redis::client;
+    /// use tokio::time;
+    /// use std::time::Duration;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let ttl = Duration::from_millis(500);
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     client.set_expires("foo", "bar".into(), ttl).await.unwrap();
+    ///
+    ///     // Getting the value immediately works
+    ///     let val = client.get("foo").await.unwrap().unwrap();
+    ///     assert_eq!(val, "bar");
+    ///
+    ///     // Wait for the TTL to expire
+    ///     time::sleep(ttl).await;
+    ///
+    ///     let val = client.get("foo").await.unwrap();
+    ///     assert!(val.is_none());
+    /// }
+    /// ```
+    pub async fn set_expires(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expiration: Duration,
+    ) -> crate::Result<()> {
+        // Create a `Set` command and pass it to `set_cmd`. A separate method is
+        // used to set a value with an expiration. The common parts of both
+        // functions are implemented by `set_cmd`.
+        self.set_cmd(Set::new(key, value, Some(expiration))).await
+    }
+
+    /// The core `SET` logic, used by both `set` and `set_expires`.
+    async fn set_cmd(&mut self, cmd: Set) -> crate::Result<()> {
+        // Convert the `Set` command into a frame
+        let frame = cmd.into_frame();
+
+        // Write the frame to the socket. This writes the full frame to the
+        // socket, waiting if necessary.
+        self.write_request(&frame).await?;
+
+        // Wait for the response from the server. On success, the server
+        // responds simply with `OK`. Any other response indicates an error.
+        match self.read_response().await? {
+            Frame::Simple(response) if response == "OK" => Ok(()),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Set a timeout on `key`. The key is removed once `expiration` elapses.
+    ///
+    /// The timeout is rounded down to whole seconds, a timeout shorter than a
+    /// second removes the key right away. Returns `false` if the key does not
+    /// exist.
+    pub async fn expire(&mut self, key: &str, expiration: Duration) -> crate::Result<bool> {
+        let seconds = i64::try_from(expiration.as_secs())?;
+        let frame = Expire::new(key, seconds).into_frame();
+        self.write_request(&frame).await?;
+
+        self.read_bool().await
+    }
+
+    /// Remove the timeout on `key`, so it no longer expires.
+    ///
+    /// Returns `true` only if the key existed and had a timeout.
+    pub async fn persist(&mut self, key: &str) -> crate::Result<bool> {
+        let frame = Persist::new(key).into_frame();
+        self.write_request(&frame).await?;
+
+        self.read_bool().await
+    }
+
+    /// Get the remaining time to live of `key`, with millisecond precision.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use my_redis::client;
+    /// use my_redis::db::Ttl;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     match client.ttl("foo").await.unwrap() {
+    ///         Ttl::Missing => println!("no such key"),
+    ///         Ttl::Persistent => println!("never expires"),
+    ///         Ttl::Expires(remaining) => println!("expires in {:?}", remaining),
+    ///     }
+    /// }
+    /// ```
+    pub async fn ttl(&mut self, key: &str) -> crate::Result<db::Ttl> {
+        // `PTTL` gives the most precise answer
+        let frame = Ttl::new(key, true).into_frame();
+        self.write_request(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(-2) => Ok(db::Ttl::Missing),
+            Frame::Integer(-1) => Ok(db::Ttl::Persistent),
+            Frame::Integer(ms) if ms >= 0 => Ok(db::Ttl::Expires(Duration::from_millis(ms as u64))),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Posts `message` to the given `channel`.
+    ///
+    /// Returns the number of subscribers currently listening on the channel.
+    /// There is no guarantee that these subscribers receive the message as
+    /// they may disconnect at any time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use my_redis::client;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut client = client::connect("localhost:6379").await.unwrap();
+    ///
+    ///     let val = client.publish("foo", "bar".into()).await.unwrap();
+    ///     println!("Got = {:?}", val);
+    /// }
+    /// ```
+    pub async fn publish(&mut self, channel: &str, message: Bytes) -> crate::Result<u64> {
+        // Convert the `Publish` command into a frame
+        let frame = Publish::new(channel, message).into_frame();
+
+        // Write the frame to the socket
+        self.write_request(&frame).await?;
+
+        // Read the response
+        match self.read_response().await? {
+            Frame::Integer(response) => Ok(response as u64),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Subscribes the client to the specified channels.
+    ///
+    /// Once a client issues a subscribe command, it may no longer issue any
+    /// non-pub/sub commands. The function consumes `self` and returns a
+    /// `Subscriber`.
+    ///
+    /// The `Subscriber` value is used to receive messages as well as manage the
+    /// list of channels the client is subscribed to.
+    pub async fn subscribe(mut self, channels: Vec<String>) -> crate::Result<Subscriber> {
+        // Issue the subscribe command to the server and wait for confirmation.
+        // The client will then have been transitioned into the "subscriber"
+        // state and may only issue pub/sub commands from that point on.
+        self.subscribe_cmd(&channels).await?;
+
+        // Return the `Subscriber` type
+        Ok(Subscriber {
+            client: self,
+            subscribed_channels: channels,
+        })
+    }
+
+    /// The core `SUBSCRIBE` logic, used by misc subscribe fns
+    async fn subscribe_cmd(&mut self, channels: &[String]) -> crate::Result<()> {
+        // Convert the `Subscribe` command into a frame
+        let frame = Subscribe::new(channels.to_vec()).into_frame();
+
+        // Write the frame to the socket
+        self.write_request(&frame).await?;
+
+        // For each channel being subscribed to, the server responds with a
+        // message confirming subscription to that channel.
+        for channel in channels {
+            // Read the response
+            let response = self.read_response().await?;
+
+            // Verify it is confirmation of subscription.
+            match response {
+                Frame::Array(ref frame) => match frame.as_slice() {
+                    // The server responds with an array frame in the form of:
+                    //
+                    // ```
+                    // [ "subscribe", channel, num-subscribed ]
+                    // ```
+                    //
+                    // where channel is the name of the channel and
+                    // num-subscribed is the number of channels that the client
+                    // is currently subscribed to.
+                    [subscribe, schannel, ..]
+                        if *subscribe == "subscribe" && *schannel == &channel[..] => {}
+                    _ => return Err(response.to_error()),
+                },
+                frame => return Err(frame.to_error()),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Reads an integer response used as a boolean, `1` or `0`.
+    async fn read_bool(&mut self) -> crate::Result<bool> {
+        match self.read_response().await? {
+            Frame::Integer(0) => Ok(false),
+            Frame::Integer(1) => Ok(true),
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Returns `true` if a request was cut short before its reply was read,
+    /// in which case the connection can not be used for other requests.
+    pub(crate) fn awaiting_reply(&self) -> bool {
+        self.awaiting_reply
+    }
+
+    /// Writes a request frame to the socket, its reply is read with
+    /// `read_response`.
+    async fn write_request(&mut self, frame: &Frame) -> crate::Result<()> {
+        self.awaiting_reply = true;
+        self.connection.write_frame(frame).await?;
+
+        Ok(())
+    }
+
+    /// Reads a response frame from the socket.
+    ///
+    /// If an `Error` frame is received, it is converted to a `ServerError`.
+    async fn read_response(&mut self) -> crate::Result<Frame> {
+        let response = self.connection.read_frame().await?;
+
+        if response.is_some() {
+            self.awaiting_reply = false;
+        }
+
+        match response {
+            // Error frames are converted to `Err`
+            Some(Frame::Error(msg)) => Err(ServerError(msg).into()),
+            Some(frame) => Ok(frame),
+            None => {
+                // Receiving `None` here indicates the server has closed the
+                // connection without sending a frame. This is unexpected and is
+                // represented as a "connection reset by peer" error.
+                let err = Error::new(ErrorKind::ConnectionReset, "connection reset by server");
+
+                Err(err.into())
+            }
+        }
+    }
+}
+
+impl Subscriber {
+    /// Returns the set of channels currently subscribed to.
+    pub fn get_subscribed(&self) -> &[String] {
+        &self.subscribed_channels
+    }
+
+    /// Receive the next message published on a subscribed channel, waiting if
+    /// necessary.
+    ///
+    /// `None` indicates the subscription has been terminated.
+    pub async fn next_message(&mut self) -> crate::Result<Option<Message>> {
+        let frame = match self.client.connection.read_frame().await? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        // Messages are pushed as `[ "message", channel, content ]`. The
+        // content is arbitrary bytes, so it is taken out of the frame as is.
+        match frame {
+            Frame::Array(parts) => match <[Frame; 3]>::try_from(parts) {
+                Ok([message, channel, Frame::Bulk(content)]) if message == "message" => {
+                    Ok(Some(Message {
+                        channel: channel.to_string(),
+                        content,
+                    }))
+                }
+                Ok(parts) => Err(Frame::Array(parts.into()).to_error()),
+                Err(parts) => Err(Frame::Array(parts).to_error()),
+            },
+            frame => Err(frame.to_error()),
+        }
+    }
+
+    /// Subscribe to a list of new channels
+    pub async fn subscribe(&mut self, channels: &[String]) -> crate::Result<()> {
+        // Issue the subscribe command
+        self.client.subscribe_cmd(channels).await?;
+
+        // Update the set of subscribed channels.
+        self.subscribed_channels
+            .extend(channels.iter().map(Clone::clone));
+
+        Ok(())
+    }
+
+    /// Unsubscribe to a list of new channels
+    pub async fn unsubscribe(&mut self, channels: &[String]) -> crate::Result<()> {
+        let frame = Unsubscribe::new(channels.to_vec()).into_frame();
+
+        // Write the frame to the socket
+        self.client.write_request(&frame).await?;
+
+        // if the input channel list is empty, server acknowledges as unsubscribing
+        // from all subscribed channels, so we assert that the unsubscribe list received
+        // matches the client subscribed one
+        let num = if channels.is_empty() {
+            self.subscribed_channels.len()
+        } else {
+            channels.len()
+        };
+
+        // Read the response
+        for _ in 0..num {
+            let response = self.client.read_response().await?;
+
+            match response {
+                Frame::Array(ref frame) => match frame.as_slice() {
+                    [unsubscribe, channel, ..] if *unsubscribe == "unsubscribe" => {
+                        let len = self.subscribed_channels.len();
+
+                        if len == 0 {
+                            // There must be at least one channel
+                            return Err(response.to_error());
+                        }
+
+                        // unsubscribed channel should exist in the subscribed list at this point
+                        self.subscribed_channels.retain(|c| *channel != &c[..]);
+
+                        // Only a single channel should be removed from the
+                        // list of subscribed channels.
+                        if self.subscribed_channels.len() != len - 1 {
+                            return Err(response.to_error());
+                        }
+                    }
+                    _ => return Err(response.to_error()),
+                },
+                frame => return Err(frame.to_error()),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
+    }
+}
+
+impl std::error::Error for ServerError {}
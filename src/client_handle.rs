@@ -0,0 +1,278 @@
+//! A `Client` shared between tasks.
+//!
+//! A `Client` needs `&mut self` to issue a command, so it cannot be used by
+//! several tasks at once. `ClientHandle` moves the client into a dedicated
+//! manager task. Handles send requests to the task over an `mpsc` channel,
+//! each request carrying a `oneshot` sender the task uses to send the response
+//! back.
+
+use crate::client::{Client, ServerError};
+use crate::db::Ttl;
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// Error returned once the manager task is gone.
+const MANAGER_STOPPED: &str = "client manager task has stopped";
+
+/// Provided by the requester and used by the manager task to send the command
+/// response back to the requester.
+type Responder<T> = oneshot::Sender<crate::Result<T>>;
+
+/// Multiple different commands are multiplexed over a single channel.
+#[derive(Debug)]
+enum Request {
+    Get {
+        key: String,
+        resp: Responder<Option<Bytes>>,
+    },
+    Set {
+        key: String,
+        value: Bytes,
+        expire: Option<Duration>,
+        resp: Responder<()>,
+    },
+    Expire {
+        key: String,
+        expiration: Duration,
+        resp: Responder<bool>,
+    },
+    Persist {
+        key: String,
+        resp: Responder<bool>,
+    },
+    Ttl {
+        key: String,
+        resp: Responder<Ttl>,
+    },
+    Publish {
+        channel: String,
+        message: Bytes,
+        resp: Responder<u64>,
+    },
+    Ping {
+        msg: Option<Bytes>,
+        resp: Responder<Bytes>,
+    },
+}
+
+/// Cloneable handle to a `Client` owned by a manager task.
+///
+/// Every clone sends its requests to the same manager task, which issues them
+/// on the one connection in the order they are received. The manager task
+/// exits and the connection is closed once the last handle is dropped.
+///
+/// Subscribing takes over a connection, so it is not available on a shared
+/// handle. Use [`Client::subscribe`] on a dedicated client instead.
+///
+/// # Examples
+///
+/// ```no_run
+/// use my_redis::{client, ClientHandle};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = client::connect("localhost:6379").await.unwrap();
+///     let handle = ClientHandle::new(client, 32);
+///
+///     let other = handle.clone();
+///     tokio::spawn(async move {
+///         other.set("foo", "bar".into()).await.unwrap();
+///     });
+///
+///     let value = handle.get("foo").await.unwrap();
+///     println!("got {:?}", value);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientHandle {
+    tx: mpsc::Sender<Request>,
+}
+
+impl ClientHandle {
+    /// Move `client` into a new manager task and return a handle to it.
+    ///
+    /// At most `queue_depth` requests wait for the manager task at any time.
+    /// Once the queue is full, requests wait for room before being queued.
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `queue_depth` is zero.
+    pub fn new(client: Client, queue_depth: usize) -> ClientHandle {
+        let (tx, rx) = mpsc::channel(queue_depth);
+
+        tokio::spawn(run(client, rx));
+
+        ClientHandle { tx }
+    }
+
+    /// Get the value of `key`, see [`Client::get`].
+    pub async fn get(&self, key: &str) -> crate::Result<Option<Bytes>> {
+        self.request(|resp| Request::Get {
+            key: key.to_string(),
+            resp,
+        })
+        .await
+    }
+
+    /// Set `key` to hold `value`, see [`Client::set`].
+    pub async fn set(&self, key: &str, value: Bytes) -> crate::Result<()> {
+        self.request(|resp| Request::Set {
+            key: key.to_string(),
+            value,
+            expire: None,
+            resp,
+        })
+        .await
+    }
+
+    /// Set `key` to hold `value` until `expiration` elapses, see
+    /// [`Client::set_expires`].
+    pub async fn set_expires(
+        &self,
+        key: &str,
+        value: Bytes,
+        expiration: Duration,
+    ) -> crate::Result<()> {
+        self.request(|resp| Request::Set {
+            key: key.to_string(),
+            value,
+            expire: Some(expiration),
+            resp,
+        })
+        .await
+    }
+
+    /// Set a timeout on `key`, see [`Client::expire`].
+    pub async fn expire(&self, key: &str, expiration: Duration) -> crate::Result<bool> {
+        self.request(|resp| Request::Expire {
+            key: key.to_string(),
+            expiration,
+            resp,
+        })
+        .await
+    }
+
+    /// Remove the timeout on `key`, see [`Client::persist`].
+    pub async fn persist(&self, key: &str) -> crate::Result<bool> {
+        self.request(|resp| Request::Persist {
+            key: key.to_string(),
+            resp,
+        })
+        .await
+    }
+
+    /// Get the remaining time to live of `key`, see [`Client::ttl`].
+    pub async fn ttl(&self, key: &str) -> crate::Result<Ttl> {
+        self.request(|resp| Request::Ttl {
+            key: key.to_string(),
+            resp,
+        })
+        .await
+    }
+
+    /// Post `message` to `channel`, see [`Client::publish`].
+    pub async fn publish(&self, channel: &str, message: Bytes) -> crate::Result<u64> {
+        self.request(|resp| Request::Publish {
+            channel: channel.to_string(),
+            message,
+            resp,
+        })
+        .await
+    }
+
+    /// Ping the server, see [`Client::ping`].
+    pub async fn ping(&self, msg: Option<Bytes>) -> crate::Result<Bytes> {
+        self.request(|resp| Request::Ping { msg, resp }).await
+    }
+
+    /// Send a request to the manager task and wait for the response.
+    ///
+    /// The manager task being gone, before or while handling the request, is
+    /// reported as an error.
+    async fn request<T>(&self, request: impl FnOnce(Responder<T>) -> Request) -> crate::Result<T> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+
+        self.tx
+            .send(request(resp_tx))
+            .await
+            .map_err(|_| MANAGER_STOPPED)?;
+
+        // The responder is dropped without being used if the manager task
+        // stops before answering.
+        resp_rx.await.map_err(|_| MANAGER_STOPPED)?
+    }
+}
+
+/// The manager task. Issues the requests received on `rx` one at a time.
+///
+/// Returns once every `ClientHandle` has been dropped, or once the connection
+/// to the server is broken. Requests still queued at that point are answered
+/// with an error when their responders are dropped.
+async fn run(mut client: Client, mut rx: mpsc::Receiver<Request>) {
+    while let Some(request) = rx.recv().await {
+        use Request::*;
+
+        let res = match request {
+            Get { key, resp } => respond(resp, client.get(&key).await),
+            Set {
+                key,
+                value,
+                expire: None,
+                resp,
+            } => respond(resp, client.set(&key, value).await),
+            Set {
+                key,
+                value,
+                expire: Some(expiration),
+                resp,
+            } => respond(resp, client.set_expires(&key, value, expiration).await),
+            Expire {
+                key,
+                expiration,
+                resp,
+            } => respond(resp, client.expire(&key, expiration).await),
+            Persist { key, resp } => respond(resp, client.persist(&key).await),
+            Ttl { key, resp } => respond(resp, client.ttl(&key).await),
+            Publish {
+                channel,
+                message,
+                resp,
+            } => respond(resp, client.publish(&channel, message).await),
+            Ping { msg, resp } => respond(resp, client.ping(msg).await),
+        };
+
+        // Once the connection is broken, every following request would fail
+        // the same way. Stop instead, so requesters see the manager is gone.
+        if let Err(err) = res {
+            eprintln!("client connection failed; err = {}", err);
+            return;
+        }
+    }
+}
+
+/// Send `res` to the requester.
+///
+/// Errors reported by the server only concern this request and are passed
+/// along. Any other error means the connection is unusable: broken, or out
+/// of step with the server so that later replies would be matched with the
+/// wrong requests. They are passed along as well and returned so the manager
+/// task stops.
+fn respond<T>(resp: Responder<T>, res: crate::Result<T>) -> crate::Result<()> {
+    let fatal = match &res {
+        Err(err) if !err.is::<ServerError>() => Some(err.to_string()),
+        _ => None,
+    };
+
+    // A requester that gave up waiting dropped its receiver, so failing to
+    // send the response is ignored.
+    let _ = resp.send(res);
+
+    match fatal {
+        Some(msg) => Err(msg.into()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,36 @@
+use crate::aof::Aof;
+use crate::{Db, Frame, Parse};
+
+/// Rewrite the append only file in the background.
+///
+/// The new file holds one command per key, rebuilding the current keyspace,
+/// instead of every write ever made. Clients keep being served while it is
+/// written, see [`Aof::rewrite`].
+#[derive(Debug, Default)]
+pub struct BgRewriteAof;
+
+impl BgRewriteAof {
+    /// Parse a `BgRewriteAof` instance from a received frame.
+    ///
+    /// ```text
+    /// BGREWRITEAOF
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<BgRewriteAof> {
+        Ok(BgRewriteAof)
+    }
+
+    /// Start rewriting `aof` from the contents of `db`.
+    ///
+    /// `aof` is `None` when the server runs without an append only file.
+    pub(crate) fn apply(self, db: &Db, aof: Option<&Aof>) -> Frame {
+        let aof = match aof {
+            Some(aof) => aof,
+            None => return Frame::Error("ERR append only file is disabled".to_string()),
+        };
+
+        match aof.rewrite(db) {
+            Ok(()) => Frame::Simple("Background append only file rewriting started".to_string()),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use crate::db;
+use crate::{Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Set a timeout on `key`, in seconds.
+///
+/// After the timeout has expired, the key will automatically be deleted. A
+/// timeout of zero or less deletes the key right away.
+///
+/// Returns 1 if the timeout was set, 0 if the key does not exist.
+#[derive(Debug)]
+pub struct Expire {
+    key: String,
+    seconds: i64,
+}
+
+impl Expire {
+    /// Create a new `Expire` command setting a timeout of `seconds` on `key`.
+    pub fn new(key: impl ToString, seconds: i64) -> Expire {
+        Expire {
+            key: key.to_string(),
+            seconds,
+        }
+    }
+
+    /// Parse an `Expire` instance from a received frame.
+    ///
+    /// ```text
+    /// EXPIRE key seconds
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Expire> {
+        let key = parse.next_string()?;
+        let seconds = parse.next_int()?;
+
+        // The deadline is tracked as a Unix time in milliseconds, reject
+        // values that cannot be represented.
+        let now = unix_millis(SystemTime::now());
+        if seconds
+            .checked_mul(1000)
+            .and_then(|ms| ms.checked_add(now))
+            .is_none()
+        {
+            return Err("ERR invalid expire time in 'expire' command".into());
+        }
+
+        Ok(Expire { key, seconds })
+    }
+
+    /// Apply the `Expire` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        // Zero and negative timeouts expire the key immediately
+        let duration = Duration::from_secs(self.seconds.max(0) as u64);
+
+        Frame::Integer(db.expire(&self.key, duration) as i64)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("expire".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_bulk(Bytes::from(self.seconds.to_string()));
+        frame
+    }
+
+    /// The command as written to the append only file.
+    ///
+    /// Recorded as `PEXPIREAT` so that replaying the file after a restart
+    /// does not push the deadline back.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let at = unix_millis(SystemTime::now()).saturating_add(self.seconds.saturating_mul(1000));
+        pexpireat_frame(&self.key, at)
+    }
+}
+
+/// Set `key` to expire at a Unix time.
+///
+/// Handles both `EXPIREAT`, which takes the timestamp in seconds, and
+/// `PEXPIREAT`, which takes it in milliseconds. A timestamp in the past
+/// deletes the key right away.
+///
+/// Returns 1 if the timeout was set, 0 if the key does not exist.
+#[derive(Debug)]
+pub struct ExpireAt {
+    key: String,
+
+    // Unix time in milliseconds, whichever unit the command used
+    at: i64,
+
+    // `true` for `PEXPIREAT`
+    millis: bool,
+}
+
+impl ExpireAt {
+    /// Parse an `ExpireAt` instance from a received frame.
+    ///
+    /// ```text
+    /// EXPIREAT key unix-time-seconds
+    /// PEXPIREAT key unix-time-milliseconds
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, millis: bool) -> crate::Result<ExpireAt> {
+        let key = parse.next_string()?;
+        let timestamp = parse.next_int()?;
+
+        let at = if millis {
+            Some(timestamp)
+        } else {
+            timestamp.checked_mul(1000)
+        };
+
+        match at {
+            Some(at) => Ok(ExpireAt { key, at, millis }),
+            None => Err("ERR invalid expire time in 'expireat' command".into()),
+        }
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        if self.millis {
+            "pexpireat"
+        } else {
+            "expireat"
+        }
+    }
+
+    /// Apply the `ExpireAt` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        Frame::Integer(db.expire(&self.key, until_unix_millis(self.at)) as i64)
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        pexpireat_frame(&self.key, self.at)
+    }
+}
+
+/// Return the remaining time to live of `key`.
+///
+/// Handles both `TTL`, which replies in seconds, and `PTTL`, which replies in
+/// milliseconds. Replies -2 if the key does not exist and -1 if the key exists
+/// but has no associated expire.
+#[derive(Debug)]
+pub struct Ttl {
+    key: String,
+
+    // `true` for `PTTL`
+    millis: bool,
+}
+
+impl Ttl {
+    /// Create a new `Ttl` command for `key`, replying in milliseconds when
+    /// `millis` is `true`.
+    pub fn new(key: impl ToString, millis: bool) -> Ttl {
+        Ttl {
+            key: key.to_string(),
+            millis,
+        }
+    }
+
+    /// Parse a `Ttl` instance from a received frame.
+    ///
+    /// ```text
+    /// TTL key
+    /// PTTL key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, millis: bool) -> crate::Result<Ttl> {
+        let key = parse.next_string()?;
+
+        Ok(Ttl { key, millis })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        if self.millis {
+            "pttl"
+        } else {
+            "ttl"
+        }
+    }
+
+    /// Apply the `Ttl` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let ttl = match db.ttl(&self.key) {
+            db::Ttl::Missing => -2,
+            db::Ttl::Persistent => -1,
+            db::Ttl::Expires(remaining) if self.millis => remaining.as_millis() as i64,
+            // Round to the closest second, like Redis does
+            db::Ttl::Expires(remaining) => ((remaining.as_millis() + 500) / 1000) as i64,
+        };
+
+        Frame::Integer(ttl)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(self.get_name().to_string()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+}
+
+/// Remove the existing timeout on `key`.
+///
+/// Returns 1 if the timeout was removed, 0 if the key does not exist or does
+/// not have an associated timeout.
+#[derive(Debug)]
+pub struct Persist {
+    key: String,
+}
+
+impl Persist {
+    /// Create a new `Persist` command for `key`.
+    pub fn new(key: impl ToString) -> Persist {
+        Persist {
+            key: key.to_string(),
+        }
+    }
+
+    /// Parse a `Persist` instance from a received frame.
+    ///
+    /// ```text
+    /// PERSIST key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Persist> {
+        let key = parse.next_string()?;
+
+        Ok(Persist { key })
+    }
+
+    /// Apply the `Persist` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        Frame::Integer(db.persist(&self.key) as i64)
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("persist".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        Persist::new(&self.key).into_frame()
+    }
+}
+
+/// Build a `PEXPIREAT` command setting `key` to expire at the Unix time
+/// `at`, in milliseconds.
+pub(crate) fn pexpireat_frame(key: &str, at: i64) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from("pexpireat".as_bytes()));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    frame.push_int(at);
+    frame
+}
+
+/// Milliseconds elapsed between the Unix epoch and `time`, saturating at
+/// the bounds of `i64`.
+pub(crate) fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX),
+        Err(err) => i64::try_from(err.duration().as_millis()).map_or(i64::MIN, |ms| -ms),
+    }
+}
+
+/// Time left until the Unix time `at`, in milliseconds. Zero if it has
+/// already passed.
+pub(crate) fn until_unix_millis(at: i64) -> Duration {
+    let remaining = at.saturating_sub(unix_millis(SystemTime::now()));
+    Duration::from_millis(remaining.max(0) as u64)
+}
@@ -0,0 +1,67 @@
+use crate::db::{Value, WrongType};
+use crate::{Db, Frame, Parse};
+
+use bytes::Bytes;
+
+/// Get the value of key.
+///
+/// If the key does not exist the special value nil is returned. An error is
+/// returned if the value stored at key is not a string, because GET only
+/// handles string values.
+#[derive(Debug)]
+pub struct Get {
+    /// Name of the key to get
+    key: String,
+}
+
+impl Get {
+    /// Create a new `Get` command which fetches `key`.
+    pub fn new(key: impl ToString) -> Get {
+        Get {
+            key: key.to_string(),
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Parse a `Get` instance from a received frame.
+    ///
+    /// The `GET` string has already been consumed.
+    ///
+    /// Expects an array frame containing two entries.
+    ///
+    /// ```text
+    /// GET key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Get> {
+        let key = parse.next_string()?;
+
+        Ok(Get { key })
+    }
+
+    /// Apply the `Get` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            // If a value is present, it is written to the client in "bulk"
+            // format.
+            Some(Value::String(data)) => Frame::Bulk(data.clone()),
+            Some(_) => Frame::Error(WrongType.to_string()),
+            // If there is no value, `Null` is written.
+            None => Frame::Null,
+        })
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Get` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("get".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame
+    }
+}
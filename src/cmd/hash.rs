@@ -0,0 +1,582 @@
+use crate::cmd::list::remaining_bytes;
+use crate::db::{Value, WrongType};
+use crate::parse::ParseError::{self, EndOfStream};
+use crate::{Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Number of fields `HSCAN` visits when no `COUNT` is given.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Set fields of the hash stored at `key`, creating the hash if needed.
+///
+/// Returns the number of fields that were added, fields already present have
+/// their value replaced without being counted.
+#[derive(Debug)]
+pub struct HSet {
+    key: String,
+    pairs: Vec<(Bytes, Bytes)>,
+}
+
+/// Get the value of `field` in the hash stored at `key`, nil if either does
+/// not exist.
+#[derive(Debug)]
+pub struct HGet {
+    key: String,
+    field: Bytes,
+}
+
+/// Get the values of several fields, nil for the missing ones.
+#[derive(Debug)]
+pub struct HMGet {
+    key: String,
+    fields: Vec<Bytes>,
+}
+
+/// Remove fields from the hash stored at `key`, returning how many existed.
+///
+/// The hash is removed along with its last field.
+#[derive(Debug)]
+pub struct HDel {
+    key: String,
+    fields: Vec<Bytes>,
+}
+
+/// Returns 1 if `field` exists in the hash stored at `key`, 0 otherwise.
+#[derive(Debug)]
+pub struct HExists {
+    key: String,
+    field: Bytes,
+}
+
+/// Returns the number of fields in the hash stored at `key`.
+#[derive(Debug)]
+pub struct HLen {
+    key: String,
+}
+
+/// Returns the fields, the values, or both, of the hash stored at `key`.
+///
+/// Handles `HKEYS`, `HVALS` and `HGETALL`. `HGETALL` replies with a map,
+/// which RESP2 clients receive as an array alternating fields and values.
+#[derive(Debug)]
+pub struct HGetAll {
+    key: String,
+    part: Part,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Part {
+    Fields,
+    Values,
+    Both,
+}
+
+/// Add `increment` to the integer stored in `field`, a missing field
+/// counting as 0. Returns the new value.
+#[derive(Debug)]
+pub struct HIncrBy {
+    key: String,
+    field: Bytes,
+    increment: i64,
+}
+
+/// Iterate over the fields of the hash stored at `key`, a few at a time.
+///
+/// The reply holds the cursor to pass to the next call, followed by the
+/// field-value pairs found. Iteration starts with cursor 0 and ends once 0
+/// is returned.
+///
+/// Fields are visited in the order of a fixed hash of their name rather than
+/// by position, so the cursor stays valid while the hash is modified: a
+/// field present during the whole iteration is always returned. Fields with
+/// the same hash are returned together, hence a call may return more than
+/// `COUNT` fields. `MATCH` filters the visited fields with a glob-style
+/// pattern.
+#[derive(Debug)]
+pub struct HScan {
+    key: String,
+    cursor: u64,
+    pattern: Option<Bytes>,
+    count: usize,
+}
+
+impl HSet {
+    /// Parse a `HSet` instance from a received frame.
+    ///
+    /// ```text
+    /// HSET key field value [field value ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HSet> {
+        let key = parse.next_string()?;
+        let args = remaining_bytes(parse, 2)?;
+
+        // A field without a value
+        if args.len() % 2 != 0 {
+            return Err(ParseError::EndOfStream.into());
+        }
+
+        let pairs = args
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+
+        Ok(HSet { key, pairs })
+    }
+
+    /// Apply the `HSet` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let hash = hash_mut(value)?;
+            let mut added = 0;
+
+            for (field, value) in self.pairs {
+                if hash.insert(field, value).is_none() {
+                    added += 1;
+                }
+            }
+
+            Ok((Frame::Integer(added), true))
+        });
+
+        res.unwrap_or_else(|err: WrongType| Frame::Error(err.to_string()))
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("hset", &self.key);
+        for (field, value) in &self.pairs {
+            frame.push_bulk(field.clone());
+            frame.push_bulk(value.clone());
+        }
+        frame
+    }
+}
+
+impl HGet {
+    /// Parse a `HGet` instance from a received frame.
+    ///
+    /// ```text
+    /// HGET key field
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HGet> {
+        let key = parse.next_string()?;
+        let field = parse.next_bytes()?;
+
+        Ok(HGet { key, field })
+    }
+
+    /// Apply the `HGet` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_hash(db, &self.key, |hash| match hash.get(&self.field) {
+            Some(value) => Frame::Bulk(value.clone()),
+            None => Frame::Null,
+        })
+    }
+}
+
+impl HMGet {
+    /// Parse a `HMGet` instance from a received frame.
+    ///
+    /// ```text
+    /// HMGET key field [field ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HMGet> {
+        let key = parse.next_string()?;
+        let fields = remaining_bytes(parse, 1)?;
+
+        Ok(HMGet { key, fields })
+    }
+
+    /// Apply the `HMGet` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_hash(db, &self.key, |hash| {
+            let values = self
+                .fields
+                .iter()
+                .map(|field| match hash.get(field) {
+                    Some(value) => Frame::Bulk(value.clone()),
+                    None => Frame::Null,
+                })
+                .collect();
+
+            Frame::Array(values)
+        })
+    }
+}
+
+impl HDel {
+    /// Parse a `HDel` instance from a received frame.
+    ///
+    /// ```text
+    /// HDEL key field [field ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HDel> {
+        let key = parse.next_string()?;
+        let fields = remaining_bytes(parse, 1)?;
+
+        Ok(HDel { key, fields })
+    }
+
+    /// Apply the `HDel` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let hash = match value {
+                None => return Ok((0, false)),
+                Some(Value::Hash(hash)) => hash,
+                Some(_) => return Err(WrongType),
+            };
+
+            let removed = self
+                .fields
+                .iter()
+                .filter(|field| hash.remove(*field).is_some())
+                .count();
+
+            Ok((removed as i64, removed > 0))
+        });
+
+        match res {
+            Ok(removed) => Frame::Integer(removed),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("hdel", &self.key);
+        for field in &self.fields {
+            frame.push_bulk(field.clone());
+        }
+        frame
+    }
+}
+
+impl HExists {
+    /// Parse a `HExists` instance from a received frame.
+    ///
+    /// ```text
+    /// HEXISTS key field
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HExists> {
+        let key = parse.next_string()?;
+        let field = parse.next_bytes()?;
+
+        Ok(HExists { key, field })
+    }
+
+    /// Apply the `HExists` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_hash(db, &self.key, |hash| {
+            Frame::Integer(hash.contains_key(&self.field) as i64)
+        })
+    }
+}
+
+impl HLen {
+    /// Parse a `HLen` instance from a received frame.
+    ///
+    /// ```text
+    /// HLEN key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HLen> {
+        let key = parse.next_string()?;
+
+        Ok(HLen { key })
+    }
+
+    /// Apply the `HLen` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_hash(db, &self.key, |hash| Frame::Integer(hash.len() as i64))
+    }
+}
+
+impl HGetAll {
+    /// Parse a `HGetAll` instance from a received frame.
+    ///
+    /// ```text
+    /// HKEYS key
+    /// HVALS key
+    /// HGETALL key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, command_name: &str) -> crate::Result<HGetAll> {
+        let key = parse.next_string()?;
+
+        let part = match command_name {
+            "hkeys" => Part::Fields,
+            "hvals" => Part::Values,
+            _ => Part::Both,
+        };
+
+        Ok(HGetAll { key, part })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.part {
+            Part::Fields => "hkeys",
+            Part::Values => "hvals",
+            Part::Both => "hgetall",
+        }
+    }
+
+    /// Apply the `HGetAll` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_hash(db, &self.key, |hash| {
+            let pairs = hash.iter();
+
+            match self.part {
+                Part::Fields => {
+                    Frame::Array(pairs.map(|(field, _)| Frame::Bulk(field.clone())).collect())
+                }
+                Part::Values => {
+                    Frame::Array(pairs.map(|(_, value)| Frame::Bulk(value.clone())).collect())
+                }
+                Part::Both => Frame::Map(
+                    pairs
+                        .map(|(field, value)| {
+                            (Frame::Bulk(field.clone()), Frame::Bulk(value.clone()))
+                        })
+                        .collect(),
+                ),
+            }
+        })
+    }
+}
+
+impl HIncrBy {
+    /// Parse a `HIncrBy` instance from a received frame.
+    ///
+    /// ```text
+    /// HINCRBY key field increment
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HIncrBy> {
+        let key = parse.next_string()?;
+        let field = parse.next_bytes()?;
+        let increment = parse.next_int()?;
+
+        Ok(HIncrBy {
+            key,
+            field,
+            increment,
+        })
+    }
+
+    /// Apply the `HIncrBy` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            // Checked before `hash_mut`, which would create the hash
+            if !matches!(value, None | Some(Value::Hash(_))) {
+                return Err(WrongType.to_string());
+            }
+
+            let current = match value.as_ref().and_then(|value| match value {
+                Value::Hash(hash) => hash.get(&self.field),
+                _ => None,
+            }) {
+                Some(current) => std::str::from_utf8(current)
+                    .ok()
+                    .and_then(|current| current.parse::<i64>().ok())
+                    .ok_or("ERR hash value is not an integer")?,
+                None => 0,
+            };
+
+            let new = current
+                .checked_add(self.increment)
+                .ok_or("ERR increment or decrement would overflow")?;
+
+            let hash = hash_mut(value).map_err(|err| err.to_string())?;
+            hash.insert(self.field.clone(), Bytes::from(new.to_string()));
+
+            Ok((new, true))
+        });
+
+        match res {
+            Ok(new) => Frame::Integer(new),
+            Err(err) => Frame::Error(err),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("hincrby", &self.key);
+        frame.push_bulk(self.field.clone());
+        frame.push_int(self.increment);
+        frame
+    }
+}
+
+impl HScan {
+    /// Parse a `HScan` instance from a received frame.
+    ///
+    /// ```text
+    /// HSCAN key cursor [MATCH pattern] [COUNT count]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<HScan> {
+        let key = parse.next_string()?;
+        let cursor = parse
+            .next_string()?
+            .parse()
+            .map_err(|_| "ERR invalid cursor")?;
+
+        let mut scan = HScan {
+            key,
+            cursor,
+            pattern: None,
+            count: DEFAULT_SCAN_COUNT,
+        };
+
+        loop {
+            match parse.next_string() {
+                Ok(s) if s.to_uppercase() == "MATCH" => {
+                    scan.pattern = Some(parse.next_bytes()?);
+                }
+                Ok(s) if s.to_uppercase() == "COUNT" => match parse.next_int()? {
+                    count if count > 0 => scan.count = count as usize,
+                    _ => return Err("ERR syntax error".into()),
+                },
+                Ok(_) => return Err("ERR syntax error".into()),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Apply the `HScan` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_hash(db, &self.key, |hash| {
+            let mut fields: Vec<_> = hash
+                .iter()
+                .map(|(field, value)| (scan_hash(field), field, value))
+                .filter(|(scan_hash, _, _)| *scan_hash >= self.cursor)
+                .collect();
+            fields.sort_unstable_by_key(|(scan_hash, _, _)| *scan_hash);
+
+            // Extend the batch to every field sharing the last hash, the
+            // cursor could not tell them apart.
+            let mut end = fields.len().min(self.count);
+            while end < fields.len() && fields[end].0 == fields[end - 1].0 {
+                end += 1;
+            }
+
+            let next = fields.get(end).map_or(0, |(scan_hash, _, _)| *scan_hash);
+
+            let mut found = vec![];
+            for (_, field, value) in &fields[..end] {
+                let matched = match &self.pattern {
+                    Some(pattern) => glob_match(pattern, field),
+                    None => true,
+                };
+
+                if matched {
+                    found.push(Frame::Bulk((*field).clone()));
+                    found.push(Frame::Bulk((*value).clone()));
+                }
+            }
+
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from(next.to_string())),
+                Frame::Array(found),
+            ])
+        })
+    }
+}
+
+/// Run `f` on the hash stored at `key`, an empty one if the key does not
+/// exist.
+fn read_hash(db: &Db, key: &str, f: impl FnOnce(&HashMap<Bytes, Bytes>) -> Frame) -> Frame {
+    db.read(key, |value| match value {
+        None => f(&HashMap::new()),
+        Some(Value::Hash(hash)) => f(hash),
+        Some(_) => Frame::Error(WrongType.to_string()),
+    })
+}
+
+/// The hash stored in `value`, created if missing.
+fn hash_mut(value: &mut Option<Value>) -> Result<&mut HashMap<Bytes, Bytes>, WrongType> {
+    match value.get_or_insert_with(|| Value::Hash(HashMap::new())) {
+        Value::Hash(hash) => Ok(hash),
+        _ => Err(WrongType),
+    }
+}
+
+fn command_frame(name: &str, key: &str) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(name.to_string()));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    frame
+}
+
+/// Position of `field` in the `HSCAN` order.
+///
+/// `DefaultHasher::new` always uses the same keys, so a field keeps its
+/// position for as long as the server runs.
+fn scan_hash(field: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    field.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Match `s` against a glob-style pattern, as used by `MATCH`.
+///
+/// Supports `*`, `?`, character classes like `[a-z]` or `[^abc]`, and `\`
+/// to escape the next character.
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && glob_match(rest, &s[1..]),
+        Some((b'[', rest)) => {
+            let (c, s) = match s.split_first() {
+                Some(split) => split,
+                None => return false,
+            };
+
+            let (negate, mut rest) = match rest.split_first() {
+                Some((b'^', rest)) => (true, rest),
+                _ => (false, rest),
+            };
+
+            let mut matched = false;
+
+            loop {
+                match rest {
+                    // An unterminated class matches like a terminated one
+                    [] => break,
+                    [b']', tail @ ..] => {
+                        rest = tail;
+                        break;
+                    }
+                    [b'\\', escaped, tail @ ..] => {
+                        matched |= escaped == c;
+                        rest = tail;
+                    }
+                    [low, b'-', high, tail @ ..] if *high != b']' => {
+                        let (low, high) = if low <= high {
+                            (low, high)
+                        } else {
+                            (high, low)
+                        };
+                        matched |= (low..=high).contains(&c);
+                        rest = tail;
+                    }
+                    [other, tail @ ..] => {
+                        matched |= other == c;
+                        rest = tail;
+                    }
+                }
+            }
+
+            matched != negate && glob_match(rest, s)
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            s.first() == Some(escaped) && glob_match(rest, &s[1..])
+        }
+        Some((c, rest)) => s.first() == Some(c) && glob_match(rest, &s[1..]),
+    }
+}
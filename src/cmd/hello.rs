@@ -0,0 +1,71 @@
+use crate::connection::Protocol;
+use crate::parse::ParseError::EndOfStream;
+use crate::{Connection, Frame, Parse};
+
+use bytes::Bytes;
+
+/// Switch the connection to another protocol version.
+///
+/// Without a version, the protocol in use is kept. Replies with a map
+/// describing the server, already written with the protocol that was
+/// selected.
+#[derive(Debug, Default)]
+pub struct Hello {
+    /// `None` keeps the current protocol
+    protocol: Option<Protocol>,
+}
+
+impl Hello {
+    /// Create a new `Hello` command switching to `protocol`, if any.
+    pub fn new(protocol: Option<Protocol>) -> Hello {
+        Hello { protocol }
+    }
+
+    /// Parse a `Hello` instance from a received frame.
+    ///
+    /// The `HELLO` string has already been consumed. Authentication and
+    /// client names are not supported.
+    ///
+    /// ```text
+    /// HELLO [protover]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Hello> {
+        let protocol = match parse.next_int() {
+            Ok(2) => Some(Protocol::Resp2),
+            Ok(3) => Some(Protocol::Resp3),
+            Ok(_) => return Err("NOPROTO unsupported protocol version".into()),
+            Err(EndOfStream) => return Ok(Hello::default()),
+            Err(_) => return Err("ERR Protocol version is not an integer or out of range".into()),
+        };
+
+        match parse.next_string() {
+            Ok(option) => Err(format!("ERR Syntax error in HELLO option '{}'", option).into()),
+            Err(EndOfStream) => Ok(Hello { protocol }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Apply the `Hello` command to the connection it was received on,
+    /// returning the response.
+    pub(crate) fn apply(self, dst: &mut Connection) -> Frame {
+        if let Some(protocol) = self.protocol {
+            dst.set_protocol(protocol);
+        }
+
+        let proto = match dst.protocol() {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+
+        let field = |name: &'static str| Frame::Bulk(Bytes::from_static(name.as_bytes()));
+
+        Frame::Map(vec![
+            (field("server"), field("my-redis")),
+            (field("version"), field(env!("CARGO_PKG_VERSION"))),
+            (field("proto"), Frame::Integer(proto)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), Frame::Array(vec![])),
+        ])
+    }
+}
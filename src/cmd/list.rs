@@ -0,0 +1,637 @@
+use crate::db::{self, End, Pop as Popped, Value, WrongType};
+use crate::parse::ParseError::{self, EndOfStream};
+use crate::{Aof, Connection, Db, Frame, Parse, Shutdown};
+
+use bytes::Bytes;
+use std::future;
+use tokio::time::{self, Duration};
+
+/// Insert elements at the head or the tail of the list stored at `key`.
+///
+/// Handles both `LPUSH`, which inserts at the head, and `RPUSH`, which
+/// inserts at the tail. Elements are inserted one after the other, so
+/// `LPUSH key a b c` leaves `c` at the head. The list is created if `key`
+/// does not exist.
+///
+/// Clients blocked on the list are served right after the push. Returns the
+/// length of the list once every element was inserted.
+#[derive(Debug)]
+pub struct Push {
+    key: String,
+    elements: Vec<Bytes>,
+    end: End,
+}
+
+/// Remove and return elements from the head or the tail of the list stored
+/// at `key`.
+///
+/// Handles both `LPOP` and `RPOP`. Without a count, returns a single element
+/// or nil if `key` does not exist. With a count, returns an array of up to
+/// `count` elements.
+#[derive(Debug)]
+pub struct Pop {
+    key: String,
+    count: Option<usize>,
+    end: End,
+}
+
+/// Like [`Pop`], but blocks until an element is pushed when every list is
+/// empty.
+///
+/// Handles both `BLPOP` and `BRPOP`. Pops from the first non-empty list
+/// among the given keys. Otherwise the connection waits for a push to any of
+/// them, for at most `timeout` seconds, zero meaning forever. Clients
+/// waiting on the same list are served in the order they started waiting.
+///
+/// Returns the key and the element, or nil when the timeout expires.
+#[derive(Debug)]
+pub struct BlockingPop {
+    keys: Vec<String>,
+    timeout: Option<Duration>,
+    end: End,
+}
+
+/// Return the elements of the list stored at `key` between `start` and
+/// `stop`, both included.
+///
+/// Negative offsets count from the end of the list, -1 being the last
+/// element. Out of range offsets are clamped to the list.
+#[derive(Debug)]
+pub struct LRange {
+    key: String,
+    start: i64,
+    stop: i64,
+}
+
+/// Return the length of the list stored at `key`, 0 if it does not exist.
+#[derive(Debug)]
+pub struct LLen {
+    key: String,
+}
+
+/// Return the element at `index` in the list stored at `key`, nil if out of
+/// range. Negative indices count from the end.
+#[derive(Debug)]
+pub struct LIndex {
+    key: String,
+    index: i64,
+}
+
+/// Trim the list stored at `key` to the elements between `start` and `stop`,
+/// both included, with the same offsets as [`LRange`].
+#[derive(Debug)]
+pub struct LTrim {
+    key: String,
+    start: i64,
+    stop: i64,
+}
+
+/// Atomically pop an element from one end of the list at `source` and push
+/// it onto one end of the list at `destination`.
+///
+/// Returns the element moved, nil if `source` does not exist.
+#[derive(Debug)]
+pub struct LMove {
+    source: String,
+    destination: String,
+    from: End,
+    to: End,
+}
+
+impl Push {
+    /// Parse a `Push` instance from a received frame.
+    ///
+    /// ```text
+    /// LPUSH key element [element ...]
+    /// RPUSH key element [element ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, end: End) -> crate::Result<Push> {
+        let key = parse.next_string()?;
+        let elements = remaining_bytes(parse, 1)?;
+
+        Ok(Push { key, elements, end })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.end {
+            End::Left => "lpush",
+            End::Right => "rpush",
+        }
+    }
+
+    /// Apply the `Push` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        self.apply_logged(db).0
+    }
+
+    /// Apply the command, also returning what to write to the append only
+    /// file: the push itself, followed by a pop for every blocked client it
+    /// served.
+    pub(crate) fn apply_logged(self, db: &Db) -> (Frame, Vec<Frame>) {
+        let record = self.to_frame();
+        let Push { key, elements, end } = self;
+
+        let res = db.push(&key, |value| {
+            if !matches!(value, None | Some(Value::List(_))) {
+                return Err(WrongType);
+            }
+
+            for element in elements {
+                db::list_push(value, end, element);
+            }
+
+            match value {
+                Some(Value::List(list)) => Ok((list.len(), true)),
+                _ => unreachable!(),
+            }
+        });
+
+        match res {
+            Ok((len, served)) => {
+                let mut records = vec![record];
+                records.extend(served.into_iter().map(|end| pop_frame(&key, end)));
+
+                (Frame::Integer(len as i64), records)
+            }
+            Err(err) => (Frame::Error(err.to_string()), vec![]),
+        }
+    }
+
+    fn to_frame(&self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(self.get_name().to_string()));
+        frame.push_bulk(Bytes::copy_from_slice(self.key.as_bytes()));
+        for element in &self.elements {
+            frame.push_bulk(element.clone());
+        }
+        frame
+    }
+}
+
+impl Pop {
+    /// Parse a `Pop` instance from a received frame.
+    ///
+    /// ```text
+    /// LPOP key [count]
+    /// RPOP key [count]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, end: End) -> crate::Result<Pop> {
+        let key = parse.next_string()?;
+
+        let count = match parse.next_int() {
+            Ok(count) if count >= 0 => Some(count as usize),
+            Ok(_) => return Err("ERR value is out of range, must be positive".into()),
+            Err(EndOfStream) => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Pop { key, count, end })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.end {
+            End::Left => "lpop",
+            End::Right => "rpop",
+        }
+    }
+
+    /// Apply the `Pop` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let list = match value {
+                None => return Ok((Frame::Null, false)),
+                Some(Value::List(list)) => list,
+                Some(_) => return Err(WrongType),
+            };
+
+            let len = list.len();
+            let response = match self.count {
+                None => Frame::Bulk(db::list_pop(list, self.end).unwrap()),
+                Some(count) => Frame::Array(
+                    (0..count)
+                        .map_while(|_| db::list_pop(list, self.end))
+                        .map(Frame::Bulk)
+                        .collect(),
+                ),
+            };
+
+            Ok((response, list.len() != len))
+        });
+
+        res.unwrap_or_else(|err| Frame::Error(err.to_string()))
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = pop_frame(&self.key, self.end);
+        if let Some(count) = self.count {
+            frame.push_int(count as i64);
+        }
+        frame
+    }
+}
+
+impl BlockingPop {
+    /// Parse a `BlockingPop` instance from a received frame.
+    ///
+    /// ```text
+    /// BLPOP key [key ...] timeout
+    /// BRPOP key [key ...] timeout
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, end: End) -> crate::Result<BlockingPop> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // The last argument is the timeout, there must be a key before it
+        let timeout = keys.pop().unwrap();
+
+        if keys.is_empty() {
+            return Err(ParseError::EndOfStream.into());
+        }
+
+        let timeout = match timeout.parse::<f64>() {
+            Ok(secs) if secs < 0.0 => return Err("ERR timeout is negative".into()),
+            Ok(0.0) => None,
+            Ok(secs) => match Duration::try_from_secs_f64(secs) {
+                Ok(timeout) => Some(timeout),
+                Err(_) => return Err("ERR timeout is out of range".into()),
+            },
+            Err(_) => return Err("ERR timeout is not a float or out of range".into()),
+        };
+
+        Ok(BlockingPop { keys, timeout, end })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.end {
+            End::Left => "blpop",
+            End::Right => "brpop",
+        }
+    }
+
+    /// Apply the `BlockingPop` command on behalf of the client connected on
+    /// `dst`.
+    ///
+    /// An element popped right away is logged to `aof` as a plain pop. One
+    /// handed over later was logged by the push. Responses buffered on `dst`
+    /// are flushed before waiting.
+    ///
+    /// Waiting stops early when the server shuts down or the client
+    /// disconnects.
+    pub(crate) async fn apply(
+        self,
+        db: &Db,
+        aof: Option<&Aof>,
+        dst: &mut Connection,
+        shutdown: &mut Shutdown,
+    ) -> crate::Result<Frame> {
+        let pop = || {
+            let res = db.pop_or_block(&self.keys, self.end);
+
+            let records = match &res {
+                Ok(Popped::Ready(key, _)) => vec![pop_frame(key, self.end)],
+                _ => vec![],
+            };
+
+            (res, records)
+        };
+
+        // Not in the middle of a transaction, the lock is released before
+        // waiting
+        let res = {
+            let _gate = db.lock_shared();
+
+            match aof {
+                Some(aof) => match aof.log(pop) {
+                    Ok(res) => res,
+                    Err(err) => return Ok(Frame::Error(err.to_string())),
+                },
+                None => pop().0,
+            }
+        };
+
+        let mut blocked = match res {
+            Ok(Popped::Ready(key, element)) => {
+                if let Some(aof) = aof {
+                    if let Err(err) = aof.flush_logged().await {
+                        return Ok(Frame::Error(err.to_string()));
+                    }
+                }
+
+                return Ok(pop_response(key, element));
+            }
+            Ok(Popped::Blocked(blocked)) => blocked,
+            Err(err) => return Ok(Frame::Error(err.to_string())),
+        };
+
+        dst.flush().await?;
+
+        let timeout = async {
+            match self.timeout {
+                Some(timeout) => time::sleep(timeout).await,
+                None => future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            (key, element) = blocked.recv() => return Ok(pop_response(key, element)),
+            _ = timeout => {}
+            _ = shutdown.recv() => {}
+            res = dst.closed() => res?,
+        }
+
+        // An element may have been handed over right as waiting stopped
+        match blocked.cancel() {
+            Some((key, element)) => Ok(pop_response(key, element)),
+            None => Ok(Frame::Null),
+        }
+    }
+
+    /// Apply the command without waiting, as inside a transaction, where
+    /// `nil` is returned when every list is empty.
+    ///
+    /// Also returns what to write to the append only file: the element
+    /// popped, as a plain pop.
+    pub(crate) fn apply_now(self, db: &Db) -> (Frame, Vec<Frame>) {
+        for key in self.keys {
+            let pop = Pop {
+                key: key.clone(),
+                count: None,
+                end: self.end,
+            };
+            let record = pop.aof_frame();
+
+            match pop.apply(db) {
+                // Missing keys are skipped, as when not in a transaction
+                Frame::Null => continue,
+                Frame::Bulk(element) => return (pop_response(key, element), vec![record]),
+                response => return (response, vec![]),
+            }
+        }
+
+        (Frame::Null, vec![])
+    }
+}
+
+impl LRange {
+    /// Parse a `LRange` instance from a received frame.
+    ///
+    /// ```text
+    /// LRANGE key start stop
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LRange> {
+        let key = parse.next_string()?;
+        let start = parse.next_int()?;
+        let stop = parse.next_int()?;
+
+        Ok(LRange { key, start, stop })
+    }
+
+    /// Apply the `LRange` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Array(vec![]),
+            Some(Value::List(list)) => {
+                let elements = match range(self.start, self.stop, list.len()) {
+                    Some(range) => list.range(range).cloned().map(Frame::Bulk).collect(),
+                    None => vec![],
+                };
+
+                Frame::Array(elements)
+            }
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl LLen {
+    /// Parse a `LLen` instance from a received frame.
+    ///
+    /// ```text
+    /// LLEN key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LLen> {
+        let key = parse.next_string()?;
+
+        Ok(LLen { key })
+    }
+
+    /// Apply the `LLen` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Integer(0),
+            Some(Value::List(list)) => Frame::Integer(list.len() as i64),
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl LIndex {
+    /// Parse a `LIndex` instance from a received frame.
+    ///
+    /// ```text
+    /// LINDEX key index
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LIndex> {
+        let key = parse.next_string()?;
+        let index = parse.next_int()?;
+
+        Ok(LIndex { key, index })
+    }
+
+    /// Apply the `LIndex` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Null,
+            Some(Value::List(list)) => match range(self.index, self.index, list.len()) {
+                Some(range) => Frame::Bulk(list[range.start].clone()),
+                None => Frame::Null,
+            },
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl LTrim {
+    /// Parse a `LTrim` instance from a received frame.
+    ///
+    /// ```text
+    /// LTRIM key start stop
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LTrim> {
+        let key = parse.next_string()?;
+        let start = parse.next_int()?;
+        let stop = parse.next_int()?;
+
+        Ok(LTrim { key, start, stop })
+    }
+
+    /// Apply the `LTrim` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let list = match value {
+                None => return Ok(((), false)),
+                Some(Value::List(list)) => list,
+                Some(_) => return Err(WrongType),
+            };
+
+            let len = list.len();
+
+            // An empty range empties the list, which removes the key
+            match range(self.start, self.stop, list.len()) {
+                Some(range) => {
+                    list.truncate(range.end);
+                    list.drain(..range.start);
+                }
+                None => list.clear(),
+            }
+
+            Ok(((), list.len() != len))
+        });
+
+        match res {
+            Ok(()) => Frame::Simple("OK".to_string()),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("ltrim".as_bytes()));
+        frame.push_bulk(Bytes::copy_from_slice(self.key.as_bytes()));
+        frame.push_int(self.start);
+        frame.push_int(self.stop);
+        frame
+    }
+}
+
+impl LMove {
+    /// Parse a `LMove` instance from a received frame.
+    ///
+    /// ```text
+    /// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<LMove> {
+        let source = parse.next_string()?;
+        let destination = parse.next_string()?;
+        let from = parse_end(&parse.next_string()?)?;
+        let to = parse_end(&parse.next_string()?)?;
+
+        Ok(LMove {
+            source,
+            destination,
+            from,
+            to,
+        })
+    }
+
+    /// Apply the `LMove` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        self.apply_logged(db).0
+    }
+
+    /// Apply the command, also returning what to write to the append only
+    /// file: the move itself, followed by a pop for every client blocked on
+    /// `destination` it served.
+    pub(crate) fn apply_logged(self, db: &Db) -> (Frame, Vec<Frame>) {
+        let res = db.pop_push(&self.source, &self.destination, self.from, self.to);
+
+        match res {
+            Ok((Some(element), served)) => {
+                let mut records = vec![self.to_frame()];
+                records.extend(
+                    served
+                        .into_iter()
+                        .map(|end| pop_frame(&self.destination, end)),
+                );
+
+                (Frame::Bulk(element), records)
+            }
+            Ok((None, _)) => (Frame::Null, vec![]),
+            Err(err) => (Frame::Error(err.to_string()), vec![]),
+        }
+    }
+
+    fn to_frame(&self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("lmove".as_bytes()));
+        frame.push_bulk(Bytes::copy_from_slice(self.source.as_bytes()));
+        frame.push_bulk(Bytes::copy_from_slice(self.destination.as_bytes()));
+        frame.push_bulk(Bytes::from(end_name(self.from)));
+        frame.push_bulk(Bytes::from(end_name(self.to)));
+        frame
+    }
+}
+
+/// Convert inclusive `start` and `stop` offsets, possibly negative, into a
+/// range of indices into a sequence of `len` elements.
+///
+/// Returns `None` when the range is empty.
+pub(crate) fn range(start: i64, stop: i64, len: usize) -> Option<std::ops::Range<usize>> {
+    let len = len as i64;
+
+    let start = if start < 0 { start + len } else { start }.max(0);
+    let stop = if stop < 0 { stop + len } else { stop }.min(len - 1);
+
+    if start > stop {
+        return None;
+    }
+
+    Some(start as usize..stop as usize + 1)
+}
+
+/// Read every remaining argument as bytes, requiring at least `min` of them.
+pub(crate) fn remaining_bytes(parse: &mut Parse, min: usize) -> crate::Result<Vec<Bytes>> {
+    let mut items = vec![];
+
+    loop {
+        match parse.next_bytes() {
+            Ok(item) => items.push(item),
+            Err(EndOfStream) if items.len() >= min => return Ok(items),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+fn parse_end(s: &str) -> crate::Result<End> {
+    match &s.to_uppercase()[..] {
+        "LEFT" => Ok(End::Left),
+        "RIGHT" => Ok(End::Right),
+        _ => Err("ERR syntax error".into()),
+    }
+}
+
+fn end_name(end: End) -> &'static str {
+    match end {
+        End::Left => "left",
+        End::Right => "right",
+    }
+}
+
+/// Build a pop of a single element, as written to the append only file.
+fn pop_frame(key: &str, end: End) -> Frame {
+    let name = match end {
+        End::Left => "lpop",
+        End::Right => "rpop",
+    };
+
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(name));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    frame
+}
+
+fn pop_response(key: String, element: Bytes) -> Frame {
+    Frame::Array(vec![Frame::Bulk(Bytes::from(key)), Frame::Bulk(element)])
+}
@@ -0,0 +1,496 @@
+mod bgrewriteaof;
+pub use bgrewriteaof::BgRewriteAof;
+
+pub(crate) mod expire;
+pub use expire::{Expire, ExpireAt, Persist, Ttl};
+
+mod get;
+pub use get::Get;
+
+mod hash;
+pub use hash::{HDel, HExists, HGet, HGetAll, HIncrBy, HLen, HMGet, HScan, HSet};
+
+mod hello;
+pub use hello::Hello;
+
+mod list;
+pub use list::{BlockingPop, LIndex, LLen, LMove, LRange, LTrim, Pop, Push};
+
+mod ping;
+pub use ping::Ping;
+
+mod publish;
+pub use publish::Publish;
+
+mod save;
+pub use save::{BgSave, Save};
+
+pub(crate) mod set;
+pub use set::Set;
+
+mod sets;
+pub use sets::{SAdd, SIsMember, SMembers, SRem, SetOp};
+
+mod strings;
+pub use strings::{
+    Append, GetDel, GetEx, GetRange, GetSet, IncrBy, IncrByFloat, MGet, MSet, SetNx, SetRange,
+    StrLen,
+};
+
+mod subscribe;
+pub use subscribe::{Subscribe, Unsubscribe};
+
+mod transaction;
+pub(crate) use transaction::Transaction;
+pub use transaction::{Discard, Exec, Multi, Unwatch, Watch};
+
+mod unknown;
+pub use unknown::Unknown;
+
+pub(crate) mod zset;
+pub use zset::{ZAdd, ZIncrBy, ZPopMin, ZRange, ZRank, ZRem, ZScore};
+
+use crate::db::End;
+use crate::parse::ParseError;
+use crate::{Db, Frame, Parse};
+
+/// Enumeration of supported Redis commands.
+///
+/// Methods called on `Command` are delegated to the command implementation.
+#[derive(Debug)]
+pub enum Command {
+    Append(Append),
+    BgRewriteAof(BgRewriteAof),
+    BgSave(BgSave),
+    BlockingPop(BlockingPop),
+    Discard(Discard),
+    Exec(Exec),
+    Expire(Expire),
+    ExpireAt(ExpireAt),
+    Get(Get),
+    GetDel(GetDel),
+    GetEx(GetEx),
+    GetRange(GetRange),
+    GetSet(GetSet),
+    HDel(HDel),
+    HExists(HExists),
+    HGet(HGet),
+    HGetAll(HGetAll),
+    HIncrBy(HIncrBy),
+    HLen(HLen),
+    HMGet(HMGet),
+    HScan(HScan),
+    HSet(HSet),
+    Hello(Hello),
+    IncrBy(IncrBy),
+    IncrByFloat(IncrByFloat),
+    LIndex(LIndex),
+    LLen(LLen),
+    LMove(LMove),
+    LRange(LRange),
+    LTrim(LTrim),
+    MGet(MGet),
+    MSet(MSet),
+    Multi(Multi),
+    Persist(Persist),
+    Ping(Ping),
+    Pop(Pop),
+    Publish(Publish),
+    Push(Push),
+    SAdd(SAdd),
+    SIsMember(SIsMember),
+    SMembers(SMembers),
+    SRem(SRem),
+    Save(Save),
+    Set(Set),
+    SetNx(SetNx),
+    SetOp(SetOp),
+    SetRange(SetRange),
+    StrLen(StrLen),
+    Subscribe(Subscribe),
+    Ttl(Ttl),
+    Unknown(Unknown),
+    Unsubscribe(Unsubscribe),
+    Unwatch(Unwatch),
+    Watch(Watch),
+    ZAdd(ZAdd),
+    ZIncrBy(ZIncrBy),
+    ZPopMin(ZPopMin),
+    ZRange(ZRange),
+    ZRank(ZRank),
+    ZRem(ZRem),
+    ZScore(ZScore),
+}
+
+impl Command {
+    /// Parse a command from a received frame.
+    ///
+    /// The `Frame` must represent a Redis command supported by `my-redis` and
+    /// be the array variant.
+    ///
+    /// # Returns
+    ///
+    /// On success, the command value is returned, otherwise, `Err` is returned.
+    /// Errors are meant to be sent back to the client as an error frame.
+    pub fn from_frame(frame: Frame) -> crate::Result<Command> {
+        // The frame value is decorated with `Parse`. `Parse` provides a
+        // "cursor" like API which makes parsing the command easier.
+        //
+        // The frame value must be an array variant. Any other frame variants
+        // result in an error being returned.
+        let mut parse = Parse::new(frame)?;
+
+        // All redis commands begin with the command name as a string. The name
+        // is read and converted to lower cases in order to do case insensitive
+        // matching.
+        let command_name = match parse.next_string() {
+            Ok(name) => name.to_lowercase(),
+            Err(ParseError::EndOfStream) => return Err("ERR empty command".into()),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Match the command name, delegating the rest of the parsing to the
+        // specific command.
+        let command = match &command_name[..] {
+            "append" => Append::parse_frames(&mut parse).map(Command::Append),
+            "bgrewriteaof" => BgRewriteAof::parse_frames(&mut parse).map(Command::BgRewriteAof),
+            "bgsave" => BgSave::parse_frames(&mut parse).map(Command::BgSave),
+            "blpop" => BlockingPop::parse_frames(&mut parse, End::Left).map(Command::BlockingPop),
+            "brpop" => BlockingPop::parse_frames(&mut parse, End::Right).map(Command::BlockingPop),
+            "decr" | "decrby" | "incr" | "incrby" => {
+                IncrBy::parse_frames(&mut parse, &command_name).map(Command::IncrBy)
+            }
+            "discard" => Discard::parse_frames(&mut parse).map(Command::Discard),
+            "exec" => Exec::parse_frames(&mut parse).map(Command::Exec),
+            "expire" => Expire::parse_frames(&mut parse).map(Command::Expire),
+            "expireat" => ExpireAt::parse_frames(&mut parse, false).map(Command::ExpireAt),
+            "get" => Get::parse_frames(&mut parse).map(Command::Get),
+            "getdel" => GetDel::parse_frames(&mut parse).map(Command::GetDel),
+            "getex" => GetEx::parse_frames(&mut parse).map(Command::GetEx),
+            "getrange" => GetRange::parse_frames(&mut parse).map(Command::GetRange),
+            "getset" => GetSet::parse_frames(&mut parse).map(Command::GetSet),
+            "hdel" => HDel::parse_frames(&mut parse).map(Command::HDel),
+            "hello" => Hello::parse_frames(&mut parse).map(Command::Hello),
+            "hexists" => HExists::parse_frames(&mut parse).map(Command::HExists),
+            "hget" => HGet::parse_frames(&mut parse).map(Command::HGet),
+            "hgetall" | "hkeys" | "hvals" => {
+                HGetAll::parse_frames(&mut parse, &command_name).map(Command::HGetAll)
+            }
+            "hincrby" => HIncrBy::parse_frames(&mut parse).map(Command::HIncrBy),
+            "hlen" => HLen::parse_frames(&mut parse).map(Command::HLen),
+            "hmget" => HMGet::parse_frames(&mut parse).map(Command::HMGet),
+            "hscan" => HScan::parse_frames(&mut parse).map(Command::HScan),
+            "hset" => HSet::parse_frames(&mut parse).map(Command::HSet),
+            "incrbyfloat" => IncrByFloat::parse_frames(&mut parse).map(Command::IncrByFloat),
+            "lindex" => LIndex::parse_frames(&mut parse).map(Command::LIndex),
+            "llen" => LLen::parse_frames(&mut parse).map(Command::LLen),
+            "lmove" => LMove::parse_frames(&mut parse).map(Command::LMove),
+            "lpop" => Pop::parse_frames(&mut parse, End::Left).map(Command::Pop),
+            "lpush" => Push::parse_frames(&mut parse, End::Left).map(Command::Push),
+            "lrange" => LRange::parse_frames(&mut parse).map(Command::LRange),
+            "ltrim" => LTrim::parse_frames(&mut parse).map(Command::LTrim),
+            "mget" => MGet::parse_frames(&mut parse).map(Command::MGet),
+            "mset" | "msetnx" => MSet::parse_frames(&mut parse, &command_name).map(Command::MSet),
+            "multi" => Multi::parse_frames(&mut parse).map(Command::Multi),
+            "pexpireat" => ExpireAt::parse_frames(&mut parse, true).map(Command::ExpireAt),
+            "persist" => Persist::parse_frames(&mut parse).map(Command::Persist),
+            "ping" => Ping::parse_frames(&mut parse).map(Command::Ping),
+            "pttl" => Ttl::parse_frames(&mut parse, true).map(Command::Ttl),
+            "publish" => Publish::parse_frames(&mut parse).map(Command::Publish),
+            "rpop" => Pop::parse_frames(&mut parse, End::Right).map(Command::Pop),
+            "rpush" => Push::parse_frames(&mut parse, End::Right).map(Command::Push),
+            "sadd" => SAdd::parse_frames(&mut parse).map(Command::SAdd),
+            "save" => Save::parse_frames(&mut parse).map(Command::Save),
+            "sdiff" | "sinter" | "sunion" => {
+                SetOp::parse_frames(&mut parse, &command_name).map(Command::SetOp)
+            }
+            "set" => Set::parse_frames(&mut parse).map(Command::Set),
+            "setnx" => SetNx::parse_frames(&mut parse).map(Command::SetNx),
+            "setrange" => SetRange::parse_frames(&mut parse).map(Command::SetRange),
+            "sismember" => SIsMember::parse_frames(&mut parse).map(Command::SIsMember),
+            "smembers" => SMembers::parse_frames(&mut parse).map(Command::SMembers),
+            "srem" => SRem::parse_frames(&mut parse).map(Command::SRem),
+            "strlen" => StrLen::parse_frames(&mut parse).map(Command::StrLen),
+            "subscribe" => Subscribe::parse_frames(&mut parse).map(Command::Subscribe),
+            "ttl" => Ttl::parse_frames(&mut parse, false).map(Command::Ttl),
+            "unsubscribe" => Unsubscribe::parse_frames(&mut parse).map(Command::Unsubscribe),
+            "unwatch" => Unwatch::parse_frames(&mut parse).map(Command::Unwatch),
+            "watch" => Watch::parse_frames(&mut parse).map(Command::Watch),
+            "zadd" => ZAdd::parse_frames(&mut parse).map(Command::ZAdd),
+            "zincrby" => ZIncrBy::parse_frames(&mut parse).map(Command::ZIncrBy),
+            "zpopmin" => ZPopMin::parse_frames(&mut parse).map(Command::ZPopMin),
+            "zrange" => ZRange::parse_frames(&mut parse).map(Command::ZRange),
+            "zrank" => ZRank::parse_frames(&mut parse).map(Command::ZRank),
+            "zrem" => ZRem::parse_frames(&mut parse).map(Command::ZRem),
+            "zscore" => ZScore::parse_frames(&mut parse).map(Command::ZScore),
+            _ => {
+                // The command is not recognized and an Unknown command is
+                // returned.
+                //
+                // `return` is called here to skip the `finish()` call below. As
+                // the command is not recognized, there is most likely
+                // unconsumed fields remaining in the `Parse` instance.
+                return Ok(Command::Unknown(Unknown::new(command_name)));
+            }
+        };
+
+        // Running out of arguments while parsing (`EndOfStream`) or having
+        // unconsumed arguments left once the command is parsed both mean the
+        // client passed the wrong number of arguments.
+        let wrong_arity = match &command {
+            Ok(_) => parse.finish().is_err(),
+            Err(err) => matches!(err.downcast_ref(), Some(ParseError::EndOfStream)),
+        };
+
+        if wrong_arity {
+            return Err(format!(
+                "ERR wrong number of arguments for '{}' command",
+                command_name
+            )
+            .into());
+        }
+
+        // The command has been successfully parsed
+        command
+    }
+
+    /// Apply the command to the specified `Db` instance.
+    ///
+    /// Returns the response to write back to the client.
+    ///
+    /// `SUBSCRIBE` takes over the connection until the client unsubscribes,
+    /// `HELLO` changes the protocol of the connection, and `BGREWRITEAOF`,
+    /// `SAVE` and `BGSAVE` need the server's persistence settings, so the
+    /// connection handler runs them with their own `apply` instead. So does
+    /// `BLPOP`, which may wait on the connection for a push, as well as the
+    /// transaction commands, which work on the state of the connection.
+    /// Queued in a transaction, `BLPOP` pops without waiting.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        use Command::*;
+
+        match self {
+            Append(cmd) => cmd.apply(db),
+            BlockingPop(cmd) => cmd.apply_now(db).0,
+            Expire(cmd) => cmd.apply(db),
+            ExpireAt(cmd) => cmd.apply(db),
+            Get(cmd) => cmd.apply(db),
+            GetDel(cmd) => cmd.apply(db),
+            GetEx(cmd) => cmd.apply(db),
+            GetRange(cmd) => cmd.apply(db),
+            GetSet(cmd) => cmd.apply(db),
+            HDel(cmd) => cmd.apply(db),
+            HExists(cmd) => cmd.apply(db),
+            HGet(cmd) => cmd.apply(db),
+            HGetAll(cmd) => cmd.apply(db),
+            HIncrBy(cmd) => cmd.apply(db),
+            HLen(cmd) => cmd.apply(db),
+            HMGet(cmd) => cmd.apply(db),
+            HScan(cmd) => cmd.apply(db),
+            HSet(cmd) => cmd.apply(db),
+            IncrBy(cmd) => cmd.apply(db),
+            IncrByFloat(cmd) => cmd.apply(db),
+            LIndex(cmd) => cmd.apply(db),
+            LLen(cmd) => cmd.apply(db),
+            LMove(cmd) => cmd.apply(db),
+            LRange(cmd) => cmd.apply(db),
+            LTrim(cmd) => cmd.apply(db),
+            MGet(cmd) => cmd.apply(db),
+            MSet(cmd) => cmd.apply(db),
+            Persist(cmd) => cmd.apply(db),
+            Ping(cmd) => cmd.apply(),
+            Pop(cmd) => cmd.apply(db),
+            Publish(cmd) => cmd.apply(db),
+            Push(cmd) => cmd.apply(db),
+            SAdd(cmd) => cmd.apply(db),
+            SIsMember(cmd) => cmd.apply(db),
+            SMembers(cmd) => cmd.apply(db),
+            SRem(cmd) => cmd.apply(db),
+            Set(cmd) => cmd.apply(db),
+            SetNx(cmd) => cmd.apply(db),
+            SetOp(cmd) => cmd.apply(db),
+            SetRange(cmd) => cmd.apply(db),
+            StrLen(cmd) => cmd.apply(db),
+            Ttl(cmd) => cmd.apply(db),
+            Unknown(cmd) => cmd.apply(),
+            ZAdd(cmd) => cmd.apply(db),
+            ZIncrBy(cmd) => cmd.apply(db),
+            ZPopMin(cmd) => cmd.apply(db),
+            ZRange(cmd) => cmd.apply(db),
+            ZRank(cmd) => cmd.apply(db),
+            ZRem(cmd) => cmd.apply(db),
+            ZScore(cmd) => cmd.apply(db),
+            // Only run when queued in a transaction, which stopped watching
+            // keys when it started running
+            Unwatch(_) => Frame::Simple("OK".to_string()),
+            // `UNSUBSCRIBE` is only handled while subscribed
+            BgRewriteAof(_) | BgSave(_) | Discard(_) | Exec(_) | Hello(_) | Multi(_) | Save(_)
+            | Subscribe(_) | Unsubscribe(_) | Watch(_) => Frame::Error(format!(
+                "ERR '{}' is not allowed in this context",
+                self.get_name()
+            )),
+        }
+    }
+
+    /// Returns `true` if the command may change the keyspace, in which case
+    /// it is logged to the append only file.
+    pub(crate) fn is_write(&self) -> bool {
+        use Command::*;
+
+        matches!(
+            self,
+            Append(_)
+                | BlockingPop(_)
+                | Expire(_)
+                | ExpireAt(_)
+                | GetDel(_)
+                | GetEx(_)
+                | GetSet(_)
+                | HDel(_)
+                | HIncrBy(_)
+                | HSet(_)
+                | IncrBy(_)
+                | IncrByFloat(_)
+                | LMove(_)
+                | LTrim(_)
+                | MSet(_)
+                | Persist(_)
+                | Pop(_)
+                | Push(_)
+                | SAdd(_)
+                | SRem(_)
+                | Set(_)
+                | SetNx(_)
+                | SetRange(_)
+                | ZAdd(_)
+                | ZIncrBy(_)
+                | ZPopMin(_)
+                | ZRem(_)
+        )
+    }
+
+    /// Apply the command like `apply`, also returning what to write to the
+    /// append only file.
+    ///
+    /// Nothing is written for a command that failed. A push hands elements
+    /// over to blocked clients, which is written as pops following the push.
+    /// Sorted set updates are written with the resulting scores.
+    pub(crate) fn apply_logged(self, db: &Db) -> (Frame, Vec<Frame>) {
+        match self {
+            Command::BlockingPop(cmd) => cmd.apply_now(db),
+            Command::LMove(cmd) => cmd.apply_logged(db),
+            Command::Push(cmd) => cmd.apply_logged(db),
+            Command::ZAdd(cmd) => cmd.apply_logged(db),
+            Command::ZIncrBy(cmd) => cmd.apply_logged(db),
+            cmd => {
+                let record = cmd.aof_frame();
+                let response = cmd.apply(db);
+
+                match (&response, record) {
+                    (Frame::Error(_), _) | (_, None) => (response, vec![]),
+                    (_, Some(record)) => (response, vec![record]),
+                }
+            }
+        }
+    }
+
+    /// The command as written to the append only file, or `None` if it is
+    /// not written as is.
+    ///
+    /// Commands depending on the current time are rewritten so that
+    /// replaying them later gives the same result, e.g. a time to live
+    /// becomes a Unix time.
+    fn aof_frame(&self) -> Option<Frame> {
+        match self {
+            Command::Append(cmd) => Some(cmd.aof_frame()),
+            Command::Expire(cmd) => Some(cmd.aof_frame()),
+            Command::ExpireAt(cmd) => Some(cmd.aof_frame()),
+            Command::GetDel(cmd) => Some(cmd.aof_frame()),
+            Command::GetEx(cmd) => cmd.aof_frame(),
+            Command::GetSet(cmd) => Some(cmd.aof_frame()),
+            Command::HDel(cmd) => Some(cmd.aof_frame()),
+            Command::HIncrBy(cmd) => Some(cmd.aof_frame()),
+            Command::HSet(cmd) => Some(cmd.aof_frame()),
+            Command::IncrBy(cmd) => Some(cmd.aof_frame()),
+            Command::IncrByFloat(cmd) => Some(cmd.aof_frame()),
+            Command::LTrim(cmd) => Some(cmd.aof_frame()),
+            Command::MSet(cmd) => Some(cmd.aof_frame()),
+            Command::Persist(cmd) => Some(cmd.aof_frame()),
+            Command::Pop(cmd) => Some(cmd.aof_frame()),
+            Command::SAdd(cmd) => Some(cmd.aof_frame()),
+            Command::SRem(cmd) => Some(cmd.aof_frame()),
+            Command::Set(cmd) => Some(cmd.aof_frame()),
+            Command::SetNx(cmd) => Some(cmd.aof_frame()),
+            Command::SetRange(cmd) => Some(cmd.aof_frame()),
+            Command::ZPopMin(cmd) => Some(cmd.aof_frame()),
+            Command::ZRem(cmd) => Some(cmd.aof_frame()),
+            _ => None,
+        }
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self {
+            Command::Append(_) => "append",
+            Command::BgRewriteAof(_) => "bgrewriteaof",
+            Command::BgSave(_) => "bgsave",
+            Command::BlockingPop(cmd) => cmd.get_name(),
+            Command::Discard(_) => "discard",
+            Command::Exec(_) => "exec",
+            Command::Expire(_) => "expire",
+            Command::ExpireAt(cmd) => cmd.get_name(),
+            Command::Get(_) => "get",
+            Command::GetDel(_) => "getdel",
+            Command::GetEx(_) => "getex",
+            Command::GetRange(_) => "getrange",
+            Command::GetSet(_) => "getset",
+            Command::HDel(_) => "hdel",
+            Command::HExists(_) => "hexists",
+            Command::HGet(_) => "hget",
+            Command::HGetAll(cmd) => cmd.get_name(),
+            Command::HIncrBy(_) => "hincrby",
+            Command::HLen(_) => "hlen",
+            Command::HMGet(_) => "hmget",
+            Command::HScan(_) => "hscan",
+            Command::HSet(_) => "hset",
+            Command::Hello(_) => "hello",
+            Command::IncrBy(cmd) => cmd.get_name(),
+            Command::IncrByFloat(_) => "incrbyfloat",
+            Command::LIndex(_) => "lindex",
+            Command::LLen(_) => "llen",
+            Command::LMove(_) => "lmove",
+            Command::LRange(_) => "lrange",
+            Command::LTrim(_) => "ltrim",
+            Command::MGet(_) => "mget",
+            Command::MSet(cmd) => cmd.get_name(),
+            Command::Multi(_) => "multi",
+            Command::Persist(_) => "persist",
+            Command::Ping(_) => "ping",
+            Command::Pop(cmd) => cmd.get_name(),
+            Command::Publish(_) => "publish",
+            Command::Push(cmd) => cmd.get_name(),
+            Command::SAdd(_) => "sadd",
+            Command::SIsMember(_) => "sismember",
+            Command::SMembers(_) => "smembers",
+            Command::SRem(_) => "srem",
+            Command::Save(_) => "save",
+            Command::Set(_) => "set",
+            Command::SetNx(_) => "setnx",
+            Command::SetOp(cmd) => cmd.get_name(),
+            Command::SetRange(_) => "setrange",
+            Command::StrLen(_) => "strlen",
+            Command::Subscribe(_) => "subscribe",
+            Command::Ttl(cmd) => cmd.get_name(),
+            Command::Unknown(cmd) => cmd.get_name(),
+            Command::Unsubscribe(_) => "unsubscribe",
+            Command::Unwatch(_) => "unwatch",
+            Command::Watch(_) => "watch",
+            Command::ZAdd(_) => "zadd",
+            Command::ZIncrBy(_) => "zincrby",
+            Command::ZPopMin(_) => "zpopmin",
+            Command::ZRange(_) => "zrange",
+            Command::ZRank(_) => "zrank",
+            Command::ZRem(_) => "zrem",
+            Command::ZScore(_) => "zscore",
+        }
+    }
+}
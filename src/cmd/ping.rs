@@ -0,0 +1,74 @@
+use crate::connection::Protocol;
+use crate::parse::ParseError::EndOfStream;
+use crate::{Frame, Parse};
+
+use bytes::Bytes;
+
+/// Returns PONG if no argument is provided, otherwise
+/// return a copy of the argument as a bulk.
+///
+/// This command is often used to test if a connection
+/// is still alive, or to measure latency.
+#[derive(Debug, Default)]
+pub struct Ping {
+    /// optional message to be returned
+    msg: Option<Bytes>,
+}
+
+impl Ping {
+    /// Create a new `Ping` command with optional `msg`.
+    pub fn new(msg: Option<Bytes>) -> Ping {
+        Ping { msg }
+    }
+
+    /// Parse a `Ping` instance from a received frame.
+    ///
+    /// The `PING` string has already been consumed.
+    ///
+    /// ```text
+    /// PING [message]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Ping> {
+        match parse.next_bytes() {
+            Ok(msg) => Ok(Ping::new(Some(msg))),
+            Err(EndOfStream) => Ok(Ping::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Apply the `Ping` command, returning the response.
+    pub(crate) fn apply(self) -> Frame {
+        match self.msg {
+            None => Frame::Simple("PONG".to_string()),
+            Some(msg) => Frame::Bulk(msg),
+        }
+    }
+
+    /// Apply the `Ping` command on a subscribed connection.
+    ///
+    /// RESP2 clients can not tell a reply from a message there, so like
+    /// Redis the reply has the shape of a message: `["pong", message]`, the
+    /// message being empty if none was given.
+    pub(crate) fn apply_subscribed(self, protocol: Protocol) -> Frame {
+        match protocol {
+            Protocol::Resp2 => Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"pong")),
+                Frame::Bulk(self.msg.unwrap_or_default()),
+            ]),
+            Protocol::Resp3 => self.apply(),
+        }
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Ping` command to send
+    /// to the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("ping".as_bytes()));
+        if let Some(msg) = self.msg {
+            frame.push_bulk(msg);
+        }
+        frame
+    }
+}
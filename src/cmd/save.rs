@@ -0,0 +1,69 @@
+use crate::snapshot::Snapshots;
+use crate::{Frame, Parse};
+
+/// Save a snapshot of the keyspace, answering once it is on disk.
+///
+/// Every other client is served in the meantime, but this connection waits
+/// for the whole file to be written. [`BgSave`] answers right away instead.
+#[derive(Debug, Default)]
+pub struct Save;
+
+/// Save a snapshot of the keyspace in the background.
+#[derive(Debug, Default)]
+pub struct BgSave;
+
+impl Save {
+    /// Parse a `Save` instance from a received frame.
+    ///
+    /// ```text
+    /// SAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Save> {
+        Ok(Save)
+    }
+
+    /// Save a snapshot to `snapshots`, `None` when the server is not
+    /// configured to save any.
+    pub(crate) async fn apply(self, snapshots: Option<&Snapshots>) -> Frame {
+        let snapshots = match snapshots {
+            Some(snapshots) => snapshots.clone(),
+            None => return disabled(),
+        };
+
+        // Writing the file blocks, keep it off the runtime threads
+        match tokio::task::spawn_blocking(move || snapshots.save()).await {
+            Ok(Ok(())) => Frame::Simple("OK".to_string()),
+            Ok(Err(err)) => Frame::Error(err.to_string()),
+            Err(err) => Frame::Error(format!("ERR failed to save snapshot: {}", err)),
+        }
+    }
+}
+
+impl BgSave {
+    /// Parse a `BgSave` instance from a received frame.
+    ///
+    /// ```text
+    /// BGSAVE
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<BgSave> {
+        Ok(BgSave)
+    }
+
+    /// Start saving a snapshot to `snapshots`, `None` when the server is not
+    /// configured to save any.
+    pub(crate) fn apply(self, snapshots: Option<&Snapshots>) -> Frame {
+        let snapshots = match snapshots {
+            Some(snapshots) => snapshots,
+            None => return disabled(),
+        };
+
+        match snapshots.bgsave() {
+            Ok(()) => Frame::Simple("Background saving started".to_string()),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+}
+
+fn disabled() -> Frame {
+    Frame::Error("ERR snapshots are disabled".to_string())
+}
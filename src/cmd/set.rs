@@ -0,0 +1,194 @@
+use crate::cmd::expire::{unix_millis, until_unix_millis};
+use crate::parse::ParseError::EndOfStream;
+use crate::{Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::time::{Duration, SystemTime};
+
+/// Set `key` to hold the string `value`.
+///
+/// If `key` already holds a value, it is overwritten, regardless of its type.
+/// Any previous time to live associated with the key is discarded on
+/// successful SET operation.
+///
+/// # Options
+///
+/// Currently, the following options are supported:
+///
+/// * EX `seconds` -- Set the specified expire time, in seconds.
+/// * PX `milliseconds` -- Set the specified expire time, in milliseconds.
+/// * EXAT `timestamp` -- Expire the key at the given Unix time, in seconds.
+/// * PXAT `timestamp` -- Expire the key at the given Unix time, in
+///   milliseconds.
+#[derive(Debug)]
+pub struct Set {
+    /// the lookup key
+    key: String,
+
+    /// the value to be stored
+    value: Bytes,
+
+    /// When to expire the key
+    expire: Option<Duration>,
+}
+
+impl Set {
+    /// Create a new `Set` command which sets `key` to `value`.
+    ///
+    /// If `expire` is `Some`, the value should expire after the specified
+    /// duration.
+    pub fn new(key: impl ToString, value: Bytes, expire: Option<Duration>) -> Set {
+        Set {
+            key: key.to_string(),
+            value,
+            expire,
+        }
+    }
+
+    /// Get the key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the value
+    pub fn value(&self) -> &Bytes {
+        &self.value
+    }
+
+    /// Get the expire
+    pub fn expire(&self) -> Option<Duration> {
+        self.expire
+    }
+
+    /// Parse a `Set` instance from a received frame.
+    ///
+    /// The `SET` string has already been consumed.
+    ///
+    /// Expects an array frame containing at least 3 entries.
+    ///
+    /// ```text
+    /// SET key value [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp]
+    /// ```
+    ///
+    /// Unix timestamps are turned into a time to live right away. One that
+    /// has already passed still sets the key, which expires immediately.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Set> {
+        // Read the key to set. This is a required field
+        let key = parse.next_string()?;
+
+        // Read the value to set. This is a required field.
+        let value = parse.next_bytes()?;
+
+        // The expiration is optional. If nothing else follows, then it is
+        // `None`.
+        let mut expire = None;
+
+        // Attempt to parse another string.
+        match parse.next_string() {
+            Ok(s) if s.to_uppercase() == "EX" => {
+                // An expiration is specified in seconds. The next value is an
+                // integer.
+                let secs = parse.next_int()?;
+                expire = Some(relative_millis(secs.checked_mul(1000), "set")?);
+            }
+            Ok(s) if s.to_uppercase() == "PX" => {
+                // An expiration is specified in milliseconds. The next value is
+                // an integer.
+                let ms = parse.next_int()?;
+                expire = Some(relative_millis(Some(ms), "set")?);
+            }
+            Ok(s) if s.to_uppercase() == "EXAT" => {
+                let secs = parse.next_int()?;
+                let at = positive_millis(secs.checked_mul(1000), "set")?;
+                expire = Some(until_unix_millis(at.as_millis() as i64));
+            }
+            Ok(s) if s.to_uppercase() == "PXAT" => {
+                let ms = parse.next_int()?;
+                let at = positive_millis(Some(ms), "set")?;
+                expire = Some(until_unix_millis(at.as_millis() as i64));
+            }
+            // Currently, `my-redis` does not support any of the other SET
+            // options.
+            Ok(_) => return Err("ERR syntax error".into()),
+            // The `EndOfStream` error indicates there is no further data to
+            // parse. In this case, it is a normal run time situation and
+            // indicates there are no specified `SET` options.
+            Err(EndOfStream) => {}
+            // All other errors are bubbled up and reported to the client.
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(Set { key, value, expire })
+    }
+
+    /// Apply the `Set` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        // Set the value in the shared database state.
+        db.set(self.key, self.value, self.expire);
+
+        // Create a success response
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Converts the command into an equivalent `Frame`.
+    ///
+    /// This is called by the client when encoding a `Set` command to send to
+    /// the server.
+    pub(crate) fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from("set".as_bytes()));
+        frame.push_bulk(Bytes::from(self.key.into_bytes()));
+        frame.push_bulk(self.value);
+        if let Some(ms) = self.expire {
+            // Expirations in Redis protocol can be specified in two ways
+            // 1. SET key value EX seconds
+            // 2. SET key value PX milliseconds
+            // We use the second option because it allows greater precision.
+            frame.push_bulk(Bytes::from("px".as_bytes()));
+            frame.push_bulk(Bytes::from(ms.as_millis().to_string()));
+        }
+        frame
+    }
+
+    /// The command as written to the append only file.
+    ///
+    /// The time to live is replaced with the Unix time it ends at, so the key
+    /// expires at the same moment when the file is replayed after a restart.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let expires_at = self.expire.map(|expire| SystemTime::now() + expire);
+        frame_at(&self.key, &self.value, expires_at)
+    }
+}
+
+/// Build a `SET` command storing `value` at `key` until `expires_at`, if
+/// given.
+///
+/// Used for the append only file, which records deadlines as Unix time.
+pub(crate) fn frame_at(key: &str, value: &Bytes, expires_at: Option<SystemTime>) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from("set".as_bytes()));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    frame.push_bulk(value.clone());
+    if let Some(when) = expires_at {
+        frame.push_bulk(Bytes::from("pxat".as_bytes()));
+        frame.push_int(unix_millis(when));
+    }
+    frame
+}
+
+/// Validate the expire time given to `command`, which must be a positive
+/// number of milliseconds that did not overflow while being converted.
+pub(crate) fn positive_millis(ms: Option<i64>, command: &str) -> crate::Result<Duration> {
+    match ms {
+        Some(ms) if ms > 0 => Ok(Duration::from_millis(ms as u64)),
+        _ => Err(format!("ERR invalid expire time in '{}' command", command).into()),
+    }
+}
+
+/// Like [`positive_millis`], for an expire time counted from now. The Unix
+/// time it ends at must fit in milliseconds too, as that is what the append
+/// only file records.
+pub(crate) fn relative_millis(ms: Option<i64>, command: &str) -> crate::Result<Duration> {
+    let now = unix_millis(SystemTime::now());
+    positive_millis(ms.filter(|ms| ms.checked_add(now).is_some()), command)
+}
@@ -0,0 +1,274 @@
+use crate::cmd::list::remaining_bytes;
+use crate::db::{Value, WrongType};
+use crate::parse::ParseError::EndOfStream;
+use crate::{Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::collections::HashSet;
+
+/// Add members to the set stored at `key`, creating the set if needed.
+///
+/// Returns the number of members that were not in the set yet.
+#[derive(Debug)]
+pub struct SAdd {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+/// Remove members from the set stored at `key`, returning how many were in
+/// it.
+///
+/// The set is removed along with its last member.
+#[derive(Debug)]
+pub struct SRem {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+/// Returns every member of the set stored at `key`.
+#[derive(Debug)]
+pub struct SMembers {
+    key: String,
+}
+
+/// Returns 1 if `member` is in the set stored at `key`, 0 otherwise.
+#[derive(Debug)]
+pub struct SIsMember {
+    key: String,
+    member: Bytes,
+}
+
+/// Combine the sets stored at `keys`.
+///
+/// Handles `SINTER`, `SUNION` and `SDIFF`, the latter returning the members
+/// of the first set missing from every other one. A missing key counts as an
+/// empty set.
+#[derive(Debug)]
+pub struct SetOp {
+    keys: Vec<String>,
+    op: Op,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SAdd {
+    /// Parse a `SAdd` instance from a received frame.
+    ///
+    /// ```text
+    /// SADD key member [member ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SAdd> {
+        let key = parse.next_string()?;
+        let members = remaining_bytes(parse, 1)?;
+
+        Ok(SAdd { key, members })
+    }
+
+    /// Apply the `SAdd` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let set = match value.get_or_insert_with(|| Value::Set(HashSet::new())) {
+                Value::Set(set) => set,
+                _ => return Err(WrongType),
+            };
+
+            let added = self
+                .members
+                .iter()
+                .filter(|member| set.insert((*member).clone()))
+                .count();
+
+            Ok((added as i64, added > 0))
+        });
+
+        match res {
+            Ok(added) => Frame::Integer(added),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        members_frame("sadd", &self.key, &self.members)
+    }
+}
+
+impl SRem {
+    /// Parse a `SRem` instance from a received frame.
+    ///
+    /// ```text
+    /// SREM key member [member ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SRem> {
+        let key = parse.next_string()?;
+        let members = remaining_bytes(parse, 1)?;
+
+        Ok(SRem { key, members })
+    }
+
+    /// Apply the `SRem` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let set = match value {
+                None => return Ok((0, false)),
+                Some(Value::Set(set)) => set,
+                Some(_) => return Err(WrongType),
+            };
+
+            let removed = self
+                .members
+                .iter()
+                .filter(|member| set.remove(*member))
+                .count();
+
+            Ok((removed as i64, removed > 0))
+        });
+
+        match res {
+            Ok(removed) => Frame::Integer(removed),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        members_frame("srem", &self.key, &self.members)
+    }
+}
+
+impl SMembers {
+    /// Parse a `SMembers` instance from a received frame.
+    ///
+    /// ```text
+    /// SMEMBERS key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SMembers> {
+        let key = parse.next_string()?;
+
+        Ok(SMembers { key })
+    }
+
+    /// Apply the `SMembers` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Set(vec![]),
+            Some(Value::Set(set)) => set_frame(set.iter()),
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl SIsMember {
+    /// Parse a `SIsMember` instance from a received frame.
+    ///
+    /// ```text
+    /// SISMEMBER key member
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SIsMember> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+
+        Ok(SIsMember { key, member })
+    }
+
+    /// Apply the `SIsMember` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Integer(0),
+            Some(Value::Set(set)) => Frame::Integer(set.contains(&self.member) as i64),
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl SetOp {
+    /// Parse a `SetOp` instance from a received frame.
+    ///
+    /// ```text
+    /// SINTER key [key ...]
+    /// SUNION key [key ...]
+    /// SDIFF key [key ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, command_name: &str) -> crate::Result<SetOp> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let op = match command_name {
+            "sinter" => Op::Inter,
+            "sunion" => Op::Union,
+            _ => Op::Diff,
+        };
+
+        Ok(SetOp { keys, op })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.op {
+            Op::Inter => "sinter",
+            Op::Union => "sunion",
+            Op::Diff => "sdiff",
+        }
+    }
+
+    /// Apply the `SetOp` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read_many(&self.keys, |values| {
+            let empty = HashSet::new();
+            let mut sets = Vec::with_capacity(values.len());
+
+            for value in values {
+                match value {
+                    None => sets.push(&empty),
+                    Some(Value::Set(set)) => sets.push(set),
+                    Some(_) => return Frame::Error(WrongType.to_string()),
+                }
+            }
+
+            let (first, others) = sets.split_first().unwrap();
+
+            match self.op {
+                Op::Inter => set_frame(
+                    first
+                        .iter()
+                        .filter(|member| others.iter().all(|set| set.contains(*member))),
+                ),
+                Op::Union => {
+                    let union: HashSet<_> = sets.iter().flat_map(|set| set.iter()).collect();
+                    set_frame(union.into_iter())
+                }
+                Op::Diff => set_frame(
+                    first
+                        .iter()
+                        .filter(|member| !others.iter().any(|set| set.contains(*member))),
+                ),
+            }
+        })
+    }
+}
+
+fn set_frame<'a>(members: impl Iterator<Item = &'a Bytes>) -> Frame {
+    Frame::Set(members.cloned().map(Frame::Bulk).collect())
+}
+
+fn members_frame(name: &str, key: &str, members: &[Bytes]) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(name.to_string()));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    for member in members {
+        frame.push_bulk(member.clone());
+    }
+    frame
+}
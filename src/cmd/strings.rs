@@ -0,0 +1,711 @@
+use crate::cmd::expire::{pexpireat_frame, unix_millis, until_unix_millis};
+use crate::cmd::list;
+use crate::cmd::set::{positive_millis, relative_millis};
+use crate::cmd::zset::parse_score;
+use crate::db::{Expiry, Value, WrongType};
+use crate::frame::format_double;
+use crate::parse::ParseError::EndOfStream;
+use crate::{Db, Frame, Parse};
+
+use bytes::{Bytes, BytesMut};
+use std::time::SystemTime;
+use std::{mem, str};
+
+/// Longest string `APPEND` and `SETRANGE` may build, the default
+/// `proto-max-bulk-len` of Redis.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// Add to the integer stored at `key`, returning the result.
+///
+/// Handles `INCR`, `DECR`, `INCRBY` and `DECRBY`. A missing key counts as 0.
+/// Fails if the value is not a base 10 integer fitting in 64 bits, or if the
+/// result would not fit.
+#[derive(Debug)]
+pub struct IncrBy {
+    key: String,
+    increment: i64,
+    step: Step,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Incr,
+    Decr,
+    IncrBy,
+    DecrBy,
+}
+
+/// Add a floating point number to the value stored at `key`, returning the
+/// result.
+///
+/// A missing key counts as 0. The result must be finite.
+#[derive(Debug)]
+pub struct IncrByFloat {
+    key: String,
+    increment: f64,
+}
+
+/// Append `value` to the string stored at `key`, creating it if needed.
+///
+/// Returns the length of the string.
+#[derive(Debug)]
+pub struct Append {
+    key: String,
+    value: Bytes,
+}
+
+/// Returns the length of the string stored at `key`, 0 if it does not exist.
+#[derive(Debug)]
+pub struct StrLen {
+    key: String,
+}
+
+/// Returns the bytes of the string stored at `key` between two offsets,
+/// both included.
+///
+/// Negative offsets count from the end of the string, like `LRANGE`.
+#[derive(Debug)]
+pub struct GetRange {
+    key: String,
+    start: i64,
+    end: i64,
+}
+
+/// Overwrite the string stored at `key` with `value`, starting at `offset`.
+///
+/// The string is padded with zero bytes if it is shorter than `offset`.
+/// Returns the length of the string.
+#[derive(Debug)]
+pub struct SetRange {
+    key: String,
+    offset: usize,
+    value: Bytes,
+}
+
+/// Set `key` to `value`, returning the string it held before.
+///
+/// Like `SET`, the time to live of the key is discarded.
+#[derive(Debug)]
+pub struct GetSet {
+    key: String,
+    value: Bytes,
+}
+
+/// Remove `key`, returning the string it held.
+#[derive(Debug)]
+pub struct GetDel {
+    key: String,
+}
+
+/// Returns the string stored at `key`, changing its time to live.
+///
+/// # Options
+///
+/// * EX `seconds`, PX `milliseconds` -- Expire the key after the given
+///   duration.
+/// * EXAT `timestamp`, PXAT `timestamp` -- Expire the key at the given Unix
+///   time, in seconds or milliseconds.
+/// * PERSIST -- Remove the time to live of the key.
+///
+/// Without an option, `GETEX` behaves like `GET`.
+#[derive(Debug)]
+pub struct GetEx {
+    key: String,
+    expiry: Expiry,
+}
+
+/// Set `key` to `value` only if `key` does not exist.
+///
+/// Returns 1 if the key was set, 0 otherwise.
+#[derive(Debug)]
+pub struct SetNx {
+    key: String,
+    value: Bytes,
+}
+
+/// Set several keys at once, discarding their time to live.
+///
+/// Handles `MSET` and `MSETNX`, which sets nothing if any of the keys exists
+/// and returns whether the keys were set. No client sees only some of the
+/// keys set.
+#[derive(Debug)]
+pub struct MSet {
+    pairs: Vec<(String, Bytes)>,
+    only_new: bool,
+}
+
+/// Returns the strings stored at `keys`, nil for a key that does not exist
+/// or does not hold a string.
+#[derive(Debug)]
+pub struct MGet {
+    keys: Vec<String>,
+}
+
+impl IncrBy {
+    /// Parse a `IncrBy` instance from a received frame.
+    ///
+    /// ```text
+    /// INCR key
+    /// DECR key
+    /// INCRBY key increment
+    /// DECRBY key decrement
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, command_name: &str) -> crate::Result<IncrBy> {
+        let key = parse.next_string()?;
+
+        let (step, increment) = match command_name {
+            "incr" => (Step::Incr, 1),
+            "decr" => (Step::Decr, -1),
+            "incrby" => (Step::IncrBy, parse.next_int()?),
+            _ => {
+                let decrement = parse.next_int()?;
+                let increment = decrement
+                    .checked_neg()
+                    .ok_or("ERR decrement would overflow")?;
+                (Step::DecrBy, increment)
+            }
+        };
+
+        Ok(IncrBy {
+            key,
+            increment,
+            step,
+        })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.step {
+            Step::Incr => "incr",
+            Step::Decr => "decr",
+            Step::IncrBy => "incrby",
+            Step::DecrBy => "decrby",
+        }
+    }
+
+    /// Apply the `IncrBy` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let current = match value {
+                None => 0,
+                Some(Value::String(data)) => str::from_utf8(data)
+                    .ok()
+                    .and_then(|data| data.parse::<i64>().ok())
+                    .ok_or("ERR value is not an integer or out of range")?,
+                Some(_) => return Err(WrongType.to_string()),
+            };
+
+            let new = current
+                .checked_add(self.increment)
+                .ok_or("ERR increment or decrement would overflow")?;
+
+            *value = Some(Value::String(Bytes::from(new.to_string())));
+
+            Ok((new, true))
+        });
+
+        match res {
+            Ok(new) => Frame::Integer(new),
+            Err(err) => Frame::Error(err),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("incrby", &self.key);
+        frame.push_int(self.increment);
+        frame
+    }
+}
+
+impl IncrByFloat {
+    /// Parse a `IncrByFloat` instance from a received frame.
+    ///
+    /// ```text
+    /// INCRBYFLOAT key increment
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<IncrByFloat> {
+        let key = parse.next_string()?;
+        let increment = parse_score(&parse.next_bytes()?)?;
+
+        Ok(IncrByFloat { key, increment })
+    }
+
+    /// Apply the `IncrByFloat` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let current = match value {
+                None => 0.0,
+                Some(Value::String(data)) => parse_score(data).map_err(|err| err.to_string())?,
+                Some(_) => return Err(WrongType.to_string()),
+            };
+
+            let new = current + self.increment;
+
+            if !new.is_finite() {
+                return Err("ERR increment would produce NaN or Infinity".to_string());
+            }
+
+            let new = Bytes::from(format_double(new));
+            *value = Some(Value::String(new.clone()));
+
+            Ok((new, true))
+        });
+
+        match res {
+            Ok(new) => Frame::Bulk(new),
+            Err(err) => Frame::Error(err),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("incrbyfloat", &self.key);
+        frame.push_bulk(Bytes::from(format_double(self.increment)));
+        frame
+    }
+}
+
+impl Append {
+    /// Parse a `Append` instance from a received frame.
+    ///
+    /// ```text
+    /// APPEND key value
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Append> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        Ok(Append { key, value })
+    }
+
+    /// Apply the `Append` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let data = string_mut(value).map_err(|err| err.to_string())?;
+            check_len(data.len() + self.value.len())?;
+
+            let mut buf = take_mut(data);
+            buf.extend_from_slice(&self.value);
+            *data = buf.freeze();
+
+            Ok((data.len() as i64, true))
+        });
+
+        match res {
+            Ok(len) => Frame::Integer(len),
+            Err(err) => Frame::Error(err),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("append", &self.key);
+        frame.push_bulk(self.value.clone());
+        frame
+    }
+}
+
+impl StrLen {
+    /// Parse a `StrLen` instance from a received frame.
+    ///
+    /// ```text
+    /// STRLEN key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<StrLen> {
+        let key = parse.next_string()?;
+
+        Ok(StrLen { key })
+    }
+
+    /// Apply the `StrLen` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Integer(0),
+            Some(Value::String(data)) => Frame::Integer(data.len() as i64),
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl GetRange {
+    /// Parse a `GetRange` instance from a received frame.
+    ///
+    /// ```text
+    /// GETRANGE key start end
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<GetRange> {
+        let key = parse.next_string()?;
+        let start = parse.next_int()?;
+        let end = parse.next_int()?;
+
+        Ok(GetRange { key, start, end })
+    }
+
+    /// Apply the `GetRange` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read(&self.key, |value| match value {
+            None => Frame::Bulk(Bytes::new()),
+            Some(Value::String(data)) => match list::range(self.start, self.end, data.len()) {
+                Some(range) => Frame::Bulk(data.slice(range)),
+                None => Frame::Bulk(Bytes::new()),
+            },
+            Some(_) => Frame::Error(WrongType.to_string()),
+        })
+    }
+}
+
+impl SetRange {
+    /// Parse a `SetRange` instance from a received frame.
+    ///
+    /// ```text
+    /// SETRANGE key offset value
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SetRange> {
+        let key = parse.next_string()?;
+        let offset =
+            usize::try_from(parse.next_int()?).map_err(|_| "ERR offset is out of range")?;
+        let value = parse.next_bytes()?;
+
+        Ok(SetRange { key, offset, value })
+    }
+
+    /// Apply the `SetRange` command to the specified `Db` instance.
+    ///
+    /// Writing nothing does not create the key, nor pad the string.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            if value.is_none() && self.value.is_empty() {
+                return Ok((0, false));
+            }
+
+            let data = string_mut(value).map_err(|err| err.to_string())?;
+
+            if self.value.is_empty() {
+                return Ok((data.len() as i64, false));
+            }
+
+            let end = self.offset.saturating_add(self.value.len());
+            check_len(end)?;
+
+            let mut buf = take_mut(data);
+            if buf.len() < end {
+                buf.resize(end, 0);
+            }
+            buf[self.offset..end].copy_from_slice(&self.value);
+            *data = buf.freeze();
+
+            Ok((data.len() as i64, true))
+        });
+
+        match res {
+            Ok(len) => Frame::Integer(len),
+            Err(err) => Frame::Error(err),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("setrange", &self.key);
+        frame.push_int(self.offset as i64);
+        frame.push_bulk(self.value.clone());
+        frame
+    }
+}
+
+impl GetSet {
+    /// Parse a `GetSet` instance from a received frame.
+    ///
+    /// ```text
+    /// GETSET key value
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<GetSet> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        Ok(GetSet { key, value })
+    }
+
+    /// Apply the `GetSet` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update_expiry(&self.key, |value| {
+            let prev = bulk(value.as_ref())?;
+            *value = Some(Value::String(self.value.clone()));
+
+            Ok((prev, Some(Expiry::Persist)))
+        });
+
+        res.unwrap_or_else(|err: WrongType| Frame::Error(err.to_string()))
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("getset", &self.key);
+        frame.push_bulk(self.value.clone());
+        frame
+    }
+}
+
+impl GetDel {
+    /// Parse a `GetDel` instance from a received frame.
+    ///
+    /// ```text
+    /// GETDEL key
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<GetDel> {
+        let key = parse.next_string()?;
+
+        Ok(GetDel { key })
+    }
+
+    /// Apply the `GetDel` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let prev = bulk(value.as_ref())?;
+            let existed = value.take().is_some();
+
+            Ok((prev, existed))
+        });
+
+        res.unwrap_or_else(|err: WrongType| Frame::Error(err.to_string()))
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        command_frame("getdel", &self.key)
+    }
+}
+
+impl GetEx {
+    /// Parse a `GetEx` instance from a received frame.
+    ///
+    /// ```text
+    /// GETEX key [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp|PERSIST]
+    /// ```
+    ///
+    /// Unix timestamps are turned into a time to live right away, like for
+    /// `SET`.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<GetEx> {
+        let key = parse.next_string()?;
+
+        let expiry = match parse.next_string() {
+            Ok(s) => match &s.to_uppercase()[..] {
+                "EX" => {
+                    let secs = parse.next_int()?;
+                    Expiry::After(relative_millis(secs.checked_mul(1000), "getex")?)
+                }
+                "PX" => {
+                    let ms = parse.next_int()?;
+                    Expiry::After(relative_millis(Some(ms), "getex")?)
+                }
+                "EXAT" => {
+                    let secs = parse.next_int()?;
+                    let at = positive_millis(secs.checked_mul(1000), "getex")?;
+                    Expiry::After(until_unix_millis(at.as_millis() as i64))
+                }
+                "PXAT" => {
+                    let ms = parse.next_int()?;
+                    let at = positive_millis(Some(ms), "getex")?;
+                    Expiry::After(until_unix_millis(at.as_millis() as i64))
+                }
+                "PERSIST" => Expiry::Persist,
+                _ => return Err("ERR syntax error".into()),
+            },
+            Err(EndOfStream) => Expiry::Keep,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(GetEx { key, expiry })
+    }
+
+    /// Apply the `GetEx` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        // Only a read, the keyspace does not change
+        if self.expiry == Expiry::Keep {
+            return db
+                .read(&self.key, bulk)
+                .unwrap_or_else(|err| Frame::Error(err.to_string()));
+        }
+
+        let res = db.update_expiry(&self.key, |value| {
+            let frame = bulk(value.as_ref())?;
+            Ok((frame, value.is_some().then_some(self.expiry)))
+        });
+
+        res.unwrap_or_else(|err: WrongType| Frame::Error(err.to_string()))
+    }
+
+    /// The change to the time to live as written to the append only file,
+    /// `None` if there is none.
+    ///
+    /// Like for `SET`, the time to live is replaced with the Unix time it
+    /// ends at.
+    pub(crate) fn aof_frame(&self) -> Option<Frame> {
+        match self.expiry {
+            Expiry::Keep => None,
+            Expiry::Persist => Some(command_frame("persist", &self.key)),
+            Expiry::After(expire) => Some(pexpireat_frame(
+                &self.key,
+                unix_millis(SystemTime::now() + expire),
+            )),
+        }
+    }
+}
+
+impl SetNx {
+    /// Parse a `SetNx` instance from a received frame.
+    ///
+    /// ```text
+    /// SETNX key value
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<SetNx> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        Ok(SetNx { key, value })
+    }
+
+    /// Apply the `SetNx` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        Frame::Integer(db.set_many(vec![(self.key, self.value)], true) as i64)
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("setnx", &self.key);
+        frame.push_bulk(self.value.clone());
+        frame
+    }
+}
+
+impl MSet {
+    /// Parse a `MSet` instance from a received frame.
+    ///
+    /// ```text
+    /// MSET key value [key value ...]
+    /// MSETNX key value [key value ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse, command_name: &str) -> crate::Result<MSet> {
+        let mut pairs = vec![];
+
+        loop {
+            let key = match parse.next_string() {
+                Ok(key) => key,
+                Err(EndOfStream) if !pairs.is_empty() => break,
+                Err(err) => return Err(err.into()),
+            };
+            let value = parse.next_bytes()?;
+
+            pairs.push((key, value));
+        }
+
+        Ok(MSet {
+            pairs,
+            only_new: command_name == "msetnx",
+        })
+    }
+
+    /// Returns the command name
+    pub(crate) fn get_name(&self) -> &str {
+        match self.only_new {
+            true => "msetnx",
+            false => "mset",
+        }
+    }
+
+    /// Apply the `MSet` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let only_new = self.only_new;
+        let set = db.set_many(self.pairs, only_new);
+
+        match only_new {
+            true => Frame::Integer(set as i64),
+            false => Frame::Simple("OK".to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = Frame::array();
+        frame.push_bulk(Bytes::from(self.get_name().to_string()));
+        for (key, value) in &self.pairs {
+            frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+            frame.push_bulk(value.clone());
+        }
+        frame
+    }
+}
+
+impl MGet {
+    /// Parse a `MGet` instance from a received frame.
+    ///
+    /// ```text
+    /// MGET key [key ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<MGet> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(MGet { keys })
+    }
+
+    /// Apply the `MGet` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        db.read_many(&self.keys, |values| {
+            let frames = values
+                .iter()
+                .map(|value| match value {
+                    Some(Value::String(data)) => Frame::Bulk(data.clone()),
+                    _ => Frame::Null,
+                })
+                .collect();
+
+            Frame::Array(frames)
+        })
+    }
+}
+
+/// The string held by a key as a response, nil if the key does not exist.
+fn bulk(value: Option<&Value>) -> Result<Frame, WrongType> {
+    match value {
+        None => Ok(Frame::Null),
+        Some(Value::String(data)) => Ok(Frame::Bulk(data.clone())),
+        Some(_) => Err(WrongType),
+    }
+}
+
+/// The string stored at a key, created empty if the key does not exist.
+fn string_mut(value: &mut Option<Value>) -> Result<&mut Bytes, WrongType> {
+    match value.get_or_insert_with(|| Value::String(Bytes::new())) {
+        Value::String(data) => Ok(data),
+        _ => Err(WrongType),
+    }
+}
+
+/// Move a string out to modify it, which only copies it if it is shared.
+fn take_mut(data: &mut Bytes) -> BytesMut {
+    mem::take(data)
+        .try_into_mut()
+        .unwrap_or_else(|data| BytesMut::from(&data[..]))
+}
+
+fn check_len(len: usize) -> Result<(), String> {
+    match len > MAX_STRING_LEN {
+        true => Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string()),
+        false => Ok(()),
+    }
+}
+
+fn command_frame(name: &str, key: &str) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(name.to_string()));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    frame
+}
@@ -0,0 +1,252 @@
+use crate::aof::Aof;
+use crate::db::WatchedKeys;
+use crate::parse::ParseError::EndOfStream;
+use crate::{Command, Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::mem;
+
+/// Start a transaction.
+///
+/// The commands received next are queued instead of run, until `EXEC` runs
+/// them or `DISCARD` drops them.
+#[derive(Debug, Default)]
+pub struct Multi;
+
+/// Run the commands queued since `MULTI`, returning the array of their
+/// responses.
+///
+/// No command from another client runs in the middle of them. If a key
+/// watched with `WATCH` changed in the meantime, nothing is run and nil is
+/// returned instead.
+#[derive(Debug, Default)]
+pub struct Exec;
+
+/// Drop the commands queued since `MULTI` and stop watching keys.
+#[derive(Debug, Default)]
+pub struct Discard;
+
+/// Watch keys, so that the next `EXEC` fails if any of them changes before
+/// it runs.
+#[derive(Debug)]
+pub struct Watch {
+    keys: Vec<String>,
+}
+
+/// Stop watching keys.
+#[derive(Debug, Default)]
+pub struct Unwatch;
+
+/// Transaction state of a connection.
+///
+/// Dropping it, along with the connection, stops watching keys.
+#[derive(Default)]
+pub(crate) struct Transaction {
+    /// Commands queued since `MULTI`, `None` outside of a transaction
+    queued: Option<Vec<Command>>,
+
+    /// A command could not be queued, `EXEC` discards the transaction
+    failed: bool,
+
+    /// Keys watched until the next `EXEC`, `DISCARD` or `UNWATCH`
+    watched: Option<WatchedKeys>,
+}
+
+impl Multi {
+    /// Parse a `Multi` instance from a received frame.
+    ///
+    /// ```text
+    /// MULTI
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Multi> {
+        Ok(Multi)
+    }
+
+    /// Start queueing the commands of the connection.
+    pub(crate) fn apply(self, transaction: &mut Transaction) -> Frame {
+        transaction.queued = Some(vec![]);
+
+        Frame::Simple("OK".to_string())
+    }
+}
+
+impl Exec {
+    /// Parse a `Exec` instance from a received frame.
+    ///
+    /// ```text
+    /// EXEC
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Exec> {
+        Ok(Exec)
+    }
+
+    /// Run the queued commands while holding the store exclusively.
+    ///
+    /// The changes they make are written to `aof` at once, between `MULTI`
+    /// and `EXEC`, so that a transaction cut off the end of the file is not
+    /// replayed.
+    pub(crate) fn apply(self, db: &Db, aof: Option<&Aof>, transaction: &mut Transaction) -> Frame {
+        let commands = match transaction.queued.take() {
+            Some(commands) => commands,
+            None => return Frame::Error("ERR EXEC without MULTI".to_string()),
+        };
+
+        // Keys are only watched for one transaction, whatever its outcome
+        let watched = transaction.watched.take();
+
+        if mem::take(&mut transaction.failed) {
+            return Frame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            );
+        }
+
+        let _gate = db.lock_exclusive();
+
+        // Checked under the lock, no key can change until the commands ran
+        if watched.as_ref().is_some_and(WatchedKeys::is_dirty) {
+            return Frame::Null;
+        }
+
+        let run = || {
+            let mut responses = Vec::with_capacity(commands.len());
+            let mut records = vec![];
+
+            for cmd in commands {
+                let response = match aof {
+                    Some(_) if cmd.is_write() => {
+                        let (response, logged) = cmd.apply_logged(db);
+                        records.extend(logged);
+                        response
+                    }
+                    _ => cmd.apply(db),
+                };
+
+                responses.push(response);
+            }
+
+            if !records.is_empty() {
+                records.insert(0, command_frame("multi"));
+                records.push(command_frame("exec"));
+            }
+
+            (Frame::Array(responses), records)
+        };
+
+        match aof {
+            Some(aof) => aof
+                .log(run)
+                .unwrap_or_else(|err| Frame::Error(err.to_string())),
+            None => run().0,
+        }
+    }
+}
+
+impl Discard {
+    /// Parse a `Discard` instance from a received frame.
+    ///
+    /// ```text
+    /// DISCARD
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Discard> {
+        Ok(Discard)
+    }
+
+    /// Drop the transaction of the connection.
+    pub(crate) fn apply(self, transaction: &mut Transaction) -> Frame {
+        if transaction.queued.is_none() {
+            return Frame::Error("ERR DISCARD without MULTI".to_string());
+        }
+
+        *transaction = Transaction::default();
+
+        Frame::Simple("OK".to_string())
+    }
+}
+
+impl Watch {
+    /// Parse a `Watch` instance from a received frame.
+    ///
+    /// ```text
+    /// WATCH key [key ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<Watch> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Watch { keys })
+    }
+
+    /// Add the keys to those watched by the connection.
+    pub(crate) fn apply(self, db: &Db, transaction: &mut Transaction) -> Frame {
+        let watched = transaction.watched.get_or_insert_with(|| db.watch());
+
+        for key in self.keys {
+            watched.add(key);
+        }
+
+        Frame::Simple("OK".to_string())
+    }
+}
+
+impl Unwatch {
+    /// Parse a `Unwatch` instance from a received frame.
+    ///
+    /// ```text
+    /// UNWATCH
+    /// ```
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> crate::Result<Unwatch> {
+        Ok(Unwatch)
+    }
+
+    /// Stop watching the keys of the connection.
+    pub(crate) fn apply(self, transaction: &mut Transaction) -> Frame {
+        transaction.watched = None;
+
+        Frame::Simple("OK".to_string())
+    }
+}
+
+impl Transaction {
+    /// Returns `true` between `MULTI` and `EXEC` or `DISCARD`.
+    pub(crate) fn is_open(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    /// Queue a command received while the transaction is open.
+    ///
+    /// A command that could not be parsed fails the whole transaction. A
+    /// nested `MULTI` or a `WATCH` is only refused.
+    pub(crate) fn queue(&mut self, command: crate::Result<Command>) -> Frame {
+        let queued = self.queued.as_mut().expect("no transaction is open");
+
+        let response = match command {
+            Ok(Command::Multi(_)) => {
+                return Frame::Error("ERR MULTI calls can not be nested".to_string())
+            }
+            Ok(Command::Watch(_)) => {
+                return Frame::Error("ERR WATCH inside MULTI is not allowed".to_string())
+            }
+            Ok(Command::Unknown(cmd)) => cmd.apply(),
+            Ok(cmd) => {
+                queued.push(cmd);
+                return Frame::Simple("QUEUED".to_string());
+            }
+            Err(err) => Frame::Error(err.to_string()),
+        };
+
+        self.failed = true;
+
+        response
+    }
+}
+
+fn command_frame(name: &'static str) -> Frame {
+    Frame::Array(vec![Frame::Bulk(Bytes::from_static(name.as_bytes()))])
+}
@@ -0,0 +1,664 @@
+use crate::cmd::list::{self, remaining_bytes};
+use crate::db::{Value, WrongType};
+use crate::frame::format_double;
+use crate::parse::ParseError::EndOfStream;
+use crate::sorted_set::SortedSet;
+use crate::{Db, Frame, Parse};
+
+use bytes::Bytes;
+use std::ops::Bound;
+use std::str;
+
+/// Add members to the sorted set stored at `key`, or update their score.
+///
+/// Options, given before the score-member pairs:
+///
+/// * `NX`: only add new members. `XX`: only update existing members.
+/// * `GT`, `LT`: only update a member if its new score is greater, or lower,
+///   than the current one. New members are still added.
+/// * `CH`: count the members whose score changed along with the added ones.
+/// * `INCR`: add the score to the current one, like `ZINCRBY`, and return
+///   the new score, or nil if the options prevented the update.
+///
+/// Returns the number of members added.
+#[derive(Debug)]
+pub struct ZAdd {
+    key: String,
+    only: Option<Only>,
+    compare: Option<Compare>,
+    changed: bool,
+    incr: bool,
+    pairs: Vec<(f64, Bytes)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Only {
+    New,
+    Existing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compare {
+    Greater,
+    Lower,
+}
+
+/// Remove members from the sorted set stored at `key`, returning how many
+/// were in it.
+#[derive(Debug)]
+pub struct ZRem {
+    key: String,
+    members: Vec<Bytes>,
+}
+
+/// Returns the score of `member`, nil if it is not in the sorted set.
+#[derive(Debug)]
+pub struct ZScore {
+    key: String,
+    member: Bytes,
+}
+
+/// Returns the rank of `member`, 0 being the lowest score, nil if it is not
+/// in the sorted set.
+#[derive(Debug)]
+pub struct ZRank {
+    key: String,
+    member: Bytes,
+}
+
+/// Returns a range of members of the sorted set stored at `key`.
+///
+/// The range is given by rank by default, with negative ranks counting from
+/// the highest score. With `BYSCORE`, it is given by score, `(` marking an
+/// exclusive bound and `-inf`/`+inf` the ends. With `BYLEX`, it is given by
+/// member, `[` or `(` marking an inclusive or exclusive bound and `-`/`+`
+/// the ends, which is only meaningful when all the scores are equal.
+///
+/// `REV` returns the members from the highest score down. Score and lex
+/// ranges are then given as `max min`. `LIMIT offset count` pages through
+/// score and lex ranges. `WITHSCORES` returns each score after its member.
+#[derive(Debug)]
+pub struct ZRange {
+    key: String,
+    by: By,
+    rev: bool,
+    limit: Option<(i64, i64)>,
+    with_scores: bool,
+}
+
+#[derive(Debug)]
+enum By {
+    Rank(i64, i64),
+    Score(Bound<f64>, Bound<f64>),
+    /// `None` for a range that can hold no member, like `+` as the minimum.
+    Lex(Option<(Bound<Bytes>, Bound<Bytes>)>),
+}
+
+/// Add `increment` to the score of `member`, a missing member counting as
+/// 0. Returns the new score.
+#[derive(Debug)]
+pub struct ZIncrBy {
+    key: String,
+    increment: f64,
+    member: Bytes,
+}
+
+/// Remove and return up to `count` members with the lowest scores, each
+/// followed by its score.
+#[derive(Debug)]
+pub struct ZPopMin {
+    key: String,
+    count: Option<usize>,
+}
+
+impl ZAdd {
+    /// Parse a `ZAdd` instance from a received frame.
+    ///
+    /// ```text
+    /// ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZAdd> {
+        let key = parse.next_string()?;
+        let args = remaining_bytes(parse, 1)?;
+
+        let mut zadd = ZAdd {
+            key,
+            only: None,
+            compare: None,
+            changed: false,
+            incr: false,
+            pairs: vec![],
+        };
+
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        let mut args = &args[..];
+
+        while let Some((arg, rest)) = args.split_first() {
+            match &arg.to_ascii_uppercase()[..] {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GT" => gt = true,
+                b"LT" => lt = true,
+                b"CH" => zadd.changed = true,
+                b"INCR" => zadd.incr = true,
+                _ => break,
+            }
+
+            args = rest;
+        }
+
+        if nx && xx {
+            return Err("ERR XX and NX options at the same time are not compatible".into());
+        }
+
+        if (gt && lt) || (nx && (gt || lt)) {
+            return Err("ERR GT, LT, and/or NX options at the same time are not compatible".into());
+        }
+
+        zadd.only = match (nx, xx) {
+            (true, _) => Some(Only::New),
+            (_, true) => Some(Only::Existing),
+            _ => None,
+        };
+        zadd.compare = match (gt, lt) {
+            (true, _) => Some(Compare::Greater),
+            (_, true) => Some(Compare::Lower),
+            _ => None,
+        };
+
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err("ERR syntax error".into());
+        }
+
+        for pair in args.chunks(2) {
+            zadd.pairs.push((parse_score(&pair[0])?, pair[1].clone()));
+        }
+
+        if zadd.incr && zadd.pairs.len() > 1 {
+            return Err("ERR INCR option supports a single increment-element pair".into());
+        }
+
+        Ok(zadd)
+    }
+
+    /// Apply the `ZAdd` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        self.apply_logged(db).0
+    }
+
+    /// Apply the command, also returning what to write to the append only
+    /// file: the resulting score of every member that changed, so that
+    /// replaying does not depend on the options.
+    pub(crate) fn apply_logged(self, db: &Db) -> (Frame, Vec<Frame>) {
+        let res = db.update(&self.key, |value| {
+            let set = sorted_set_mut(value)?;
+
+            let mut added = 0;
+            let mut changed = vec![];
+            let mut last = None;
+
+            for (score, member) in self.pairs {
+                let prev = set.score(&member);
+
+                let skip = matches!(
+                    (prev, self.only),
+                    (Some(_), Some(Only::New)) | (None, Some(Only::Existing))
+                );
+
+                if skip {
+                    continue;
+                }
+
+                let score = match (prev, self.incr) {
+                    (Some(prev), true) => prev + score,
+                    _ => score,
+                };
+
+                if score.is_nan() {
+                    return Err("ERR resulting score is not a number (NaN)".to_string());
+                }
+
+                let allowed = match (prev, self.compare) {
+                    (Some(prev), Some(Compare::Greater)) => score > prev,
+                    (Some(prev), Some(Compare::Lower)) => score < prev,
+                    _ => true,
+                };
+
+                if !allowed {
+                    continue;
+                }
+
+                last = Some(score);
+
+                if prev.is_none() {
+                    added += 1;
+                }
+
+                if prev != Some(score) {
+                    set.insert(member.clone(), score);
+                    changed.push((member, score));
+                }
+            }
+
+            let modified = !changed.is_empty();
+            Ok(((added, changed, last), modified))
+        });
+
+        let (added, changed, last) = match res {
+            Ok(res) => res,
+            Err(err) => return (Frame::Error(err), vec![]),
+        };
+
+        let response = if self.incr {
+            last.map_or(Frame::Null, Frame::Double)
+        } else if self.changed {
+            Frame::Integer(changed.len() as i64)
+        } else {
+            Frame::Integer(added)
+        };
+
+        let records = match changed.is_empty() {
+            true => vec![],
+            false => vec![zadd_frame(
+                &self.key,
+                changed.iter().map(|(member, score)| (member, *score)),
+            )],
+        };
+
+        (response, records)
+    }
+}
+
+impl ZRem {
+    /// Parse a `ZRem` instance from a received frame.
+    ///
+    /// ```text
+    /// ZREM key member [member ...]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZRem> {
+        let key = parse.next_string()?;
+        let members = remaining_bytes(parse, 1)?;
+
+        Ok(ZRem { key, members })
+    }
+
+    /// Apply the `ZRem` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let set = match value {
+                None => return Ok((0, false)),
+                Some(Value::SortedSet(set)) => set,
+                Some(_) => return Err(WrongType),
+            };
+
+            let removed = self
+                .members
+                .iter()
+                .filter(|member| set.remove(member).is_some())
+                .count();
+
+            Ok((removed as i64, removed > 0))
+        });
+
+        match res {
+            Ok(removed) => Frame::Integer(removed),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("zrem", &self.key);
+        for member in &self.members {
+            frame.push_bulk(member.clone());
+        }
+        frame
+    }
+}
+
+impl ZScore {
+    /// Parse a `ZScore` instance from a received frame.
+    ///
+    /// ```text
+    /// ZSCORE key member
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZScore> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+
+        Ok(ZScore { key, member })
+    }
+
+    /// Apply the `ZScore` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_sorted_set(db, &self.key, |set| {
+            set.score(&self.member).map_or(Frame::Null, Frame::Double)
+        })
+    }
+}
+
+impl ZRank {
+    /// Parse a `ZRank` instance from a received frame.
+    ///
+    /// ```text
+    /// ZRANK key member
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZRank> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+
+        Ok(ZRank { key, member })
+    }
+
+    /// Apply the `ZRank` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        read_sorted_set(db, &self.key, |set| match set.rank(&self.member) {
+            Some(rank) => Frame::Integer(rank as i64),
+            None => Frame::Null,
+        })
+    }
+}
+
+impl ZRange {
+    /// Parse a `ZRange` instance from a received frame.
+    ///
+    /// ```text
+    /// ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZRange> {
+        let key = parse.next_string()?;
+        let start = parse.next_bytes()?;
+        let stop = parse.next_bytes()?;
+
+        let mut by_score = false;
+        let mut by_lex = false;
+        let mut rev = false;
+        let mut limit = None;
+        let mut with_scores = false;
+
+        loop {
+            let option = match parse.next_string() {
+                Ok(option) => option.to_uppercase(),
+                Err(EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            match &option[..] {
+                "BYSCORE" => by_score = true,
+                "BYLEX" => by_lex = true,
+                "REV" => rev = true,
+                "LIMIT" => limit = Some((parse.next_int()?, parse.next_int()?)),
+                "WITHSCORES" => with_scores = true,
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        // Score and lex ranges are given from the end the members come from
+        let (min, max) = match rev {
+            true => (&stop, &start),
+            false => (&start, &stop),
+        };
+
+        let by = match (by_score, by_lex) {
+            (true, true) => return Err("ERR syntax error".into()),
+            (true, false) => By::Score(score_bound(min)?, score_bound(max)?),
+            (false, true) => {
+                if with_scores {
+                    return Err(
+                        "ERR syntax error, WITHSCORES not supported in combination with BYLEX"
+                            .into(),
+                    );
+                }
+
+                match (lex_bound(min, true)?, lex_bound(max, false)?) {
+                    (Some(min), Some(max)) => By::Lex(Some((min, max))),
+                    _ => By::Lex(None),
+                }
+            }
+            (false, false) => {
+                if limit.is_some() {
+                    return Err("ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".into());
+                }
+
+                By::Rank(parse_int(&start)?, parse_int(&stop)?)
+            }
+        };
+
+        Ok(ZRange {
+            key,
+            by,
+            rev,
+            limit,
+            with_scores,
+        })
+    }
+
+    /// Apply the `ZRange` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let (offset, count) = match self.limit {
+            // A negative offset selects nothing
+            Some((offset, _)) if offset < 0 => return Frame::Array(vec![]),
+            // A negative count selects everything after the offset
+            Some((offset, count)) => (offset as usize, usize::try_from(count).ok()),
+            None => (0, None),
+        };
+
+        read_sorted_set(db, &self.key, |set| {
+            let members: Box<dyn Iterator<Item = (&Bytes, f64)>> = match &self.by {
+                By::Rank(start, stop) => match list::range(*start, *stop, set.len()) {
+                    Some(range) => {
+                        Box::new(set.range_by_rank(range.start, range.end - 1, self.rev))
+                    }
+                    None => Box::new(std::iter::empty()),
+                },
+                By::Score(min, max) => Box::new(set.range_by_score(*min, *max, self.rev, offset)),
+                By::Lex(Some((min, max))) => Box::new(set.range_by_lex(min, max, self.rev, offset)),
+                By::Lex(None) => Box::new(std::iter::empty()),
+            };
+
+            let mut frames = vec![];
+            for (member, score) in members.take(count.unwrap_or(usize::MAX)) {
+                frames.push(Frame::Bulk(member.clone()));
+                if self.with_scores {
+                    frames.push(Frame::Double(score));
+                }
+            }
+
+            Frame::Array(frames)
+        })
+    }
+}
+
+impl ZIncrBy {
+    /// Parse a `ZIncrBy` instance from a received frame.
+    ///
+    /// ```text
+    /// ZINCRBY key increment member
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZIncrBy> {
+        let key = parse.next_string()?;
+        let increment = parse_score(&parse.next_bytes()?)?;
+        let member = parse.next_bytes()?;
+
+        Ok(ZIncrBy {
+            key,
+            increment,
+            member,
+        })
+    }
+
+    /// Apply the `ZIncrBy` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        self.apply_logged(db).0
+    }
+
+    /// Apply the command, also returning what to write to the append only
+    /// file: the resulting score, so that replaying does not add up rounding
+    /// differently.
+    pub(crate) fn apply_logged(self, db: &Db) -> (Frame, Vec<Frame>) {
+        let res = db.update(&self.key, |value| {
+            let set = sorted_set_mut(value)?;
+            let score = set.score(&self.member).unwrap_or(0.0) + self.increment;
+
+            if score.is_nan() {
+                return Err("ERR resulting score is not a number (NaN)".to_string());
+            }
+
+            set.insert(self.member.clone(), score);
+            Ok((score, true))
+        });
+
+        match res {
+            Ok(score) => {
+                let record = zadd_frame(&self.key, [(&self.member, score)].into_iter());
+                (Frame::Double(score), vec![record])
+            }
+            Err(err) => (Frame::Error(err), vec![]),
+        }
+    }
+}
+
+impl ZPopMin {
+    /// Parse a `ZPopMin` instance from a received frame.
+    ///
+    /// ```text
+    /// ZPOPMIN key [count]
+    /// ```
+    pub(crate) fn parse_frames(parse: &mut Parse) -> crate::Result<ZPopMin> {
+        let key = parse.next_string()?;
+
+        let count = match parse.next_int() {
+            Ok(count) if count >= 0 => Some(count as usize),
+            Ok(_) => return Err("ERR value is out of range, must be positive".into()),
+            Err(EndOfStream) => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(ZPopMin { key, count })
+    }
+
+    /// Apply the `ZPopMin` command to the specified `Db` instance.
+    pub(crate) fn apply(self, db: &Db) -> Frame {
+        let res = db.update(&self.key, |value| {
+            let set = match value {
+                None => return Ok((vec![], false)),
+                Some(Value::SortedSet(set)) => set,
+                Some(_) => return Err(WrongType),
+            };
+
+            let mut frames = vec![];
+            for _ in 0..self.count.unwrap_or(1) {
+                match set.pop_min() {
+                    Some((member, score)) => {
+                        frames.push(Frame::Bulk(member));
+                        frames.push(Frame::Double(score));
+                    }
+                    None => break,
+                }
+            }
+
+            let modified = !frames.is_empty();
+            Ok((frames, modified))
+        });
+
+        match res {
+            Ok(frames) => Frame::Array(frames),
+            Err(err) => Frame::Error(err.to_string()),
+        }
+    }
+
+    /// The command as written to the append only file.
+    pub(crate) fn aof_frame(&self) -> Frame {
+        let mut frame = command_frame("zpopmin", &self.key);
+        if let Some(count) = self.count {
+            frame.push_int(count as i64);
+        }
+        frame
+    }
+}
+
+/// Build a `ZADD` setting the score of each member.
+pub(crate) fn zadd_frame<'a>(key: &str, members: impl Iterator<Item = (&'a Bytes, f64)>) -> Frame {
+    let mut frame = command_frame("zadd", key);
+    for (member, score) in members {
+        frame.push_bulk(Bytes::from(format_double(score)));
+        frame.push_bulk(member.clone());
+    }
+    frame
+}
+
+fn command_frame(name: &str, key: &str) -> Frame {
+    let mut frame = Frame::array();
+    frame.push_bulk(Bytes::from(name.to_string()));
+    frame.push_bulk(Bytes::copy_from_slice(key.as_bytes()));
+    frame
+}
+
+/// Run `f` on the sorted set stored at `key`, an empty one if the key does
+/// not exist.
+fn read_sorted_set(db: &Db, key: &str, f: impl FnOnce(&SortedSet) -> Frame) -> Frame {
+    db.read(key, |value| match value {
+        None => f(&SortedSet::new()),
+        Some(Value::SortedSet(set)) => f(set),
+        Some(_) => Frame::Error(WrongType.to_string()),
+    })
+}
+
+/// The sorted set stored in `value`, created if missing.
+fn sorted_set_mut(value: &mut Option<Value>) -> Result<&mut SortedSet, String> {
+    match value.get_or_insert_with(|| Value::SortedSet(SortedSet::new())) {
+        Value::SortedSet(set) => Ok(set),
+        _ => Err(WrongType.to_string()),
+    }
+}
+
+pub(crate) fn parse_score(src: &[u8]) -> crate::Result<f64> {
+    match str::from_utf8(src)
+        .ok()
+        .and_then(|src| src.parse::<f64>().ok())
+    {
+        Some(score) if !score.is_nan() => Ok(score),
+        _ => Err("ERR value is not a valid float".into()),
+    }
+}
+
+fn parse_int(src: &[u8]) -> crate::Result<i64> {
+    str::from_utf8(src)
+        .ok()
+        .and_then(|src| src.parse().ok())
+        .ok_or_else(|| "ERR value is not an integer or out of range".into())
+}
+
+/// Parse a score bound of `ZRANGE BYSCORE`, like `1.5`, `(1.5` or `-inf`.
+fn score_bound(src: &[u8]) -> crate::Result<Bound<f64>> {
+    const MSG: &str = "ERR min or max is not a float";
+
+    let (exclusive, src) = match src.split_first() {
+        Some((b'(', rest)) => (true, rest),
+        _ => (false, src),
+    };
+
+    let score = parse_score(src).map_err(|_| MSG)?;
+
+    Ok(match exclusive {
+        true => Bound::Excluded(score),
+        false => Bound::Included(score),
+    })
+}
+
+/// Parse a member bound of `ZRANGE BYLEX`, like `[a`, `(a`, `-` or `+`.
+///
+/// Returns `None` for a bound no member can satisfy, `+` as the minimum or
+/// `-` as the maximum.
+fn lex_bound(src: &[u8], is_min: bool) -> crate::Result<Option<Bound<Bytes>>> {
+    let bound = match src.split_first() {
+        Some((b'-', [])) if is_min => Bound::Unbounded,
+        Some((b'+', [])) if !is_min => Bound::Unbounded,
+        Some((b'-' | b'+', [])) => return Ok(None),
+        Some((b'[', rest)) => Bound::Included(Bytes::copy_from_slice(rest)),
+        Some((b'(', rest)) => Bound::Excluded(Bytes::copy_from_slice(rest)),
+        _ => return Err("ERR min or max not valid string range item".into()),
+    };
+
+    Ok(Some(bound))
+}
@@ -1,82 +1,322 @@
-use bytes::{Buf, BytesMut};
-use mini_redis::{Frame, Result};
-use mini_redis::frame::Error::Incomplete;
-use std::io::cursor;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpListener;
+use crate::frame::{self, format_double, Frame};
+use crate::inline::split_args;
+use crate::Result;
 
+use bytes::{Bytes, BytesMut};
+use std::io::{self, Cursor};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+
+/// Send and receive `Frame` values from a remote peer.
+///
+/// Reads are buffered in `buffer` until a whole frame is available, writes go
+/// through a `BufWriter`. `write_frame` flushes every frame, while
+/// `buffer_frame` leaves it to the caller to `flush` once a batch of frames has
+/// been written.
+///
+/// Bulk strings in received frames are not copied out of `buffer`, they share
+/// its memory. A bulk string that outlives its frame keeps the chunk of the
+/// buffer it was read into alive, so commands copy the values they store in
+/// the `Db`.
+///
+/// Every frame type is accepted when reading. When writing, the frame types
+/// introduced by RESP3 are only used once the connection is switched to
+/// [`Protocol::Resp3`]. Until then they are sent as their closest RESP2
+/// equivalent, the way Redis answers RESP2 clients: maps are flattened into
+/// arrays of keys and values, sets and pushes become arrays, doubles and big
+/// numbers become bulk strings, booleans become the integers 1 and 0, and
+/// attributes are left out.
+///
+/// What the peer may send is bounded by [`Limits`]. A frame exceeding them is
+/// reported as an error by `read_frame`, after which the connection should be
+/// closed.
 pub struct Connection {
     stream: BufWriter<TcpStream>,
     buffer: BytesMut,
+
+    // Bytes at the front of `buffer` already searched for the end of an
+    // inline command, so that a line received in many reads is only scanned
+    // once.
+    inline_scanned: usize,
+
+    protocol: Protocol,
+    limits: Limits,
+}
+
+/// Bounds on the frames a peer may send, so a peer cannot make the process
+/// buffer an unbounded amount of data, or recurse without end while checking
+/// nested frames.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Longest bulk string accepted, in bytes.
+    pub max_bulk_len: usize,
+
+    /// Most elements in an array or any other aggregate frame, each entry
+    /// counting once in maps. Also applies to the arguments of inline
+    /// commands.
+    pub max_array_len: usize,
+
+    /// Most aggregate frames nested in each other. A command, an array of
+    /// bulk strings, needs one level.
+    pub max_depth: usize,
+
+    /// Longest line accepted as an inline command, in bytes.
+    pub max_inline_len: usize,
+
+    /// Most bytes buffered while waiting for a frame to be complete. Must
+    /// leave room for the longest bulk string along with the rest of its
+    /// frame.
+    pub max_buffered_bytes: usize,
+}
+
+/// Version of the protocol used to write frames, see [`Connection`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Default for Limits {
+    /// The limits Redis applies to its clients by default: 512 MiB bulk
+    /// strings, 64 KiB inline commands and 1 GiB of buffered data.
+    fn default() -> Limits {
+        Limits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_array_len: 1024 * 1024,
+            max_depth: 32,
+            max_inline_len: 64 * 1024,
+            max_buffered_bytes: 1024 * 1024 * 1024,
+        }
+    }
 }
 
 impl Connection {
     pub fn new(stream: TcpStream) -> Connection {
+        Connection::with_limits(stream, Limits::default())
+    }
+
+    /// Create a connection accepting frames within `limits`.
+    pub fn with_limits(stream: TcpStream, limits: Limits) -> Connection {
         Connection {
             stream: BufWriter::new(stream),
             // Allocate the buffer with 4kb of capacity
             buffer: BytesMut::with_capacity(4096),
+            inline_scanned: 0,
+            protocol: Protocol::Resp2,
+            limits,
         }
     }
+
+    /// Returns the protocol frames are written with.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Write the following frames with `protocol`, e.g. after a `HELLO`.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Read a single `Frame` from the underlying stream.
+    ///
+    /// Waits until enough data has been received to parse a whole frame. Any
+    /// data remaining in the buffer after the frame is kept for the next call.
+    ///
+    /// Returns `None` if the peer closed the connection cleanly, and an error
+    /// if it closed the connection in the middle of a frame.
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
-        // Attempt to parse a frame from the buffered data.
-        // Once enough data has been buffered, the frame
-        // is returned
+        loop {
+            // Attempt to parse a frame from the buffered data.
+            // Once enough data has been buffered, the frame
+            // is returned
+            if let Some(frame) = self.parse_frame()? {
+                return Ok(Some(frame));
+            }
 
-        if let Some(frame) = self.parse_frame()? {
-            return Ok(Some(frame));
+            // A peer that never completes its frame, e.g. by sending a line
+            // without an end, must not grow the buffer forever.
+            if self.buffer.len() >= self.limits.max_buffered_bytes {
+                return Err(format!(
+                    "protocol error; buffered data exceeds the limit of {} bytes",
+                    self.limits.max_buffered_bytes
+                )
+                .into());
+            }
+
+            // There is not enough buffered data to read a frame,
+            // Attempt to read more data from the socket.
+            //
+            // On success, the number of bytes is returned.
+            // `0` indicates end of stream.
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                // The remote closed the connection. For this to be
+                // a clean shutdown, there should be no data in the
+                // read buffer. If there is, this means the
+                // peer closed the socket while sending a frame
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                } else {
+                    let err =
+                        io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer");
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+
+    /// Wait for the peer to close the connection.
+    ///
+    /// Used while the server has nothing to read, e.g. while a client is
+    /// blocked on a list. Data received in the meantime is kept for the next
+    /// `read_frame` call, within the same limit.
+    pub(crate) async fn closed(&mut self) -> Result<()> {
+        loop {
+            if self.buffer.len() >= self.limits.max_buffered_bytes {
+                return Err(format!(
+                    "protocol error; buffered data exceeds the limit of {} bytes",
+                    self.limits.max_buffered_bytes
+                )
+                .into());
+            }
+
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return Ok(());
+            }
         }
+    }
 
-        // There is not enough buffered data to read a frame,
-        // Attempt to read more data from the socket.
-        //
-        // On success, the number of bytes is returned.
-        // `0` indicates end of stream.
-        if 0 == self.stream.read_buf(&mut self.buffer).await? {
-            // The remote closed the connection. For this to be
-            // a clean shutdown, there should be no data in the
-            // read buffer. If there is, this means the
-            // peer closed the socket while sending a frame
-            if self.buffer.is_empty() {
-                return Ok(None);
-            } else {
-                return Err("connection reset by peer".into);
+    /// Try to parse a frame from the data already buffered.
+    ///
+    /// Data that does not start with a RESP type byte is an inline command,
+    /// like `SET key "some value"` typed over telnet. It is returned as the
+    /// same array of bulk strings a client library would have sent, blank
+    /// lines are skipped.
+    ///
+    /// Returns `None` without consuming anything if the buffer does not hold
+    /// a whole frame yet.
+    pub fn parse_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            match self.buffer.first() {
+                Some(&byte) if !frame::is_type_byte(byte) => match self.parse_inline()? {
+                    Some(args) if args.is_empty() => continue,
+                    Some(args) => {
+                        let frame = Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+                        return Ok(Some(frame));
+                    }
+                    None => return Ok(None),
+                },
+                _ => return self.parse_resp(),
             }
         }
     }
 
-    pub async fn parse_frame(&mut self) -> Result<Option<Frame>> {
+    /// Split the first line of the buffer into the arguments of an inline
+    /// command, with the quoting rules of `redis-cli`.
+    ///
+    /// Lines may end with `\r\n` or a bare `\n`.
+    fn parse_inline(&mut self) -> Result<Option<Vec<Bytes>>> {
+        let scanned = self.inline_scanned;
+        let newline = self.buffer[scanned..].iter().position(|&b| b == b'\n');
+
+        // Until its end is received, the line is as long as the buffer
+        let len = newline.map_or(self.buffer.len(), |pos| scanned + pos);
+
+        if len > self.limits.max_inline_len {
+            return Err(format!(
+                "protocol error; inline command exceeds the limit of {} bytes",
+                self.limits.max_inline_len
+            )
+            .into());
+        }
+
+        if newline.is_none() {
+            self.inline_scanned = self.buffer.len();
+            return Ok(None);
+        }
+
+        let line = self.buffer.split_to(len + 1);
+        self.inline_scanned = 0;
+
+        // The trailing `\r\n` is whitespace to `split_args`
+        let args = match split_args(&line[..]) {
+            Ok(args) => args,
+            Err(_) => return Err("protocol error; unbalanced quotes in inline command".into()),
+        };
+
+        if args.len() > self.limits.max_array_len {
+            return Err(format!(
+                "protocol error; element count exceeds the limit of {}",
+                self.limits.max_array_len
+            )
+            .into());
+        }
+
+        Ok(Some(args))
+    }
+
+    /// Try to parse a RESP encoded frame from the data already buffered.
+    fn parse_resp(&mut self) -> Result<Option<Frame>> {
         // Create the `T: Buf` type
         let mut buf = Cursor::new(&self.buffer[..]);
 
-        // Check wheter a full frame is available
-        match Frame::check(&mut buf) {
-             Ok(_) => {
-                 // Get the byte length of the frame
-                 let len = buf.position as usize;
+        // Check whether a full frame is available
+        match Frame::check(&mut buf, &self.limits) {
+            Ok(_) => {
+                // Get the byte length of the frame
+                let len = buf.position() as usize;
 
-                 // Reset the internal cursor for 
-                 // the call to parse
-                 buf.set_position(0);
+                // Split the frame off the buffer. Freezing does not copy
+                // anything, and the bulk strings parsed out of the frame
+                // point into the same memory.
+                let mut src = self.buffer.split_to(len).freeze();
 
-                 // Parse the frame
-                 let frame = Frame::parse(&mut buf)?;
+                // Parse the frame
+                let frame = Frame::parse(&mut src)?;
 
-                 // Discard the frame from the buffer
-                 self.buffer.advnace.len();
-
-                 // Return the frame to the caller
-                 Ok(Some(frame))
-             }
-             // Not enough data has been buffered
-             Err(Incomplete) => Ok(None),
+                // Return the frame to the caller
+                Ok(Some(frame))
+            }
+            // Not enough data has been buffered
+            Err(frame::Error::Incomplete) => Ok(None),
 
-             // An error occured
-             Err(e) => Err(e.into()),
+            // An error occurred
+            Err(e) => Err(e.into()),
         }
     }
 
+    /// Write a single `Frame` to the underlying stream and flush it.
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.buffer_frame(frame).await?;
+
+        // Everything was written to the `BufWriter`, push it to the socket
+        self.flush().await
+    }
+
+    /// Write a single `Frame` to the write buffer without flushing it.
+    ///
+    /// The frame is only guaranteed to reach the socket once `flush` is
+    /// called, although a full buffer is written out early. Buffering several
+    /// frames and flushing once sends them with as few `write` calls as
+    /// possible.
+    pub async fn buffer_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.write_value(frame).await
+    }
+
+    /// Push every buffered frame to the socket.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush().await
+    }
+
+    /// Encode a single frame into the write buffer without flushing.
+    ///
+    /// Aggregates are written as their element count followed by every
+    /// element. Elements are encoded by calling `write_value` again, so
+    /// aggregates may be nested and may contain any other variant, including
+    /// `Null`.
+    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
+        let resp3 = self.protocol == Protocol::Resp3;
+
         match frame {
             Frame::Simple(val) => {
                 self.stream.write_u8(b'+').await?;
@@ -90,23 +330,129 @@ impl Connection {
             }
             Frame::Integer(val) => {
                 self.stream.write_u8(b':').await?;
-                self.stream.write_decimal(*val).await?;
+                self.write_decimal(*val).await?;
+            }
+            Frame::Null if resp3 => {
+                self.stream.write_all(b"_\r\n").await?;
             }
             Frame::Null => {
                 self.stream.write_all(b"$-1\r\n").await?;
             }
-            Frame::Bulk(val) => {
-                let len = val.len();
-
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
+            Frame::Bulk(val) => self.write_bulk(val).await?,
+            Frame::Array(val) => self.write_aggregate(b'*', val).await?,
+            Frame::Double(val) if resp3 => {
+                self.stream.write_u8(b',').await?;
+                self.stream
+                    .write_all(format_double(*val).as_bytes())
+                    .await?;
+                self.stream.write_all(b"\r\n").await?;
+            }
+            Frame::Double(val) => self.write_bulk(format_double(*val).as_bytes()).await?,
+            Frame::Boolean(val) if resp3 => {
+                let line: &[u8] = if *val { b"#t\r\n" } else { b"#f\r\n" };
+                self.stream.write_all(line).await?;
+            }
+            Frame::Boolean(val) => {
+                self.stream.write_u8(b':').await?;
+                self.write_decimal(*val as i64).await?;
+            }
+            Frame::BigNumber(val) if resp3 => {
+                self.stream.write_u8(b'(').await?;
+                self.stream.write_all(val.as_bytes()).await?;
+                self.stream.write_all(b"\r\n").await?;
+            }
+            Frame::BigNumber(val) => self.write_bulk(val.as_bytes()).await?,
+            Frame::Verbatim(format, val) if resp3 => {
+                // The length covers the format and the `:` separator
+                self.stream.write_u8(b'=').await?;
+                self.write_decimal((format.len() + 1 + val.len()) as i64)
+                    .await?;
+                self.stream.write_all(format.as_bytes()).await?;
+                self.stream.write_u8(b':').await?;
                 self.stream.write_all(val).await?;
                 self.stream.write_all(b"\r\n").await?;
             }
-            Frame::Array(_val) => unimplemented!(),
+            Frame::Verbatim(_, val) => self.write_bulk(val).await?,
+            Frame::Map(pairs) => {
+                if resp3 {
+                    self.stream.write_u8(b'%').await?;
+                    self.write_decimal(pairs.len() as i64).await?;
+                } else {
+                    self.stream.write_u8(b'*').await?;
+                    self.write_decimal(2 * pairs.len() as i64).await?;
+                }
+
+                self.write_pairs(pairs).await?;
+            }
+            Frame::Set(val) => {
+                let prefix = if resp3 { b'~' } else { b'*' };
+                self.write_aggregate(prefix, val).await?;
+            }
+            Frame::Push(val) => {
+                let prefix = if resp3 { b'>' } else { b'*' };
+                self.write_aggregate(prefix, val).await?;
+            }
+            Frame::Attribute(pairs, val) => {
+                if resp3 {
+                    self.stream.write_u8(b'|').await?;
+                    self.write_decimal(pairs.len() as i64).await?;
+                    self.write_pairs(pairs).await?;
+                }
+
+                Box::pin(self.write_value(val)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a bulk string.
+    async fn write_bulk(&mut self, val: &[u8]) -> io::Result<()> {
+        self.stream.write_u8(b'$').await?;
+        self.write_decimal(val.len() as i64).await?;
+        self.stream.write_all(val).await?;
+        self.stream.write_all(b"\r\n").await?;
+
+        Ok(())
+    }
+
+    /// Write the element count of an aggregate frame, then every element.
+    async fn write_aggregate(&mut self, prefix: u8, entries: &[Frame]) -> io::Result<()> {
+        self.stream.write_u8(prefix).await?;
+        self.write_decimal(entries.len() as i64).await?;
+
+        for entry in entries {
+            // Recursive `async fn` calls must be boxed so the
+            // future has a known size
+            Box::pin(self.write_value(entry)).await?;
         }
-        
-        self.stream.flush().await;
+
+        Ok(())
+    }
+
+    /// Write every key followed by its value.
+    async fn write_pairs(&mut self, pairs: &[(Frame, Frame)]) -> io::Result<()> {
+        for (key, value) in pairs {
+            Box::pin(self.write_value(key)).await?;
+            Box::pin(self.write_value(value)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a decimal number followed by the `\r\n` line terminator.
+    async fn write_decimal(&mut self, val: i64) -> io::Result<()> {
+        use std::io::Write;
+
+        // Format the value into a stack buffer, an i64 has at most 20
+        // characters including the sign
+        let mut buf = [0u8; 20];
+        let mut buf = Cursor::new(&mut buf[..]);
+        write!(&mut buf, "{}", val)?;
+
+        let pos = buf.position() as usize;
+        self.stream.write_all(&buf.get_ref()[..pos]).await?;
+        self.stream.write_all(b"\r\n").await?;
 
         Ok(())
     }
@@ -0,0 +1,1137 @@
+use crate::sorted_set::SortedSet;
+
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::time::SystemTime;
+use std::{fmt, mem};
+use tokio::sync::{broadcast, oneshot, Notify};
+use tokio::time::{self, Duration, Instant};
+
+/// Default number of shards used when the server is not configured otherwise.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Key-value store shared by every connection.
+///
+/// Instead of a single `Mutex<HashMap<_, _>>`, the keyspace is split across
+/// `N` independently locked shards. The shard holding a key is picked by
+/// hashing the key, so connections working on different keys rarely contend
+/// on the same lock.
+///
+/// The pub/sub channels live next to the keyspace. They are not part of it:
+/// a channel and a key with the same name are unrelated.
+///
+/// A key holds a string, a list, a hash, a set or a sorted set, see
+/// [`Value`]. Commands expecting one type fail with [`WrongType`] on a key
+/// holding another.
+///
+/// Clients may block until an element is pushed onto an empty list. They are
+/// served in the order they started waiting, the element is handed to them
+/// directly by the push.
+///
+/// Keys may be given a time to live. Expired keys are never returned, and a
+/// background task removes them once their deadline has passed so the store
+/// does not keep growing with dead entries.
+///
+/// Commands run while holding [`lock_shared`](Db::lock_shared), so that a
+/// transaction holding [`lock_exclusive`](Db::lock_exclusive) runs on its
+/// own. Clients may [`watch`](Db::watch) keys to find out whether they
+/// changed before their transaction runs.
+///
+/// `Db` is cheap to clone: cloning only increments the reference count of the
+/// shared state.
+#[derive(Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    shards: Box<[Mutex<Shard>]>,
+
+    // Held for reading by every command and for writing by transactions, see
+    // `Db::lock_exclusive`. Always taken before any shard.
+    gate: RwLock<()>,
+
+    // Used to pick the shard for a key. Each `Db` gets its own random keys so
+    // clients cannot craft keys that all land in the same shard.
+    hasher: RandomState,
+
+    // Pub/sub channels, one broadcast sender per channel that has had at
+    // least one subscriber. Publishing is far less frequent than GET/SET, so
+    // a single lock is enough here.
+    pub_sub: Mutex<HashMap<String, broadcast::Sender<Bytes>>>,
+
+    // Number of modifications made to the keyspace, see `Db::changes`.
+    changes: AtomicU64,
+
+    // Identifies the clients blocked on lists, see `Db::pop_or_block`.
+    next_waiter_id: AtomicU64,
+
+    // Wakes the purge task when a key is given a deadline earlier than the
+    // one the task is sleeping until, and when the `Db` is dropped.
+    purge_task: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<String, Entry>,
+
+    // Keys with a time to live, ordered by deadline. The purge task pops
+    // entries off the front until it reaches one that has not expired yet.
+    //
+    // Two keys may share a deadline, so the key is part of the set entry to
+    // keep them distinct.
+    expirations: BTreeSet<(Instant, String)>,
+
+    // Clients blocked until an element is pushed onto a list, by key, in the
+    // order they started waiting.
+    blocked: HashMap<String, VecDeque<Waiter>>,
+
+    // Flags of the watches on each key, set when the key changes.
+    watched: HashMap<String, Vec<Arc<AtomicBool>>>,
+}
+
+struct Entry {
+    data: Value,
+
+    // When the entry expires and should be removed. `None` means the entry
+    // lives until it is overwritten or deleted.
+    expires_at: Option<Instant>,
+}
+
+/// Value stored at a key.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(Bytes),
+
+    /// Never empty, a list is removed along with its last element.
+    List(VecDeque<Bytes>),
+
+    /// Field-value pairs, never empty either.
+    Hash(HashMap<Bytes, Bytes>),
+
+    /// Never empty.
+    Set(HashSet<Bytes>),
+
+    /// Never empty.
+    SortedSet(SortedSet),
+}
+
+/// End of a list, where elements are pushed or popped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum End {
+    Left,
+    Right,
+}
+
+/// Error returned when a command operates on a key holding the wrong kind of
+/// value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongType;
+
+/// Outcome of [`Db::pop_or_block`].
+pub(crate) enum Pop {
+    /// An element was popped right away, from the given key.
+    Ready(String, Bytes),
+
+    /// Every list was empty, the element will be handed over once pushed.
+    Blocked(Blocked),
+}
+
+/// A client blocked until an element is pushed onto one of its lists.
+///
+/// Dropping it stops waiting.
+pub(crate) struct Blocked {
+    db: Db,
+
+    id: u64,
+
+    /// Keys the client is queued on
+    keys: Vec<String>,
+
+    rx: oneshot::Receiver<(String, Bytes)>,
+}
+
+/// Entry in the queue of clients blocked on a key.
+struct Waiter {
+    id: u64,
+
+    /// End the client pops from
+    end: End,
+
+    /// Shared by the entries of a client blocked on several keys. Whoever
+    /// takes the sender hands the client its element, which happens once.
+    slot: Arc<Mutex<Option<Handover>>>,
+}
+
+/// Hands a popped element, along with its key, to a blocked client.
+type Handover = oneshot::Sender<(String, Bytes)>;
+
+/// Keys watched by a client, created by [`Db::watch`].
+///
+/// The watch becomes dirty once any of its keys is changed, or expires.
+/// Dropping it stops watching.
+pub(crate) struct WatchedKeys {
+    db: Db,
+
+    keys: Vec<String>,
+
+    /// Shared with the shards holding the keys
+    dirty: Arc<AtomicBool>,
+}
+
+/// What a write does to the time to live of its key, see
+/// [`Db::update_expiry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Expiry {
+    /// Leave the time to live as it is.
+    Keep,
+
+    /// Remove the time to live.
+    Persist,
+
+    /// Expire the key after the given duration.
+    After(Duration),
+}
+
+/// Remaining time to live of a key, as reported by [`Db::ttl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ttl {
+    /// The key does not exist.
+    Missing,
+
+    /// The key exists and does not expire.
+    Persistent,
+
+    /// The key exists and expires after the given duration.
+    Expires(Duration),
+}
+
+impl Db {
+    /// Create a new, empty store split across `num_shards` shards.
+    ///
+    /// This spawns the task removing expired keys, so it must be called from
+    /// within a Tokio runtime. The task exits once every handle to the `Db`
+    /// has been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_shards` is zero.
+    pub fn new(num_shards: usize) -> Db {
+        assert!(num_shards > 0, "a Db needs at least one shard");
+
+        let shards = (0..num_shards)
+            .map(|_| Mutex::new(Shard::default()))
+            .collect();
+
+        let shared = Arc::new(Shared {
+            shards,
+            gate: RwLock::new(()),
+            hasher: RandomState::new(),
+            pub_sub: Mutex::new(HashMap::new()),
+            changes: AtomicU64::new(0),
+            next_waiter_id: AtomicU64::new(0),
+            purge_task: Arc::new(Notify::new()),
+        });
+
+        // The task only holds a weak reference, so it does not keep the
+        // shards alive after the last `Db` is gone.
+        tokio::spawn(purge_expired_tasks(
+            Arc::downgrade(&shared),
+            shared.purge_task.clone(),
+        ));
+
+        Db { shared }
+    }
+
+    /// Number of shards the keyspace is split across.
+    pub fn num_shards(&self) -> usize {
+        self.shared.shards.len()
+    }
+
+    /// Number of modifications made to the keyspace since the `Db` was
+    /// created.
+    ///
+    /// Only ever grows. Persistence compares it between two points in time
+    /// to decide whether the keyspace needs saving. Keys removed because
+    /// they expired are not counted.
+    pub fn changes(&self) -> u64 {
+        self.shared.changes.load(Ordering::Relaxed)
+    }
+
+    /// Get the string value associated with a key.
+    ///
+    /// Returns `None` if there is no value associated with the key, if the
+    /// key has expired, or if the key does not hold a string.
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        // The lock is only held for the duration of the lookup. `Bytes` is
+        // reference counted, so cloning the value is cheap.
+        self.read(key, |value| match value {
+            Some(Value::String(data)) => Some(data.clone()),
+            _ => None,
+        })
+    }
+
+    /// Run `f` on the value of a key, `None` if the key does not exist.
+    ///
+    /// The shard holding the key is locked while `f` runs, so it should not
+    /// take long.
+    pub(crate) fn read<T>(&self, key: &str, f: impl FnOnce(Option<&Value>) -> T) -> T {
+        let shard = self.shard(key).lock().unwrap();
+        f(shard.get(key, Instant::now()).map(|entry| &entry.data))
+    }
+
+    /// Like [`read`](Db::read), for several keys at once.
+    ///
+    /// Every shard holding one of the keys is locked while `f` runs, so `f`
+    /// sees the keys as they were at a single point in time.
+    pub(crate) fn read_many<T>(
+        &self,
+        keys: &[String],
+        f: impl FnOnce(&[Option<&Value>]) -> T,
+    ) -> T {
+        let now = Instant::now();
+        let shards = self.lock_shards(keys);
+
+        let values: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let shard = &shards[&self.shard_index(key)];
+                shard.get(key, now).map(|entry| &entry.data)
+            })
+            .collect();
+
+        f(&values)
+    }
+
+    /// Run `f` on the value of a key, which it may create, modify or remove
+    /// by setting it to `None`. Along with its result, `f` returns whether it
+    /// modified the value.
+    ///
+    /// The key keeps its time to live when modified. An emptied list or hash
+    /// is removed. A modification counts as one change to the keyspace, and
+    /// is seen by the clients watching the key.
+    ///
+    /// The shard holding the key is locked while `f` runs, so it should not
+    /// take long.
+    pub(crate) fn update<T, E>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Option<Value>) -> Result<(T, bool), E>,
+    ) -> Result<T, E> {
+        self.update_expiry(key, |value| {
+            f(value).map(|(ret, modified)| (ret, modified.then_some(Expiry::Keep)))
+        })
+    }
+
+    /// Like [`update`](Db::update), `f` also deciding what happens to the
+    /// time to live of the key if it is left with a value. `None` if the key
+    /// was not modified at all.
+    pub(crate) fn update_expiry<T, E>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Option<Value>) -> Result<(T, Option<Expiry>), E>,
+    ) -> Result<T, E> {
+        let now = Instant::now();
+
+        let (ret, notify) = {
+            let mut shard = self.shard(key).lock().unwrap();
+
+            let (ret, expiry) = match shard.update(key, now, f)? {
+                (ret, Some(expiry)) => (ret, expiry),
+                // Nothing changed, the key is neither touched nor counted
+                (ret, None) => return Ok(ret),
+            };
+
+            shard.touch(key);
+
+            let notify = match expiry {
+                Expiry::Keep => false,
+                Expiry::Persist => shard.set_deadline(key, None),
+                Expiry::After(duration) => shard.set_deadline(key, Some(now + duration)),
+            };
+
+            (ret, notify)
+        };
+
+        self.changed();
+
+        if notify {
+            self.shared.purge_task.notify_one();
+        }
+
+        Ok(ret)
+    }
+
+    /// Like [`update`](Db::update), then hand elements of the list left at
+    /// `key` to the clients blocked on it, in the order they started waiting.
+    ///
+    /// Returns the end each served client popped from, in order, so that the
+    /// pops can be recorded.
+    pub(crate) fn push<T, E>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Option<Value>) -> Result<(T, bool), E>,
+    ) -> Result<(T, Vec<End>), E> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let (ret, modified) = shard.update(key, Instant::now(), f)?;
+
+        if !modified {
+            return Ok((ret, vec![]));
+        }
+
+        shard.touch(key);
+
+        self.changed();
+
+        Ok((ret, shard.serve_blocked(key)))
+    }
+
+    /// Atomically pop an element from the list at `src` and push it onto the
+    /// list at `dst`, serving clients blocked on `dst`.
+    ///
+    /// Returns the element, `None` if `src` does not exist, along with the
+    /// end each served client popped from.
+    pub(crate) fn pop_push(
+        &self,
+        src: &str,
+        dst: &str,
+        from: End,
+        to: End,
+    ) -> Result<(Option<Bytes>, Vec<End>), WrongType> {
+        let now = Instant::now();
+        let src_shard = self.shard(src);
+        let dst_shard = self.shard(dst);
+
+        let mut src_guard = src_shard.lock().unwrap();
+
+        // Lock both shards, always in the same order so that two concurrent
+        // calls in opposite directions cannot deadlock.
+        let mut dst_guard = if std::ptr::eq(src_shard, dst_shard) {
+            None
+        } else if (src_shard as *const _) < (dst_shard as *const _) {
+            Some(dst_shard.lock().unwrap())
+        } else {
+            drop(src_guard);
+            let dst_guard = dst_shard.lock().unwrap();
+            src_guard = src_shard.lock().unwrap();
+            Some(dst_guard)
+        };
+
+        // Check both types before changing anything
+        let src_value = src_guard.get(src, now).map(|entry| &entry.data);
+        let dst_value = match &dst_guard {
+            Some(dst_guard) => dst_guard.get(dst, now),
+            None => src_guard.get(dst, now),
+        };
+
+        let src_ok = matches!(src_value, None | Some(Value::List(_)));
+        let dst_ok = matches!(
+            dst_value.map(|entry| &entry.data),
+            None | Some(Value::List(_))
+        );
+
+        if !src_ok || !dst_ok {
+            return Err(WrongType);
+        }
+
+        let popped = src_guard.update(src, now, |value| match value {
+            Some(Value::List(list)) => list_pop(list, from),
+            _ => None,
+        });
+
+        let element = match popped {
+            Some(element) => element,
+            None => return Ok((None, vec![])),
+        };
+
+        src_guard.touch(src);
+
+        let dst_guard = dst_guard.as_deref_mut().unwrap_or(&mut src_guard);
+
+        dst_guard.update(dst, now, |value| {
+            list_push(value, to, element.clone());
+        });
+        dst_guard.touch(dst);
+        let served = dst_guard.serve_blocked(dst);
+
+        self.changed();
+
+        Ok((Some(element), served))
+    }
+
+    /// Pop an element from the first non-empty list among `keys`, or queue
+    /// the caller on every one of them if they are all empty.
+    ///
+    /// Keys are looked at in order, a missing key counts as an empty list.
+    pub(crate) fn pop_or_block(&self, keys: &[String], end: End) -> Result<Pop, WrongType> {
+        let (tx, rx) = oneshot::channel();
+        let slot = Arc::new(Mutex::new(Some(tx)));
+
+        let mut blocked = Blocked {
+            db: self.clone(),
+            id: self.shared.next_waiter_id.fetch_add(1, Ordering::Relaxed),
+            keys: vec![],
+            rx,
+        };
+
+        for key in keys {
+            let mut shard = self.shard(key).lock().unwrap();
+
+            let is_empty = match shard.get(key, Instant::now()).map(|entry| &entry.data) {
+                None => true,
+                Some(Value::List(_)) => false,
+                // Keep the element if one was already handed over, otherwise
+                // make sure none can be from now on.
+                Some(_) => match slot.lock().unwrap().take() {
+                    Some(_) => return Err(WrongType),
+                    None => break,
+                },
+            };
+
+            if !is_empty {
+                // A push to one of the keys already queued on may have handed
+                // the caller an element in the meantime.
+                if slot.lock().unwrap().take().is_none() {
+                    break;
+                }
+
+                let element = shard.update(key, Instant::now(), |value| match value {
+                    Some(Value::List(list)) => list_pop(list, end),
+                    _ => None,
+                });
+                shard.touch(key);
+
+                self.changed();
+
+                return Ok(Pop::Ready(key.clone(), element.unwrap()));
+            }
+
+            shard
+                .blocked
+                .entry(key.clone())
+                .or_default()
+                .push_back(Waiter {
+                    id: blocked.id,
+                    end,
+                    slot: slot.clone(),
+                });
+
+            blocked.keys.push(key.clone());
+        }
+
+        Ok(Pop::Blocked(blocked))
+    }
+
+    /// Set the value associated with a key, replacing any previous value.
+    ///
+    /// If `expire` is `Some`, the key is removed once the duration elapses.
+    /// Otherwise the key does not expire, even if the previous value did.
+    pub fn set(&self, key: String, value: Bytes, expire: Option<Duration>) {
+        self.insert(key, Value::String(value), expire)
+    }
+
+    /// Store `value` at `key` whatever the key held before, like
+    /// [`set`](Db::set).
+    pub(crate) fn insert(&self, key: String, value: Value, expire: Option<Duration>) {
+        let expires_at = expire.map(|duration| Instant::now() + duration);
+
+        let notify = {
+            let mut shard = self.shard(&key).lock().unwrap();
+
+            let prev = shard.entries.insert(
+                key.clone(),
+                Entry {
+                    data: value,
+                    expires_at: None,
+                },
+            );
+
+            // Drop the previous deadline from the index before tracking the
+            // new one.
+            if let Some(when) = prev.and_then(|entry| entry.expires_at) {
+                shard.expirations.remove(&(when, key.clone()));
+            }
+
+            shard.touch(&key);
+            shard.set_deadline(&key, expires_at)
+        };
+
+        self.changed();
+
+        if notify {
+            self.shared.purge_task.notify_one();
+        }
+    }
+
+    /// Set several keys to string values at once, like [`set`](Db::set)
+    /// without a time to live.
+    ///
+    /// Every shard holding one of the keys is locked while the keys are set,
+    /// so no client sees only some of them set. With `only_new`, nothing is
+    /// set if any of the keys exists. Returns `true` if the keys were set.
+    pub(crate) fn set_many(&self, pairs: Vec<(String, Bytes)>, only_new: bool) -> bool {
+        let now = Instant::now();
+        let keys: Vec<_> = pairs.iter().map(|(key, _)| key.clone()).collect();
+        let mut shards = self.lock_shards(&keys);
+
+        if only_new
+            && keys
+                .iter()
+                .any(|key| shards[&self.shard_index(key)].get(key, now).is_some())
+        {
+            return false;
+        }
+
+        for (key, value) in pairs {
+            let shard = shards.get_mut(&self.shard_index(&key)).unwrap();
+
+            shard.remove(&key);
+            shard.touch(&key);
+            shard.entries.insert(
+                key,
+                Entry {
+                    data: Value::String(value),
+                    expires_at: None,
+                },
+            );
+        }
+
+        self.changed();
+
+        true
+    }
+
+    /// Set a time to live on an existing key.
+    ///
+    /// A zero duration removes the key immediately. Returns `false` if the
+    /// key does not exist.
+    pub fn expire(&self, key: &str, duration: Duration) -> bool {
+        let now = Instant::now();
+
+        let notify = {
+            let mut shard = self.shard(key).lock().unwrap();
+
+            if shard.get(key, now).is_none() {
+                return false;
+            }
+
+            shard.touch(key);
+            self.changed();
+
+            if duration.is_zero() {
+                shard.remove(key);
+                return true;
+            }
+
+            shard.set_deadline(key, Some(now + duration))
+        };
+
+        if notify {
+            self.shared.purge_task.notify_one();
+        }
+
+        true
+    }
+
+    /// Remove the time to live of a key.
+    ///
+    /// Returns `true` only if the key existed and had a time to live.
+    pub fn persist(&self, key: &str) -> bool {
+        let mut shard = self.shard(key).lock().unwrap();
+
+        match shard.get(key, Instant::now()) {
+            Some(entry) if entry.expires_at.is_some() => {
+                shard.set_deadline(key, None);
+                shard.touch(key);
+                self.changed();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the remaining time to live of a key.
+    pub fn ttl(&self, key: &str) -> Ttl {
+        let now = Instant::now();
+        let shard = self.shard(key).lock().unwrap();
+
+        match shard.get(key, now).map(|entry| entry.expires_at) {
+            None => Ttl::Missing,
+            Some(None) => Ttl::Persistent,
+            Some(Some(when)) => Ttl::Expires(when - now),
+        }
+    }
+
+    /// Lock held while running a command, so that it does not run in the
+    /// middle of a transaction.
+    ///
+    /// Must be released before waiting on anything but the store.
+    pub(crate) fn lock_shared(&self) -> RwLockReadGuard<'_, ()> {
+        self.shared.gate.read().unwrap()
+    }
+
+    /// Lock held while running a transaction, which waits for the commands
+    /// running on other connections and keeps new ones from starting.
+    pub(crate) fn lock_exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.shared.gate.write().unwrap()
+    }
+
+    /// Start watching keys on behalf of a client, see [`WatchedKeys`].
+    pub(crate) fn watch(&self) -> WatchedKeys {
+        WatchedKeys {
+            db: self.clone(),
+            keys: vec![],
+            dirty: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Copy every live key, along with the wall-clock time it expires at.
+    ///
+    /// Strings are reference counted, so this does not copy their contents,
+    /// but the elements of a list are cloned one by one. Shards are locked
+    /// one at a time, callers hold the gate exclusively to get a consistent
+    /// copy, see [`Db::lock_exclusive`].
+    pub(crate) fn snapshot(&self) -> Vec<(String, Value, Option<SystemTime>)> {
+        let now = Instant::now();
+        let wall_now = SystemTime::now();
+        let mut entries = vec![];
+
+        for shard in self.shared.shards.iter() {
+            let shard = shard.lock().unwrap();
+
+            for (key, entry) in &shard.entries {
+                let expires_at = match entry.expires_at {
+                    Some(when) if when <= now => continue,
+                    Some(when) => Some(wall_now + (when - now)),
+                    None => None,
+                };
+
+                entries.push((key.clone(), entry.data.clone(), expires_at));
+            }
+        }
+
+        entries
+    }
+
+    /// Returns a `Receiver` for the requested channel.
+    ///
+    /// The returned `Receiver` is used to receive values broadcast by `PUBLISH`
+    /// commands.
+    pub fn subscribe(&self, channel: String) -> broadcast::Receiver<Bytes> {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+
+        // If there is no entry for the requested channel, then create a new
+        // broadcast channel and associate it with the key. If one already
+        // exists, return an associated receiver.
+        pub_sub
+            .entry(channel)
+            .or_insert_with(|| {
+                // The channel is created with a capacity of `1024` messages. A
+                // subscriber that falls further behind than that misses the
+                // oldest messages instead of slowing down the publisher.
+                let (tx, _) = broadcast::channel(1024);
+                tx
+            })
+            .subscribe()
+    }
+
+    /// Publish a message to the channel. Returns the number of subscribers
+    /// listening on the channel.
+    pub fn publish(&self, channel: &str, value: Bytes) -> usize {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+
+        let tx = match pub_sub.get(channel) {
+            Some(tx) => tx,
+            None => return 0,
+        };
+
+        match tx.send(value) {
+            Ok(num_subscribers) => num_subscribers,
+            // Every subscriber is gone, forget about the channel so
+            // abandoned channel names do not accumulate.
+            Err(_) => {
+                pub_sub.remove(channel);
+                0
+            }
+        }
+    }
+
+    /// Count one modification of the keyspace.
+    fn changed(&self) {
+        self.shared.changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Find the shard responsible for `key`.
+    fn shard(&self, key: &str) -> &Mutex<Shard> {
+        &self.shared.shards[self.shard_index(key)]
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        let hash = self.shared.hasher.hash_one(key);
+        hash as usize % self.shared.shards.len()
+    }
+
+    /// Lock every shard holding one of `keys`, by index.
+    ///
+    /// Shards are always locked in the same order, so that two callers
+    /// locking overlapping shards cannot deadlock.
+    fn lock_shards(&self, keys: &[String]) -> BTreeMap<usize, MutexGuard<'_, Shard>> {
+        let indices: BTreeSet<_> = keys.iter().map(|key| self.shard_index(key)).collect();
+
+        indices
+            .into_iter()
+            .map(|index| (index, self.shared.shards[index].lock().unwrap()))
+            .collect()
+    }
+}
+
+impl Default for Db {
+    fn default() -> Db {
+        Db::new(DEFAULT_SHARDS)
+    }
+}
+
+impl Value {
+    /// Returns `true` for a collection without any element, which must not
+    /// be stored.
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::SortedSet(set) => set.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        "WRONGTYPE Operation against a key holding the wrong kind of value".fmt(fmt)
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+impl Blocked {
+    /// Wait for an element to be handed over. Returns the key it was pushed
+    /// onto along with the element.
+    ///
+    /// Cancel safe: if the future is dropped, the element is picked up by the
+    /// next call, or by [`cancel`](Blocked::cancel).
+    pub(crate) async fn recv(&mut self) -> (String, Bytes) {
+        // The senders are only dropped once the waiter is removed from every
+        // queue, which only happens when `self` is dropped.
+        (&mut self.rx).await.expect("blocked client was dropped")
+    }
+
+    /// Stop waiting. Returns the element handed over just before, if any.
+    pub(crate) fn cancel(mut self) -> Option<(String, Bytes)> {
+        self.rx.close();
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Drop for Blocked {
+    fn drop(&mut self) {
+        for key in &self.keys {
+            let mut shard = self.db.shard(key).lock().unwrap();
+
+            if let Some(queue) = shard.blocked.get_mut(key) {
+                queue.retain(|waiter| waiter.id != self.id);
+
+                if queue.is_empty() {
+                    shard.blocked.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl WatchedKeys {
+    /// Watch one more key.
+    pub(crate) fn add(&mut self, key: String) {
+        if self.keys.contains(&key) {
+            return;
+        }
+
+        let mut shard = self.db.shard(&key).lock().unwrap();
+        shard
+            .watched
+            .entry(key.clone())
+            .or_default()
+            .push(self.dirty.clone());
+
+        self.keys.push(key);
+    }
+
+    /// Returns `true` if a watched key changed since it was added.
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for WatchedKeys {
+    fn drop(&mut self) {
+        for key in &self.keys {
+            let mut shard = self.db.shard(key).lock().unwrap();
+
+            if let Some(flags) = shard.watched.get_mut(key) {
+                flags.retain(|flag| !Arc::ptr_eq(flag, &self.dirty));
+
+                if flags.is_empty() {
+                    shard.watched.remove(key);
+                }
+            }
+        }
+    }
+}
+
+impl Shared {
+    /// Remove every expired key from every shard.
+    ///
+    /// Returns the earliest deadline among the remaining keys, which is when
+    /// the purge task needs to run next.
+    fn purge_expired_keys(&self) -> Option<Instant> {
+        let now = Instant::now();
+
+        self.shards
+            .iter()
+            .filter_map(|shard| shard.lock().unwrap().purge_expired_keys(now))
+            .min()
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Let the purge task notice that the `Db` is gone and exit
+        self.purge_task.notify_one();
+    }
+}
+
+impl Shard {
+    /// Run `f` on the value at `key`, see `Db::update`.
+    fn update<T>(&mut self, key: &str, now: Instant, f: impl FnOnce(&mut Option<Value>) -> T) -> T {
+        // An expired entry the purge task has not removed yet is removed
+        // first, `f` must see it as missing.
+        if self.entries.contains_key(key) && self.get(key, now).is_none() {
+            self.remove(key);
+        }
+
+        // The value is moved out while `f` runs, leaving a placeholder that
+        // does not allocate.
+        let mut slot = self
+            .entries
+            .get_mut(key)
+            .map(|entry| mem::replace(&mut entry.data, Value::String(Bytes::new())));
+        let existed = slot.is_some();
+
+        let ret = f(&mut slot);
+
+        if slot.as_ref().is_some_and(Value::is_empty) {
+            slot = None;
+        }
+
+        match slot {
+            Some(value) if existed => self.entries.get_mut(key).unwrap().data = value,
+            Some(value) => {
+                let entry = Entry {
+                    data: value,
+                    expires_at: None,
+                };
+                self.entries.insert(key.to_string(), entry);
+            }
+            None if existed => {
+                self.remove(key);
+            }
+            None => {}
+        }
+
+        ret
+    }
+
+    /// Hand elements of the list at `key` to the clients blocked on it, see
+    /// `Db::push`.
+    fn serve_blocked(&mut self, key: &str) -> Vec<End> {
+        let mut served = vec![];
+
+        let queue = match self.blocked.get_mut(key) {
+            Some(queue) => queue,
+            None => return served,
+        };
+
+        if let Some(Entry {
+            data: Value::List(list),
+            ..
+        }) = self.entries.get_mut(key)
+        {
+            while !list.is_empty() {
+                let waiter = match queue.pop_front() {
+                    Some(waiter) => waiter,
+                    None => break,
+                };
+
+                // Already served through another key
+                let tx = match waiter.slot.lock().unwrap().take() {
+                    Some(tx) => tx,
+                    None => continue,
+                };
+
+                let element = list_pop(list, waiter.end).unwrap();
+
+                match tx.send((key.to_string(), element)) {
+                    Ok(()) => served.push(waiter.end),
+                    // The client stopped waiting, put the element back
+                    Err((_, element)) => match waiter.end {
+                        End::Left => list.push_front(element),
+                        End::Right => list.push_back(element),
+                    },
+                }
+            }
+        }
+
+        if queue.is_empty() {
+            self.blocked.remove(key);
+        }
+
+        // Every element may have been handed over
+        if matches!(self.entries.get(key), Some(Entry { data: Value::List(list), .. }) if list.is_empty())
+        {
+            self.remove(key);
+        }
+
+        served
+    }
+
+    /// Mark the watches on `key` dirty, after it changed.
+    fn touch(&mut self, key: &str) {
+        if let Some(flags) = self.watched.get(key) {
+            for flag in flags {
+                flag.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Look up an entry, treating expired entries as missing.
+    ///
+    /// The purge task may not have removed an entry yet even though its
+    /// deadline has passed.
+    fn get(&self, key: &str, now: Instant) -> Option<&Entry> {
+        self.entries
+            .get(key)
+            .filter(|entry| entry.expires_at.is_none_or(|when| when > now))
+    }
+
+    /// Remove an entry along with its deadline.
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+
+        if let Some(when) = entry.expires_at {
+            self.expirations.remove(&(when, key.to_string()));
+        }
+
+        Some(entry)
+    }
+
+    /// Replace the deadline of an existing entry, keeping `expirations` in
+    /// sync.
+    ///
+    /// Returns `true` if the new deadline is earlier than every other
+    /// deadline in this shard, in which case the purge task must be woken up
+    /// to reschedule itself.
+    fn set_deadline(&mut self, key: &str, when: Option<Instant>) -> bool {
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        if let Some(prev) = entry.expires_at.take() {
+            self.expirations.remove(&(prev, key.to_string()));
+        }
+
+        let when = match when {
+            Some(when) => when,
+            None => return false,
+        };
+
+        entry.expires_at = Some(when);
+
+        let notify = self
+            .expirations
+            .first()
+            .is_none_or(|(next, _)| when < *next);
+
+        self.expirations.insert((when, key.to_string()));
+
+        notify
+    }
+
+    /// Remove the entries whose deadline is at or before `now`.
+    ///
+    /// Returns the deadline of the next entry to expire, if any.
+    fn purge_expired_keys(&mut self, now: Instant) -> Option<Instant> {
+        while let Some((when, _)) = self.expirations.first() {
+            if *when > now {
+                // Done purging, `when` is the next deadline
+                return Some(*when);
+            }
+
+            let (_, key) = self.expirations.pop_first().unwrap();
+            self.entries.remove(&key);
+            self.touch(&key);
+        }
+
+        None
+    }
+}
+
+/// Pop an element from one end of a list.
+pub(crate) fn list_pop(list: &mut VecDeque<Bytes>, end: End) -> Option<Bytes> {
+    match end {
+        End::Left => list.pop_front(),
+        End::Right => list.pop_back(),
+    }
+}
+
+/// Push an element onto one end of the list in `value`, creating the list if
+/// needed. `value` must not hold anything but a list.
+pub(crate) fn list_push(value: &mut Option<Value>, end: End, element: Bytes) {
+    let list = match value.get_or_insert_with(|| Value::List(VecDeque::new())) {
+        Value::List(list) => list,
+        _ => unreachable!("pushing onto a value that is not a list"),
+    };
+
+    match end {
+        End::Left => list.push_front(element),
+        End::Right => list.push_back(element),
+    }
+}
+
+/// Background task removing expired keys.
+///
+/// Sleeps until the earliest deadline across all shards, or until notified
+/// that an earlier deadline was set. Exits once the `Db` has been dropped.
+async fn purge_expired_tasks(shared: Weak<Shared>, notify: Arc<Notify>) {
+    loop {
+        // Only hold a strong reference while purging, never across an
+        // `.await`, otherwise the `Db` could never be dropped.
+        let next = match shared.upgrade() {
+            Some(shared) => shared.purge_expired_keys(),
+            None => return,
+        };
+
+        match next {
+            Some(when) => {
+                tokio::select! {
+                    _ = time::sleep_until(when) => {}
+                    _ = notify.notified() => {}
+                }
+            }
+            None => notify.notified().await,
+        }
+    }
+}
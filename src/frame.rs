@@ -0,0 +1,556 @@
+//! Provides a type representing a Redis protocol frame as well as utilities
+//! for parsing frames from a byte array.
+
+use crate::connection::Limits;
+
+use bytes::{Buf, Bytes};
+use std::fmt;
+use std::io::Cursor;
+use std::num::TryFromIntError;
+use std::str;
+use std::string::FromUtf8Error;
+
+/// A frame in the Redis protocol.
+///
+/// The variants following `Array` were introduced by RESP3. They are only
+/// sent as is to clients that switched to RESP3 with `HELLO 3`, see
+/// [`Connection`](crate::Connection) for how they are written to RESP2
+/// clients.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    /// An integer too large for `i64`, as its decimal digits.
+    BigNumber(String),
+    /// A string along with its three letter format, `txt` or `mkd`.
+    Verbatim(String, Bytes),
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    /// Key/value pairs describing the frame that follows them.
+    Attribute(Vec<(Frame, Frame)>, Box<Frame>),
+    /// Data sent without being requested, like pub/sub messages.
+    Push(Vec<Frame>),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Not enough data is available to parse a message
+    Incomplete,
+
+    /// Invalid message encoding
+    Other(crate::Error),
+}
+
+impl Frame {
+    /// Returns an empty array
+    pub(crate) fn array() -> Frame {
+        Frame::Array(vec![])
+    }
+
+    /// Push a "bulk" frame into the array. `self` must be an Array frame.
+    ///
+    /// # Panics
+    ///
+    /// panics if `self` is not an array
+    pub(crate) fn push_bulk(&mut self, bytes: Bytes) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(Frame::Bulk(bytes));
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
+    /// Push an "integer" frame into the array. `self` must be an Array frame.
+    ///
+    /// # Panics
+    ///
+    /// panics if `self` is not an array
+    pub(crate) fn push_int(&mut self, value: i64) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(Frame::Integer(value));
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
+    /// Converts the frame to an "unexpected frame" error
+    pub(crate) fn to_error(&self) -> crate::Error {
+        format!("unexpected frame: {}", self).into()
+    }
+
+    /// Checks if an entire message can be decoded from `src`
+    ///
+    /// Frames exceeding `limits` are rejected as soon as the offending length
+    /// or nesting level is read, without waiting for the rest of the frame.
+    /// `max_buffered_bytes` is left to the caller.
+    pub fn check(src: &mut Cursor<&[u8]>, limits: &Limits) -> Result<(), Error> {
+        check_frame(src, limits, 0)
+    }
+
+    /// Parse a frame from the front of `src`, consuming it.
+    ///
+    /// The message has already been validated with `check`. Bulk strings are
+    /// split off `src` instead of being copied, so the returned frame shares
+    /// the allocation backing `src`.
+    pub fn parse(src: &mut Bytes) -> Result<Frame, Error> {
+        match take_u8(src)? {
+            b'+' => {
+                // Read the line and convert it to `Vec<u8>`
+                let line = take_line(src)?.to_vec();
+
+                // Convert the line to a String
+                let string = String::from_utf8(line)?;
+
+                Ok(Frame::Simple(string))
+            }
+            b'-' => {
+                // Read the line and convert it to `Vec<u8>`
+                let line = take_line(src)?.to_vec();
+
+                // Convert the line to a String
+                let string = String::from_utf8(line)?;
+
+                Ok(Frame::Error(string))
+            }
+            b':' => {
+                let value = parse_line(&take_line(src)?)?;
+                Ok(Frame::Integer(value))
+            }
+            b'$' => match take_bulk(src)? {
+                Some(data) => Ok(Frame::Bulk(data)),
+                None => Ok(Frame::Null),
+            },
+            b'*' => match take_len(src)? {
+                Some(len) => Ok(Frame::Array(take_frames(src, len)?)),
+                None => Ok(Frame::Null),
+            },
+            b'_' => {
+                if !take_line(src)?.is_empty() {
+                    return Err("protocol error; invalid frame format".into());
+                }
+
+                Ok(Frame::Null)
+            }
+            b'#' => match &take_line(src)?[..] {
+                b"t" => Ok(Frame::Boolean(true)),
+                b"f" => Ok(Frame::Boolean(false)),
+                _ => Err("protocol error; invalid frame format".into()),
+            },
+            b',' => {
+                let value = parse_line(&take_line(src)?)?;
+                Ok(Frame::Double(value))
+            }
+            b'(' => {
+                let line = take_line(src)?;
+                let digits = line.strip_prefix(b"-").unwrap_or(&line);
+
+                if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                    return Err("protocol error; invalid frame format".into());
+                }
+
+                // Only ASCII digits and a sign, so this cannot fail
+                Ok(Frame::BigNumber(String::from_utf8(line.to_vec())?))
+            }
+            b'!' => {
+                let data = take_bulk(src)?.ok_or("protocol error; invalid frame format")?;
+                Ok(Frame::Error(String::from_utf8(data.to_vec())?))
+            }
+            b'=' => {
+                let data = take_bulk(src)?.ok_or("protocol error; invalid frame format")?;
+
+                // The data starts with its format, e.g. `txt:`
+                if data.len() < 4 || data[3] != b':' {
+                    return Err("protocol error; invalid frame format".into());
+                }
+
+                let format = String::from_utf8(data[..3].to_vec())?;
+                Ok(Frame::Verbatim(format, data.slice(4..)))
+            }
+            b'~' => {
+                let len = take_len(src)?.ok_or("protocol error; invalid frame format")?;
+                Ok(Frame::Set(take_frames(src, len)?))
+            }
+            b'>' => {
+                let len = take_len(src)?.ok_or("protocol error; invalid frame format")?;
+                Ok(Frame::Push(take_frames(src, len)?))
+            }
+            b'%' => {
+                let len = take_len(src)?.ok_or("protocol error; invalid frame format")?;
+                Ok(Frame::Map(take_pairs(src, len)?))
+            }
+            b'|' => {
+                // Attributes may describe a frame that has attributes too,
+                // they are read in a loop rather than recursively.
+                let mut chain = vec![];
+
+                loop {
+                    let len = take_len(src)?.ok_or("protocol error; invalid frame format")?;
+                    chain.push(take_pairs(src, len)?);
+
+                    if src.first() != Some(&b'|') {
+                        break;
+                    }
+
+                    src.advance(1);
+                }
+
+                let frame = Frame::parse(src)?;
+
+                Ok(chain.into_iter().rev().fold(frame, |frame, attributes| {
+                    Frame::Attribute(attributes, Box::new(frame))
+                }))
+            }
+            actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
+        }
+    }
+}
+
+impl PartialEq<&str> for Frame {
+    fn eq(&self, other: &&str) -> bool {
+        match self {
+            Frame::Simple(s) => s.eq(other),
+            Frame::Bulk(s) | Frame::Verbatim(_, s) => s.eq(other),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Frame::Simple(response) => response.fmt(fmt),
+            Frame::Error(msg) => write!(fmt, "error: {}", msg),
+            Frame::Integer(num) => num.fmt(fmt),
+            Frame::Bulk(msg) | Frame::Verbatim(_, msg) => match str::from_utf8(msg) {
+                Ok(string) => string.fmt(fmt),
+                Err(_) => write!(fmt, "{:?}", msg),
+            },
+            Frame::Null => "(nil)".fmt(fmt),
+            Frame::Array(parts) | Frame::Set(parts) | Frame::Push(parts) => {
+                for (i, part) in parts.iter().enumerate() {
+                    if i > 0 {
+                        // use space as the array element display separator
+                        write!(fmt, " ")?;
+                    }
+
+                    part.fmt(fmt)?;
+                }
+
+                Ok(())
+            }
+            Frame::Double(num) => format_double(*num).fmt(fmt),
+            Frame::Boolean(value) => value.fmt(fmt),
+            Frame::BigNumber(num) => num.fmt(fmt),
+            Frame::Map(pairs) => {
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " ")?;
+                    }
+
+                    write!(fmt, "{} {}", key, value)?;
+                }
+
+                Ok(())
+            }
+            // Attributes are metadata, only the frame they describe is shown
+            Frame::Attribute(_, frame) => frame.fmt(fmt),
+        }
+    }
+}
+
+/// Format a double the way RESP3 encodes it, e.g. `1.5`, `inf` or `nan`.
+pub(crate) fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        // `f64` displays as `NaN`
+        "nan".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Returns `true` if `byte` starts a RESP2 or RESP3 frame.
+pub(crate) fn is_type_byte(byte: u8) -> bool {
+    b"+-:$*_#,(!=~>%|".contains(&byte)
+}
+
+fn peek_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+    if !src.has_remaining() {
+        return Err(Error::Incomplete);
+    }
+
+    Ok(src.chunk()[0])
+}
+
+fn get_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+    if !src.has_remaining() {
+        return Err(Error::Incomplete);
+    }
+
+    Ok(src.get_u8())
+}
+
+fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
+    if src.remaining() < n {
+        return Err(Error::Incomplete);
+    }
+
+    src.advance(n);
+    Ok(())
+}
+
+/// Check a frame nested in `depth` aggregate frames
+fn check_frame(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<(), Error> {
+    match get_u8(src)? {
+        b'+' | b'-' | b'_' | b'#' | b',' | b'(' => {
+            get_line(src)?;
+            Ok(())
+        }
+        b':' => {
+            let _ = get_int(src)?;
+            Ok(())
+        }
+        b'$' | b'!' | b'=' => {
+            if b'-' == peek_u8(src)? {
+                // Skip '-1\r\n'
+                skip(src, 4)
+            } else {
+                // Read the bulk string
+                let len: usize = get_decimal(src)?.try_into()?;
+
+                if len > limits.max_bulk_len {
+                    return Err(format!(
+                        "protocol error; bulk length exceeds the limit of {} bytes",
+                        limits.max_bulk_len
+                    )
+                    .into());
+                }
+
+                // skip that number of bytes + 2 (\r\n).
+                skip(src, len + 2)
+            }
+        }
+        b'*' | b'~' | b'>' => {
+            if b'-' == peek_u8(src)? {
+                // A null array, '*-1\r\n'
+                return skip(src, 4);
+            }
+
+            let len = check_aggregate(src, limits, depth)?;
+
+            for _ in 0..len {
+                check_frame(src, limits, depth + 1)?;
+            }
+
+            Ok(())
+        }
+        b'%' => check_pairs(src, limits, depth),
+        b'|' => {
+            check_pairs(src, limits, depth)?;
+
+            // Attributes are followed by the frame they describe, one level
+            // deeper so that chained attributes count towards the limit
+            check_frame(src, limits, depth + 1)
+        }
+        actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
+    }
+}
+
+/// Read the element count of an aggregate frame nested in `depth` others,
+/// enforcing `limits`
+fn check_aggregate(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<u64, Error> {
+    if depth >= limits.max_depth {
+        return Err(format!(
+            "protocol error; nesting exceeds the limit of {} levels",
+            limits.max_depth
+        )
+        .into());
+    }
+
+    let len = get_decimal(src)?;
+
+    if len > limits.max_array_len as u64 {
+        return Err(format!(
+            "protocol error; element count exceeds the limit of {}",
+            limits.max_array_len
+        )
+        .into());
+    }
+
+    Ok(len)
+}
+
+/// Check the key/value pairs of a map or attribute frame
+fn check_pairs(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<(), Error> {
+    let len = check_aggregate(src, limits, depth)?;
+
+    for _ in 0..len {
+        check_frame(src, limits, depth + 1)?;
+        check_frame(src, limits, depth + 1)?;
+    }
+
+    Ok(())
+}
+
+/// Read a new-line terminated unsigned decimal, used for lengths
+fn get_decimal(src: &mut Cursor<&[u8]>) -> Result<u64, Error> {
+    let line = get_line(src)?;
+
+    parse_line(line)
+}
+
+/// Read a new-line terminated signed decimal, used for integer frames
+fn get_int(src: &mut Cursor<&[u8]>) -> Result<i64, Error> {
+    let line = get_line(src)?;
+
+    parse_line(line)
+}
+
+fn parse_line<T: str::FromStr>(line: &[u8]) -> Result<T, Error> {
+    str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| "protocol error; invalid frame format".into())
+}
+
+/// Consume the next byte of a validated frame
+fn take_u8(src: &mut Bytes) -> Result<u8, Error> {
+    if !src.has_remaining() {
+        return Err(Error::Incomplete);
+    }
+
+    Ok(src.get_u8())
+}
+
+/// Consume a line of a validated frame, returning it without the \r\n
+fn take_line(src: &mut Bytes) -> Result<Bytes, Error> {
+    let end = src
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .ok_or(Error::Incomplete)?;
+
+    let line = src.split_to(end);
+    src.advance(2);
+
+    Ok(line)
+}
+
+/// Consume the length line of a bulk string or aggregate frame. `-1`, which
+/// stands for null, is returned as `None`.
+fn take_len(src: &mut Bytes) -> Result<Option<usize>, Error> {
+    let line = take_line(src)?;
+
+    if line.starts_with(b"-") {
+        if line != b"-1"[..] {
+            return Err("protocol error; invalid frame format".into());
+        }
+
+        return Ok(None);
+    }
+
+    Ok(Some(parse_line::<u64>(&line)?.try_into()?))
+}
+
+/// Consume a length prefixed string, `None` if it is null
+fn take_bulk(src: &mut Bytes) -> Result<Option<Bytes>, Error> {
+    let len = match take_len(src)? {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    if src.remaining() < len + 2 {
+        return Err(Error::Incomplete);
+    }
+
+    // Split the payload off without copying it, then skip the trailing \r\n.
+    let data = src.split_to(len);
+    src.advance(2);
+
+    Ok(Some(data))
+}
+
+/// Consume the `len` elements of an aggregate frame
+fn take_frames(src: &mut Bytes, len: usize) -> Result<Vec<Frame>, Error> {
+    let mut out = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        out.push(Frame::parse(src)?);
+    }
+
+    Ok(out)
+}
+
+/// Consume the `len` key/value pairs of a map or attribute frame
+fn take_pairs(src: &mut Bytes, len: usize) -> Result<Vec<(Frame, Frame)>, Error> {
+    let mut out = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        let key = Frame::parse(src)?;
+        let value = Frame::parse(src)?;
+        out.push((key, value));
+    }
+
+    Ok(out)
+}
+
+/// Find a line
+fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
+    // Scan the bytes directly
+    let start = src.position() as usize;
+    let buf = *src.get_ref();
+
+    // Scan to the second to last byte
+    for i in start..buf.len().saturating_sub(1) {
+        if buf[i] == b'\r' && buf[i + 1] == b'\n' {
+            // We found a line, update the position to be *after* the \n
+            src.set_position((i + 2) as u64);
+
+            // Return the line
+            return Ok(&buf[start..i]);
+        }
+    }
+
+    Err(Error::Incomplete)
+}
+
+impl From<String> for Error {
+    fn from(src: String) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(src: &str) -> Error {
+        src.to_string().into()
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(_src: FromUtf8Error) -> Error {
+        "protocol error; invalid frame format".into()
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(_src: TryFromIntError) -> Error {
+        "protocol error; invalid frame format".into()
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Incomplete => "stream ended early".fmt(fmt),
+            Error::Other(err) => err.fmt(fmt),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Splitting of command lines into arguments.
+//!
+//! Inline commands sent to the server and commands typed in the `client`
+//! tool are both split the same way `redis-cli` does it.
+
+use bytes::Bytes;
+
+/// Split a command line into arguments.
+///
+/// Arguments are separated by whitespace. An argument may be quoted to
+/// include whitespace:
+///
+/// * Double quotes support the `\n`, `\r`, `\t`, `\b`, `\a`, `\\`, `\"` and
+///   `\xHH` escape sequences.
+/// * Single quotes take everything literally, except `\'`.
+///
+/// A closing quote must be followed by whitespace or the end of the line.
+/// Unbalanced quotes are an error.
+///
+/// # Examples
+///
+/// ```
+/// use my_redis::inline::split_args;
+///
+/// let args = split_args(r#"SET greeting "hello world""#).unwrap();
+/// assert_eq!(args, vec!["SET", "greeting", "hello world"]);
+/// ```
+pub fn split_args(line: impl AsRef<[u8]>) -> crate::Result<Vec<Bytes>> {
+    let mut args = vec![];
+    let mut chars = line.as_ref().iter().copied().peekable();
+
+    loop {
+        // Skip blanks between arguments
+        while chars.next_if(u8::is_ascii_whitespace).is_some() {}
+
+        let first = match chars.peek() {
+            Some(&c) => c,
+            None => return Ok(args),
+        };
+
+        let mut arg = vec![];
+
+        match first {
+            b'"' => {
+                chars.next();
+
+                loop {
+                    match chars.next().ok_or(INVALID)? {
+                        b'"' => break,
+                        b'\\' => match chars.next().ok_or(INVALID)? {
+                            b'n' => arg.push(b'\n'),
+                            b'r' => arg.push(b'\r'),
+                            b't' => arg.push(b'\t'),
+                            b'b' => arg.push(0x08),
+                            b'a' => arg.push(0x07),
+                            b'x' => {
+                                let hi = chars.next().and_then(hex_digit);
+                                let lo = chars.next().and_then(hex_digit);
+
+                                match (hi, lo) {
+                                    (Some(hi), Some(lo)) => arg.push(hi << 4 | lo),
+                                    _ => return Err(INVALID.into()),
+                                }
+                            }
+                            c => arg.push(c),
+                        },
+                        c => arg.push(c),
+                    }
+                }
+
+                closing_quote(chars.peek())?;
+            }
+            b'\'' => {
+                chars.next();
+
+                loop {
+                    match chars.next().ok_or(INVALID)? {
+                        b'\'' => break,
+                        b'\\' if chars.peek() == Some(&b'\'') => {
+                            arg.push(b'\'');
+                            chars.next();
+                        }
+                        c => arg.push(c),
+                    }
+                }
+
+                closing_quote(chars.peek())?;
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_ascii_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+
+        args.push(Bytes::from(arg));
+    }
+}
+
+/// Error message used by `redis-cli` for lines that cannot be split.
+const INVALID: &str = "Invalid argument(s)";
+
+/// A closing quote must be followed by whitespace or the end of the line.
+fn closing_quote(next: Option<&u8>) -> crate::Result<()> {
+    match next {
+        Some(c) if !c.is_ascii_whitespace() => Err(INVALID.into()),
+        _ => Ok(()),
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|digit| digit as u8)
+}
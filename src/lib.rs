@@ -0,0 +1,48 @@
+pub mod aof;
+pub use aof::Aof;
+
+pub mod cli;
+
+pub mod client;
+
+pub mod client_handle;
+pub use client_handle::ClientHandle;
+
+pub mod cmd;
+pub use cmd::Command;
+
+pub mod connection;
+pub use connection::Connection;
+
+pub mod db;
+pub use db::Db;
+
+pub mod frame;
+pub use frame::Frame;
+
+pub mod inline;
+
+mod parse;
+use parse::Parse;
+
+pub mod pool;
+pub use pool::Pool;
+
+pub mod server;
+
+pub mod snapshot;
+pub use snapshot::Snapshots;
+
+mod shutdown;
+use shutdown::Shutdown;
+
+mod sorted_set;
+
+/// Error returned by most functions.
+///
+/// Boxing a `std::error::Error` keeps the error handling simple while the
+/// crate is small. It is `Send + Sync` so errors can cross task boundaries.
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// A specialized `Result` type for my-redis operations.
+pub type Result<T> = std::result::Result<T, Error>;
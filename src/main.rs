@@ -1,91 +1,38 @@
-use bytes::Bytes;
-use mini_redis::client;
-use tokio::sync::{mpsc, oneshot};
+use my_redis::{client, ClientHandle};
 
-// Provided by the requester and used by the manager task to send
-// the command response back to the requester
-type Responder = oneshot::Sender<mini_redis::Result<T>>;
-
-// Multiple different commands are multiplexed over a single channel
-#[derive(Debug)]
-enum Command {
-    Get {
-        key: String,
-        resp: Responder<Option<Bytes>>,
-    },
-    Set {
-        key: String,
-        val: Bytes,
-        resp: Responder<()>,
-    },
-}
+/// Number of requests that may wait for the shared connection.
+const QUEUE_DEPTH: usize = 32;
 
 #[tokio::main]
-async fn main() {
-    // tokio channel with a max capacity of 32
-    let (tx, mut rx) = mpsc::channel(32);
-
-    // The `move` keyword is used to move ownership of **rx** into the task
-    let manager = tokio::spawn(async move {
-        // Establish connection to the server
-        let mut client = client::connect("127.0.0.1:6379").await.unwrap();
+async fn main() -> my_redis::Result<()> {
+    // The server address may be given as the first argument
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:6379".to_string());
 
-        // Start receiving messages
-        while let Some(cmd) = rx.recv().await {
-            use Command::*;
-
-            match cmd {
-                Get { key, resp } => {
-                    let res = client.get(&key).await;
-                    // Ignore errors
-                    let _ = resp.send(res);
-                }
-                Set { key, val, resp } => {
-                    let res = client.set(&key, val).await;
-                    // Ignore errors
-                    let _ = resp.send(res);
-                }
-            }
-        }
-    });
+    // Establish the connection and hand it to the manager task
+    let client = client::connect(&addr).await?;
+    let handle = ClientHandle::new(client, QUEUE_DEPTH);
 
-    // The `Sender` handles are moved into the tasks.
-    // As there are two tasks, we need a second `Sender`
-    let tx2 = tx.clone();
+    // Every task gets its own clone of the handle, all of them share the one
+    // connection.
+    let getter = handle.clone();
 
     // Spawn two tasks, one gets a key, the other sets a key
     let t1 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = Command::Get {
-            key: "foo".to_string(),
-            resp: resp_tx,
-        };
-
-        // Send the GET request
-        tx.send(cmd).await.unwrap();
-
-        // Await the response
-        let res = resp_rx;
+        let res = getter.get("foo").await;
         println!("GOT = {:?}", res);
     });
 
     let t2 = tokio::spawn(async move {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        let cmd = Command::Set {
-            key: "foo".to_string(),
-            val: "bar".into(),
-            resp: resp_tx,
-        };
-
-        // Send the SET request
-        tx2.send(cmd).await.unwrap();
-
-        // Await the response
-        let res = resp_rx.await;
+        let res = handle.set("foo", "bar".into()).await;
         println!("GOT = {:?}", res);
     });
 
-    t1.await.unwrap();
-    t2.await.unwrap();
-    manager.await.unwrap();
+    t1.await?;
+    t2.await?;
+
+    // Both handles are dropped by now, so the manager task closes the
+    // connection and exits.
+    Ok(())
 }
@@ -0,0 +1,153 @@
+use crate::Frame;
+
+use bytes::Bytes;
+use std::{fmt, str, vec};
+
+/// Utility for parsing a command
+///
+/// Commands are represented as array frames. Each entry in the frame is a
+/// "token". A `Parse` is initialized with the array frame and provides a
+/// cursor-like API. Each command struct includes a `parse_frames` method that
+/// uses a `Parse` to extract its fields.
+#[derive(Debug)]
+pub(crate) struct Parse {
+    /// Array frame iterator.
+    parts: vec::IntoIter<Frame>,
+}
+
+/// Error encountered while parsing a frame.
+///
+/// Any error is reported back to the client as an error frame. The frame was
+/// fully read off the connection, so the connection stays usable.
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    /// Attempting to extract a value failed due to the frame being fully
+    /// consumed.
+    EndOfStream,
+
+    /// All other errors
+    Other(crate::Error),
+}
+
+impl Parse {
+    /// Create a new `Parse` to parse the contents of `frame`.
+    ///
+    /// Returns `Err` if `frame` is not an array frame.
+    pub(crate) fn new(frame: Frame) -> Result<Parse, ParseError> {
+        let array = match frame {
+            Frame::Array(array) => array,
+            frame => {
+                return Err(format!("ERR protocol error; expected array, got {:?}", frame).into())
+            }
+        };
+
+        Ok(Parse {
+            parts: array.into_iter(),
+        })
+    }
+
+    /// Return the next entry. Array frames are arrays of frames, so the next
+    /// entry is a frame.
+    fn next(&mut self) -> Result<Frame, ParseError> {
+        self.parts.next().ok_or(ParseError::EndOfStream)
+    }
+
+    /// Return the next entry as a string.
+    ///
+    /// If the next entry cannot be represented as a String, then an error is
+    /// returned.
+    pub(crate) fn next_string(&mut self) -> Result<String, ParseError> {
+        match self.next()? {
+            // Both `Simple` and `Bulk` representation may be strings. Strings
+            // are parsed to UTF-8.
+            Frame::Simple(s) => Ok(s),
+            Frame::Bulk(data) => str::from_utf8(&data[..])
+                .map(|s| s.to_string())
+                .map_err(|_| "ERR protocol error; invalid string".into()),
+            frame => Err(format!(
+                "ERR protocol error; expected simple frame or bulk frame, got {:?}",
+                frame
+            )
+            .into()),
+        }
+    }
+
+    /// Return the next entry as raw bytes.
+    ///
+    /// The bytes are copied into their own allocation. Bulk strings point
+    /// into the connection's read buffer, and a value stored in the `Db`
+    /// would otherwise keep the whole chunk it was read into alive.
+    ///
+    /// If the next entry cannot be represented as raw bytes, an error is
+    /// returned.
+    pub(crate) fn next_bytes(&mut self) -> Result<Bytes, ParseError> {
+        match self.next()? {
+            // Both `Simple` and `Bulk` representation may be raw bytes.
+            Frame::Simple(s) => Ok(Bytes::from(s.into_bytes())),
+            Frame::Bulk(data) => Ok(Bytes::copy_from_slice(&data)),
+            frame => Err(format!(
+                "ERR protocol error; expected simple frame or bulk frame, got {:?}",
+                frame
+            )
+            .into()),
+        }
+    }
+
+    /// Return the next entry as an integer.
+    ///
+    /// This includes `Simple`, `Bulk`, and `Integer` frame types. `Simple` and
+    /// `Bulk` frame types are parsed.
+    ///
+    /// If the next entry cannot be represented as an integer, then an error is
+    /// returned.
+    pub(crate) fn next_int(&mut self) -> Result<i64, ParseError> {
+        const MSG: &str = "ERR value is not an integer or out of range";
+
+        match self.next()? {
+            // An integer frame type is already stored as an integer.
+            Frame::Integer(v) => Ok(v),
+            // Simple and bulk frames must be parsed as integers. If the parsing
+            // fails, an error is returned.
+            Frame::Simple(data) => data.parse().map_err(|_| MSG.into()),
+            Frame::Bulk(data) => str::from_utf8(&data)
+                .ok()
+                .and_then(|data| data.parse().ok())
+                .ok_or_else(|| MSG.into()),
+            frame => {
+                Err(format!("ERR protocol error; expected int frame but got {:?}", frame).into())
+            }
+        }
+    }
+
+    /// Ensure there are no more entries in the array
+    pub(crate) fn finish(&mut self) -> Result<(), ParseError> {
+        if self.parts.next().is_none() {
+            Ok(())
+        } else {
+            Err("ERR protocol error; expected end of frame, but there was more".into())
+        }
+    }
+}
+
+impl From<String> for ParseError {
+    fn from(src: String) -> ParseError {
+        ParseError::Other(src.into())
+    }
+}
+
+impl From<&str> for ParseError {
+    fn from(src: &str) -> ParseError {
+        src.to_string().into()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::EndOfStream => "ERR protocol error; unexpected end of stream".fmt(f),
+            ParseError::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,372 @@
+//! A pool of `Client` connections shared between tasks.
+//!
+//! Unlike [`ClientHandle`](crate::ClientHandle), which funnels every request
+//! through one connection, a `Pool` lends a whole connection to a task for as
+//! long as it holds on to it. Connections are handed back to the pool when
+//! the [`PooledClient`] is dropped.
+
+use crate::client::{self, Client};
+
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Duration, Instant};
+
+/// Pool settings, see [`Pool::connect`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of connections kept open even when they are not used.
+    pub min_connections: usize,
+
+    /// Maximum number of connections open at the same time. Once they are
+    /// all checked out, `Pool::get` waits for one to be returned.
+    pub max_connections: usize,
+
+    /// Connections unused for longer than this are closed, as long as more
+    /// than `min_connections` are open.
+    pub idle_timeout: Duration,
+}
+
+/// A pool of connections to a single server.
+///
+/// Connections are checked out with [`get`](Pool::get). An idle connection
+/// is validated with a `PING` before being handed out, and replaced if it is
+/// broken. When every connection is checked out, tasks wait for one to be
+/// returned, first come first served.
+///
+/// Every `idle_timeout / 2`, a background task closes connections idle for
+/// longer than `idle_timeout` and opens new ones when fewer than
+/// `min_connections` are open, see [`maintain`](Pool::maintain). It exits
+/// once the last handle to the pool is dropped.
+///
+/// `Pool` is cheap to clone, every clone shares the same connections.
+///
+/// # Examples
+///
+/// ```no_run
+/// use my_redis::pool::{Config, Pool};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let pool = Pool::connect("localhost:6379", Config::default()).await.unwrap();
+///
+///     let mut client = pool.get().await.unwrap();
+///     client.set("foo", "bar".into()).await.unwrap();
+///
+///     // The connection goes back to the pool here
+///     drop(client);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+/// A connection checked out of a [`Pool`].
+///
+/// Dereferences to [`Client`]. The connection is returned to the pool when
+/// the `PooledClient` is dropped, unless a request on it was cancelled before
+/// its reply arrived, in which case it is closed.
+pub struct PooledClient {
+    /// Always `Some` until the value is dropped.
+    client: Option<Client>,
+
+    /// Only a weak reference, so a checked out connection does not keep the
+    /// pool alive.
+    pool: Weak<Shared>,
+
+    /// Returned to the semaphore on drop, letting the next waiter in.
+    _permit: OwnedSemaphorePermit,
+}
+
+struct Shared {
+    /// Server address, resolved again on every new connection.
+    addr: String,
+
+    config: Config,
+
+    /// One permit per connection that may be checked out. Tokio's semaphore
+    /// is fair, so waiters are served in the order they started waiting.
+    permits: Arc<Semaphore>,
+
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Connections not checked out. Connections are returned to the back and
+    /// checked out from the back, so the least recently used ones gather at
+    /// the front where the reaper finds them.
+    idle: VecDeque<Idle>,
+
+    /// Every open connection: idle, checked out, or being opened.
+    open: usize,
+}
+
+struct Idle {
+    client: Client,
+
+    /// When the connection was returned to the pool
+    since: Instant,
+}
+
+impl Pool {
+    /// Create a pool of connections to the server at `addr`.
+    ///
+    /// `config.min_connections` connections are opened right away, an error
+    /// opening any of them is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.max_connections` is zero or lower than
+    /// `config.min_connections`, or if `config.idle_timeout` is zero.
+    pub async fn connect(addr: impl Into<String>, config: Config) -> crate::Result<Pool> {
+        assert!(
+            config.max_connections > 0,
+            "a pool needs at least one connection"
+        );
+        assert!(
+            config.min_connections <= config.max_connections,
+            "`min_connections` is larger than `max_connections`"
+        );
+        assert!(
+            !config.idle_timeout.is_zero(),
+            "`idle_timeout` must not be zero"
+        );
+
+        let shared = Arc::new(Shared {
+            addr: addr.into(),
+            permits: Arc::new(Semaphore::new(config.max_connections)),
+            state: Mutex::new(State {
+                idle: VecDeque::new(),
+                open: 0,
+            }),
+            config,
+        });
+
+        for _ in 0..shared.config.min_connections {
+            let client = shared.open().await?;
+            shared.release(client);
+        }
+
+        // The task only holds a weak reference, so it does not keep the pool
+        // alive once every `Pool` handle is gone.
+        tokio::spawn(maintenance_task(Arc::downgrade(&shared)));
+
+        Ok(Pool { shared })
+    }
+
+    /// Check out a connection, waiting for one to be returned if all of them
+    /// are in use.
+    ///
+    /// Idle connections that fail a `PING` are closed and the next one is
+    /// tried. A new connection is opened if none is idle, an error is only
+    /// returned if that fails.
+    pub async fn get(&self) -> crate::Result<PooledClient> {
+        // The semaphore is never closed, so acquiring cannot fail
+        let permit = self.shared.permits.clone().acquire_owned().await.unwrap();
+
+        loop {
+            let idle = self.shared.state.lock().unwrap().idle.pop_back();
+
+            let client = match idle {
+                Some(mut idle) => {
+                    // The connection stops being counted if it is broken, or
+                    // if this call is cancelled during the health check.
+                    let guard = OpenGuard::new(&self.shared);
+
+                    // Anything but the `PONG` asked for means the replies
+                    // are out of step with the requests
+                    match idle.client.ping(None).await {
+                        Ok(pong) if pong == "PONG" => {
+                            guard.disarm();
+                            idle.client
+                        }
+                        // The connection is broken, drop it and try the next
+                        // one
+                        _ => continue,
+                    }
+                }
+                None => self.shared.open().await?,
+            };
+
+            return Ok(PooledClient {
+                client: Some(client),
+                pool: Arc::downgrade(&self.shared),
+                _permit: permit,
+            });
+        }
+    }
+
+    /// Close idle connections and open new ones right away, as the
+    /// background task does periodically.
+    ///
+    /// Connections idle for longer than `idle_timeout` are closed, down to
+    /// `min_connections`. Connections are then opened until there are
+    /// `min_connections` again, e.g. after some failed a health check. An
+    /// error opening one is returned.
+    pub async fn maintain(&self) -> crate::Result<()> {
+        self.shared.maintain().await
+    }
+
+    /// Number of open connections, checked out or not.
+    pub fn open_connections(&self) -> usize {
+        self.shared.state.lock().unwrap().open
+    }
+
+    /// Number of connections waiting in the pool to be checked out.
+    pub fn idle_connections(&self) -> usize {
+        self.shared.state.lock().unwrap().idle.len()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            min_connections: 1,
+            max_connections: 16,
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Shared {
+    /// Open a new connection, counting it as open while it is being
+    /// established so concurrent callers do not overshoot the limits.
+    async fn open(&self) -> crate::Result<Client> {
+        self.state.lock().unwrap().open += 1;
+
+        let guard = OpenGuard::new(self);
+        let client = client::connect(&self.addr[..]).await?;
+        guard.disarm();
+
+        Ok(client)
+    }
+
+    /// Put a connection back into the pool.
+    fn release(&self, client: Client) {
+        self.state.lock().unwrap().idle.push_back(Idle {
+            client,
+            since: Instant::now(),
+        });
+    }
+
+    /// Close the connections idle for longer than `idle_timeout`, keeping at
+    /// least `min_connections` open.
+    fn close_idle_connections(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        while state.open > self.config.min_connections {
+            match state.idle.front() {
+                Some(idle) if now - idle.since >= self.config.idle_timeout => {
+                    state.idle.pop_front();
+                    state.open -= 1;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// See [`Pool::maintain`].
+    async fn maintain(&self) -> crate::Result<()> {
+        self.close_idle_connections();
+
+        while self.state.lock().unwrap().open < self.config.min_connections {
+            let client = self.open().await?;
+            self.release(client);
+        }
+
+        Ok(())
+    }
+}
+
+/// Stops counting a connection as open when dropped, unless disarmed.
+///
+/// Covers both errors and the future owning the connection being dropped
+/// while waiting on the network.
+struct OpenGuard<'a> {
+    shared: &'a Shared,
+    armed: bool,
+}
+
+impl OpenGuard<'_> {
+    fn new(shared: &Shared) -> OpenGuard<'_> {
+        OpenGuard {
+            shared,
+            armed: true,
+        }
+    }
+
+    /// The connection was handed out, keep counting it.
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for OpenGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.shared.state.lock().unwrap().open -= 1;
+        }
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let client = self.client.take().unwrap();
+
+        // If the pool is gone, the connection is simply closed
+        if let Some(pool) = self.pool.upgrade() {
+            if client.awaiting_reply() {
+                // A request was cancelled, its reply would be taken for the
+                // reply to the next one. The connection is closed instead.
+                pool.state.lock().unwrap().open -= 1;
+            } else {
+                pool.release(client);
+            }
+        }
+    }
+}
+
+/// Background task closing idle connections and keeping `min_connections`
+/// open. Exits once the pool has been dropped.
+async fn maintenance_task(shared: Weak<Shared>) {
+    let period = match shared.upgrade() {
+        Some(shared) => shared.config.idle_timeout / 2,
+        None => return,
+    };
+
+    // The pool was just filled up, nothing to do before the first period
+    let mut interval = time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        // Only hold a strong reference while working on the pool, otherwise
+        // the pool could never be dropped.
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        if let Err(err) = shared.maintain().await {
+            eprintln!("failed to open pooled connection; err = {}", err);
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use crate::cmd::Transaction;
+use crate::connection::Limits;
+use crate::{Aof, Command, Connection, Db, Frame, Shutdown, Snapshots};
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::time::{self, Duration};
+
+/// Default maximum number of concurrent connections the server accepts.
+///
+/// Once reached, the server stops accepting until a connection closes. New
+/// clients wait in the OS backlog in the meantime.
+pub const MAX_CONNECTIONS: usize = 250;
+
+/// Default time `run` waits for open connections to finish once shutdown has
+/// been signalled. Connections still open after that are abandoned.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Server settings, see [`run_with_config`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of clients served at the same time.
+    pub max_connections: usize,
+
+    /// How long to wait for open connections to close during shutdown.
+    pub drain_timeout: Duration,
+
+    /// Bounds on what a client may send. A client exceeding them gets an
+    /// error and is disconnected.
+    pub limits: Limits,
+
+    /// Append only file every write command is logged to, opened with
+    /// [`Aof::open`]. `None` keeps the keyspace in memory only.
+    pub aof: Option<Aof>,
+
+    /// Where `SAVE` and `BGSAVE` write snapshots, created with
+    /// [`Snapshots::new`]. `None` disables both commands.
+    pub snapshots: Option<Snapshots>,
+}
+
+/// Server listener state. Created in the `run` call, it owns the listening
+/// socket and hands every accepted connection to its own task.
+struct Listener {
+    /// Shared database handle.
+    ///
+    /// Cloning the `Db` only clones the handle, every connection shares the
+    /// same shards.
+    db: Db,
+
+    /// TCP listener supplied by the `run` caller.
+    listener: TcpListener,
+
+    /// Applied to every accepted connection.
+    limits: Limits,
+
+    /// Shared by every connection, see `Config::aof`.
+    aof: Option<Aof>,
+
+    /// Shared by every connection, see `Config::snapshots`.
+    snapshots: Option<Snapshots>,
+
+    /// Limit the max number of connections.
+    ///
+    /// A `Semaphore` is used to limit the max number of connections. Before
+    /// attempting to accept a new connection, a permit is acquired from the
+    /// semaphore. If none are available, the listener waits for one.
+    ///
+    /// When handlers complete processing a connection, the permit is returned
+    /// to the semaphore.
+    limit_connections: Arc<Semaphore>,
+
+    /// Broadcasts a shutdown signal to all active connections.
+    ///
+    /// Each connection subscribes to the channel when it is accepted. When
+    /// the sender is dropped, every connection sees the signal, finishes the
+    /// command it is running, and closes.
+    notify_shutdown: broadcast::Sender<()>,
+
+    /// Used as part of the graceful shutdown process to wait for client
+    /// connections to complete processing.
+    ///
+    /// Tokio channels are closed once all `Sender` handles go out of scope.
+    /// When a channel is closed, the receiver receives `None`. This is
+    /// leveraged to detect all connection handlers completing: each handler
+    /// holds a clone of this sender and drops it when it returns.
+    shutdown_complete_tx: mpsc::Sender<()>,
+}
+
+/// Run the server until `shutdown` completes.
+///
+/// Every connection is handled on its own task and all of them operate on
+/// the same `db`, so a value SET by one client is visible to every other
+/// client.
+///
+/// Once `shutdown` completes, the listener is closed so no new connections
+/// are accepted, and every open connection is told to stop once it has
+/// answered the command it is processing. `run` returns when all of them are
+/// closed, or after [`DRAIN_TIMEOUT`].
+///
+/// `tokio::signal::ctrl_c()` can be used as the `shutdown` argument.
+pub async fn run(listener: TcpListener, db: Db, shutdown: impl Future) {
+    run_with_config(listener, db, Config::default(), shutdown).await
+}
+
+/// Run the server with the given settings until `shutdown` completes.
+///
+/// Behaves like [`run`], but serves at most `config.max_connections` clients
+/// at the same time and waits `config.drain_timeout` for them to close on
+/// shutdown.
+///
+/// # Panics
+///
+/// Panics if `config.max_connections` is zero.
+pub async fn run_with_config(listener: TcpListener, db: Db, config: Config, shutdown: impl Future) {
+    assert!(
+        config.max_connections > 0,
+        "the server needs to accept at least one connection"
+    );
+
+    // When the provided `shutdown` future completes, we must send a shutdown
+    // message to all active connections. We use a broadcast channel for this
+    // purpose. The call below ignores the receiver of the broadcast pair, and
+    // when a receiver is needed, the subscribe() method on the sender is used
+    // to create one.
+    let (notify_shutdown, _) = broadcast::channel(1);
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
+
+    let mut server = Listener {
+        db,
+        listener,
+        limits: config.limits.clone(),
+        aof: config.aof.clone(),
+        snapshots: config.snapshots.clone(),
+        limit_connections: Arc::new(Semaphore::new(config.max_connections)),
+        notify_shutdown,
+        shutdown_complete_tx,
+    };
+
+    // Accept connections until either the listener fails or the shutdown
+    // signal is received.
+    tokio::select! {
+        res = server.run() => {
+            // Accepting kept failing even after backing off, so the server
+            // stops. The open connections are still drained below.
+            if let Err(err) = res {
+                eprintln!("failed to accept; err = {}", err);
+            }
+        }
+        _ = shutdown => {
+            println!("shutting down");
+        }
+    }
+
+    // Take the listener apart so each piece can be dropped explicitly. This
+    // is important, as the `.await` below would otherwise never complete.
+    let Listener {
+        listener,
+        notify_shutdown,
+        shutdown_complete_tx,
+        ..
+    } = server;
+
+    // Close the listening socket, new connections are refused from now on.
+    drop(listener);
+
+    // When `notify_shutdown` is dropped, all tasks which have `subscribe`d
+    // will receive the shutdown signal and can exit.
+    drop(notify_shutdown);
+
+    // Drop final `Sender` so the `Receiver` below can complete
+    drop(shutdown_complete_tx);
+
+    // Wait for all active connections to finish processing. As the `Sender`
+    // handle held by the listener has been dropped above, the only remaining
+    // `Sender` instances are held by connection handler tasks. When those
+    // drop, the `mpsc` channel will close and `recv()` will return `None`.
+    if time::timeout(config.drain_timeout, shutdown_complete_rx.recv())
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "connections still open after {:?}, exiting anyway",
+            config.drain_timeout
+        );
+    }
+
+    // Whatever the fsync policy, make sure the last writes are on disk
+    if let Some(aof) = &config.aof {
+        if let Err(err) = aof.sync() {
+            eprintln!("failed to fsync append only file; err = {}", err);
+        }
+    }
+
+    // Like Redis, save on the way out when snapshots are taken automatically
+    if let Some(snapshots) = &config.snapshots {
+        if !snapshots.rules().is_empty() && snapshots.unsaved_changes() > 0 {
+            if let Err(err) = snapshots.save() {
+                eprintln!("failed to save snapshot; err = {}", err);
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_connections: MAX_CONNECTIONS,
+            drain_timeout: DRAIN_TIMEOUT,
+            limits: Limits::default(),
+            aof: None,
+            snapshots: None,
+        }
+    }
+}
+
+impl Listener {
+    /// Accept inbound connections, spawning a task to process each of them.
+    ///
+    /// Only returns if accepting fails repeatedly, see `accept`.
+    async fn run(&mut self) -> crate::Result<()> {
+        loop {
+            // Wait for a permit to become available
+            //
+            // `acquire_owned` returns a permit that is bound to the semaphore.
+            // When the permit value is dropped, it is automatically returned
+            // to the semaphore.
+            //
+            // `acquire_owned()` returns `Err` when the semaphore has been
+            // closed. We don't ever close the semaphore, so `unwrap()` is safe.
+            let permit = self
+                .limit_connections
+                .clone()
+                .acquire_owned()
+                .await
+                .unwrap();
+
+            // The second item contains the IP and port of the new connection
+            let (socket, peer) = self.accept().await?;
+
+            // Responses are already batched by `process`. Left enabled,
+            // Nagle's algorithm holds back the second part of a batch that
+            // was read in two goes until the client acknowledges the first
+            // part, which a client waiting for the whole batch delays.
+            if let Err(err) = socket.set_nodelay(true) {
+                eprintln!("failed to set TCP_NODELAY; peer = {}, err = {}", peer, err);
+            }
+
+            let db = self.db.clone();
+            let limits = self.limits.clone();
+            let aof = self.aof.clone();
+            let snapshots = self.snapshots.clone();
+
+            // Receive shutdown notifications
+            let shutdown = Shutdown::new(self.notify_shutdown.subscribe());
+
+            // Notifies the receiver half once all clones are dropped
+            let shutdown_complete = self.shutdown_complete_tx.clone();
+
+            tokio::spawn(async move {
+                // An error only affects this connection. It is logged and the
+                // socket is closed when `process` returns.
+                if let Err(err) = process(socket, db, limits, aof, snapshots, shutdown).await {
+                    eprintln!("connection error; peer = {}, err = {}", peer, err);
+                }
+
+                // The connection is closed, let `run` know and make room for
+                // another client.
+                drop(shutdown_complete);
+                drop(permit);
+            });
+        }
+    }
+
+    /// Accept an inbound connection.
+    ///
+    /// Errors are handled by backing off and retrying. An exponential backoff
+    /// strategy is used. After the first failure, the task waits for 1 second.
+    /// After the second failure, the task waits for 2 seconds. Each subsequent
+    /// failure doubles the wait time. If accepting fails on the 6th try after
+    /// waiting for 64 seconds, then this function returns with an error.
+    ///
+    /// Running out of file descriptors (`EMFILE`) is the typical failure, and
+    /// it clears up by itself once some connections close.
+    async fn accept(&mut self) -> crate::Result<(TcpStream, SocketAddr)> {
+        let mut backoff = 1;
+
+        // Try to accept a few times
+        loop {
+            // Perform the accept operation. If a socket is successfully
+            // accepted, return it. Otherwise, save the error.
+            match self.listener.accept().await {
+                Ok(accepted) => return Ok(accepted),
+                Err(err) => {
+                    if backoff > 64 {
+                        // Accept has failed too many times. Return the error.
+                        return Err(err.into());
+                    }
+
+                    eprintln!("failed to accept, retrying in {}s; err = {}", backoff, err);
+                }
+            }
+
+            // Pause execution until the back off period elapses.
+            time::sleep(Duration::from_secs(backoff)).await;
+
+            // Double the back off
+            backoff *= 2;
+        }
+    }
+}
+
+/// Handle a single connection until the client disconnects or the server
+/// shuts down.
+///
+/// Commands that cannot be parsed or executed are answered with an error
+/// frame and the connection carries on. Bytes that are not valid RESP leave
+/// the stream in an unknown state, so the client is sent an error and the
+/// connection is closed. Frames exceeding `limits` are treated the same way,
+/// before they are fully received.
+///
+/// Pipelined commands are answered in batches: every command already received
+/// is executed in order and the responses are flushed together, instead of
+/// paying for one `write` call per response.
+///
+/// Commands changing the keyspace are logged to `aof`, if given, before they
+/// are answered.
+///
+/// Between `MULTI` and `EXEC`, commands are queued on the connection and run
+/// all at once by `EXEC`, see [`Transaction`].
+///
+/// The shutdown signal is only checked while waiting for the next command,
+/// so a command that has been read is always executed and answered.
+async fn process(
+    socket: TcpStream,
+    db: Db,
+    limits: Limits,
+    aof: Option<Aof>,
+    snapshots: Option<Snapshots>,
+    mut shutdown: Shutdown,
+) -> crate::Result<()> {
+    // The `Connection` lets us read/write redis **frames** instead
+    // of byte streams. The `Connection` type is defined in `connection.rs`.
+    let mut connection = Connection::with_limits(socket, limits);
+
+    // Commands queued since `MULTI` and keys watched by the client
+    let mut transaction = Transaction::default();
+
+    // As long as the shutdown signal has not been received, try to read a
+    // new request frame.
+    while !shutdown.is_shutdown() {
+        // While reading a request frame, also listen for the shutdown signal.
+        let res = tokio::select! {
+            res = connection.read_frame() => res,
+            _ = shutdown.recv() => {
+                // If a shutdown signal is received, return from `process`.
+                // This will result in the task terminating.
+                return Ok(());
+            }
+        };
+
+        let mut frame = match res {
+            Ok(Some(frame)) => frame,
+            // The client closed the connection
+            Ok(None) => return Ok(()),
+            Err(err) => return close_with_error(&mut connection, err).await,
+        };
+
+        // Execute the frame that was just read, then every other frame that
+        // arrived along with it. Responses are only buffered here.
+        loop {
+            match Command::from_frame(frame) {
+                // Between `MULTI` and `EXEC`, commands are queued instead of
+                // being run
+                res if transaction.is_open()
+                    && !matches!(res, Ok(Command::Exec(_) | Command::Discard(_))) =>
+                {
+                    let response = transaction.queue(res);
+                    connection.buffer_frame(&response).await?;
+                }
+                // Subscribing switches the connection into the subscribed
+                // state, the command takes over the connection until the
+                // client unsubscribes from every channel or the server shuts
+                // down. Responses buffered so far are flushed by the first
+                // subscription confirmation.
+                Ok(Command::Subscribe(cmd)) => {
+                    cmd.apply(&db, &mut connection, &mut shutdown).await?;
+                }
+                // The response to `HELLO` is written with the protocol it
+                // switched to
+                Ok(Command::Hello(cmd)) => {
+                    let response = cmd.apply(&mut connection);
+                    connection.buffer_frame(&response).await?;
+                }
+                // Waits for a push when every list is empty. Responses
+                // buffered so far are flushed before waiting.
+                Ok(Command::BlockingPop(cmd)) => {
+                    let response = cmd
+                        .apply(&db, aof.as_ref(), &mut connection, &mut shutdown)
+                        .await?;
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::BgRewriteAof(cmd)) => {
+                    let response = cmd.apply(&db, aof.as_ref());
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::Save(cmd)) => {
+                    let response = cmd.apply(snapshots.as_ref()).await;
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::BgSave(cmd)) => {
+                    let response = cmd.apply(snapshots.as_ref());
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::Multi(cmd)) => {
+                    let response = cmd.apply(&mut transaction);
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::Exec(cmd)) => {
+                    let mut response = cmd.apply(&db, aof.as_ref(), &mut transaction);
+
+                    if let Some(aof) = &aof {
+                        if let Err(err) = aof.flush_logged().await {
+                            response = Frame::Error(err.to_string());
+                        }
+                    }
+
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::Discard(cmd)) => {
+                    let response = cmd.apply(&mut transaction);
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::Watch(cmd)) => {
+                    let response = cmd.apply(&db, &mut transaction);
+                    connection.buffer_frame(&response).await?;
+                }
+                Ok(Command::Unwatch(cmd)) => {
+                    let response = cmd.apply(&mut transaction);
+                    connection.buffer_frame(&response).await?;
+                }
+                // Every other command computes its response from the shared
+                // `Db`, never in the middle of a transaction
+                Ok(cmd) => {
+                    let is_write = cmd.is_write();
+
+                    let mut response = {
+                        let _gate = db.lock_shared();
+
+                        match &aof {
+                            Some(aof) if is_write => match aof.log(|| cmd.apply_logged(&db)) {
+                                Ok(response) => response,
+                                Err(err) => Frame::Error(err.to_string()),
+                            },
+                            _ => cmd.apply(&db),
+                        }
+                    };
+
+                    // The write reaches the disk before it is answered, if
+                    // the fsync policy asks for it
+                    if let Some(aof) = aof.as_ref().filter(|_| is_write) {
+                        if let Err(err) = aof.flush_logged().await {
+                            response = Frame::Error(err.to_string());
+                        }
+                    }
+
+                    connection.buffer_frame(&response).await?;
+                }
+                // The frame was valid RESP but not a valid command, e.g. the
+                // wrong number of arguments were given.
+                Err(err) => {
+                    let response = Frame::Error(err.to_string());
+                    connection.buffer_frame(&response).await?;
+                }
+            }
+
+            // Only look at data that is already buffered, waiting for more
+            // would delay the responses of the batch.
+            frame = match connection.parse_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(err) => return close_with_error(&mut connection, err).await,
+            };
+        }
+
+        // Write the responses of the whole batch to the client
+        connection.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Report a protocol error to the client before the connection is closed.
+///
+/// Any buffered response is flushed along with the error. This is best
+/// effort: the socket may already be gone, so a failed write is ignored in
+/// favor of the original error.
+pub(crate) async fn close_with_error(
+    connection: &mut Connection,
+    err: crate::Error,
+) -> crate::Result<()> {
+    let response = Frame::Error(format!("ERR {}", err));
+    let _ = connection.write_frame(&response).await;
+
+    Err(err)
+}
@@ -0,0 +1,686 @@
+//! Point-in-time snapshots of the keyspace.
+//!
+//! A snapshot is a compact binary file holding every key, its value and its
+//! deadline. It is written to a temporary file first, then renamed over the
+//! previous snapshot, so a crash while saving never leaves a half written
+//! file behind.
+//!
+//! # Format
+//!
+//! ```text
+//! file      = "MYREDIS" version entry* EOF checksum
+//! version   = u8, currently 1
+//! entry     = [EXPIRE_MS unix-ms] value
+//! value     = TYPE_STRING key string | TYPE_LIST key list | TYPE_HASH key hash
+//!           | TYPE_SET key list | TYPE_ZSET key zset
+//! unix-ms   = i64, little endian
+//! key       = string
+//! list      = length string*, the number of elements
+//! hash      = length (string string)*, the number of field-value pairs
+//! zset      = length (string score)*, the number of members
+//! score     = f64, little endian
+//! string    = length bytes
+//! length    = unsigned LEB128 varint
+//! checksum  = CRC-32 of everything before it, u32, little endian
+//! ```
+
+use crate::cmd::expire::unix_millis;
+use crate::db::Value;
+use crate::sorted_set::SortedSet;
+use crate::Db;
+
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{self, Instant};
+
+const MAGIC: &[u8] = b"MYREDIS";
+
+const VERSION: u8 = 1;
+
+/// Precedes an entry expiring at the Unix time, in milliseconds, that
+/// follows.
+const EXPIRE_MS: u8 = 0xfc;
+
+/// Precedes an entry holding a string value.
+const TYPE_STRING: u8 = 0x00;
+
+/// Precedes an entry holding a list.
+const TYPE_LIST: u8 = 0x01;
+
+/// Precedes an entry holding a hash.
+const TYPE_HASH: u8 = 0x02;
+
+/// Precedes an entry holding a set.
+const TYPE_SET: u8 = 0x03;
+
+/// Precedes an entry holding a sorted set.
+const TYPE_ZSET: u8 = 0x04;
+
+/// Marks the end of the entries, the checksum follows.
+const EOF: u8 = 0xff;
+
+/// How long to wait after a failed automatic save before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Save automatically once at least `changes` modifications were made and
+/// `seconds` elapsed since the last successful save, like the Redis `save`
+/// setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Handle saving snapshots of a `Db` to a file.
+///
+/// Snapshots are taken on demand with [`save`](Snapshots::save) and
+/// [`bgsave`](Snapshots::bgsave), and by a background task whenever one of
+/// the [`SaveRule`]s is met. The task exits once every handle has been
+/// dropped.
+///
+/// `Snapshots` is cheap to clone, every clone shares the same file.
+#[derive(Clone)]
+pub struct Snapshots {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+
+    rules: Vec<SaveRule>,
+
+    db: Db,
+
+    state: Mutex<State>,
+}
+
+struct State {
+    /// A save is being written. Only one runs at a time, they would write to
+    /// the same temporary file.
+    in_progress: bool,
+
+    /// When the last successful save started
+    last_save: Instant,
+
+    /// Value of `Db::changes` when the last successful save started
+    saved_changes: u64,
+
+    /// When the last automatic save failed, so it is not retried right away
+    last_failure: Option<Instant>,
+}
+
+impl Snapshots {
+    /// Save snapshots of `db` to the file at `path`.
+    ///
+    /// The file is not loaded, see [`load`] for that. `rules` may be empty,
+    /// in which case snapshots are only saved on demand.
+    ///
+    /// This spawns the task checking `rules`, so it must be called from
+    /// within a Tokio runtime.
+    pub fn new(path: impl Into<PathBuf>, rules: Vec<SaveRule>, db: &Db) -> Snapshots {
+        let shared = Arc::new(Shared {
+            path: path.into(),
+            rules,
+            state: Mutex::new(State {
+                in_progress: false,
+                last_save: Instant::now(),
+                saved_changes: db.changes(),
+                last_failure: None,
+            }),
+            db: db.clone(),
+        });
+
+        if !shared.rules.is_empty() {
+            // The task only holds a weak reference, so it does not keep the
+            // `Db` alive after the last handle is gone.
+            tokio::spawn(save_on_rules(Arc::downgrade(&shared)));
+        }
+
+        Snapshots { shared }
+    }
+
+    /// Path of the snapshot file.
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    /// Rules triggering automatic saves.
+    pub fn rules(&self) -> &[SaveRule] {
+        &self.shared.rules
+    }
+
+    /// Returns `true` while a save has not completed yet.
+    pub fn save_in_progress(&self) -> bool {
+        self.shared.state.lock().unwrap().in_progress
+    }
+
+    /// Number of modifications made to the `Db` since the last successful
+    /// save.
+    pub fn unsaved_changes(&self) -> u64 {
+        let saved_changes = self.shared.state.lock().unwrap().saved_changes;
+        self.shared.db.changes() - saved_changes
+    }
+
+    /// Save a snapshot, blocking until it is written.
+    ///
+    /// Fails if a save is already in progress.
+    pub fn save(&self) -> crate::Result<()> {
+        self.shared.start()?;
+        let entries = self.shared.copy();
+
+        self.shared
+            .finish(entries)
+            .map_err(|err| format!("ERR failed to save snapshot: {}", err).into())
+    }
+
+    /// Start saving a snapshot in the background.
+    ///
+    /// Returns right away, the keyspace is copied and the file written by a
+    /// blocking task. A failure is logged. Fails if a save is already in
+    /// progress.
+    pub fn bgsave(&self) -> crate::Result<()> {
+        self.shared.start()?;
+        let shared = self.shared.clone();
+
+        // Copying takes time in proportion to the keyspace, keep it off the
+        // runtime threads too
+        tokio::task::spawn_blocking(move || {
+            let entries = shared.copy();
+
+            if let Err(err) = shared.finish(entries) {
+                eprintln!("failed to save snapshot; err = {}", err);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Load the snapshot at `path` into `db`.
+///
+/// Returns `false` if there is no file at `path`. The whole file is checked
+/// before any key is stored, so a corrupt snapshot leaves `db` untouched.
+/// Keys whose deadline passed while the server was down are skipped.
+pub fn load(path: impl AsRef<Path>, db: &Db) -> crate::Result<bool> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    let now = SystemTime::now();
+
+    for (key, value, expires_at) in decode(&data)? {
+        let expire = match expires_at {
+            Some(when) => match when.duration_since(now) {
+                Ok(remaining) => Some(remaining),
+                // Expired already
+                Err(_) => continue,
+            },
+            None => None,
+        };
+
+        db.insert(key, value, expire);
+    }
+
+    Ok(true)
+}
+
+impl fmt::Debug for Snapshots {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Snapshots")
+            .field("path", &self.shared.path)
+            .field("rules", &self.shared.rules)
+            .finish()
+    }
+}
+
+impl SaveRule {
+    /// Parse a list of rules as given to the Redis `save` setting: pairs of
+    /// seconds and changes separated by spaces, e.g. `"3600 1 300 100"`.
+    ///
+    /// An empty string is an empty list, which disables automatic saves.
+    pub fn parse_list(s: &str) -> crate::Result<Vec<SaveRule>> {
+        let numbers = s
+            .split_whitespace()
+            .map(u64::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid save rules `{}`", s))?;
+
+        if numbers.len() % 2 != 0 {
+            return Err(format!("invalid save rules `{}`", s).into());
+        }
+
+        Ok(numbers
+            .chunks(2)
+            .map(|pair| SaveRule {
+                seconds: pair[0],
+                changes: pair[1],
+            })
+            .collect())
+    }
+
+    /// The rules Redis uses by default.
+    pub fn defaults() -> Vec<SaveRule> {
+        vec![
+            SaveRule {
+                seconds: 3600,
+                changes: 1,
+            },
+            SaveRule {
+                seconds: 300,
+                changes: 100,
+            },
+            SaveRule {
+                seconds: 60,
+                changes: 10000,
+            },
+        ]
+    }
+}
+
+type Entries = Vec<(String, Value, Option<SystemTime>)>;
+
+impl Shared {
+    /// Mark a save as in progress, to be completed with `copy` then
+    /// `finish`.
+    fn start(&self) -> crate::Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.in_progress {
+            return Err("ERR Background save already in progress".into());
+        }
+
+        state.in_progress = true;
+
+        Ok(())
+    }
+
+    /// Copy the keyspace, along with when and after how many changes.
+    fn copy(&self) -> (Entries, Instant, u64) {
+        // Commands and transactions are either entirely part of the copy or
+        // not at all, even when they write to several shards
+        let _gate = self.db.lock_exclusive();
+
+        // Read before copying, so the changes counted are all part of the
+        // copy.
+        let changes = self.db.changes();
+        let started = Instant::now();
+
+        (self.db.snapshot(), started, changes)
+    }
+
+    /// Write a copy of the keyspace taken by `copy`, recording the outcome.
+    fn finish(&self, (entries, started, changes): (Entries, Instant, u64)) -> io::Result<()> {
+        let res = self.write(&entries);
+
+        let mut state = self.state.lock().unwrap();
+        state.in_progress = false;
+
+        match res {
+            Ok(()) => {
+                state.last_save = started;
+                state.saved_changes = changes;
+                state.last_failure = None;
+            }
+            Err(_) => state.last_failure = Some(Instant::now()),
+        }
+
+        res
+    }
+
+    /// Write `entries` to a temporary file, then rename it over the snapshot.
+    fn write(&self, entries: &Entries) -> io::Result<()> {
+        let mut tmp_path = OsString::from(self.path.as_os_str());
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let res = (|| {
+            let mut out = BufWriter::new(File::create(&tmp_path)?);
+            encode(entries, &mut out)?;
+
+            let file = out.into_inner().map_err(|err| err.into_error())?;
+            file.sync_all()?;
+
+            fs::rename(&tmp_path, &self.path)
+        })();
+
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+
+        res
+    }
+
+    /// Returns `true` if one of the rules is met and no save is running.
+    fn should_save(&self) -> bool {
+        let state = self.state.lock().unwrap();
+
+        if state.in_progress {
+            return false;
+        }
+
+        if let Some(when) = state.last_failure {
+            if when.elapsed() < RETRY_DELAY {
+                return false;
+            }
+        }
+
+        let changes = self.db.changes() - state.saved_changes;
+        let elapsed = state.last_save.elapsed();
+
+        self.rules.iter().any(|rule| {
+            changes > 0 && changes >= rule.changes && elapsed >= Duration::from_secs(rule.seconds)
+        })
+    }
+}
+
+/// Encode `entries` in the snapshot format.
+fn encode(entries: &Entries, dst: &mut impl Write) -> io::Result<()> {
+    let mut dst = Encoder {
+        dst,
+        hasher: crc32fast::Hasher::new(),
+    };
+
+    dst.write(MAGIC)?;
+    dst.write(&[VERSION])?;
+
+    for (key, value, expires_at) in entries {
+        if let Some(when) = expires_at {
+            dst.write(&[EXPIRE_MS])?;
+            dst.write(&unix_millis(*when).to_le_bytes())?;
+        }
+
+        match value {
+            Value::String(data) => {
+                dst.write(&[TYPE_STRING])?;
+                dst.string(key.as_bytes())?;
+                dst.string(data)?;
+            }
+            Value::List(list) => {
+                dst.write(&[TYPE_LIST])?;
+                dst.string(key.as_bytes())?;
+                dst.len(list.len())?;
+
+                for element in list {
+                    dst.string(element)?;
+                }
+            }
+            Value::Hash(hash) => {
+                dst.write(&[TYPE_HASH])?;
+                dst.string(key.as_bytes())?;
+                dst.len(hash.len())?;
+
+                for (field, value) in hash {
+                    dst.string(field)?;
+                    dst.string(value)?;
+                }
+            }
+            Value::Set(set) => {
+                dst.write(&[TYPE_SET])?;
+                dst.string(key.as_bytes())?;
+                dst.len(set.len())?;
+
+                for member in set {
+                    dst.string(member)?;
+                }
+            }
+            Value::SortedSet(set) => {
+                dst.write(&[TYPE_ZSET])?;
+                dst.string(key.as_bytes())?;
+                dst.len(set.len())?;
+
+                for (member, score) in set.iter() {
+                    dst.string(member)?;
+                    dst.write(&score.to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    dst.write(&[EOF])?;
+
+    let checksum = dst.hasher.finalize();
+    dst.dst.write_all(&checksum.to_le_bytes())
+}
+
+/// Writes a snapshot, computing its checksum along the way.
+struct Encoder<W> {
+    dst: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.hasher.update(data);
+        self.dst.write_all(data)
+    }
+
+    fn len(&mut self, mut len: usize) -> io::Result<()> {
+        let mut buf = [0; 10];
+        let mut n = 0;
+
+        while len >= 0x80 {
+            buf[n] = len as u8 | 0x80;
+            len >>= 7;
+            n += 1;
+        }
+
+        buf[n] = len as u8;
+        self.write(&buf[..=n])
+    }
+
+    fn string(&mut self, data: &[u8]) -> io::Result<()> {
+        self.len(data.len())?;
+        self.write(data)
+    }
+}
+
+/// Decode a snapshot, validating the whole file first.
+fn decode(data: &[u8]) -> crate::Result<Entries> {
+    if data.len() < MAGIC.len() + 1 + 1 + 4 || !data.starts_with(MAGIC) {
+        return Err("not a snapshot file".into());
+    }
+
+    let (body, checksum) = data.split_at(data.len() - 4);
+
+    if crc32fast::hash(body).to_le_bytes() != checksum {
+        return Err("snapshot checksum mismatch".into());
+    }
+
+    let version = body[MAGIC.len()];
+
+    if version != VERSION {
+        return Err(format!("unsupported snapshot version {}", version).into());
+    }
+
+    let mut src = Reader {
+        data: body,
+        pos: MAGIC.len() + 1,
+    };
+    let mut entries = vec![];
+
+    loop {
+        let mut expires_at = None;
+
+        let mut tag = src.u8()?;
+
+        if tag == EXPIRE_MS {
+            let millis = i64::from_le_bytes(src.bytes(8)?.try_into().unwrap());
+            expires_at = Some(UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64));
+            tag = src.u8()?;
+        }
+
+        match tag {
+            EOF if expires_at.is_none() => break,
+            TYPE_STRING => {
+                let key = src.key()?;
+                let value = Bytes::copy_from_slice(src.string()?);
+
+                entries.push((key, Value::String(value), expires_at));
+            }
+            TYPE_LIST => {
+                let key = src.key()?;
+                let len = src.len()?;
+
+                // Not trusting `len` with the allocation, the elements have to
+                // be there
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    list.push_back(Bytes::copy_from_slice(src.string()?));
+                }
+
+                if list.is_empty() {
+                    return Err("snapshot holds an empty list".into());
+                }
+
+                entries.push((key, Value::List(list), expires_at));
+            }
+            TYPE_HASH => {
+                let key = src.key()?;
+                let len = src.len()?;
+
+                let mut hash = HashMap::new();
+                for _ in 0..len {
+                    let field = Bytes::copy_from_slice(src.string()?);
+                    let value = Bytes::copy_from_slice(src.string()?);
+                    hash.insert(field, value);
+                }
+
+                if hash.is_empty() {
+                    return Err("snapshot holds an empty hash".into());
+                }
+
+                entries.push((key, Value::Hash(hash), expires_at));
+            }
+            TYPE_SET => {
+                let key = src.key()?;
+                let len = src.len()?;
+
+                let mut set = HashSet::new();
+                for _ in 0..len {
+                    set.insert(Bytes::copy_from_slice(src.string()?));
+                }
+
+                if set.is_empty() {
+                    return Err("snapshot holds an empty set".into());
+                }
+
+                entries.push((key, Value::Set(set), expires_at));
+            }
+            TYPE_ZSET => {
+                let key = src.key()?;
+                let len = src.len()?;
+
+                let mut set = SortedSet::new();
+                for _ in 0..len {
+                    let member = Bytes::copy_from_slice(src.string()?);
+                    let score = f64::from_le_bytes(src.bytes(8)?.try_into().unwrap());
+
+                    if score.is_nan() {
+                        return Err("snapshot holds a NaN score".into());
+                    }
+
+                    set.insert(member, score);
+                }
+
+                if set.is_empty() {
+                    return Err("snapshot holds an empty sorted set".into());
+                }
+
+                entries.push((key, Value::SortedSet(set), expires_at));
+            }
+            tag => return Err(format!("unknown snapshot entry type {:#04x}", tag).into()),
+        }
+    }
+
+    if src.pos != body.len() {
+        return Err("unexpected data after the end of the snapshot".into());
+    }
+
+    Ok(entries)
+}
+
+/// Cursor over the body of a snapshot.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> crate::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        match self.data.get(self.pos..).and_then(|rest| rest.get(..len)) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err("snapshot is truncated".into()),
+        }
+    }
+
+    fn len(&mut self) -> crate::Result<usize> {
+        let mut len: usize = 0;
+
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            len |= ((byte & 0x7f) as usize) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+        }
+
+        Err("invalid length in snapshot".into())
+    }
+
+    fn string(&mut self) -> crate::Result<&'a [u8]> {
+        let len = self.len()?;
+        self.bytes(len)
+    }
+
+    fn key(&mut self) -> crate::Result<String> {
+        String::from_utf8(self.string()?.to_vec())
+            .map_err(|_| "snapshot key is not valid UTF-8".into())
+    }
+}
+
+/// Background task saving a snapshot whenever a rule is met. Exits once
+/// every handle has been dropped.
+async fn save_on_rules(shared: Weak<Shared>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        // Only hold a strong reference while checking, otherwise the handles
+        // could never be dropped.
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+
+        if !shared.should_save() {
+            continue;
+        }
+
+        let snapshots = Snapshots { shared };
+
+        // Someone else may have started a save since the check
+        if let Err(err) = snapshots.bgsave() {
+            eprintln!("failed to start automatic save; err = {}", err);
+        }
+    }
+}
@@ -0,0 +1,512 @@
+//! Sorted set value type.
+//!
+//! Members are kept ordered by score, then by their bytes for equal scores,
+//! in a skip list. Each link of the skip list records how many members it
+//! skips, so members can be found by rank as well as by score or by name in
+//! logarithmic time, like the Redis implementation. A hash map from member to
+//! score sits next to it for constant time lookups.
+//!
+//! Nodes live in a `Vec` and link to each other by index. Removed nodes are
+//! recycled by later insertions.
+
+use bytes::Bytes;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::ops::Bound;
+
+/// Number of levels of the skip list, enough for 2^32 members.
+const MAX_LEVEL: usize = 32;
+
+/// Index of the head node, which holds no member.
+const HEAD: usize = 0;
+
+/// Members ordered by score.
+///
+/// Scores are never NaN, which keeps them totally ordered.
+#[derive(Clone)]
+pub(crate) struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    list: SkipList,
+}
+
+#[derive(Clone)]
+struct SkipList {
+    nodes: Vec<Node>,
+
+    /// Removed nodes, reused before growing `nodes`.
+    free: Vec<usize>,
+
+    /// Last node, where reverse iterations start.
+    tail: Option<usize>,
+
+    /// Number of levels in use, at least 1.
+    level: usize,
+
+    /// State of the generator picking the level of new nodes.
+    seed: u64,
+}
+
+#[derive(Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    levels: Vec<Link>,
+
+    /// Previous node on the lowest level, `None` for the first one.
+    backward: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Link {
+    next: Option<usize>,
+
+    /// Number of nodes on the lowest level between this node, excluded, and
+    /// `next`, included.
+    span: usize,
+}
+
+impl SortedSet {
+    pub(crate) fn new() -> SortedSet {
+        SortedSet {
+            scores: HashMap::new(),
+            list: SkipList::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub(crate) fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add `member` with `score`, or move it to `score` if already present.
+    ///
+    /// Returns the previous score of the member.
+    pub(crate) fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        debug_assert!(!score.is_nan());
+
+        let prev = self.scores.insert(member.clone(), score);
+
+        match prev {
+            Some(prev) if prev == score => {}
+            Some(prev) => {
+                self.list.remove(prev, &member);
+                self.list.insert(score, member);
+            }
+            None => self.list.insert(score, member),
+        }
+
+        prev
+    }
+
+    /// Remove `member`, returning its score.
+    pub(crate) fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(score, member);
+        Some(score)
+    }
+
+    /// Position of `member` in the set, starting at 0 for the lowest score.
+    pub(crate) fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(score, member)
+    }
+
+    /// Remove and return the member with the lowest score.
+    pub(crate) fn pop_min(&mut self) -> Option<(Bytes, f64)> {
+        let first = self.list.nodes[HEAD].levels[0].next?;
+        let node = &self.list.nodes[first];
+        let (member, score) = (node.member.clone(), node.score);
+
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// Every member along with its score, in order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.list.walk(self.list.nodes[HEAD].levels[0].next, false)
+    }
+
+    /// Members from rank `start` to `stop`, both included. With `rev`, ranks
+    /// count from the highest score and members come in descending order.
+    pub(crate) fn range_by_rank(
+        &self,
+        start: usize,
+        stop: usize,
+        rev: bool,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let first = match start < self.len() {
+            true if rev => self.list.by_rank(self.len() - 1 - start),
+            true => self.list.by_rank(start),
+            false => None,
+        };
+
+        self.list
+            .walk(first, rev)
+            .take((stop + 1).saturating_sub(start))
+    }
+
+    /// Members with a score between `min` and `max`, skipping the first
+    /// `offset` of them.
+    pub(crate) fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        offset: usize,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let below = move |score: f64, _: &[u8]| match min {
+            Bound::Included(min) => score < min,
+            Bound::Excluded(min) => score <= min,
+            Bound::Unbounded => false,
+        };
+        let above = move |score: f64, _: &[u8]| match max {
+            Bound::Included(max) => score > max,
+            Bound::Excluded(max) => score >= max,
+            Bound::Unbounded => false,
+        };
+
+        self.range_where(below, above, rev, offset)
+    }
+
+    /// Members between `min` and `max` in byte order, skipping the first
+    /// `offset` of them.
+    ///
+    /// Only meaningful when every member has the same score, as the set is
+    /// ordered by score first.
+    pub(crate) fn range_by_lex<'a>(
+        &'a self,
+        min: &'a Bound<Bytes>,
+        max: &'a Bound<Bytes>,
+        rev: bool,
+        offset: usize,
+    ) -> impl Iterator<Item = (&'a Bytes, f64)> {
+        let below = move |_: f64, member: &[u8]| match min {
+            Bound::Included(min) => member < &min[..],
+            Bound::Excluded(min) => member <= &min[..],
+            Bound::Unbounded => false,
+        };
+        let above = move |_: f64, member: &[u8]| match max {
+            Bound::Included(max) => member > &max[..],
+            Bound::Excluded(max) => member >= &max[..],
+            Bound::Unbounded => false,
+        };
+
+        self.range_where(below, above, rev, offset)
+    }
+
+    /// Members for which neither `below` nor `above` holds. Both must be
+    /// monotonic along the order of the set.
+    fn range_where<'a>(
+        &'a self,
+        below: impl Fn(f64, &[u8]) -> bool + 'a,
+        above: impl Fn(f64, &[u8]) -> bool + 'a,
+        rev: bool,
+        offset: usize,
+    ) -> impl Iterator<Item = (&'a Bytes, f64)> {
+        let first = match rev {
+            true => self.list.last_not(&above),
+            false => self.list.first_not(&below),
+        };
+
+        self.list
+            .walk(first, rev)
+            .take_while(move |(member, score)| match rev {
+                true => !below(*score, member),
+                false => !above(*score, member),
+            })
+            .skip(offset)
+    }
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &SortedSet) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl fmt::Debug for SortedSet {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl SkipList {
+    fn new() -> SkipList {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            levels: vec![
+                Link {
+                    next: None,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+            backward: None,
+        };
+
+        SkipList {
+            nodes: vec![head],
+            free: vec![],
+            tail: None,
+            level: 1,
+            // Odd, as xorshift must not start from zero
+            seed: RandomState::new().hash_one(0) | 1,
+        }
+    }
+
+    /// Returns `true` if `node` comes before `(score, member)`.
+    fn before(&self, node: usize, score: f64, member: &[u8]) -> bool {
+        let node = &self.nodes[node];
+        node.score < score || (node.score == score && node.member[..] < *member)
+    }
+
+    /// Pick the level of a new node, each level being half as likely as
+    /// the one below.
+    fn random_level(&mut self) -> usize {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+
+        (self.seed.trailing_ones() as usize + 1).min(MAX_LEVEL)
+    }
+
+    /// Insert a member that is not in the list yet.
+    fn insert(&mut self, score: f64, member: Bytes) {
+        // Last node before the new one on each level, and its rank
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if !self.before(next, score, &member) {
+                    break;
+                }
+
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+
+            update[i] = x;
+        }
+
+        let level = self.random_level();
+        let len = self.len();
+
+        if level > self.level {
+            for i in self.level..level {
+                self.nodes[HEAD].levels[i].span = len;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            levels: vec![
+                Link {
+                    next: None,
+                    span: 0,
+                };
+                level
+            ],
+            backward: (update[0] != HEAD).then_some(update[0]),
+        };
+
+        let new = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            let skipped = rank[0] - rank[i];
+
+            self.nodes[new].levels[i] = Link {
+                next: prev.next,
+                span: prev.span - skipped,
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                next: Some(new),
+                span: skipped + 1,
+            };
+        }
+
+        // Higher links now jump over one more node
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+
+        match self.nodes[new].levels[0].next {
+            Some(next) => self.nodes[next].backward = Some(new),
+            None => self.tail = Some(new),
+        }
+    }
+
+    /// Remove a member known to be in the list.
+    fn remove(&mut self, score: f64, member: &[u8]) {
+        let mut update = [HEAD; MAX_LEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if !self.before(next, score, member) {
+                    break;
+                }
+                x = next;
+            }
+
+            update[i] = x;
+        }
+
+        let removed = self.nodes[x].levels[0].next.unwrap();
+        debug_assert!(self.nodes[removed].member == *member);
+
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            let link = self.nodes[*prev].levels[i];
+
+            self.nodes[*prev].levels[i] = if link.next == Some(removed) {
+                let skipped = self.nodes[removed].levels[i];
+                Link {
+                    next: skipped.next,
+                    span: link.span + skipped.span - 1,
+                }
+            } else {
+                Link {
+                    next: link.next,
+                    span: link.span - 1,
+                }
+            };
+        }
+
+        let backward = self.nodes[removed].backward;
+        match self.nodes[removed].levels[0].next {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].next.is_none() {
+            self.level -= 1;
+        }
+
+        // Release the member, the node itself is kept for reuse
+        self.nodes[removed].member = Bytes::new();
+        self.free.push(removed);
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len() - 1 - self.free.len()
+    }
+
+    /// 0-based rank of a member.
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                // Stop past the member, landing on it
+                let node = &self.nodes[next];
+                if node.score > score || (node.score == score && node.member[..] > *member) {
+                    break;
+                }
+
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+
+        (x != HEAD && self.nodes[x].member == *member).then(|| rank - 1)
+    }
+
+    /// Node at 0-based `rank`.
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                let span = self.nodes[x].levels[i].span;
+                if traversed + span > target {
+                    break;
+                }
+
+                traversed += span;
+                x = next;
+            }
+
+            if traversed == target {
+                return Some(x);
+            }
+        }
+
+        None
+    }
+
+    /// First node for which `before` does not hold.
+    fn first_not(&self, before: impl Fn(f64, &[u8]) -> bool) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                let node = &self.nodes[next];
+                if !before(node.score, &node.member) {
+                    break;
+                }
+                x = next;
+            }
+        }
+
+        self.nodes[x].levels[0].next
+    }
+
+    /// Last node for which `after` does not hold.
+    fn last_not(&self, after: impl Fn(f64, &[u8]) -> bool) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                let node = &self.nodes[next];
+                if after(node.score, &node.member) {
+                    break;
+                }
+                x = next;
+            }
+        }
+
+        (x != HEAD).then_some(x)
+    }
+
+    /// Members from `first` onwards, in descending order with `rev`.
+    fn walk(&self, first: Option<usize>, rev: bool) -> impl Iterator<Item = (&Bytes, f64)> {
+        let mut next = first;
+
+        std::iter::from_fn(move || {
+            let node = &self.nodes[next?];
+            next = if rev {
+                node.backward
+            } else {
+                node.levels[0].next
+            };
+            Some((&node.member, node.score))
+        })
+    }
+}
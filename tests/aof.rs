@@ -0,0 +1,691 @@
+mod support;
+
+use my_redis::aof::{Aof, Fsync};
+use my_redis::db::Ttl;
+use my_redis::server::{self, Config};
+use my_redis::{Db, Frame};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use support::{command, connect, read, send};
+use tokio::net::TcpListener;
+use tokio::time::{self, Duration};
+
+/// Start a server logging its writes to `aof`.
+async fn start_server(db: Db, aof: Aof) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = Config {
+        aof: Some(aof),
+        ..Config::default()
+    };
+
+    tokio::spawn(server::run_with_config(
+        listener,
+        db,
+        config,
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}
+
+/// Start a server without persistence, to inspect a reloaded `Db`.
+async fn serve(db: Db) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(server::run(listener, db, std::future::pending::<()>()));
+
+    addr
+}
+
+/// Replay the file at `path` into a fresh `Db`, as a restarted server would.
+fn reload(path: &Path) -> Db {
+    let db = Db::new(4);
+    Aof::open(path, Fsync::Always, &db).unwrap();
+    db
+}
+
+async fn wait_for_rewrite(aof: &Aof) {
+    for _ in 0..500 {
+        if !aof.rewrite_in_progress() {
+            return;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("rewrite did not complete");
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+fn expires_within(ttl: Ttl, max: Duration) -> bool {
+    matches!(ttl, Ttl::Expires(remaining) if remaining > max - Duration::from_secs(5) && remaining <= max)
+}
+
+#[tokio::test]
+async fn writes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof).await).await;
+
+    assert_eq!(send(&mut connection, &["SET", "a", "1"]).await, "OK");
+    assert_eq!(
+        send(&mut connection, &["SET", "b", "2", "EX", "100"]).await,
+        "OK"
+    );
+    assert_eq!(send(&mut connection, &["SET", "c", "3"]).await, "OK");
+    assert_eq!(
+        send(&mut connection, &["EXPIRE", "c", "50"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["SET", "d", "4", "PX", "100000"]).await,
+        "OK"
+    );
+    assert_eq!(
+        send(&mut connection, &["PERSIST", "d"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(send(&mut connection, &["SET", "e", "5"]).await, "OK");
+    assert_eq!(
+        send(&mut connection, &["EXPIRE", "e", "0"]).await,
+        Frame::Integer(1)
+    );
+
+    let db = reload(&path);
+
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.ttl("a"), Ttl::Persistent);
+    assert!(expires_within(db.ttl("b"), Duration::from_secs(100)));
+    assert!(expires_within(db.ttl("c"), Duration::from_secs(50)));
+    assert_eq!(db.get("d").unwrap(), "4");
+    assert_eq!(db.ttl("d"), Ttl::Persistent);
+    assert_eq!(db.get("e"), None);
+}
+
+#[tokio::test]
+async fn only_successful_writes_are_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::No, &db).unwrap();
+    let mut connection = connect(start_server(db, aof).await).await;
+
+    assert_eq!(send(&mut connection, &["SET", "a", "1"]).await, "OK");
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, "1");
+    assert_eq!(
+        send(&mut connection, &["TTL", "a"]).await,
+        Frame::Integer(-1)
+    );
+    assert!(matches!(
+        send(&mut connection, &["SET", "a", "2", "EX", "0"]).await,
+        Frame::Error(_)
+    ));
+
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        b"*3\r\n$3\r\nset\r\n$1\r\na\r\n$1\r\n1\r\n"
+    );
+}
+
+#[tokio::test]
+async fn deadlines_are_absolute() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    // Written in 1970, long expired by the time the file is replayed
+    fs::write(
+        &path,
+        "*5\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n$4\r\nPXAT\r\n$4\r\n1000\r\n\
+         *3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n\
+         *3\r\n$9\r\nPEXPIREAT\r\n$1\r\nb\r\n$4\r\n1000\r\n\
+         *3\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\n3\r\n",
+    )
+    .unwrap();
+
+    let db = reload(&path);
+
+    assert_eq!(db.get("a"), None);
+    assert_eq!(db.get("b"), None);
+    assert_eq!(db.get("c").unwrap(), "3");
+}
+
+#[tokio::test]
+async fn far_deadlines_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof).await).await;
+
+    // Deadlines past the largest Unix time in milliseconds are refused
+    let max = i64::MAX.to_string();
+    for args in [
+        &["SET", "a", "1", "PX", &max][..],
+        &["SET", "a", "1", "EX", &(i64::MAX / 1000).to_string()],
+        &["EXPIRE", "a", &(i64::MAX / 1000).to_string()],
+        &["GETEX", "a", "PX", &max],
+    ] {
+        assert!(
+            matches!(send(&mut connection, args).await, Frame::Error(_)),
+            "{:?}",
+            args
+        );
+    }
+
+    // Just below it, a day's margin for the clock
+    let px = (i64::MAX - 2 * 86_400_000 - now_millis()).to_string();
+    assert_eq!(
+        send(&mut connection, &["SET", "a", "1", "PX", &px]).await,
+        "OK"
+    );
+    send(&mut connection, &["SET", "b", "2"]).await;
+    send(&mut connection, &["SET", "c", "3"]).await;
+    assert_eq!(send(&mut connection, &["GETEX", "c", "PX", &px]).await, "3");
+    let ex = (i64::MAX / 1000 - 2 * 86_400 - now_millis() / 1000).to_string();
+    assert_eq!(
+        send(&mut connection, &["EXPIRE", "b", &ex]).await,
+        Frame::Integer(1)
+    );
+
+    let db = reload(&path);
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.get("b").unwrap(), "2");
+    assert!(matches!(db.ttl("a"), Ttl::Expires(_)));
+    assert!(matches!(db.ttl("b"), Ttl::Expires(_)));
+    assert_eq!(db.get("c").unwrap(), "3");
+    assert!(matches!(db.ttl("c"), Ttl::Expires(_)));
+}
+
+#[tokio::test]
+async fn truncated_final_command_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let complete = "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+    fs::write(
+        &path,
+        format!("{}*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1", complete),
+    )
+    .unwrap();
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.get("b"), None);
+    assert_eq!(fs::read(&path).unwrap(), complete.as_bytes());
+
+    // New commands are appended after the last complete one
+    let mut connection = connect(start_server(db, aof).await).await;
+    assert_eq!(send(&mut connection, &["SET", "b", "2"]).await, "OK");
+
+    let db = reload(&path);
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.get("b").unwrap(), "2");
+}
+
+#[tokio::test]
+async fn unfinished_transaction_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let complete =
+        "*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*1\r\n$4\r\nEXEC\r\n";
+    fs::write(
+        &path,
+        format!(
+            "{}*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n",
+            complete
+        ),
+    )
+    .unwrap();
+
+    let db = reload(&path);
+
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.get("b"), None);
+    assert_eq!(fs::read(&path).unwrap(), complete.as_bytes());
+}
+
+#[tokio::test]
+async fn transactions_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof).await).await;
+
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["SET", "a", "1"]).await;
+    send(&mut connection, &["INCR", "a"]).await;
+    send(&mut connection, &["INCR", "b"]).await;
+    send(&mut connection, &["GET", "a"]).await;
+    // Fails at run time, not logged
+    send(&mut connection, &["LPUSH", "a", "x"]).await;
+    send(&mut connection, &["EXEC"]).await;
+
+    // Discarded, not logged
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["SET", "c", "1"]).await;
+    send(&mut connection, &["DISCARD"]).await;
+
+    let db = reload(&path);
+    assert_eq!(db.get("a").unwrap(), "2");
+    assert_eq!(db.get("b").unwrap(), "1");
+    assert_eq!(db.get("c"), None);
+}
+
+#[tokio::test]
+async fn corrupt_file_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    for content in [
+        "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\ngarbage\r\n",
+        "*2\r\n$3\r\nSET\r\n$1\r\na\r\n",
+    ] {
+        fs::write(&path, content).unwrap();
+
+        let db = Db::new(4);
+        assert!(
+            Aof::open(&path, Fsync::Always, &db).is_err(),
+            "{:?} was accepted",
+            content
+        );
+    }
+
+    // The error names the record
+    fs::write(
+        &path,
+        "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*2\r\n$3\r\nSET\r\n$1\r\nb\r\n",
+    )
+    .unwrap();
+
+    let err = match Aof::open(&path, Fsync::Always, &Db::new(4)) {
+        Ok(_) => panic!("the invalid record was accepted"),
+        Err(err) => err.to_string(),
+    };
+    assert!(
+        err.starts_with(
+            "invalid append only file at byte 27, in the record starting with \
+             `*2\\r\\n$3\\r\\nSET\\r\\n$1\\r\\nb\\r\\n`;"
+        ),
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn corrupt_file_can_be_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let valid = "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+    for invalid in [
+        // Not a command
+        "garbage\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n",
+        // A command that can not be replayed, in a transaction
+        "*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n\
+         *5\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\n3\r\n$4\r\nPXAT\r\n$2\r\n-1\r\n\
+         *1\r\n$4\r\nEXEC\r\n",
+    ] {
+        fs::write(&path, format!("{}{}", valid, invalid)).unwrap();
+
+        let db = Db::new(4);
+        let aof = Aof::open_truncating_invalid(&path, Fsync::Always, &db).unwrap();
+
+        assert_eq!(db.get("a").unwrap(), "1");
+        assert_eq!(db.get("b"), None);
+        assert_eq!(fs::read(&path).unwrap(), valid.as_bytes(), "{:?}", invalid);
+
+        // The server goes on from there
+        let mut connection = connect(start_server(db, aof).await).await;
+        send(&mut connection, &["SET", "b", "2"]).await;
+        assert_eq!(reload(&path).get("b").unwrap(), "2");
+    }
+}
+
+#[tokio::test]
+async fn rewrite_compacts_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof.clone()).await).await;
+
+    for i in 0..100 {
+        let value = i.to_string();
+        assert_eq!(
+            send(&mut connection, &["SET", "counter", &value]).await,
+            "OK"
+        );
+    }
+
+    assert_eq!(
+        send(&mut connection, &["BGREWRITEAOF"]).await,
+        "Background append only file rewriting started"
+    );
+    wait_for_rewrite(&aof).await;
+
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        b"*3\r\n$3\r\nset\r\n$7\r\ncounter\r\n$2\r\n99\r\n"
+    );
+
+    // The new file keeps being appended to
+    assert_eq!(
+        send(&mut connection, &["SET", "other", "1", "EX", "100"]).await,
+        "OK"
+    );
+
+    let db = reload(&path);
+    assert_eq!(db.get("counter").unwrap(), "99");
+    assert_eq!(db.get("other").unwrap(), "1");
+    assert!(expires_within(db.ttl("other"), Duration::from_secs(100)));
+}
+
+#[tokio::test]
+async fn writes_during_a_rewrite_are_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::No, &db).unwrap();
+    let addr = start_server(db, aof.clone()).await;
+    let mut connection = connect(addr).await;
+
+    for i in 0..1000 {
+        let key = format!("before-{}", i);
+        assert_eq!(send(&mut connection, &["SET", &key, "1"]).await, "OK");
+    }
+
+    // Pipelined right behind the rewrite, so some of them are likely to be
+    // applied while it runs. A counter tells if any of them ends up both in
+    // the rewritten file and kept aside.
+    connection
+        .write_frame(&command(&["BGREWRITEAOF"]))
+        .await
+        .unwrap();
+    for i in 0..1000 {
+        let key = format!("after-{}", i);
+        connection
+            .write_frame(&command(&["SET", &key, "2"]))
+            .await
+            .unwrap();
+        connection
+            .write_frame(&command(&["INCR", "counter"]))
+            .await
+            .unwrap();
+    }
+
+    for _ in 0..2001 {
+        let response = read(&mut connection).await;
+        assert!(!matches!(response, Frame::Error(_)), "{:?}", response);
+    }
+
+    wait_for_rewrite(&aof).await;
+
+    let db = reload(&path);
+    for i in 0..1000 {
+        assert_eq!(db.get(&format!("before-{}", i)).unwrap(), "1");
+        assert_eq!(db.get(&format!("after-{}", i)).unwrap(), "2");
+    }
+    assert_eq!(db.get("counter").unwrap(), "1000");
+}
+
+#[tokio::test]
+async fn list_writes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let addr = start_server(db, aof.clone()).await;
+    let mut connection = connect(addr).await;
+    let mut blocked = connect(addr).await;
+
+    let elements: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+    let mut args = vec!["RPUSH", "long"];
+    args.extend(elements.iter().map(String::as_str));
+    assert_eq!(send(&mut connection, &args).await, Frame::Integer(100));
+
+    send(&mut connection, &["RPUSH", "list", "a", "b", "c", "d"]).await;
+    send(&mut connection, &["LPOP", "list"]).await;
+    send(&mut connection, &["LTRIM", "list", "0", "1"]).await;
+    send(
+        &mut connection,
+        &["LMOVE", "list", "moved", "RIGHT", "LEFT"],
+    )
+    .await;
+    send(&mut connection, &["BLPOP", "list", "0"]).await;
+
+    // Handed over to a blocked client, the element must not come back
+    blocked
+        .write_frame(&command(&["BLPOP", "handed", "0"]))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+    send(&mut connection, &["RPUSH", "handed", "x", "y"]).await;
+    read(&mut blocked).await;
+
+    let expected = [
+        ("list", vec![]),
+        ("moved", vec!["c"]),
+        ("handed", vec!["y"]),
+        ("long", elements.iter().map(String::as_str).collect()),
+    ];
+
+    let check = |db: Db| async {
+        let mut connection = connect(serve(db).await).await;
+
+        for (key, elements) in &expected {
+            let response = send(&mut connection, &["LRANGE", key, "0", "-1"]).await;
+            let elements = elements
+                .iter()
+                .map(|element| Frame::Bulk(element.to_string().into()))
+                .collect();
+            assert_eq!(response, Frame::Array(elements), "{}", key);
+        }
+    };
+
+    check(reload(&path)).await;
+
+    assert_eq!(
+        send(&mut connection, &["BGREWRITEAOF"]).await,
+        "Background append only file rewriting started"
+    );
+    wait_for_rewrite(&aof).await;
+
+    check(reload(&path)).await;
+}
+
+#[tokio::test]
+async fn hash_writes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof.clone()).await).await;
+
+    for i in 0..100 {
+        let field = format!("f{}", i);
+        send(&mut connection, &["HSET", "big", &field, "1"]).await;
+    }
+    send(&mut connection, &["HSET", "hash", "a", "1", "b", "2"]).await;
+    send(&mut connection, &["HINCRBY", "hash", "a", "10"]).await;
+    send(&mut connection, &["HDEL", "hash", "b"]).await;
+    // Failed, not logged
+    send(&mut connection, &["HINCRBY", "big", "f0", "x"]).await;
+
+    let check = |db: Db| async {
+        let mut connection = connect(serve(db).await).await;
+
+        assert_eq!(send(&mut connection, &["HGET", "hash", "a"]).await, "11");
+        assert_eq!(
+            send(&mut connection, &["HEXISTS", "hash", "b"]).await,
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            send(&mut connection, &["HLEN", "big"]).await,
+            Frame::Integer(100)
+        );
+    };
+
+    check(reload(&path)).await;
+
+    assert_eq!(
+        send(&mut connection, &["BGREWRITEAOF"]).await,
+        "Background append only file rewriting started"
+    );
+    wait_for_rewrite(&aof).await;
+
+    check(reload(&path)).await;
+}
+
+#[tokio::test]
+async fn set_writes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof.clone()).await).await;
+
+    send(&mut connection, &["SADD", "set", "a", "b", "c"]).await;
+    send(&mut connection, &["SREM", "set", "b"]).await;
+    send(
+        &mut connection,
+        &["ZADD", "zset", "1", "a", "2", "b", "3", "c"],
+    )
+    .await;
+    send(&mut connection, &["ZINCRBY", "zset", "0.5", "a"]).await;
+    send(&mut connection, &["ZADD", "zset", "GT", "INCR", "10", "b"]).await;
+    send(&mut connection, &["ZPOPMIN", "zset"]).await;
+    send(&mut connection, &["ZREM", "zset", "missing"]).await;
+
+    let check = |db: Db| async {
+        let mut connection = connect(serve(db).await).await;
+
+        assert_eq!(
+            send(&mut connection, &["SISMEMBER", "set", "a"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            send(&mut connection, &["SISMEMBER", "set", "b"]).await,
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            send(
+                &mut connection,
+                &["ZRANGE", "zset", "0", "-1", "WITHSCORES"]
+            )
+            .await,
+            Frame::Array(
+                ["c", "3", "b", "12"]
+                    .iter()
+                    .map(|item| Frame::Bulk(item.to_string().into()))
+                    .collect()
+            )
+        );
+    };
+
+    check(reload(&path)).await;
+
+    assert_eq!(
+        send(&mut connection, &["BGREWRITEAOF"]).await,
+        "Background append only file rewriting started"
+    );
+    wait_for_rewrite(&aof).await;
+
+    check(reload(&path)).await;
+}
+
+#[tokio::test]
+async fn string_writes_survive_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("appendonly.aof");
+
+    let db = Db::new(4);
+    let aof = Aof::open(&path, Fsync::Always, &db).unwrap();
+    let mut connection = connect(start_server(db, aof.clone()).await).await;
+
+    send(&mut connection, &["INCR", "counter"]).await;
+    send(&mut connection, &["DECRBY", "counter", "5"]).await;
+    send(&mut connection, &["INCRBYFLOAT", "float", "0.1"]).await;
+    send(&mut connection, &["INCRBYFLOAT", "float", "0.2"]).await;
+    send(&mut connection, &["APPEND", "s", "Hello"]).await;
+    send(&mut connection, &["SETRANGE", "s", "1", "ALLO"]).await;
+    send(&mut connection, &["MSET", "a", "1", "b", "2"]).await;
+    send(&mut connection, &["MSETNX", "b", "3", "c", "3"]).await;
+    send(&mut connection, &["SETNX", "d", "4"]).await;
+    send(&mut connection, &["GETSET", "a", "10"]).await;
+    send(&mut connection, &["GETDEL", "b"]).await;
+    send(&mut connection, &["GETEX", "d", "EX", "100"]).await;
+    // Failed, not logged
+    send(&mut connection, &["INCR", "s"]).await;
+
+    let check = |db: Db| async {
+        let mut connection = connect(serve(db).await).await;
+
+        assert_eq!(send(&mut connection, &["GET", "counter"]).await, "-4");
+        assert_eq!(
+            send(&mut connection, &["GET", "float"]).await,
+            "0.30000000000000004"
+        );
+        assert_eq!(send(&mut connection, &["GET", "s"]).await, "HALLO");
+        assert_eq!(send(&mut connection, &["GET", "a"]).await, "10");
+        assert_eq!(send(&mut connection, &["GET", "b"]).await, Frame::Null);
+        assert_eq!(send(&mut connection, &["GET", "c"]).await, Frame::Null);
+        assert_eq!(
+            send(&mut connection, &["TTL", "d"]).await,
+            Frame::Integer(100)
+        );
+    };
+
+    check(reload(&path)).await;
+
+    assert_eq!(
+        send(&mut connection, &["BGREWRITEAOF"]).await,
+        "Background append only file rewriting started"
+    );
+    wait_for_rewrite(&aof).await;
+
+    check(reload(&path)).await;
+}
+
+#[tokio::test]
+async fn rewrite_requires_an_append_only_file() {
+    let mut connection = connect(support::start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["BGREWRITEAOF"]).await,
+        Frame::Error("ERR append only file is disabled".to_string())
+    );
+}
+
+#[test]
+fn fsync_policies() {
+    assert_eq!("always".parse::<Fsync>().unwrap(), Fsync::Always);
+    assert_eq!("everysec".parse::<Fsync>().unwrap(), Fsync::EverySec);
+    assert_eq!("no".parse::<Fsync>().unwrap(), Fsync::No);
+    assert!("sometimes".parse::<Fsync>().is_err());
+}
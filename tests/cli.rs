@@ -0,0 +1,194 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::cli::{format_frame, split_args};
+use my_redis::Frame;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use support::start_server;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+fn bulk(val: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(val.as_bytes()))
+}
+
+/// Run the `client` binary against `addr`, feeding it `stdin`, and return
+/// its standard output.
+async fn client(addr: SocketAddr, args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_client"))
+        .args(["-h", "127.0.0.1", "-p", &addr.port().to_string()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin.as_bytes()).await.unwrap();
+    drop(input);
+
+    let output = child.wait_with_output().await.unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn split_plain_arguments() {
+    assert_eq!(split_args("GET foo").unwrap(), vec!["GET", "foo"]);
+    assert_eq!(
+        split_args("  SET\tfoo   bar  ").unwrap(),
+        vec!["SET", "foo", "bar"]
+    );
+    assert!(split_args("").unwrap().is_empty());
+    assert!(split_args("   ").unwrap().is_empty());
+}
+
+#[test]
+fn split_quoted_arguments() {
+    let args = split_args(r#"SET "hello world" 'it''s'"#);
+    assert!(args.is_err(), "quotes must be followed by a space");
+
+    let args = split_args(r#"SET "hello world" 'it\'s' """#).unwrap();
+    assert_eq!(args, vec!["SET", "hello world", "it's", ""]);
+
+    let args = split_args(r#"SET k "a\"b\\c\n\x41\x7e""#).unwrap();
+    assert_eq!(args, vec!["SET", "k", "a\"b\\c\nA~"]);
+
+    // Single quotes do not interpret escapes
+    let args = split_args(r"SET k 'a\nb'").unwrap();
+    assert_eq!(args, vec!["SET", "k", "a\\nb"]);
+
+    // Escaped bytes do not have to be valid UTF-8
+    let args = split_args(r#"SET k "\xff\x00""#).unwrap();
+    assert_eq!(&args[2][..], b"\xff\x00");
+}
+
+#[test]
+fn split_invalid_arguments() {
+    for line in [r#"GET "foo"#, "GET 'foo", r#"GET "\x4""#, r#"GET "\xzz""#] {
+        let err = split_args(line).unwrap_err();
+        assert_eq!("Invalid argument(s)", err.to_string(), "{}", line);
+    }
+}
+
+#[test]
+fn format_scalars() {
+    assert_eq!(format_frame(&Frame::Simple("OK".into())), "OK");
+    assert_eq!(
+        format_frame(&Frame::Error("ERR nope".into())),
+        "(error) ERR nope"
+    );
+    assert_eq!(format_frame(&Frame::Integer(-3)), "(integer) -3");
+    assert_eq!(format_frame(&Frame::Null), "(nil)");
+    assert_eq!(format_frame(&bulk("bar")), "\"bar\"");
+    assert_eq!(
+        format_frame(&Frame::Bulk(Bytes::from_static(b"a\"b\n\x00\xff"))),
+        r#""a\"b\n\x00\xff""#
+    );
+    assert_eq!(format_frame(&Frame::Array(vec![])), "(empty array)");
+}
+
+#[test]
+fn format_nested_arrays() {
+    let frame = Frame::Array(vec![
+        bulk("a"),
+        Frame::Array(vec![bulk("b"), Frame::Array(vec![Frame::Integer(1)])]),
+        Frame::Null,
+    ]);
+
+    let expected = "\
+1) \"a\"
+2) 1) \"b\"
+   2) 1) (integer) 1
+3) (nil)";
+
+    assert_eq!(format_frame(&frame), expected);
+}
+
+#[test]
+fn format_resp3_frames() {
+    assert_eq!(format_frame(&Frame::Double(1.5)), "(double) 1.5");
+    assert_eq!(format_frame(&Frame::Boolean(true)), "(true)");
+    assert_eq!(
+        format_frame(&Frame::BigNumber("12345678901234567890".into())),
+        "(big number) 12345678901234567890"
+    );
+    assert_eq!(
+        format_frame(&Frame::Verbatim("txt".into(), Bytes::from("a\nb"))),
+        "a\nb"
+    );
+    assert_eq!(format_frame(&Frame::Set(vec![])), "(empty set)");
+    assert_eq!(format_frame(&Frame::Map(vec![])), "(empty hash)");
+
+    let frame = Frame::Map(vec![
+        (bulk("name"), bulk("foo")),
+        (bulk("tags"), Frame::Set(vec![bulk("a"), bulk("b")])),
+    ]);
+
+    let expected = "\
+1# \"name\" => \"foo\"
+2# \"tags\" => 1~ \"a\"
+             2~ \"b\"";
+
+    assert_eq!(format_frame(&frame), expected);
+}
+
+#[test]
+fn format_aligns_indices() {
+    let frame = Frame::Array((0..10).map(Frame::Integer).collect());
+    let out = format_frame(&frame);
+    let lines: Vec<_> = out.lines().collect();
+
+    assert_eq!(lines[0], " 1) (integer) 0");
+    assert_eq!(lines[9], "10) (integer) 9");
+}
+
+#[tokio::test]
+async fn one_shot_command() {
+    let addr = start_server().await;
+
+    assert_eq!(client(addr, &["SET", "foo", "a b"], "").await, "OK\n");
+    assert_eq!(client(addr, &["GET", "foo"], "").await, "\"a b\"\n");
+    assert_eq!(client(addr, &["GET", "missing"], "").await, "(nil)\n");
+    assert_eq!(
+        client(addr, &["NOPE"], "").await,
+        "(error) ERR unknown command 'nope'\n"
+    );
+}
+
+#[tokio::test]
+async fn commands_from_stdin() {
+    let addr = start_server().await;
+
+    let script = "\
+SET greeting \"hello world\"
+get greeting
+
+TTL greeting
+PING
+SUBSCRIBE
+";
+
+    let expected = "\
+OK
+\"hello world\"
+(integer) -1
+PONG
+(error) ERR wrong number of arguments for 'subscribe' command
+";
+
+    assert_eq!(client(addr, &[], script).await, expected);
+}
+
+#[tokio::test]
+async fn quit_stops_reading_commands() {
+    let addr = start_server().await;
+
+    let output = client(addr, &[], "SET a 1\nquit\nSET a 2\n").await;
+    assert_eq!(output, "OK\n");
+
+    assert_eq!(client(addr, &["GET", "a"], "").await, "\"1\"\n");
+}
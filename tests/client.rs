@@ -0,0 +1,163 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::{client, Connection, Frame};
+use support::start_server;
+use tokio::net::TcpListener;
+use tokio::time::{self, Duration};
+
+#[tokio::test]
+async fn ping_pong() {
+    let addr = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(Bytes::from("PONG"), client.ping(None).await.unwrap());
+
+    let echo = client.ping(Some("hello".into())).await.unwrap();
+    assert_eq!(Bytes::from("hello"), echo);
+}
+
+#[tokio::test]
+async fn set_then_get() {
+    let addr = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(None, client.get("hello").await.unwrap());
+
+    client.set("hello", "world".into()).await.unwrap();
+    assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+}
+
+#[tokio::test]
+async fn binary_values_round_trip() {
+    let addr = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    let value = Bytes::from_static(b"\x00\xff\r\n\x01");
+    client.set("bin", value.clone()).await.unwrap();
+    assert_eq!(Some(value), client.get("bin").await.unwrap());
+}
+
+#[tokio::test]
+async fn set_expires() {
+    let addr = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    client
+        .set_expires("hello", "world".into(), Duration::from_millis(50))
+        .await
+        .unwrap();
+    assert_eq!(Some("world".into()), client.get("hello").await.unwrap());
+
+    time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(None, client.get("hello").await.unwrap());
+}
+
+#[tokio::test]
+async fn numeric_arguments_are_bulk_strings() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut connection = Connection::new(socket);
+
+        let frame = connection.read_frame().await.unwrap().unwrap();
+        connection
+            .write_frame(&Frame::Simple("OK".to_string()))
+            .await
+            .unwrap();
+        frame
+    });
+
+    let mut client = client::connect(addr).await.unwrap();
+    client
+        .set_expires("hello", "world".into(), Duration::from_millis(1500))
+        .await
+        .unwrap();
+
+    // Servers only accept commands made of bulk strings
+    let args = ["set", "hello", "world", "px", "1500"];
+    assert_eq!(
+        Frame::Array(args.map(|arg| Frame::Bulk(Bytes::from(arg))).to_vec()),
+        server.await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn server_errors_are_returned() {
+    let addr = start_server().await;
+    let mut client = client::connect(addr).await.unwrap();
+
+    // A zero expiration is rejected by the server
+    let err = client
+        .set_expires("hello", "world".into(), Duration::ZERO)
+        .await
+        .unwrap_err();
+    assert_eq!("ERR invalid expire time in 'set' command", err.to_string());
+
+    // The client is still usable
+    assert_eq!(None, client.get("hello").await.unwrap());
+}
+
+#[tokio::test]
+async fn connect_error() {
+    // Bind then drop a listener to find a port nobody listens on
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    assert!(client::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn publish_and_subscribe() {
+    let addr = start_server().await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec!["news".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(&["news".to_string()], subscriber.get_subscribed());
+
+    let mut publisher = client::connect(addr).await.unwrap();
+    assert_eq!(1, publisher.publish("news", "hello".into()).await.unwrap());
+    assert_eq!(0, publisher.publish("other", "nope".into()).await.unwrap());
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("news", message.channel);
+    assert_eq!(Bytes::from("hello"), message.content);
+}
+
+#[tokio::test]
+async fn subscribe_and_unsubscribe_more_channels() {
+    let addr = start_server().await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber.subscribe(vec!["a".to_string()]).await.unwrap();
+
+    subscriber
+        .subscribe(&["b".to_string(), "c".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(3, subscriber.get_subscribed().len());
+
+    subscriber.unsubscribe(&["a".to_string()]).await.unwrap();
+    assert_eq!(
+        &["b".to_string(), "c".to_string()],
+        subscriber.get_subscribed()
+    );
+
+    let mut publisher = client::connect(addr).await.unwrap();
+    assert_eq!(0, publisher.publish("a", "one".into()).await.unwrap());
+    assert_eq!(1, publisher.publish("c", "two".into()).await.unwrap());
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("c", message.channel);
+    assert_eq!(Bytes::from("two"), message.content);
+
+    // Unsubscribing from everything
+    subscriber.unsubscribe(&[]).await.unwrap();
+    assert!(subscriber.get_subscribed().is_empty());
+}
@@ -0,0 +1,172 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::client::{self, ServerError};
+use my_redis::db::Ttl;
+use my_redis::{ClientHandle, Connection};
+use std::time::Duration;
+use support::start_server;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time;
+
+async fn handle(queue_depth: usize) -> ClientHandle {
+    let addr = start_server().await;
+    let client = client::connect(addr).await.unwrap();
+
+    ClientHandle::new(client, queue_depth)
+}
+
+#[tokio::test]
+async fn every_command() {
+    let handle = handle(4).await;
+
+    assert_eq!(Bytes::from("PONG"), handle.ping(None).await.unwrap());
+    assert_eq!(
+        Bytes::from("hi"),
+        handle.ping(Some("hi".into())).await.unwrap()
+    );
+
+    assert_eq!(None, handle.get("key").await.unwrap());
+    assert_eq!(Ttl::Missing, handle.ttl("key").await.unwrap());
+
+    handle.set("key", "value".into()).await.unwrap();
+    assert_eq!(Some("value".into()), handle.get("key").await.unwrap());
+    assert_eq!(Ttl::Persistent, handle.ttl("key").await.unwrap());
+
+    assert!(handle
+        .expire("key", Duration::from_secs(100))
+        .await
+        .unwrap());
+    assert!(!handle
+        .expire("missing", Duration::from_secs(100))
+        .await
+        .unwrap());
+    match handle.ttl("key").await.unwrap() {
+        Ttl::Expires(remaining) => assert!(remaining > Duration::from_secs(99)),
+        ttl => panic!("unexpected ttl {:?}", ttl),
+    }
+
+    assert!(handle.persist("key").await.unwrap());
+    assert!(!handle.persist("key").await.unwrap());
+    assert_eq!(Ttl::Persistent, handle.ttl("key").await.unwrap());
+
+    handle
+        .set_expires("short", "lived".into(), Duration::from_millis(20))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(None, handle.get("short").await.unwrap());
+
+    assert_eq!(0, handle.publish("news", "hello".into()).await.unwrap());
+}
+
+#[tokio::test]
+async fn clones_share_one_connection() {
+    let handle = handle(2).await;
+
+    // More concurrent requests than the queue holds
+    let tasks: Vec<_> = (0..32)
+        .map(|i| {
+            let handle = handle.clone();
+
+            tokio::spawn(async move {
+                let key = format!("key:{}", i);
+                handle.set(&key, i.to_string().into()).await.unwrap();
+                handle.get(&key).await.unwrap()
+            })
+        })
+        .collect();
+
+    for (i, task) in tasks.into_iter().enumerate() {
+        assert_eq!(Some(Bytes::from(i.to_string())), task.await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn server_errors_do_not_stop_the_manager() {
+    let handle = handle(4).await;
+
+    let err = handle
+        .set_expires("key", "value".into(), Duration::ZERO)
+        .await
+        .unwrap_err();
+    assert!(err.is::<ServerError>());
+    assert_eq!("ERR invalid expire time in 'set' command", err.to_string());
+
+    assert_eq!(Bytes::from("PONG"), handle.ping(None).await.unwrap());
+}
+
+#[tokio::test]
+async fn dropping_the_last_handle_closes_the_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (client, accepted) = tokio::join!(client::connect(addr), listener.accept());
+    let mut server_side = Connection::new(accepted.unwrap().0);
+
+    let handle = ClientHandle::new(client.unwrap(), 4);
+    let clone = handle.clone();
+
+    drop(handle);
+
+    // One handle is still alive, the connection stays open
+    let open = time::timeout(Duration::from_millis(50), server_side.read_frame()).await;
+    assert!(open.is_err());
+
+    drop(clone);
+
+    let closed = time::timeout(Duration::from_secs(5), server_side.read_frame())
+        .await
+        .expect("connection was not closed");
+    assert_eq!(None, closed.unwrap());
+}
+
+#[tokio::test]
+async fn errors_once_the_manager_is_gone() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (client, accepted) = tokio::join!(client::connect(addr), listener.accept());
+    let handle = ClientHandle::new(client.unwrap(), 4);
+
+    // The server goes away
+    drop(accepted.unwrap());
+
+    // The request that finds out about it gets the connection error, which
+    // stops the manager task.
+    let err = handle.get("key").await.unwrap_err();
+    assert!(err.is::<std::io::Error>(), "{}", err);
+
+    for _ in 0..2 {
+        let err = handle.get("key").await.unwrap_err();
+        assert_eq!("client manager task has stopped", err.to_string());
+    }
+}
+
+#[tokio::test]
+async fn garbage_from_the_server_stops_the_manager() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (client, accepted) = tokio::join!(client::connect(addr), listener.accept());
+    let handle = ClientHandle::new(client.unwrap(), 4);
+
+    // Answers the first request with something that is not RESP
+    let mut socket = accepted.unwrap().0;
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        assert!(socket.read(&mut buf).await.unwrap() > 0);
+        socket.write_all(b"*garbage\r\n").await.unwrap();
+
+        // Keep the connection open
+        while socket.read(&mut buf).await.unwrap() > 0 {}
+    });
+
+    let err = handle.get("key").await.unwrap_err();
+    assert!(!err.is::<ServerError>(), "{}", err);
+
+    // The replies that follow could not be matched with their requests
+    let err = handle.get("key").await.unwrap_err();
+    assert_eq!("client manager task has stopped", err.to_string());
+}
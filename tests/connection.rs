@@ -0,0 +1,420 @@
+use bytes::Bytes;
+use my_redis::connection::{Limits, Protocol};
+use my_redis::{Command, Connection, Frame};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Open a TCP connection to ourselves and return both ends.
+async fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+
+    (client.unwrap(), server.unwrap().0)
+}
+
+/// Wrap both ends of a local TCP connection in a `Connection`.
+async fn pair() -> (Connection, Connection) {
+    let (client, server) = socket_pair().await;
+    (Connection::new(client), Connection::new(server))
+}
+
+/// Write `frame` on one end and assert that the other end parses it back.
+async fn assert_round_trip(frame: Frame) {
+    let (mut tx, mut rx) = pair().await;
+
+    tx.write_frame(&frame).await.unwrap();
+    let received = rx.read_frame().await.unwrap().unwrap();
+
+    assert_eq!(frame, received);
+}
+
+fn bulk(val: &'static str) -> Frame {
+    Frame::Bulk(Bytes::from(val))
+}
+
+#[tokio::test]
+async fn empty_array() {
+    assert_round_trip(Frame::Array(vec![])).await;
+}
+
+#[tokio::test]
+async fn flat_array_of_every_variant() {
+    assert_round_trip(Frame::Array(vec![
+        Frame::Simple("OK".to_string()),
+        Frame::Error("ERR oops".to_string()),
+        Frame::Integer(42),
+        Frame::Integer(-1),
+        bulk("hello"),
+        Frame::Bulk(Bytes::new()),
+        Frame::Null,
+    ]))
+    .await;
+}
+
+#[tokio::test]
+async fn array_of_nulls() {
+    assert_round_trip(Frame::Array(vec![Frame::Null, bulk("a"), Frame::Null])).await;
+}
+
+#[tokio::test]
+async fn nested_arrays() {
+    assert_round_trip(Frame::Array(vec![
+        bulk("message"),
+        Frame::Array(vec![
+            bulk("inner"),
+            Frame::Array(vec![Frame::Integer(1), Frame::Null]),
+            Frame::Array(vec![]),
+        ]),
+        Frame::Integer(7),
+    ]))
+    .await;
+}
+
+#[tokio::test]
+async fn array_encoding_matches_resp() {
+    let (tx, mut rx) = socket_pair().await;
+    let mut tx = Connection::new(tx);
+
+    tx.write_frame(&Frame::Array(vec![
+        bulk("GET"),
+        Frame::Null,
+        Frame::Array(vec![Frame::Integer(3)]),
+    ]))
+    .await
+    .unwrap();
+
+    let expected = b"*3\r\n$3\r\nGET\r\n$-1\r\n*1\r\n:3\r\n";
+    let mut buf = vec![0; expected.len()];
+    rx.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(&expected[..], &buf[..]);
+}
+
+#[tokio::test]
+async fn several_frames_in_sequence() {
+    let (mut tx, mut rx) = pair().await;
+
+    let frames = vec![
+        Frame::Array(vec![bulk("one"), Frame::Null]),
+        Frame::Simple("two".to_string()),
+        Frame::Array(vec![Frame::Array(vec![bulk("three")])]),
+    ];
+
+    for frame in &frames {
+        tx.write_frame(frame).await.unwrap();
+    }
+
+    for frame in &frames {
+        let received = rx.read_frame().await.unwrap().unwrap();
+        assert_eq!(*frame, received);
+    }
+}
+
+#[tokio::test]
+async fn buffered_frames_are_sent_on_flush() {
+    let (mut tx, mut rx) = pair().await;
+
+    tx.buffer_frame(&bulk("one")).await.unwrap();
+    tx.buffer_frame(&bulk("two")).await.unwrap();
+
+    // Nothing reaches the socket before the flush
+    let early = tokio::time::timeout(Duration::from_millis(50), rx.read_frame()).await;
+    assert!(early.is_err());
+
+    tx.flush().await.unwrap();
+
+    assert_eq!(bulk("one"), rx.read_frame().await.unwrap().unwrap());
+
+    // Both frames arrived together, the second one is already buffered
+    assert_eq!(Some(bulk("two")), rx.parse_frame().unwrap());
+    assert_eq!(None, rx.parse_frame().unwrap());
+}
+
+/// Every RESP3 frame type, nested in a map.
+fn resp3_frame() -> Frame {
+    Frame::Map(vec![
+        (bulk("double"), Frame::Double(-2.5)),
+        (bulk("infinity"), Frame::Double(f64::INFINITY)),
+        (bulk("true"), Frame::Boolean(true)),
+        (bulk("false"), Frame::Boolean(false)),
+        (
+            bulk("big"),
+            Frame::BigNumber("-3492890328409238509324850943850943825024385".to_string()),
+        ),
+        (
+            bulk("verbatim"),
+            Frame::Verbatim("txt".to_string(), Bytes::from("Some string")),
+        ),
+        (
+            bulk("set"),
+            Frame::Set(vec![Frame::Integer(1), bulk("two")]),
+        ),
+        (bulk("null"), Frame::Null),
+        (
+            bulk("attribute"),
+            Frame::Attribute(
+                vec![(bulk("ttl"), Frame::Integer(3600))],
+                Box::new(Frame::Array(vec![bulk("value")])),
+            ),
+        ),
+        (bulk("push"), Frame::Push(vec![bulk("message"), bulk("hi")])),
+    ])
+}
+
+#[tokio::test]
+async fn resp3_frames_round_trip() {
+    let (mut tx, mut rx) = pair().await;
+    tx.set_protocol(Protocol::Resp3);
+
+    tx.write_frame(&resp3_frame()).await.unwrap();
+    let received = rx.read_frame().await.unwrap().unwrap();
+
+    assert_eq!(resp3_frame(), received);
+}
+
+#[tokio::test]
+async fn resp3_encoding_matches_resp() {
+    let (tx, mut rx) = socket_pair().await;
+    let mut tx = Connection::new(tx);
+    tx.set_protocol(Protocol::Resp3);
+
+    tx.write_frame(&Frame::Map(vec![
+        (bulk("a"), Frame::Null),
+        (
+            Frame::Double(f64::NAN),
+            Frame::Set(vec![
+                Frame::Boolean(true),
+                Frame::Verbatim("txt".to_string(), Bytes::from("hi")),
+            ]),
+        ),
+    ]))
+    .await
+    .unwrap();
+    tx.write_frame(&Frame::Push(vec![Frame::BigNumber("1".to_string())]))
+        .await
+        .unwrap();
+
+    let expected = b"%2\r\n$1\r\na\r\n_\r\n,nan\r\n~2\r\n#t\r\n=6\r\ntxt:hi\r\n>1\r\n(1\r\n";
+    let mut buf = vec![0; expected.len()];
+    rx.read_exact(&mut buf).await.unwrap();
+
+    assert_eq!(&expected[..], &buf[..]);
+}
+
+#[tokio::test]
+async fn resp3_frames_are_downgraded_for_resp2() {
+    let (mut tx, mut rx) = pair().await;
+
+    tx.write_frame(&resp3_frame()).await.unwrap();
+
+    let expected = Frame::Array(vec![
+        bulk("double"),
+        bulk("-2.5"),
+        bulk("infinity"),
+        bulk("inf"),
+        bulk("true"),
+        Frame::Integer(1),
+        bulk("false"),
+        Frame::Integer(0),
+        bulk("big"),
+        bulk("-3492890328409238509324850943850943825024385"),
+        bulk("verbatim"),
+        bulk("Some string"),
+        bulk("set"),
+        Frame::Array(vec![Frame::Integer(1), bulk("two")]),
+        bulk("null"),
+        Frame::Null,
+        bulk("attribute"),
+        Frame::Array(vec![bulk("value")]),
+        bulk("push"),
+        Frame::Array(vec![bulk("message"), bulk("hi")]),
+    ]);
+
+    assert_eq!(Some(expected), rx.read_frame().await.unwrap());
+}
+
+#[tokio::test]
+async fn invalid_resp3_frames_are_rejected() {
+    let frames: [&[u8]; 5] = [
+        b"#x\r\n",
+        b"(12a\r\n",
+        b",one\r\n",
+        b"=3\r\ntxt\r\n",
+        b"_x\r\n",
+    ];
+
+    for bytes in frames {
+        let (tx, rx) = socket_pair().await;
+        let mut rx = Connection::new(rx);
+
+        let writer = tokio::spawn(send_in_chunks(tx, vec![bytes.to_vec()]));
+
+        assert!(rx.read_frame().await.is_err(), "{:?}", bytes);
+        writer.await.unwrap();
+    }
+}
+
+/// A stream holding one frame of every kind, back to back.
+fn mixed_stream() -> (Vec<u8>, Vec<Frame>) {
+    let bytes = b"+OK\r\n-ERR bad\r\n:123\r\n$5\r\nhello\r\n$0\r\n\r\n$-1\r\n\
+                  *2\r\n$3\r\nGET\r\n*2\r\n:1\r\n$-1\r\n*0\r\n"
+        .to_vec();
+
+    let frames = vec![
+        Frame::Simple("OK".to_string()),
+        Frame::Error("ERR bad".to_string()),
+        Frame::Integer(123),
+        bulk("hello"),
+        Frame::Bulk(Bytes::new()),
+        Frame::Null,
+        Frame::Array(vec![
+            bulk("GET"),
+            Frame::Array(vec![Frame::Integer(1), Frame::Null]),
+        ]),
+        Frame::Array(vec![]),
+    ];
+
+    (bytes, frames)
+}
+
+/// Send `chunks` one at a time, giving the reader a chance to see each chunk
+/// on its own before the next one arrives, then close the socket.
+async fn send_in_chunks(mut socket: TcpStream, chunks: Vec<Vec<u8>>) {
+    for chunk in chunks {
+        socket.write_all(&chunk).await.unwrap();
+        socket.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+}
+
+/// Read frames until the peer closes the connection.
+async fn read_all(connection: &mut Connection) -> my_redis::Result<Vec<Frame>> {
+    let mut frames = vec![];
+
+    while let Some(frame) = connection.read_frame().await? {
+        frames.push(frame);
+    }
+
+    Ok(frames)
+}
+
+#[tokio::test]
+async fn read_frame_with_stream_split_at_every_offset() {
+    let (bytes, expected) = mixed_stream();
+
+    for offset in 0..=bytes.len() {
+        let (tx, rx) = socket_pair().await;
+        let mut rx = Connection::new(rx);
+
+        let chunks = vec![bytes[..offset].to_vec(), bytes[offset..].to_vec()];
+        let writer = tokio::spawn(send_in_chunks(tx, chunks));
+
+        let frames = read_all(&mut rx).await.unwrap();
+        writer.await.unwrap();
+
+        assert_eq!(expected, frames, "split at offset {}", offset);
+    }
+}
+
+#[tokio::test]
+async fn read_frame_with_stream_split_into_single_bytes() {
+    let (bytes, expected) = mixed_stream();
+
+    let (tx, rx) = socket_pair().await;
+    let mut rx = Connection::new(rx);
+
+    let chunks = bytes.iter().map(|b| vec![*b]).collect();
+    let writer = tokio::spawn(send_in_chunks(tx, chunks));
+
+    let frames = read_all(&mut rx).await.unwrap();
+    writer.await.unwrap();
+
+    assert_eq!(expected, frames);
+}
+
+#[tokio::test]
+async fn read_frame_errors_when_closed_mid_frame() {
+    let bytes = b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n";
+
+    // Cutting the stream anywhere inside the frame leaves a partial frame in
+    // the buffer when the peer goes away.
+    for offset in 1..bytes.len() {
+        let (tx, rx) = socket_pair().await;
+        let mut rx = Connection::new(rx);
+
+        let writer = tokio::spawn(send_in_chunks(tx, vec![bytes[..offset].to_vec()]));
+
+        assert!(
+            rx.read_frame().await.is_err(),
+            "truncated at offset {}",
+            offset
+        );
+        writer.await.unwrap();
+    }
+}
+
+#[test]
+fn parsed_bulk_strings_share_the_source_buffer() {
+    let src = Bytes::from_static(b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n");
+    let range = src.as_ptr_range();
+
+    let mut cursor = std::io::Cursor::new(&src[..]);
+    Frame::check(&mut cursor, &Limits::default()).unwrap();
+
+    let mut rest = src.clone();
+    let frame = Frame::parse(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    let parts = match frame {
+        Frame::Array(parts) => parts,
+        frame => panic!("unexpected frame {:?}", frame),
+    };
+
+    for part in parts {
+        match part {
+            Frame::Bulk(data) => assert!(range.contains(&data.as_ptr())),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+}
+
+#[test]
+fn stored_values_do_not_share_the_source_buffer() {
+    let src = Bytes::from_static(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n");
+    let range = src.as_ptr_range();
+
+    let frame = Frame::parse(&mut src.clone()).unwrap();
+
+    match Command::from_frame(frame).unwrap() {
+        Command::Set(set) => {
+            assert_eq!(set.value(), "value");
+            assert!(!range.contains(&set.value().as_ptr()));
+        }
+        cmd => panic!("unexpected command {:?}", cmd),
+    }
+}
+
+#[tokio::test]
+async fn large_bulk_strings_round_trip() {
+    let (mut tx, mut rx) = pair().await;
+
+    // Several times larger than the read buffer, and not a round size
+    let value: Bytes = (0..3 * 1024 * 1024 + 7).map(|i| i as u8).collect();
+    let frame = Frame::Array(vec![bulk("SET"), bulk("big"), Frame::Bulk(value)]);
+
+    let writer = tokio::spawn(async move {
+        tx.buffer_frame(&frame).await.unwrap();
+        tx.buffer_frame(&bulk("after")).await.unwrap();
+        tx.flush().await.unwrap();
+        frame
+    });
+
+    let received = rx.read_frame().await.unwrap().unwrap();
+    let frame = writer.await.unwrap();
+
+    assert_eq!(frame, received);
+    assert_eq!(Some(bulk("after")), rx.read_frame().await.unwrap());
+}
@@ -0,0 +1,70 @@
+mod support;
+
+use my_redis::server::{self, Config};
+use my_redis::Db;
+use std::net::SocketAddr;
+use support::{command, connect, read, send};
+use tokio::net::TcpListener;
+use tokio::time::{self, Duration};
+
+/// Start a server serving at most `max_connections` clients at a time.
+async fn start_server(max_connections: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = Config {
+        max_connections,
+        ..Config::default()
+    };
+
+    tokio::spawn(server::run_with_config(
+        listener,
+        Db::new(4),
+        config,
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}
+
+#[tokio::test]
+async fn clients_over_the_limit_wait_for_a_free_slot() {
+    let addr = start_server(2).await;
+
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+    assert_eq!(send(&mut first, &["SET", "key", "value"]).await, "OK");
+    assert_eq!(send(&mut second, &["GET", "key"]).await, "value");
+
+    // The TCP handshake completes in the OS backlog, but the server does not
+    // pick the connection up while both slots are taken.
+    let mut third = connect(addr).await;
+    third.write_frame(&command(&["GET", "key"])).await.unwrap();
+
+    let waiting = time::timeout(Duration::from_millis(200), third.read_frame()).await;
+    assert!(waiting.is_err(), "third client was served over the limit");
+
+    // Closing a connection frees its slot
+    drop(first);
+
+    let response = time::timeout(Duration::from_secs(5), read(&mut third))
+        .await
+        .expect("third client was not served after a slot was freed");
+    assert_eq!(response, "value");
+
+    // The other client was never affected
+    assert_eq!(send(&mut second, &["GET", "key"]).await, "value");
+}
+
+#[tokio::test]
+async fn slots_are_reused() {
+    let addr = start_server(1).await;
+
+    for i in 0..16 {
+        let value = i.to_string();
+
+        let mut connection = connect(addr).await;
+        assert_eq!(send(&mut connection, &["SET", "key", &value]).await, "OK");
+        assert_eq!(send(&mut connection, &["GET", "key"]).await, &value[..]);
+    }
+}
@@ -0,0 +1,68 @@
+use bytes::Bytes;
+use my_redis::Db;
+use std::thread;
+
+#[tokio::test]
+async fn every_shard_count_holds_every_key() {
+    for num_shards in [1, 4, 64] {
+        let db = Db::new(num_shards);
+        assert_eq!(db.num_shards(), num_shards);
+
+        for i in 0..500 {
+            db.set(format!("key:{}", i), Bytes::from(i.to_string()), None);
+        }
+
+        for i in 0..500 {
+            assert_eq!(
+                db.get(&format!("key:{}", i)).unwrap(),
+                i.to_string(),
+                "{} shards",
+                num_shards
+            );
+        }
+        assert_eq!(db.get("missing"), None);
+    }
+}
+
+#[tokio::test]
+async fn clones_share_the_shards() {
+    let db = Db::new(4);
+    let other = db.clone();
+
+    db.set("key".to_string(), Bytes::from("one"), None);
+    other.set("key".to_string(), Bytes::from("two"), None);
+
+    assert_eq!(db.get("key").unwrap(), "two");
+}
+
+/// Writers on different threads, most of them holding different shards,
+/// do not lose each other's keys.
+#[tokio::test]
+async fn concurrent_writers() {
+    let db = Db::new(8);
+
+    thread::scope(|scope| {
+        for writer in 0..8 {
+            let db = db.clone();
+
+            scope.spawn(move || {
+                for i in 0..1000 {
+                    let key = format!("{}:{}", writer, i);
+                    db.set(key, Bytes::from(i.to_string()), None);
+                }
+            });
+        }
+    });
+
+    for writer in 0..8 {
+        for i in 0..1000 {
+            assert_eq!(db.get(&format!("{}:{}", writer, i)).unwrap(), i.to_string());
+        }
+    }
+}
+
+#[tokio::test]
+#[should_panic(expected = "at least one shard")]
+async fn zero_shards_is_refused() {
+    Db::new(0);
+}
@@ -0,0 +1,166 @@
+mod support;
+
+use my_redis::Frame;
+use std::net::SocketAddr;
+use support::{connect, send, start_server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn error(msg: &str) -> Frame {
+    Frame::Error(msg.to_string())
+}
+
+/// Write raw bytes to the server and return everything it sends back before
+/// closing the connection.
+async fn send_raw(addr: SocketAddr, bytes: &[u8]) -> Vec<u8> {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket.write_all(bytes).await.unwrap();
+
+    let mut response = vec![];
+    socket.read_to_end(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn unknown_command_is_an_error_reply() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    let response = send(&mut connection, &["FOO", "bar"]).await;
+    assert_eq!(error("ERR unknown command 'foo'"), response);
+
+    // The connection is still usable
+    assert_eq!(Frame::Null, send(&mut connection, &["GET", "key"]).await);
+}
+
+#[tokio::test]
+async fn wrong_number_of_arguments() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    let expected = error("ERR wrong number of arguments for 'get' command");
+
+    assert_eq!(expected, send(&mut connection, &["GET"]).await);
+    assert_eq!(expected, send(&mut connection, &["GET", "a", "b"]).await);
+
+    let response = send(&mut connection, &["SET", "key"]).await;
+    assert_eq!(
+        error("ERR wrong number of arguments for 'set' command"),
+        response
+    );
+
+    let response = send(&mut connection, &["SUBSCRIBE"]).await;
+    assert_eq!(
+        error("ERR wrong number of arguments for 'subscribe' command"),
+        response
+    );
+}
+
+#[tokio::test]
+async fn invalid_arguments() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    let response = send(&mut connection, &["EXPIRE", "key", "soon"]).await;
+    assert_eq!(
+        error("ERR value is not an integer or out of range"),
+        response
+    );
+
+    let response = send(&mut connection, &["SET", "key", "value", "NX"]).await;
+    assert_eq!(error("ERR syntax error"), response);
+
+    let response = send(&mut connection, &["SET", "key", "value", "EX", "0"]).await;
+    assert_eq!(error("ERR invalid expire time in 'set' command"), response);
+
+    // None of the failed commands changed anything
+    assert_eq!(Frame::Null, send(&mut connection, &["GET", "key"]).await);
+}
+
+#[tokio::test]
+async fn frames_that_are_not_commands() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    // Well formed RESP, but commands must be arrays of strings
+    for frame in [
+        Frame::Simple("GET".to_string()),
+        Frame::Integer(1),
+        Frame::Array(vec![]),
+        Frame::Array(vec![Frame::Array(vec![])]),
+    ] {
+        connection.write_frame(&frame).await.unwrap();
+
+        match connection.read_frame().await.unwrap().unwrap() {
+            Frame::Error(msg) => assert!(msg.starts_with("ERR "), "{}", msg),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+
+    assert_eq!(Frame::Null, send(&mut connection, &["GET", "key"]).await);
+}
+
+#[tokio::test]
+async fn garbage_bytes_close_the_connection() {
+    let addr = start_server().await;
+
+    // Anything not starting with a type byte is an inline command, so only
+    // malformed RESP and unbalanced quotes are garbage.
+    for garbage in [
+        &b"*1\r\n$abc\r\n"[..],
+        b":12three\r\n",
+        b"*2\r\n$3\r\nGET\r\n#maybe\r\n",
+        b"GET \"key\r\n",
+    ] {
+        let response = send_raw(addr, garbage).await;
+
+        assert!(
+            response.starts_with(b"-ERR protocol error"),
+            "{:?} -> {:?}",
+            garbage,
+            String::from_utf8_lossy(&response)
+        );
+    }
+
+    // Other clients are not affected
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["SET", "key", "value"]).await, "OK");
+}
+
+#[tokio::test]
+async fn garbage_after_a_valid_command() {
+    let addr = start_server().await;
+
+    // The first command is answered before the garbage is detected
+    let response = send_raw(addr, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n*garbage\r\n").await;
+
+    assert!(response.starts_with(b"$-1\r\n-ERR protocol error"));
+}
+
+#[tokio::test]
+async fn garbage_while_subscribed() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nnews\r\n*garbage\r\n").await;
+
+    assert!(
+        response.starts_with(b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n-ERR protocol error"),
+        "{:?}",
+        String::from_utf8_lossy(&response)
+    );
+}
+
+#[tokio::test]
+async fn disconnecting_mid_frame_does_not_affect_the_server() {
+    let addr = start_server().await;
+
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nke")
+        .await
+        .unwrap();
+    drop(socket);
+
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["SET", "key", "value"]).await, "OK");
+}
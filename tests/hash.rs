@@ -0,0 +1,277 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::Frame;
+use std::collections::{HashMap, HashSet};
+use support::{connect, send, start_server};
+
+fn bulk(s: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+/// The strings in an array response, sorted as hashes are not ordered.
+fn sorted(response: Frame) -> Vec<String> {
+    let mut items = strings(response);
+    items.sort();
+    items
+}
+
+fn strings(response: Frame) -> Vec<String> {
+    match response {
+        Frame::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+                frame => panic!("unexpected frame {:?}", frame),
+            })
+            .collect(),
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn set_get_and_delete_fields() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(
+            &mut connection,
+            &["HSET", "user", "name", "ann", "age", "41"]
+        )
+        .await,
+        Frame::Integer(2)
+    );
+    // Replacing a field does not count as adding one
+    assert_eq!(
+        send(
+            &mut connection,
+            &["HSET", "user", "age", "42", "city", "Oslo"]
+        )
+        .await,
+        Frame::Integer(1)
+    );
+
+    assert_eq!(send(&mut connection, &["HGET", "user", "age"]).await, "42");
+    assert_eq!(
+        send(&mut connection, &["HGET", "user", "email"]).await,
+        Frame::Null
+    );
+    assert_eq!(
+        send(&mut connection, &["HMGET", "user", "name", "email", "city"]).await,
+        Frame::Array(vec![bulk("ann"), Frame::Null, bulk("Oslo")])
+    );
+    assert_eq!(
+        send(&mut connection, &["HEXISTS", "user", "name"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["HEXISTS", "user", "email"]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(
+        send(&mut connection, &["HLEN", "user"]).await,
+        Frame::Integer(3)
+    );
+
+    assert_eq!(
+        sorted(send(&mut connection, &["HKEYS", "user"]).await),
+        ["age", "city", "name"]
+    );
+    assert_eq!(
+        sorted(send(&mut connection, &["HVALS", "user"]).await),
+        ["42", "Oslo", "ann"]
+    );
+
+    // Without `HELLO 3`, the map is sent as an array of fields and values
+    let pairs = strings(send(&mut connection, &["HGETALL", "user"]).await);
+    let pairs: HashMap<_, _> = pairs
+        .chunks(2)
+        .map(|pair| (pair[0].as_str(), pair[1].as_str()))
+        .collect();
+    assert_eq!(
+        pairs,
+        HashMap::from([("name", "ann"), ("age", "42"), ("city", "Oslo")])
+    );
+
+    assert_eq!(
+        send(&mut connection, &["HDEL", "user", "name", "email", "age"]).await,
+        Frame::Integer(2)
+    );
+    assert_eq!(
+        send(&mut connection, &["HDEL", "user", "city"]).await,
+        Frame::Integer(1)
+    );
+
+    // The emptied hash was removed
+    assert_eq!(
+        send(&mut connection, &["HLEN", "user"]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(
+        send(&mut connection, &["HGETALL", "user"]).await,
+        Frame::Array(vec![])
+    );
+    assert_eq!(send(&mut connection, &["SET", "user", "1"]).await, "OK");
+
+    assert_eq!(
+        send(&mut connection, &["HSET", "user2", "name"]).await,
+        Frame::Error("ERR wrong number of arguments for 'hset' command".to_string())
+    );
+}
+
+#[tokio::test]
+async fn increment_fields() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["HINCRBY", "counters", "a", "5"]).await,
+        Frame::Integer(5)
+    );
+    assert_eq!(
+        send(&mut connection, &["HINCRBY", "counters", "a", "-7"]).await,
+        Frame::Integer(-2)
+    );
+    assert_eq!(
+        send(&mut connection, &["HGET", "counters", "a"]).await,
+        "-2"
+    );
+
+    send(&mut connection, &["HSET", "counters", "text", "abc"]).await;
+    assert_eq!(
+        send(&mut connection, &["HINCRBY", "counters", "text", "1"]).await,
+        Frame::Error("ERR hash value is not an integer".to_string())
+    );
+
+    let max = i64::MAX.to_string();
+    send(&mut connection, &["HSET", "counters", "max", &max]).await;
+    assert_eq!(
+        send(&mut connection, &["HINCRBY", "counters", "max", "1"]).await,
+        Frame::Error("ERR increment or decrement would overflow".to_string())
+    );
+    assert_eq!(
+        send(&mut connection, &["HGET", "counters", "max"]).await,
+        &max[..]
+    );
+
+    assert_eq!(
+        send(&mut connection, &["HINCRBY", "counters", "a", "x"]).await,
+        Frame::Error("ERR value is not an integer or out of range".to_string())
+    );
+}
+
+#[tokio::test]
+async fn wrong_type_errors() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["SET", "string", "value"]).await;
+    send(&mut connection, &["HSET", "hash", "field", "value"]).await;
+
+    let wrong_type = Frame::Error(
+        "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+    );
+
+    for args in [
+        &["HSET", "string", "f", "v"][..],
+        &["HGET", "string", "f"],
+        &["HMGET", "string", "f"],
+        &["HDEL", "string", "f"],
+        &["HEXISTS", "string", "f"],
+        &["HLEN", "string"],
+        &["HKEYS", "string"],
+        &["HVALS", "string"],
+        &["HGETALL", "string"],
+        &["HINCRBY", "string", "f", "1"],
+        &["HSCAN", "string", "0"],
+        &["GET", "hash"],
+        &["LPUSH", "hash", "a"],
+    ] {
+        assert_eq!(send(&mut connection, args).await, wrong_type, "{:?}", args);
+    }
+
+    assert_eq!(send(&mut connection, &["GET", "string"]).await, "value");
+}
+
+#[tokio::test]
+async fn scan_fields() {
+    let mut connection = connect(start_server().await).await;
+
+    let mut args = vec!["HSET".to_string(), "big".to_string()];
+    for i in 0..100 {
+        args.push(format!("field:{}", i));
+        args.push(i.to_string());
+    }
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+    send(&mut connection, &args).await;
+
+    let mut cursor = "0".to_string();
+    let mut found = HashSet::new();
+    let mut calls = 0;
+
+    loop {
+        let response = send(&mut connection, &["HSCAN", "big", &cursor, "COUNT", "7"]).await;
+
+        let (next, pairs) = match response {
+            Frame::Array(mut parts) if parts.len() == 2 => {
+                let pairs = strings(parts.pop().unwrap());
+                let next = strings(Frame::Array(parts)).pop().unwrap();
+                (next, pairs)
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+
+        for pair in pairs.chunks(2).filter(|pair| pair[0] != "extra") {
+            assert_eq!(pair[0], format!("field:{}", pair[1]));
+            found.insert(pair[1].clone());
+        }
+
+        // Fields removed and added during the iteration do not hide the
+        // others
+        if calls == 2 {
+            send(&mut connection, &["HDEL", "big", "field:0", "field:1"]).await;
+            send(&mut connection, &["HSET", "big", "extra", "x"]).await;
+        }
+
+        calls += 1;
+        cursor = next;
+        if cursor == "0" {
+            break;
+        }
+    }
+
+    assert!(calls > 1);
+    for i in 2..100 {
+        assert!(found.contains(&i.to_string()), "field:{} was not found", i);
+    }
+
+    // Only the matching fields are returned
+    let response = send(
+        &mut connection,
+        &["HSCAN", "big", "0", "MATCH", "field:1?", "COUNT", "1000"],
+    )
+    .await;
+    match response {
+        Frame::Array(parts) => {
+            assert_eq!(parts[0], "0");
+            let fields: Vec<_> = sorted(parts[1].clone())
+                .into_iter()
+                .filter(|item| item.starts_with("field"))
+                .collect();
+            let expected: Vec<_> = (10..20).map(|i| format!("field:{}", i)).collect();
+            assert_eq!(fields, expected);
+        }
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+
+    assert_eq!(
+        send(&mut connection, &["HSCAN", "missing", "0"]).await,
+        Frame::Array(vec![bulk("0"), Frame::Array(vec![])])
+    );
+    assert_eq!(
+        send(&mut connection, &["HSCAN", "big", "nope"]).await,
+        Frame::Error("ERR invalid cursor".to_string())
+    );
+    assert_eq!(
+        send(&mut connection, &["HSCAN", "big", "0", "COUNT", "0"]).await,
+        Frame::Error("ERR syntax error".to_string())
+    );
+}
@@ -0,0 +1,154 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::Frame;
+use std::net::SocketAddr;
+use support::{connect, read, send, start_server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn bulk(val: &'static str) -> Frame {
+    Frame::Bulk(Bytes::from(val))
+}
+
+/// Write raw bytes to the server, close the write half and return everything
+/// the server sends back.
+async fn send_raw(addr: SocketAddr, bytes: &[u8]) -> Vec<u8> {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket.write_all(bytes).await.unwrap();
+    socket.shutdown().await.unwrap();
+
+    let mut response = vec![];
+    socket.read_to_end(&mut response).await.unwrap();
+    response
+}
+
+/// Return the value of `field` in a `HELLO` reply, a map for RESP3 clients
+/// and a flat array of keys and values for RESP2 clients.
+fn hello_field<'a>(reply: &'a Frame, field: &str) -> &'a Frame {
+    match reply {
+        Frame::Map(pairs) => &pairs.iter().find(|(key, _)| *key == field).unwrap().1,
+        Frame::Array(parts) => &parts.chunks(2).find(|pair| pair[0] == field).unwrap()[1],
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn resp2_is_the_default() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n").await;
+    assert_eq!(&b"$-1\r\n"[..], &response[..]);
+}
+
+#[tokio::test]
+async fn hello_3_switches_the_connection_to_resp3() {
+    let addr = start_server().await;
+
+    let response = send_raw(
+        addr,
+        b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
+    )
+    .await;
+
+    // The reply to `HELLO` is already a RESP3 map, and nulls are RESP3 nulls
+    assert!(response.starts_with(b"%6\r\n"), "{:?}", response);
+    assert!(response.ends_with(b"\r\n_\r\n"), "{:?}", response);
+}
+
+#[tokio::test]
+async fn hello_without_a_version_keeps_the_protocol() {
+    let addr = start_server().await;
+
+    // RESP2 clients get the reply as a flat array of keys and values
+    let response = send_raw(addr, b"*1\r\n$5\r\nHELLO\r\n").await;
+    assert!(response.starts_with(b"*12\r\n"), "{:?}", response);
+
+    let mut connection = connect(addr).await;
+    let reply = send(&mut connection, &["HELLO", "3"]).await;
+    assert_eq!(&Frame::Integer(3), hello_field(&reply, "proto"));
+
+    let reply = send(&mut connection, &["HELLO"]).await;
+    assert_eq!(&Frame::Integer(3), hello_field(&reply, "proto"));
+    assert_eq!(&bulk("standalone"), hello_field(&reply, "mode"));
+}
+
+#[tokio::test]
+async fn hello_2_switches_back_to_resp2() {
+    let addr = start_server().await;
+
+    let response = send_raw(
+        addr,
+        b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n\
+          *2\r\n$5\r\nHELLO\r\n$1\r\n2\r\n\
+          *2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
+    )
+    .await;
+
+    assert!(response.ends_with(b"\r\n$-1\r\n"), "{:?}", response);
+}
+
+#[tokio::test]
+async fn invalid_hello_is_an_error_reply() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    let response = send(&mut connection, &["HELLO", "4"]).await;
+    assert_eq!(
+        Frame::Error("NOPROTO unsupported protocol version".to_string()),
+        response
+    );
+
+    let response = send(&mut connection, &["HELLO", "three"]).await;
+    assert_eq!(
+        Frame::Error("ERR Protocol version is not an integer or out of range".to_string()),
+        response
+    );
+
+    let response = send(&mut connection, &["HELLO", "3", "SETNAME", "me"]).await;
+    assert_eq!(
+        Frame::Error("ERR Syntax error in HELLO option 'SETNAME'".to_string()),
+        response
+    );
+
+    // None of these changed the protocol
+    let reply = send(&mut connection, &["HELLO"]).await;
+    assert_eq!(&Frame::Integer(2), hello_field(&reply, "proto"));
+}
+
+#[tokio::test]
+async fn pub_sub_messages_are_pushed_to_resp3_clients() {
+    let addr = start_server().await;
+
+    let mut subscriber = connect(addr).await;
+    send(&mut subscriber, &["HELLO", "3"]).await;
+
+    let response = send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+    assert_eq!(
+        Frame::Push(vec![bulk("subscribe"), bulk("news"), Frame::Integer(1)]),
+        response
+    );
+
+    let mut publisher = connect(addr).await;
+    assert_eq!(
+        Frame::Integer(1),
+        send(&mut publisher, &["PUBLISH", "news", "hello"]).await
+    );
+
+    assert_eq!(
+        Frame::Push(vec![bulk("message"), bulk("news"), bulk("hello")]),
+        read(&mut subscriber).await
+    );
+}
+
+#[tokio::test]
+async fn pub_sub_messages_are_arrays_for_resp2_clients() {
+    let addr = start_server().await;
+
+    let mut subscriber = connect(addr).await;
+    let response = send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+    assert_eq!(
+        Frame::Array(vec![bulk("subscribe"), bulk("news"), Frame::Integer(1)]),
+        response
+    );
+}
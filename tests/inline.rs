@@ -0,0 +1,162 @@
+mod support;
+
+use my_redis::Frame;
+use std::net::SocketAddr;
+use support::{connect, send, start_server};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Write raw bytes to the server, close the write half and return everything
+/// the server sends back.
+async fn send_raw(addr: SocketAddr, bytes: &[u8]) -> Vec<u8> {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket.write_all(bytes).await.unwrap();
+    socket.shutdown().await.unwrap();
+
+    let mut response = vec![];
+    socket.read_to_end(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn inline_ping() {
+    let addr = start_server().await;
+
+    assert_eq!(&b"+PONG\r\n"[..], &send_raw(addr, b"PING\r\n").await[..]);
+}
+
+#[tokio::test]
+async fn inline_commands_are_pipelined() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"SET a b\r\nGET a\r\nping hello\r\n").await;
+    assert_eq!(&b"+OK\r\n$1\r\nb\r\n$5\r\nhello\r\n"[..], &response[..]);
+}
+
+#[tokio::test]
+async fn inline_commands_may_end_with_a_bare_newline() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"SET a b\nGET a\n").await;
+    assert_eq!(&b"+OK\r\n$1\r\nb\r\n"[..], &response[..]);
+}
+
+#[tokio::test]
+async fn inline_arguments_may_be_quoted() {
+    let addr = start_server().await;
+
+    let response = send_raw(
+        addr,
+        b"SET greeting \"hello \\\"world\\\"\\x21\"\r\nSET other 'it''s'\r\nGET greeting\r\n",
+    )
+    .await;
+
+    // `'it''s'` is not separated by whitespace after the closing quote
+    assert!(
+        response.starts_with(b"+OK\r\n-ERR protocol error"),
+        "{:?}",
+        String::from_utf8_lossy(&response)
+    );
+
+    let response = send_raw(
+        addr,
+        b"SET greeting \"hello \\\"world\\\"\\x21\"\r\nSET other '  spaced  '\r\nGET greeting\r\nGET other\r\n",
+    )
+    .await;
+
+    assert_eq!(
+        &b"+OK\r\n+OK\r\n$14\r\nhello \"world\"!\r\n$10\r\n  spaced  \r\n"[..],
+        &response[..]
+    );
+}
+
+#[tokio::test]
+async fn blank_lines_are_ignored() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"\r\n   \r\n\nPING\r\n\r\n").await;
+    assert_eq!(&b"+PONG\r\n"[..], &response[..]);
+}
+
+#[tokio::test]
+async fn inline_and_resp_commands_can_be_mixed() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"SET a 1\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\nGET a\r\n").await;
+    assert_eq!(&b"+OK\r\n$1\r\n1\r\n$1\r\n1\r\n"[..], &response[..]);
+}
+
+#[tokio::test]
+async fn inline_command_split_across_reads() {
+    let addr = start_server().await;
+
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+
+    for chunk in [&b"SE"[..], b"T key va", b"lue\r", b"\n"] {
+        socket.write_all(chunk).await.unwrap();
+        socket.flush().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+
+    let mut response = [0; 5];
+    socket.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n", &response);
+
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["GET", "key"]).await, "value");
+}
+
+#[tokio::test]
+async fn unknown_inline_command_keeps_the_connection_open() {
+    let addr = start_server().await;
+
+    let response = send_raw(addr, b"foo bar\r\nPING\r\n").await;
+    assert_eq!(
+        &b"-ERR unknown command 'foo'\r\n+PONG\r\n"[..],
+        &response[..]
+    );
+}
+
+#[tokio::test]
+async fn inline_command_received_in_many_reads() {
+    let addr = start_server().await;
+
+    // The second line starts in the same read as the end of the first one
+    let value = "v".repeat(60 * 1024);
+    let data = format!("SET a {}\r\nSET b {}\r\n", value, value);
+
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    for chunk in data.as_bytes().chunks(1000) {
+        socket.write_all(chunk).await.unwrap();
+        socket.flush().await.unwrap();
+        tokio::task::yield_now().await;
+    }
+
+    let mut response = [0; 10];
+    socket.read_exact(&mut response).await.unwrap();
+    assert_eq!(b"+OK\r\n+OK\r\n", &response);
+
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, value.as_str());
+    assert_eq!(send(&mut connection, &["GET", "b"]).await, value.as_str());
+}
+
+#[tokio::test]
+async fn inline_command_over_the_limit() {
+    let addr = start_server().await;
+    let value = "v".repeat(64 * 1024);
+
+    // Whether or not the end of the line was received
+    for line in [format!("SET a {}", value), format!("SET a {}\r\n", value)] {
+        let response = send_raw(addr, line.as_bytes()).await;
+
+        assert!(
+            response.starts_with(b"-ERR protocol error; inline command exceeds the limit"),
+            "{:?}",
+            String::from_utf8_lossy(&response)
+        );
+    }
+
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, Frame::Null);
+}
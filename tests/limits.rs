@@ -0,0 +1,219 @@
+mod support;
+
+use my_redis::connection::Limits;
+use my_redis::server::{self, Config};
+use my_redis::{Db, Frame};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use support::{connect, send};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration};
+
+/// Start a server with small limits, so tests do not have to send much.
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = Config {
+        limits: Limits {
+            max_bulk_len: 1024,
+            max_array_len: 8,
+            max_depth: 2,
+            max_inline_len: 1024,
+            max_buffered_bytes: 4096,
+        },
+        ..Config::default()
+    };
+
+    tokio::spawn(server::run_with_config(
+        listener,
+        Db::new(4),
+        config,
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}
+
+/// Write raw bytes without closing the socket, and return everything the
+/// server sends back before it closes the connection.
+///
+/// The server closes the connection without reading everything that was
+/// sent, so writing may fail and the close may be a reset.
+async fn send_until_closed(addr: SocketAddr, bytes: &[u8]) -> String {
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    let _ = socket.write_all(bytes).await;
+
+    let mut response = vec![];
+    let mut buf = [0; 1024];
+
+    loop {
+        let read = time::timeout(Duration::from_secs(5), socket.read(&mut buf))
+            .await
+            .expect("the server did not close the connection");
+
+        match read {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => break,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    String::from_utf8(response).unwrap()
+}
+
+fn set_command(key: &str, value_len: usize) -> Vec<u8> {
+    let mut bytes = format!(
+        "*3\r\n$3\r\nSET\r\n${}\r\n{}\r\n${}\r\n",
+        key.len(),
+        key,
+        value_len
+    )
+    .into_bytes();
+    bytes.extend(std::iter::repeat_n(b'x', value_len));
+    bytes.extend_from_slice(b"\r\n");
+    bytes
+}
+
+#[tokio::test]
+async fn bulk_length_over_the_limit() {
+    let addr = start_server().await;
+
+    // Rejected as soon as the length is read, the value is never sent
+    let response = send_until_closed(addr, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$1025\r\n").await;
+    assert_eq!(
+        "-ERR protocol error; bulk length exceeds the limit of 1024 bytes\r\n",
+        response
+    );
+
+    let response = send_until_closed(addr, b"$99999999999999999999\r\n").await;
+    assert!(response.starts_with("-ERR protocol error"), "{}", response);
+
+    // Values right at the limit are fine
+    let mut connection = connect(addr).await;
+    let value = "x".repeat(1024);
+    assert_eq!(send(&mut connection, &["SET", "key", &value]).await, "OK");
+    assert_eq!(send(&mut connection, &["GET", "key"]).await, &value[..]);
+}
+
+#[tokio::test]
+async fn array_length_over_the_limit() {
+    let addr = start_server().await;
+
+    let response = send_until_closed(addr, b"*9\r\n").await;
+    assert_eq!(
+        "-ERR protocol error; element count exceeds the limit of 8\r\n",
+        response
+    );
+
+    // Inline commands are held to the same limit
+    let response = send_until_closed(addr, b"a b c d e f g h i\r\n").await;
+    assert_eq!(
+        "-ERR protocol error; element count exceeds the limit of 8\r\n",
+        response
+    );
+
+    let mut connection = connect(addr).await;
+    let response = send(&mut connection, &["FOO"; 8]).await;
+    assert_eq!(
+        Frame::Error("ERR unknown command 'foo'".to_string()),
+        response
+    );
+}
+
+#[tokio::test]
+async fn nesting_over_the_limit() {
+    let addr = start_server().await;
+
+    let response = send_until_closed(addr, b"*1\r\n*1\r\n*1\r\n").await;
+    assert_eq!(
+        "-ERR protocol error; nesting exceeds the limit of 2 levels\r\n",
+        response
+    );
+
+    // Two levels are accepted, even if they do not make a valid command
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket
+        .write_all(b"*1\r\n*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n")
+        .await
+        .unwrap();
+
+    let mut response = vec![0; 256];
+    let n = socket.read(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response[..n]);
+    assert!(
+        response.starts_with("-ERR protocol error; expected"),
+        "{}",
+        response
+    );
+}
+
+#[tokio::test]
+async fn deep_nesting_is_rejected_by_default() {
+    let addr = support::start_server().await;
+
+    // Checking this frame without a limit would recurse once per level
+    let response = send_until_closed(addr, &b"*1\r\n".repeat(100_000)).await;
+    assert_eq!(
+        "-ERR protocol error; nesting exceeds the limit of 32 levels\r\n",
+        response
+    );
+}
+
+#[tokio::test]
+async fn chained_attributes_count_as_nesting() {
+    let addr = support::start_server().await;
+
+    // Each attribute describes the next one, far below the buffer limit
+    let response = send_until_closed(addr, &b"|0\r\n".repeat(200_000)).await;
+    assert_eq!(
+        "-ERR protocol error; nesting exceeds the limit of 32 levels\r\n",
+        response
+    );
+}
+
+#[tokio::test]
+async fn buffered_bytes_over_the_limit() {
+    let addr = start_server().await;
+
+    // An inline command that never ends is stopped by its own, lower limit
+    let mut line = b"SET key ".to_vec();
+    line.extend(std::iter::repeat_n(b'x', 5000));
+
+    let response = send_until_closed(addr, &line).await;
+    assert_eq!(
+        "-ERR protocol error; inline command exceeds the limit of 1024 bytes\r\n",
+        response
+    );
+
+    // A line that is never terminated, within a RESP frame
+    let mut frame = b"*2\r\n$3\r\nGET\r\n+".to_vec();
+    frame.extend(std::iter::repeat_n(b'k', 5000));
+
+    let response = send_until_closed(addr, &frame).await;
+    assert_eq!(
+        "-ERR protocol error; buffered data exceeds the limit of 4096 bytes\r\n",
+        response
+    );
+}
+
+#[tokio::test]
+async fn limits_apply_to_each_frame() {
+    let addr = start_server().await;
+
+    // Pipelined frames add up to more than the buffer limit, but each of them
+    // fits.
+    let mut bytes = vec![];
+    for i in 0..10 {
+        bytes.extend(set_command(&format!("key{}", i), 1000));
+    }
+
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket.write_all(&bytes).await.unwrap();
+
+    let mut response = vec![0; 50];
+    socket.read_exact(&mut response).await.unwrap();
+    assert_eq!(&b"+OK\r\n".repeat(10)[..], &response[..]);
+}
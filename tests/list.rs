@@ -0,0 +1,340 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::Frame;
+use support::{command, connect, read, send, start_server};
+use tokio::time::{self, Duration, Instant};
+
+/// The array of bulk strings a list command responds with.
+fn bulks(items: &[&str]) -> Frame {
+    Frame::Array(
+        items
+            .iter()
+            .map(|item| Frame::Bulk(Bytes::copy_from_slice(item.as_bytes())))
+            .collect(),
+    )
+}
+
+fn wrong_type() -> Frame {
+    Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+}
+
+#[tokio::test]
+async fn push_and_pop() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["RPUSH", "list", "a", "b"]).await,
+        Frame::Integer(2)
+    );
+    assert_eq!(
+        send(&mut connection, &["LPUSH", "list", "x", "y"]).await,
+        Frame::Integer(4)
+    );
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "list", "0", "-1"]).await,
+        bulks(&["y", "x", "a", "b"])
+    );
+    assert_eq!(
+        send(&mut connection, &["LLEN", "list"]).await,
+        Frame::Integer(4)
+    );
+
+    assert_eq!(send(&mut connection, &["LPOP", "list"]).await, "y");
+    assert_eq!(send(&mut connection, &["RPOP", "list"]).await, "b");
+    assert_eq!(
+        send(&mut connection, &["RPOP", "list", "5"]).await,
+        bulks(&["a", "x"])
+    );
+
+    // The emptied list was removed
+    assert_eq!(
+        send(&mut connection, &["LLEN", "list"]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(send(&mut connection, &["LPOP", "list"]).await, Frame::Null);
+    assert_eq!(send(&mut connection, &["GET", "list"]).await, Frame::Null);
+    assert_eq!(
+        send(&mut connection, &["LPOP", "list", "-1"]).await,
+        Frame::Error("ERR value is out of range, must be positive".to_string())
+    );
+}
+
+#[tokio::test]
+async fn ranges_and_indices() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["RPUSH", "list", "a", "b", "c", "d", "e"]).await;
+
+    for (start, stop, expected) in [
+        ("1", "2", &["b", "c"][..]),
+        ("-2", "-1", &["d", "e"]),
+        ("-100", "100", &["a", "b", "c", "d", "e"]),
+        ("3", "1", &[]),
+        ("5", "10", &[]),
+    ] {
+        assert_eq!(
+            send(&mut connection, &["LRANGE", "list", start, stop]).await,
+            bulks(expected)
+        );
+    }
+
+    assert_eq!(send(&mut connection, &["LINDEX", "list", "0"]).await, "a");
+    assert_eq!(send(&mut connection, &["LINDEX", "list", "-1"]).await, "e");
+    assert_eq!(
+        send(&mut connection, &["LINDEX", "list", "5"]).await,
+        Frame::Null
+    );
+
+    assert_eq!(
+        send(&mut connection, &["LTRIM", "list", "1", "-2"]).await,
+        "OK"
+    );
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "list", "0", "-1"]).await,
+        bulks(&["b", "c", "d"])
+    );
+
+    // Trimming to an empty range removes the list
+    assert_eq!(
+        send(&mut connection, &["LTRIM", "list", "2", "1"]).await,
+        "OK"
+    );
+    assert_eq!(
+        send(&mut connection, &["LLEN", "list"]).await,
+        Frame::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn lmove() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["RPUSH", "src", "a", "b", "c"]).await;
+
+    assert_eq!(
+        send(&mut connection, &["LMOVE", "src", "dst", "LEFT", "RIGHT"]).await,
+        "a"
+    );
+    assert_eq!(
+        send(&mut connection, &["LMOVE", "src", "dst", "right", "left"]).await,
+        "c"
+    );
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "dst", "0", "-1"]).await,
+        bulks(&["c", "a"])
+    );
+
+    // Rotating a list onto itself
+    assert_eq!(
+        send(&mut connection, &["LMOVE", "dst", "dst", "LEFT", "RIGHT"]).await,
+        "c"
+    );
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "dst", "0", "-1"]).await,
+        bulks(&["a", "c"])
+    );
+
+    assert_eq!(
+        send(
+            &mut connection,
+            &["LMOVE", "missing", "dst", "LEFT", "LEFT"]
+        )
+        .await,
+        Frame::Null
+    );
+    assert_eq!(
+        send(&mut connection, &["LMOVE", "src", "dst", "UP", "LEFT"]).await,
+        Frame::Error("ERR syntax error".to_string())
+    );
+}
+
+#[tokio::test]
+async fn wrong_type_errors() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["SET", "string", "value"]).await;
+    send(&mut connection, &["RPUSH", "list", "a"]).await;
+
+    for args in [
+        &["LPUSH", "string", "a"][..],
+        &["RPOP", "string"],
+        &["LRANGE", "string", "0", "-1"],
+        &["LLEN", "string"],
+        &["LINDEX", "string", "0"],
+        &["LTRIM", "string", "0", "1"],
+        &["LMOVE", "list", "string", "LEFT", "LEFT"],
+        &["LMOVE", "string", "list", "LEFT", "LEFT"],
+        &["BLPOP", "list2", "string", "0"],
+        &["GET", "list"],
+    ] {
+        assert_eq!(
+            send(&mut connection, args).await,
+            wrong_type(),
+            "{:?}",
+            args
+        );
+    }
+
+    // Failed commands left both keys untouched
+    assert_eq!(send(&mut connection, &["GET", "string"]).await, "value");
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "list", "0", "-1"]).await,
+        bulks(&["a"])
+    );
+
+    // SET replaces a list
+    assert_eq!(send(&mut connection, &["SET", "list", "value"]).await, "OK");
+    assert_eq!(send(&mut connection, &["GET", "list"]).await, "value");
+}
+
+#[tokio::test]
+async fn blocking_pop_returns_right_away() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["RPUSH", "b", "1", "2"]).await;
+
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "a", "b", "0"]).await,
+        bulks(&["b", "1"])
+    );
+    assert_eq!(
+        send(&mut connection, &["BRPOP", "a", "b", "0"]).await,
+        bulks(&["b", "2"])
+    );
+}
+
+#[tokio::test]
+async fn blocking_pop_times_out() {
+    let mut connection = connect(start_server().await).await;
+
+    let start = Instant::now();
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "list", "0.1"]).await,
+        Frame::Null
+    );
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // The connection still works afterwards
+    assert_eq!(send(&mut connection, &["PING"]).await, "PONG");
+
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "list", "-1"]).await,
+        Frame::Error("ERR timeout is negative".to_string())
+    );
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "list", "soon"]).await,
+        Frame::Error("ERR timeout is not a float or out of range".to_string())
+    );
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "list"]).await,
+        Frame::Error("ERR wrong number of arguments for 'blpop' command".to_string())
+    );
+}
+
+#[tokio::test]
+async fn push_wakes_up_blocked_client() {
+    let addr = start_server().await;
+    let mut blocked = connect(addr).await;
+    let mut pusher = connect(addr).await;
+
+    blocked
+        .write_frame(&command(&["BRPOP", "a", "b", "0"]))
+        .await
+        .unwrap();
+
+    // Let the server block the client
+    time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        send(&mut pusher, &["LPUSH", "b", "x", "y"]).await,
+        Frame::Integer(2)
+    );
+    assert_eq!(read(&mut blocked).await, bulks(&["b", "x"]));
+
+    // The element was handed over, not left in the list
+    assert_eq!(
+        send(&mut pusher, &["LRANGE", "b", "0", "-1"]).await,
+        bulks(&["y"])
+    );
+}
+
+#[tokio::test]
+async fn blocked_clients_are_served_in_order() {
+    let addr = start_server().await;
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+    let mut pusher = connect(addr).await;
+
+    first
+        .write_frame(&command(&["BLPOP", "list", "0"]))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+    second
+        .write_frame(&command(&["BLPOP", "other", "list", "0"]))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        send(&mut pusher, &["RPUSH", "list", "1"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(read(&mut first).await, bulks(&["list", "1"]));
+
+    assert_eq!(
+        send(&mut pusher, &["RPUSH", "list", "2", "3"]).await,
+        Frame::Integer(2)
+    );
+    assert_eq!(read(&mut second).await, bulks(&["list", "2"]));
+
+    assert_eq!(
+        send(&mut pusher, &["LRANGE", "list", "0", "-1"]).await,
+        bulks(&["3"])
+    );
+}
+
+#[tokio::test]
+async fn lmove_wakes_up_blocked_client() {
+    let addr = start_server().await;
+    let mut blocked = connect(addr).await;
+    let mut mover = connect(addr).await;
+
+    blocked
+        .write_frame(&command(&["BLPOP", "dst", "1"]))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+
+    send(&mut mover, &["RPUSH", "src", "a"]).await;
+    assert_eq!(
+        send(&mut mover, &["LMOVE", "src", "dst", "LEFT", "LEFT"]).await,
+        "a"
+    );
+    assert_eq!(read(&mut blocked).await, bulks(&["dst", "a"]));
+}
+
+#[tokio::test]
+async fn disconnected_client_stops_waiting() {
+    let addr = start_server().await;
+    let mut gone = connect(addr).await;
+    let mut waiting = connect(addr).await;
+    let mut pusher = connect(addr).await;
+
+    gone.write_frame(&command(&["BLPOP", "list", "0"]))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+    waiting
+        .write_frame(&command(&["BLPOP", "list", "0"]))
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+
+    drop(gone);
+    time::sleep(Duration::from_millis(50)).await;
+
+    send(&mut pusher, &["RPUSH", "list", "1"]).await;
+    assert_eq!(read(&mut waiting).await, bulks(&["list", "1"]));
+}
@@ -0,0 +1,124 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::{Connection, Frame};
+use support::{command, connect, read, start_server};
+
+fn bulk(val: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(val.as_bytes()))
+}
+
+/// Send every command with a single flush, the way a pipelining client does.
+async fn pipeline(connection: &mut Connection, commands: &[&[&str]]) {
+    for args in commands {
+        connection.buffer_frame(&command(args)).await.unwrap();
+    }
+
+    connection.flush().await.unwrap();
+}
+
+#[tokio::test]
+async fn responses_are_in_order() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    pipeline(
+        &mut connection,
+        &[
+            &["GET", "key"],
+            &["SET", "key", "one"],
+            &["GET", "key"],
+            &["SET", "key", "two"],
+            &["GET", "key"],
+        ],
+    )
+    .await;
+
+    assert_eq!(Frame::Null, read(&mut connection).await);
+    assert_eq!(read(&mut connection).await, "OK");
+    assert_eq!(read(&mut connection).await, "one");
+    assert_eq!(read(&mut connection).await, "OK");
+    assert_eq!(read(&mut connection).await, "two");
+}
+
+#[tokio::test]
+async fn errors_do_not_break_the_batch() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    pipeline(
+        &mut connection,
+        &[
+            &["SET", "key", "value"],
+            &["NOPE"],
+            &["GET"],
+            &["GET", "key"],
+        ],
+    )
+    .await;
+
+    assert_eq!(read(&mut connection).await, "OK");
+    assert_eq!(
+        Frame::Error("ERR unknown command 'nope'".to_string()),
+        read(&mut connection).await
+    );
+    assert_eq!(
+        Frame::Error("ERR wrong number of arguments for 'get' command".to_string()),
+        read(&mut connection).await
+    );
+    assert_eq!(read(&mut connection).await, "value");
+}
+
+#[tokio::test]
+async fn batch_larger_than_the_write_buffer() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    let value = "x".repeat(1024);
+    let mut commands = vec![];
+
+    for i in 0..256 {
+        commands.push(vec!["SET".to_string(), format!("key:{}", i), value.clone()]);
+        commands.push(vec!["GET".to_string(), format!("key:{}", i)]);
+    }
+
+    let commands: Vec<Vec<&str>> = commands
+        .iter()
+        .map(|args| args.iter().map(String::as_str).collect())
+        .collect();
+    let commands: Vec<&[&str]> = commands.iter().map(Vec::as_slice).collect();
+
+    pipeline(&mut connection, &commands).await;
+
+    for _ in 0..256 {
+        assert_eq!(read(&mut connection).await, "OK");
+        assert_eq!(bulk(&value), read(&mut connection).await);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_in_the_middle_of_a_batch() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+
+    pipeline(
+        &mut subscriber,
+        &[
+            &["SET", "key", "value"],
+            &["SUBSCRIBE", "news"],
+            &["UNSUBSCRIBE"],
+            &["GET", "key"],
+        ],
+    )
+    .await;
+
+    let reply = |kind: &str| Frame::Array(vec![bulk(kind), bulk("news"), Frame::Integer(0)]);
+
+    assert_eq!(read(&mut subscriber).await, "OK");
+    assert_eq!(
+        Frame::Array(vec![bulk("subscribe"), bulk("news"), Frame::Integer(1)]),
+        read(&mut subscriber).await
+    );
+    assert_eq!(reply("unsubscribe"), read(&mut subscriber).await);
+    assert_eq!(read(&mut subscriber).await, "value");
+}
@@ -0,0 +1,262 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::pool::{Config, Pool};
+use my_redis::{Connection, Frame};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use support::start_server;
+use tokio::net::TcpListener;
+use tokio::task::AbortHandle;
+use tokio::time::{self, Duration};
+
+fn config(min_connections: usize, max_connections: usize) -> Config {
+    Config {
+        min_connections,
+        max_connections,
+        ..Config::default()
+    }
+}
+
+/// A server answering every command with `PONG`, whose connections can all be
+/// dropped at once by aborting the returned handles.
+async fn pong_server() -> (SocketAddr, Arc<Mutex<Vec<AbortHandle>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(Mutex::new(vec![]));
+
+    let handles = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+
+            let task = tokio::spawn(async move {
+                let mut connection = Connection::new(socket);
+                let pong = Frame::Simple("PONG".to_string());
+
+                while connection.read_frame().await.unwrap().is_some() {
+                    connection.write_frame(&pong).await.unwrap();
+                }
+            });
+
+            handles.lock().unwrap().push(task.abort_handle());
+        }
+    });
+
+    (addr, connections)
+}
+
+/// Wait for the server returned by `pong_server` to accept `n` connections.
+async fn accepted(connections: &Mutex<Vec<AbortHandle>>, n: usize) {
+    while connections.lock().unwrap().len() < n {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// A server answering every command after a delay, `GET key` with `key`
+/// itself, so that each reply tells which request it belongs to.
+async fn slow_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+
+            tokio::spawn(async move {
+                let mut connection = Connection::new(socket);
+
+                while let Some(Frame::Array(args)) = connection.read_frame().await.unwrap() {
+                    time::sleep(Duration::from_millis(50)).await;
+
+                    let reply = match &args[..] {
+                        [cmd] if *cmd == "ping" => Frame::Simple("PONG".to_string()),
+                        [cmd, Frame::Bulk(key)] if *cmd == "get" => Frame::Bulk(key.clone()),
+                        _ => Frame::Simple("OK".to_string()),
+                    };
+                    connection.write_frame(&reply).await.unwrap();
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn opens_min_connections_up_front() {
+    let addr = start_server().await;
+    let pool = Pool::connect(addr.to_string(), config(3, 8)).await.unwrap();
+
+    assert_eq!(3, pool.open_connections());
+    assert_eq!(3, pool.idle_connections());
+}
+
+#[tokio::test]
+async fn connect_error() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    assert!(Pool::connect(addr.to_string(), config(1, 1)).await.is_err());
+
+    // Without connections to open up front, the error comes on checkout
+    let pool = Pool::connect(addr.to_string(), config(0, 1)).await.unwrap();
+    assert!(pool.get().await.is_err());
+    assert_eq!(0, pool.open_connections());
+}
+
+#[tokio::test]
+async fn connections_are_reused() {
+    let addr = start_server().await;
+    let pool = Pool::connect(addr.to_string(), config(0, 8)).await.unwrap();
+
+    for i in 0..10 {
+        let mut client = pool.get().await.unwrap();
+        client.set("key", i.to_string().into()).await.unwrap();
+        assert_eq!(
+            Some(Bytes::from(i.to_string())),
+            client.get("key").await.unwrap()
+        );
+    }
+
+    assert_eq!(1, pool.open_connections());
+    assert_eq!(1, pool.idle_connections());
+}
+
+#[tokio::test]
+async fn checkout_waits_when_exhausted() {
+    let addr = start_server().await;
+    let pool = Pool::connect(addr.to_string(), config(0, 2)).await.unwrap();
+
+    let first = pool.get().await.unwrap();
+    let _second = pool.get().await.unwrap();
+    assert_eq!(2, pool.open_connections());
+
+    let waiting = time::timeout(Duration::from_millis(50), pool.get()).await;
+    assert!(waiting.is_err(), "checked out more than max_connections");
+
+    drop(first);
+
+    let mut third = time::timeout(Duration::from_secs(5), pool.get())
+        .await
+        .expect("returned connection was not handed out")
+        .unwrap();
+    assert_eq!(Bytes::from("PONG"), third.ping(None).await.unwrap());
+    assert_eq!(2, pool.open_connections());
+}
+
+#[tokio::test]
+async fn waiters_are_served_in_order() {
+    let addr = start_server().await;
+    let pool = Pool::connect(addr.to_string(), config(1, 1)).await.unwrap();
+
+    let held = pool.get().await.unwrap();
+    let order = Arc::new(Mutex::new(vec![]));
+
+    let mut waiters = vec![];
+    for i in 0..8 {
+        let pool = pool.clone();
+        let order = order.clone();
+
+        waiters.push(tokio::spawn(async move {
+            let _client = pool.get().await.unwrap();
+            order.lock().unwrap().push(i);
+        }));
+
+        // Make sure waiter `i` is queued before waiter `i + 1`
+        time::sleep(Duration::from_millis(5)).await;
+    }
+
+    drop(held);
+
+    for waiter in waiters {
+        waiter.await.unwrap();
+    }
+
+    assert_eq!((0..8).collect::<Vec<_>>(), *order.lock().unwrap());
+}
+
+#[tokio::test]
+async fn broken_connections_are_replaced_on_checkout() {
+    let (addr, connections) = pong_server().await;
+    let pool = Pool::connect(addr.to_string(), config(2, 4)).await.unwrap();
+    accepted(&connections, 2).await;
+
+    // The server drops every connection
+    for connection in connections.lock().unwrap().drain(..) {
+        connection.abort();
+    }
+
+    // Both idle connections fail the health check and a new one is opened
+    let mut client = pool.get().await.unwrap();
+    assert_eq!(Bytes::from("PONG"), client.ping(None).await.unwrap());
+
+    assert_eq!(1, pool.open_connections());
+    assert_eq!(0, pool.idle_connections());
+}
+
+#[tokio::test]
+async fn idle_connections_are_closed() {
+    let addr = start_server().await;
+
+    let config = Config {
+        min_connections: 1,
+        max_connections: 4,
+        idle_timeout: Duration::from_millis(50),
+    };
+    let pool = Pool::connect(addr.to_string(), config).await.unwrap();
+
+    let clients = vec![
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+        pool.get().await.unwrap(),
+    ];
+    assert_eq!(3, pool.open_connections());
+    drop(clients);
+    assert_eq!(3, pool.idle_connections());
+
+    time::sleep(Duration::from_millis(200)).await;
+
+    // Down to the minimum, which is kept even though it is idle as well
+    assert_eq!(1, pool.open_connections());
+    assert_eq!(1, pool.idle_connections());
+}
+
+#[tokio::test]
+async fn min_connections_are_restored() {
+    let (addr, connections) = pong_server().await;
+    let pool = Pool::connect(addr.to_string(), config(2, 4)).await.unwrap();
+    accepted(&connections, 2).await;
+
+    for connection in connections.lock().unwrap().drain(..) {
+        connection.abort();
+    }
+
+    // The checkout evicts both broken connections and opens one
+    drop(pool.get().await.unwrap());
+    assert_eq!(1, pool.open_connections());
+
+    // What the background task does every `idle_timeout / 2`
+    pool.maintain().await.unwrap();
+    assert_eq!(2, pool.open_connections());
+    assert_eq!(2, pool.idle_connections());
+}
+
+#[tokio::test]
+async fn cancelled_requests_do_not_leak_replies() {
+    let addr = slow_server().await;
+    let pool = Pool::connect(addr.to_string(), config(0, 1)).await.unwrap();
+
+    let mut client = pool.get().await.unwrap();
+    let set = client.set("key", "value".into());
+    assert!(time::timeout(Duration::from_millis(10), set).await.is_err());
+    drop(client);
+
+    // The reply to `SET` is still on its way, the connection was closed
+    // rather than returned
+    assert_eq!(0, pool.open_connections());
+
+    let mut client = pool.get().await.unwrap();
+    assert_eq!(Some(Bytes::from("key")), client.get("key").await.unwrap());
+}
@@ -0,0 +1,209 @@
+mod support;
+
+use bytes::Bytes;
+use mini_redis::client;
+use my_redis::Frame;
+use support::{command, connect, read, send, start_server};
+
+fn bulk(val: &str) -> Frame {
+    Frame::Bulk(Bytes::copy_from_slice(val.as_bytes()))
+}
+
+/// Reply sent for every channel subscribed to or unsubscribed from.
+fn reply(kind: &str, channel: &str, count: i64) -> Frame {
+    Frame::Array(vec![bulk(kind), bulk(channel), Frame::Integer(count)])
+}
+
+fn message(channel: &str, payload: &str) -> Frame {
+    Frame::Array(vec![bulk("message"), bulk(channel), bulk(payload)])
+}
+
+#[tokio::test]
+async fn publish_without_subscribers() {
+    let addr = start_server().await;
+    let mut publisher = connect(addr).await;
+
+    let response = send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+    assert_eq!(Frame::Integer(0), response);
+}
+
+#[tokio::test]
+async fn mini_redis_subscriber_receives_published_messages() {
+    let addr = start_server().await;
+
+    let subscriber = client::connect(addr).await.unwrap();
+    let mut subscriber = subscriber
+        .subscribe(vec!["news".to_string()])
+        .await
+        .unwrap();
+
+    let mut publisher = client::connect(addr).await.unwrap();
+    assert_eq!(1, publisher.publish("news", "hello".into()).await.unwrap());
+
+    let message = subscriber.next_message().await.unwrap().unwrap();
+    assert_eq!("news", message.channel);
+    assert_eq!(Bytes::from("hello"), message.content);
+}
+
+#[tokio::test]
+async fn messages_are_fanned_out_to_every_subscriber() {
+    let addr = start_server().await;
+
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+
+    assert_eq!(
+        reply("subscribe", "news", 1),
+        send(&mut first, &["SUBSCRIBE", "news"]).await
+    );
+    assert_eq!(
+        reply("subscribe", "news", 1),
+        send(&mut second, &["SUBSCRIBE", "news"]).await
+    );
+
+    let mut publisher = connect(addr).await;
+    let response = send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+    assert_eq!(Frame::Integer(2), response);
+
+    assert_eq!(message("news", "hello"), read(&mut first).await);
+    assert_eq!(message("news", "hello"), read(&mut second).await);
+}
+
+#[tokio::test]
+async fn subscribe_to_more_channels_while_subscribed() {
+    let addr = start_server().await;
+
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+
+    subscriber
+        .write_frame(&command(&["SUBSCRIBE", "a", "b"]))
+        .await
+        .unwrap();
+    assert_eq!(reply("subscribe", "a", 1), read(&mut subscriber).await);
+    assert_eq!(reply("subscribe", "b", 2), read(&mut subscriber).await);
+
+    // Only SUBSCRIBE and UNSUBSCRIBE are handled in the subscribed state
+    let response = send(&mut subscriber, &["SUBSCRIBE", "c"]).await;
+    assert_eq!(reply("subscribe", "c", 3), response);
+
+    for channel in ["a", "b", "c"] {
+        let response = send(&mut publisher, &["PUBLISH", channel, channel]).await;
+        assert_eq!(Frame::Integer(1), response);
+
+        assert_eq!(message(channel, channel), read(&mut subscriber).await);
+    }
+}
+
+#[tokio::test]
+async fn other_commands_are_rejected_while_subscribed() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+
+    send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+
+    match send(&mut subscriber, &["GET", "key"]).await {
+        Frame::Error(msg) => assert!(msg.contains("'get'"), "{}", msg),
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+
+    // Still subscribed
+    let mut publisher = connect(addr).await;
+    send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+    assert_eq!(message("news", "hello"), read(&mut subscriber).await);
+}
+
+#[tokio::test]
+async fn ping_while_subscribed() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+
+    send(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+
+    assert_eq!(
+        send(&mut subscriber, &["PING"]).await,
+        Frame::Array(vec![bulk("pong"), bulk("")])
+    );
+    assert_eq!(
+        send(&mut subscriber, &["PING", "hi"]).await,
+        Frame::Array(vec![bulk("pong"), bulk("hi")])
+    );
+
+    // Still subscribed
+    let mut publisher = connect(addr).await;
+    send(&mut publisher, &["PUBLISH", "news", "hello"]).await;
+    assert_eq!(message("news", "hello"), read(&mut subscriber).await);
+}
+
+#[tokio::test]
+async fn unsubscribe_from_some_channels() {
+    let addr = start_server().await;
+
+    let mut subscriber = connect(addr).await;
+    let mut publisher = connect(addr).await;
+
+    subscriber
+        .write_frame(&command(&["SUBSCRIBE", "a", "b"]))
+        .await
+        .unwrap();
+    read(&mut subscriber).await;
+    read(&mut subscriber).await;
+
+    let response = send(&mut subscriber, &["UNSUBSCRIBE", "a"]).await;
+    assert_eq!(reply("unsubscribe", "a", 1), response);
+
+    let response = send(&mut publisher, &["PUBLISH", "a", "dropped"]).await;
+    assert_eq!(Frame::Integer(0), response);
+
+    send(&mut publisher, &["PUBLISH", "b", "kept"]).await;
+    assert_eq!(message("b", "kept"), read(&mut subscriber).await);
+}
+
+#[tokio::test]
+async fn unsubscribe_from_all_channels_leaves_subscribed_state() {
+    let addr = start_server().await;
+    let mut subscriber = connect(addr).await;
+
+    subscriber
+        .write_frame(&command(&["SUBSCRIBE", "a", "b"]))
+        .await
+        .unwrap();
+    read(&mut subscriber).await;
+    read(&mut subscriber).await;
+
+    // Without arguments, every channel is unsubscribed
+    subscriber
+        .write_frame(&command(&["UNSUBSCRIBE"]))
+        .await
+        .unwrap();
+
+    // Channels are removed in an unspecified order, the count goes down
+    // with every reply.
+    let mut channels = vec![];
+
+    for count in [1, 0] {
+        match read(&mut subscriber).await {
+            Frame::Array(parts) => {
+                assert_eq!(bulk("unsubscribe"), parts[0]);
+                assert_eq!(Frame::Integer(count), parts[2]);
+                channels.push(parts[1].to_string());
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+
+    channels.sort();
+    assert_eq!(vec!["a", "b"], channels);
+
+    // Regular commands work again
+    assert_eq!(Frame::Null, send(&mut subscriber, &["GET", "key"]).await);
+}
+
+#[tokio::test]
+async fn unsubscribe_when_not_subscribed_is_an_error() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    let response = send(&mut connection, &["UNSUBSCRIBE"]).await;
+    assert!(matches!(response, Frame::Error(_)), "{:?}", response);
+}
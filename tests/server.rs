@@ -0,0 +1,243 @@
+mod support;
+
+use bytes::Bytes;
+use mini_redis::client;
+use my_redis::Frame;
+use std::time::Duration;
+use support::{connect, send, start_server};
+
+#[tokio::test]
+async fn set_is_visible_to_other_connections() {
+    let addr = start_server().await;
+
+    let mut writer = client::connect(addr).await.unwrap();
+    let mut reader = client::connect(addr).await.unwrap();
+
+    writer.set("hello", "world".into()).await.unwrap();
+
+    let value = reader.get("hello").await.unwrap();
+    assert_eq!(Some(Bytes::from("world")), value);
+}
+
+#[tokio::test]
+async fn get_missing_key_returns_none() {
+    let addr = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+
+    assert_eq!(None, client.get("missing").await.unwrap());
+}
+
+#[tokio::test]
+async fn later_set_overwrites_value_for_every_connection() {
+    let addr = start_server().await;
+
+    let mut first = client::connect(addr).await.unwrap();
+    let mut second = client::connect(addr).await.unwrap();
+
+    first.set("key", "one".into()).await.unwrap();
+    second.set("key", "two".into()).await.unwrap();
+
+    assert_eq!(Some(Bytes::from("two")), first.get("key").await.unwrap());
+    assert_eq!(Some(Bytes::from("two")), second.get("key").await.unwrap());
+}
+
+#[tokio::test]
+async fn many_clients_share_one_keyspace() {
+    let addr = start_server().await;
+
+    // Every client writes its own key concurrently
+    let writers: Vec<_> = (0..16)
+        .map(|i| {
+            tokio::spawn(async move {
+                let mut client = client::connect(addr).await.unwrap();
+                client
+                    .set(&format!("key:{}", i), format!("value:{}", i).into())
+                    .await
+                    .unwrap();
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    // A fresh connection sees all of them
+    let mut client = client::connect(addr).await.unwrap();
+
+    for i in 0..16 {
+        let value = client.get(&format!("key:{}", i)).await.unwrap();
+        assert_eq!(Some(Bytes::from(format!("value:{}", i))), value);
+    }
+}
+
+#[tokio::test]
+async fn set_with_expiration_is_removed_after_deadline() {
+    let addr = start_server().await;
+
+    let mut client = client::connect(addr).await.unwrap();
+
+    // The mini-redis client sends `SET key value PX ms`
+    client
+        .set_expires("hello", "world".into(), Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        Some(Bytes::from("world")),
+        client.get("hello").await.unwrap()
+    );
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(None, client.get("hello").await.unwrap());
+}
+
+#[tokio::test]
+async fn set_ex_reports_ttl_and_pttl() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    assert_eq!(
+        send(&mut connection, &["SET", "key", "value", "EX", "100"]).await,
+        "OK"
+    );
+    assert_eq!(
+        Frame::Integer(100),
+        send(&mut connection, &["TTL", "key"]).await
+    );
+
+    match send(&mut connection, &["PTTL", "key"]).await {
+        Frame::Integer(ms) => assert!(ms > 99_000 && ms <= 100_000, "pttl {}", ms),
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn ttl_of_missing_and_persistent_keys() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    assert_eq!(
+        Frame::Integer(-2),
+        send(&mut connection, &["TTL", "key"]).await
+    );
+    assert_eq!(
+        Frame::Integer(-2),
+        send(&mut connection, &["PTTL", "key"]).await
+    );
+
+    send(&mut connection, &["SET", "key", "value"]).await;
+
+    assert_eq!(
+        Frame::Integer(-1),
+        send(&mut connection, &["TTL", "key"]).await
+    );
+    assert_eq!(
+        Frame::Integer(-1),
+        send(&mut connection, &["PTTL", "key"]).await
+    );
+}
+
+#[tokio::test]
+async fn expire_and_persist() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    // Neither command does anything on a missing key
+    assert_eq!(
+        Frame::Integer(0),
+        send(&mut connection, &["EXPIRE", "key", "10"]).await
+    );
+    assert_eq!(
+        Frame::Integer(0),
+        send(&mut connection, &["PERSIST", "key"]).await
+    );
+
+    send(&mut connection, &["SET", "key", "value"]).await;
+
+    // A key without a TTL cannot be persisted
+    assert_eq!(
+        Frame::Integer(0),
+        send(&mut connection, &["PERSIST", "key"]).await
+    );
+
+    assert_eq!(
+        Frame::Integer(1),
+        send(&mut connection, &["EXPIRE", "key", "10"]).await
+    );
+    assert_eq!(
+        Frame::Integer(10),
+        send(&mut connection, &["TTL", "key"]).await
+    );
+
+    assert_eq!(
+        Frame::Integer(1),
+        send(&mut connection, &["PERSIST", "key"]).await
+    );
+    assert_eq!(
+        Frame::Integer(-1),
+        send(&mut connection, &["TTL", "key"]).await
+    );
+    assert_eq!(send(&mut connection, &["GET", "key"]).await, "value");
+}
+
+#[tokio::test]
+async fn expire_in_the_past_deletes_the_key() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    send(&mut connection, &["SET", "zero", "value"]).await;
+    send(&mut connection, &["SET", "negative", "value"]).await;
+
+    assert_eq!(
+        Frame::Integer(1),
+        send(&mut connection, &["EXPIRE", "zero", "0"]).await
+    );
+    assert_eq!(
+        Frame::Integer(1),
+        send(&mut connection, &["EXPIRE", "negative", "-5"]).await
+    );
+
+    assert_eq!(Frame::Null, send(&mut connection, &["GET", "zero"]).await);
+    assert_eq!(
+        Frame::Null,
+        send(&mut connection, &["GET", "negative"]).await
+    );
+}
+
+#[tokio::test]
+async fn plain_set_clears_previous_ttl() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    send(&mut connection, &["SET", "key", "one", "PX", "50"]).await;
+    send(&mut connection, &["SET", "key", "two"]).await;
+
+    assert_eq!(
+        Frame::Integer(-1),
+        send(&mut connection, &["TTL", "key"]).await
+    );
+
+    // Outlive the first deadline, the purge task must not remove the key
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(send(&mut connection, &["GET", "key"]).await, "two");
+}
+
+#[tokio::test]
+async fn expire_reschedules_pending_deadlines() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+
+    // The purge task first sleeps until the long deadline, a shorter one set
+    // afterwards must still be honored.
+    send(&mut connection, &["SET", "long", "value", "EX", "100"]).await;
+    send(&mut connection, &["SET", "short", "value", "PX", "50"]).await;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(Frame::Null, send(&mut connection, &["GET", "short"]).await);
+    assert_eq!(send(&mut connection, &["GET", "long"]).await, "value");
+}
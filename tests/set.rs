@@ -0,0 +1,120 @@
+mod support;
+
+use my_redis::Frame;
+use support::{connect, send, start_server};
+
+/// The members in a set response, sorted as sets are not ordered.
+fn members(response: Frame) -> Vec<String> {
+    let mut members: Vec<_> = match response {
+        Frame::Array(items) => items
+            .into_iter()
+            .map(|item| match item {
+                Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+                frame => panic!("unexpected frame {:?}", frame),
+            })
+            .collect(),
+        frame => panic!("unexpected frame {:?}", frame),
+    };
+
+    members.sort();
+    members
+}
+
+#[tokio::test]
+async fn add_and_remove_members() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["SADD", "tags", "a", "b", "a"]).await,
+        Frame::Integer(2)
+    );
+    assert_eq!(
+        send(&mut connection, &["SADD", "tags", "b", "c"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        members(send(&mut connection, &["SMEMBERS", "tags"]).await),
+        ["a", "b", "c"]
+    );
+    assert_eq!(
+        send(&mut connection, &["SISMEMBER", "tags", "b"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["SISMEMBER", "tags", "z"]).await,
+        Frame::Integer(0)
+    );
+
+    assert_eq!(
+        send(&mut connection, &["SREM", "tags", "a", "z"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["SREM", "tags", "b", "c"]).await,
+        Frame::Integer(2)
+    );
+
+    // The emptied set was removed
+    assert!(members(send(&mut connection, &["SMEMBERS", "tags"]).await).is_empty());
+    assert_eq!(send(&mut connection, &["GET", "tags"]).await, Frame::Null);
+}
+
+#[tokio::test]
+async fn combine_sets() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["SADD", "a", "1", "2", "3", "4"]).await;
+    send(&mut connection, &["SADD", "b", "3", "4", "5"]).await;
+    send(&mut connection, &["SADD", "c", "4", "6"]).await;
+
+    assert_eq!(
+        members(send(&mut connection, &["SINTER", "a", "b"]).await),
+        ["3", "4"]
+    );
+    assert_eq!(
+        members(send(&mut connection, &["SINTER", "a", "b", "c"]).await),
+        ["4"]
+    );
+    assert!(members(send(&mut connection, &["SINTER", "a", "missing"]).await).is_empty());
+
+    assert_eq!(
+        members(send(&mut connection, &["SUNION", "a", "b", "c", "missing"]).await),
+        ["1", "2", "3", "4", "5", "6"]
+    );
+
+    assert_eq!(
+        members(send(&mut connection, &["SDIFF", "a", "b", "c"]).await),
+        ["1", "2"]
+    );
+    assert_eq!(
+        members(send(&mut connection, &["SDIFF", "a"]).await),
+        ["1", "2", "3", "4"]
+    );
+    assert!(members(send(&mut connection, &["SDIFF", "missing", "a"]).await).is_empty());
+}
+
+#[tokio::test]
+async fn wrong_type_errors() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["SET", "string", "value"]).await;
+    send(&mut connection, &["SADD", "set", "a"]).await;
+
+    let wrong_type = Frame::Error(
+        "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+    );
+
+    for args in [
+        &["SADD", "string", "a"][..],
+        &["SREM", "string", "a"],
+        &["SMEMBERS", "string"],
+        &["SISMEMBER", "string", "a"],
+        &["SINTER", "set", "string"],
+        &["SUNION", "string", "set"],
+        &["SDIFF", "set", "string"],
+        &["GET", "set"],
+        &["ZADD", "set", "1", "a"],
+    ] {
+        assert_eq!(send(&mut connection, args).await, wrong_type, "{:?}", args);
+    }
+}
@@ -0,0 +1,96 @@
+mod support;
+
+use my_redis::{server, Db};
+use std::net::SocketAddr;
+use support::{connect, send};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+
+/// Start a server that shuts down when the returned sender is used.
+async fn start_server() -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (tx, rx) = oneshot::channel();
+    let handle = tokio::spawn(server::run(listener, Db::new(4), rx));
+
+    (addr, tx, handle)
+}
+
+/// Wait for `run` to return, failing the test if it hangs.
+async fn join(handle: JoinHandle<()>) {
+    time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("server did not shut down")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_without_connections() {
+    let (_, tx, handle) = start_server().await;
+
+    tx.send(()).unwrap();
+    join(handle).await;
+}
+
+#[tokio::test]
+async fn idle_connections_are_closed() {
+    let (addr, tx, handle) = start_server().await;
+
+    let mut connections = vec![];
+    for _ in 0..4 {
+        let mut connection = connect(addr).await;
+        assert_eq!(send(&mut connection, &["SET", "key", "value"]).await, "OK");
+        connections.push(connection);
+    }
+
+    tx.send(()).unwrap();
+    join(handle).await;
+
+    for connection in &mut connections {
+        assert_eq!(None, connection.read_frame().await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_closed() {
+    let (addr, tx, handle) = start_server().await;
+
+    let mut connection = connect(addr).await;
+    send(&mut connection, &["SUBSCRIBE", "news"]).await;
+
+    tx.send(()).unwrap();
+    join(handle).await;
+
+    assert_eq!(None, connection.read_frame().await.unwrap());
+}
+
+#[tokio::test]
+async fn new_connections_are_refused() {
+    let (addr, tx, handle) = start_server().await;
+
+    tx.send(()).unwrap();
+    join(handle).await;
+
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn the_shared_db_outlives_the_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let db = Db::new(4);
+
+    let (tx, rx) = oneshot::channel();
+    let handle = tokio::spawn(server::run(listener, db.clone(), rx));
+
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["SET", "key", "value"]).await, "OK");
+
+    tx.send(()).unwrap();
+    join(handle).await;
+
+    assert_eq!(Some("value".into()), db.get("key"));
+}
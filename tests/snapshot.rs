@@ -0,0 +1,374 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::db::Ttl;
+use my_redis::server::{self, Config};
+use my_redis::snapshot::{self, SaveRule, Snapshots};
+use my_redis::{Db, Frame};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use support::{connect, send};
+use tokio::net::TcpListener;
+use tokio::time::{self, Duration};
+
+/// Start a server saving its snapshots with `snapshots`.
+async fn start_server(db: Db, snapshots: Snapshots) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let config = Config {
+        snapshots: Some(snapshots),
+        ..Config::default()
+    };
+
+    tokio::spawn(server::run_with_config(
+        listener,
+        db,
+        config,
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}
+
+/// Load the snapshot at `path` into a fresh `Db`, as a restarted server would.
+fn reload(path: &Path) -> Db {
+    let db = Db::new(4);
+    assert!(snapshot::load(path, &db).unwrap());
+    db
+}
+
+async fn wait_for_save(snapshots: &Snapshots) {
+    for _ in 0..500 {
+        if !snapshots.save_in_progress() {
+            return;
+        }
+
+        time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("save did not complete");
+}
+
+#[tokio::test]
+async fn snapshot_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    let db = Db::new(4);
+    db.set("a".to_string(), Bytes::from("1"), None);
+    db.set(
+        "b".to_string(),
+        Bytes::from("2"),
+        Some(Duration::from_secs(100)),
+    );
+    db.set("empty".to_string(), Bytes::new(), None);
+    db.set("large".to_string(), Bytes::from(vec![7; 100_000]), None);
+
+    let snapshots = Snapshots::new(&path, vec![], &db);
+    snapshots.save().unwrap();
+
+    let db = reload(&path);
+
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.ttl("a"), Ttl::Persistent);
+    assert_eq!(db.get("b").unwrap(), "2");
+    assert!(matches!(
+        db.ttl("b"),
+        Ttl::Expires(remaining) if remaining > Duration::from_secs(95)
+    ));
+    assert_eq!(db.get("empty").unwrap(), "");
+    assert_eq!(db.get("large").unwrap(), Bytes::from(vec![7; 100_000]));
+
+    // Only the snapshot is left, the temporary file was renamed
+    let files: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+    assert_eq!(files.len(), 1);
+}
+
+#[tokio::test]
+async fn collections_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    let db = Db::new(4);
+    let snapshots = Snapshots::new(&path, vec![], &db);
+    let mut connection = connect(start_server(db, snapshots.clone()).await).await;
+
+    send(&mut connection, &["RPUSH", "list", "a", "", "c"]).await;
+    send(&mut connection, &["HSET", "hash", "f1", "v1", "f2", ""]).await;
+    send(&mut connection, &["SADD", "set", "a", ""]).await;
+    send(
+        &mut connection,
+        &["ZADD", "zset", "2", "b", "-inf", "a", "1.5", "c"],
+    )
+    .await;
+    send(&mut connection, &["RPUSH", "expiring", "x"]).await;
+    send(&mut connection, &["EXPIRE", "expiring", "100"]).await;
+    assert_eq!(send(&mut connection, &["SAVE"]).await, "OK");
+
+    let db = reload(&path);
+    let snapshots = Snapshots::new(dir.path().join("other.rdb"), vec![], &db);
+    let mut connection = connect(start_server(db.clone(), snapshots).await).await;
+
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "list", "0", "-1"]).await,
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from("a")),
+            Frame::Bulk(Bytes::new()),
+            Frame::Bulk(Bytes::from("c")),
+        ])
+    );
+    assert_eq!(
+        send(&mut connection, &["HMGET", "hash", "f1", "f2", "f3"]).await,
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from("v1")),
+            Frame::Bulk(Bytes::new()),
+            Frame::Null,
+        ])
+    );
+    assert_eq!(
+        send(&mut connection, &["SISMEMBER", "set", ""]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZRANGE", "zset", "0", "-1", "WITHSCORES"]
+        )
+        .await,
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from("a")),
+            Frame::Bulk(Bytes::from("-inf")),
+            Frame::Bulk(Bytes::from("c")),
+            Frame::Bulk(Bytes::from("1.5")),
+            Frame::Bulk(Bytes::from("b")),
+            Frame::Bulk(Bytes::from("2")),
+        ])
+    );
+    assert!(matches!(
+        db.ttl("expiring"),
+        Ttl::Expires(remaining) if remaining > Duration::from_secs(95)
+    ));
+}
+
+#[tokio::test]
+async fn missing_snapshot_loads_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let db = Db::new(4);
+    assert!(!snapshot::load(dir.path().join("dump.rdb"), &db).unwrap());
+}
+
+#[tokio::test]
+async fn expired_keys_are_not_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    let db = Db::new(4);
+    db.set(
+        "short".to_string(),
+        Bytes::from("1"),
+        Some(Duration::from_millis(100)),
+    );
+    db.set(
+        "long".to_string(),
+        Bytes::from("2"),
+        Some(Duration::from_secs(100)),
+    );
+    Snapshots::new(&path, vec![], &db).save().unwrap();
+
+    time::sleep(Duration::from_millis(200)).await;
+
+    let db = reload(&path);
+    assert_eq!(db.get("short"), None);
+    assert_eq!(db.get("long").unwrap(), "2");
+}
+
+#[tokio::test]
+async fn far_deadlines_are_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    // Past the largest Unix time in milliseconds
+    let db = Db::new(4);
+    db.set(
+        "far".to_string(),
+        Bytes::from("1"),
+        Some(Duration::from_millis(i64::MAX as u64)),
+    );
+    Snapshots::new(&path, vec![], &db).save().unwrap();
+
+    let db = reload(&path);
+    assert_eq!(db.get("far").unwrap(), "1");
+    assert!(matches!(db.ttl("far"), Ttl::Expires(_)));
+}
+
+#[tokio::test]
+async fn corrupt_snapshot_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    let db = Db::new(4);
+    db.set("key".to_string(), Bytes::from("value"), None);
+    Snapshots::new(&path, vec![], &db).save().unwrap();
+
+    let valid = fs::read(&path).unwrap();
+
+    let mut flipped = valid.clone();
+    flipped[12] ^= 1;
+
+    let mut not_a_snapshot = valid.clone();
+    not_a_snapshot[0] = b'X';
+
+    for data in [flipped, valid[..valid.len() - 1].to_vec(), not_a_snapshot] {
+        fs::write(&path, &data).unwrap();
+
+        let db = Db::new(4);
+        assert!(snapshot::load(&path, &db).is_err());
+        assert_eq!(db.get("key"), None);
+    }
+}
+
+#[tokio::test]
+async fn save_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    let db = Db::new(4);
+    let snapshots = Snapshots::new(&path, vec![], &db);
+    let mut connection = connect(start_server(db, snapshots.clone()).await).await;
+
+    assert_eq!(send(&mut connection, &["SET", "a", "1"]).await, "OK");
+    assert_eq!(snapshots.unsaved_changes(), 1);
+    assert_eq!(send(&mut connection, &["SAVE"]).await, "OK");
+    assert_eq!(snapshots.unsaved_changes(), 0);
+    assert_eq!(reload(&path).get("a").unwrap(), "1");
+
+    // Writes that change nothing are not counted
+    send(&mut connection, &["LPOP", "missing"]).await;
+    send(&mut connection, &["SREM", "missing", "x"]).await;
+    assert_eq!(snapshots.unsaved_changes(), 0);
+
+    assert_eq!(send(&mut connection, &["SET", "b", "2"]).await, "OK");
+    assert_eq!(
+        send(&mut connection, &["BGSAVE"]).await,
+        "Background saving started"
+    );
+    wait_for_save(&snapshots).await;
+
+    let db = reload(&path);
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.get("b").unwrap(), "2");
+}
+
+/// A command writing to several shards is never half saved.
+#[tokio::test]
+async fn saves_are_point_in_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    // Enough keys for copying the keyspace to take a while
+    let db = Db::new(4);
+    for i in 0..50_000 {
+        db.set(format!("filler:{}", i), Bytes::from("x"), None);
+    }
+
+    let snapshots = Snapshots::new(&path, vec![], &db);
+    let addr = start_server(db, snapshots.clone()).await;
+
+    let writer = tokio::spawn(async move {
+        let mut connection = connect(addr).await;
+
+        for round in 0.. {
+            let round = round.to_string();
+            let mut args = vec!["MSET"];
+            for key in ["k0", "k1", "k2", "k3", "k4", "k5", "k6", "k7"] {
+                args.extend([key, &round]);
+            }
+            send(&mut connection, &args).await;
+        }
+    });
+
+    let mut connection = connect(addr).await;
+    for _ in 0..10 {
+        send(&mut connection, &["BGSAVE"]).await;
+        wait_for_save(&snapshots).await;
+
+        let db = reload(&path);
+        let values: Vec<_> = (0..8).map(|i| db.get(&format!("k{}", i))).collect();
+        assert!(
+            values.iter().all(|value| *value == values[0]),
+            "{:?}",
+            values
+        );
+    }
+
+    writer.abort();
+}
+
+#[tokio::test]
+async fn save_commands_require_snapshots() {
+    let mut connection = connect(support::start_server().await).await;
+
+    for command in ["SAVE", "BGSAVE"] {
+        assert_eq!(
+            send(&mut connection, &[command]).await,
+            Frame::Error("ERR snapshots are disabled".to_string())
+        );
+    }
+}
+
+#[tokio::test]
+async fn saves_when_a_rule_is_met() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dump.rdb");
+
+    let db = Db::new(4);
+    let rules = vec![SaveRule {
+        seconds: 0,
+        changes: 2,
+    }];
+    let snapshots = Snapshots::new(&path, rules, &db);
+
+    db.set("a".to_string(), Bytes::from("1"), None);
+
+    // The rule is checked every second
+    time::sleep(Duration::from_millis(1500)).await;
+    assert!(!path.exists(), "saved before enough changes were made");
+
+    db.set("b".to_string(), Bytes::from("2"), None);
+
+    for _ in 0..50 {
+        if path.exists() && !snapshots.save_in_progress() {
+            break;
+        }
+
+        time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let db = reload(&path);
+    assert_eq!(db.get("a").unwrap(), "1");
+    assert_eq!(db.get("b").unwrap(), "2");
+    assert_eq!(snapshots.unsaved_changes(), 0);
+}
+
+#[test]
+fn save_rules() {
+    assert_eq!(
+        SaveRule::parse_list("3600 1 300 100").unwrap(),
+        vec![
+            SaveRule {
+                seconds: 3600,
+                changes: 1
+            },
+            SaveRule {
+                seconds: 300,
+                changes: 100
+            },
+        ]
+    );
+    assert_eq!(SaveRule::parse_list("").unwrap(), vec![]);
+    assert!(SaveRule::parse_list("3600").is_err());
+    assert!(SaveRule::parse_list("3600 many").is_err());
+}
@@ -0,0 +1,377 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::Frame;
+use support::{connect, send, start_server};
+
+/// The array of bulk strings a range responds with.
+fn bulks(items: &[&str]) -> Frame {
+    Frame::Array(
+        items
+            .iter()
+            .map(|item| Frame::Bulk(Bytes::copy_from_slice(item.as_bytes())))
+            .collect(),
+    )
+}
+
+async fn leaderboard(connection: &mut my_redis::Connection) {
+    assert_eq!(
+        send(
+            connection,
+            &["ZADD", "board", "10", "ann", "30", "cat", "20", "bob", "40", "dan"]
+        )
+        .await,
+        Frame::Integer(4)
+    );
+}
+
+#[tokio::test]
+async fn add_score_and_rank() {
+    let mut connection = connect(start_server().await).await;
+    leaderboard(&mut connection).await;
+
+    assert_eq!(
+        send(&mut connection, &["ZSCORE", "board", "bob"]).await,
+        "20"
+    );
+    assert_eq!(
+        send(&mut connection, &["ZSCORE", "board", "eve"]).await,
+        Frame::Null
+    );
+    assert_eq!(
+        send(&mut connection, &["ZRANK", "board", "cat"]).await,
+        Frame::Integer(2)
+    );
+    assert_eq!(
+        send(&mut connection, &["ZRANK", "board", "eve"]).await,
+        Frame::Null
+    );
+
+    // Updating a score moves the member
+    assert_eq!(
+        send(&mut connection, &["ZADD", "board", "5", "dan"]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(
+        send(&mut connection, &["ZRANK", "board", "dan"]).await,
+        Frame::Integer(0)
+    );
+
+    assert_eq!(
+        send(&mut connection, &["ZINCRBY", "board", "2.5", "ann"]).await,
+        "12.5"
+    );
+    assert_eq!(
+        send(&mut connection, &["ZINCRBY", "board", "1", "eve"]).await,
+        "1"
+    );
+
+    assert_eq!(
+        send(&mut connection, &["ZREM", "board", "eve", "zed"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZRANGE", "board", "0", "-1", "WITHSCORES"]
+        )
+        .await,
+        bulks(&["dan", "5", "ann", "12.5", "bob", "20", "cat", "30"])
+    );
+}
+
+#[tokio::test]
+async fn add_options() {
+    let mut connection = connect(start_server().await).await;
+    leaderboard(&mut connection).await;
+
+    // NX only adds, XX only updates
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZADD", "board", "NX", "1", "ann", "1", "eve"]
+        )
+        .await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["ZSCORE", "board", "ann"]).await,
+        "10"
+    );
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZADD", "board", "XX", "CH", "2", "ann", "2", "fay"]
+        )
+        .await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["ZSCORE", "board", "fay"]).await,
+        Frame::Null
+    );
+
+    // GT only raises scores
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZADD", "board", "GT", "CH", "50", "bob", "1", "cat"]
+        )
+        .await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["ZSCORE", "board", "cat"]).await,
+        "30"
+    );
+
+    assert_eq!(
+        send(&mut connection, &["ZADD", "board", "INCR", "5", "bob"]).await,
+        "55"
+    );
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZADD", "board", "NX", "INCR", "5", "bob"]
+        )
+        .await,
+        Frame::Null
+    );
+
+    for (args, error) in [
+        (
+            &["ZADD", "board", "NX", "XX", "1", "a"][..],
+            "ERR XX and NX options at the same time are not compatible",
+        ),
+        (
+            &["ZADD", "board", "GT", "LT", "1", "a"],
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        ),
+        (
+            &["ZADD", "board", "INCR", "1", "a", "2", "b"],
+            "ERR INCR option supports a single increment-element pair",
+        ),
+        (&["ZADD", "board", "1", "a", "2"], "ERR syntax error"),
+        (
+            &["ZADD", "board", "high", "a"],
+            "ERR value is not a valid float",
+        ),
+        (
+            &["ZADD", "board", "nan", "a"],
+            "ERR value is not a valid float",
+        ),
+    ] {
+        assert_eq!(
+            send(&mut connection, args).await,
+            Frame::Error(error.to_string()),
+            "{:?}",
+            args
+        );
+    }
+
+    send(&mut connection, &["ZADD", "inf", "inf", "a"]).await;
+    assert_eq!(
+        send(&mut connection, &["ZINCRBY", "inf", "-inf", "a"]).await,
+        Frame::Error("ERR resulting score is not a number (NaN)".to_string())
+    );
+    assert_eq!(send(&mut connection, &["ZSCORE", "inf", "a"]).await, "inf");
+}
+
+#[tokio::test]
+async fn ranges() {
+    let mut connection = connect(start_server().await).await;
+    leaderboard(&mut connection).await;
+
+    for (args, expected) in [
+        (&["1", "2"][..], &["bob", "cat"][..]),
+        (&["-2", "-1"], &["cat", "dan"]),
+        (&["0", "0", "REV"], &["dan"]),
+        (&["0", "-1", "REV"], &["dan", "cat", "bob", "ann"]),
+        (&["3", "1"], &[]),
+        (&["20", "30", "BYSCORE"], &["bob", "cat"]),
+        (&["(20", "+inf", "BYSCORE"], &["cat", "dan"]),
+        (&["-inf", "(20", "BYSCORE"], &["ann"]),
+        (
+            &["+inf", "-inf", "BYSCORE", "REV"],
+            &["dan", "cat", "bob", "ann"],
+        ),
+        (&["30", "(10", "BYSCORE", "REV"], &["cat", "bob"]),
+        (
+            &["-inf", "+inf", "BYSCORE", "LIMIT", "1", "2"],
+            &["bob", "cat"],
+        ),
+        (&["-inf", "+inf", "BYSCORE", "LIMIT", "3", "-1"], &["dan"]),
+        (&["-inf", "+inf", "BYSCORE", "LIMIT", "-1", "2"], &[]),
+        (&["50", "60", "BYSCORE"], &[]),
+    ] {
+        let mut command = vec!["ZRANGE", "board"];
+        command.extend_from_slice(args);
+        assert_eq!(
+            send(&mut connection, &command).await,
+            bulks(expected),
+            "{:?}",
+            args
+        );
+    }
+
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZRANGE", "board", "15", "30", "BYSCORE", "WITHSCORES"]
+        )
+        .await,
+        bulks(&["bob", "20", "cat", "30"])
+    );
+
+    assert_eq!(
+        send(
+            &mut connection,
+            &["ZRANGE", "board", "0", "1", "LIMIT", "0", "1"]
+        )
+        .await,
+        Frame::Error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        send(&mut connection, &["ZRANGE", "board", "a", "b", "BYSCORE"]).await,
+        Frame::Error("ERR min or max is not a float".to_string())
+    );
+}
+
+#[tokio::test]
+async fn lex_ranges() {
+    let mut connection = connect(start_server().await).await;
+
+    send(
+        &mut connection,
+        &[
+            "ZADD", "names", "0", "d", "0", "a", "0", "c", "0", "b", "0", "e",
+        ],
+    )
+    .await;
+
+    for (args, expected) in [
+        (&["-", "+"][..], &["a", "b", "c", "d", "e"][..]),
+        (&["[b", "(d"], &["b", "c"]),
+        (&["(b", "+"], &["c", "d", "e"]),
+        (&["+", "-", "REV"], &["e", "d", "c", "b", "a"]),
+        (&["[d", "(a", "REV", "LIMIT", "1", "2"], &["c", "b"]),
+        (&["+", "[c"], &[]),
+    ] {
+        let mut command = vec!["ZRANGE", "names"];
+        command.extend_from_slice(args);
+        command.push("BYLEX");
+        assert_eq!(
+            send(&mut connection, &command).await,
+            bulks(expected),
+            "{:?}",
+            args
+        );
+    }
+
+    assert_eq!(
+        send(&mut connection, &["ZRANGE", "names", "a", "c", "BYLEX"]).await,
+        Frame::Error("ERR min or max not valid string range item".to_string())
+    );
+}
+
+#[tokio::test]
+async fn pop_lowest_scores() {
+    let mut connection = connect(start_server().await).await;
+    leaderboard(&mut connection).await;
+
+    assert_eq!(
+        send(&mut connection, &["ZPOPMIN", "board"]).await,
+        bulks(&["ann", "10"])
+    );
+    assert_eq!(
+        send(&mut connection, &["ZPOPMIN", "board", "2"]).await,
+        bulks(&["bob", "20", "cat", "30"])
+    );
+    assert_eq!(
+        send(&mut connection, &["ZPOPMIN", "board", "5"]).await,
+        bulks(&["dan", "40"])
+    );
+
+    // The emptied sorted set was removed
+    assert_eq!(
+        send(&mut connection, &["ZPOPMIN", "board"]).await,
+        bulks(&[])
+    );
+    assert_eq!(send(&mut connection, &["SET", "board", "1"]).await, "OK");
+}
+
+/// Many inserts, updates and removals, checking ranks and ranges against a
+/// sorted `Vec`.
+#[tokio::test]
+async fn large_set_matches_a_model() {
+    let mut connection = connect(start_server().await).await;
+
+    let mut model: Vec<(i64, String)> = vec![];
+    let mut seed = 7u64;
+
+    for step in 0..1500 {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        let member = format!("m{}", (seed >> 33) % 300);
+        let score = ((seed >> 20) % 50) as i64;
+
+        model.retain(|(_, m)| *m != member);
+
+        if step % 3 == 0 {
+            send(&mut connection, &["ZREM", "big", &member]).await;
+        } else {
+            send(
+                &mut connection,
+                &["ZADD", "big", &score.to_string(), &member],
+            )
+            .await;
+            model.push((score, member));
+        }
+    }
+
+    model.sort();
+
+    let all: Vec<_> = model.iter().map(|(_, m)| m.as_str()).collect();
+    assert_eq!(
+        send(&mut connection, &["ZRANGE", "big", "0", "-1"]).await,
+        bulks(&all)
+    );
+
+    for (rank, (_, member)) in model.iter().enumerate() {
+        assert_eq!(
+            send(&mut connection, &["ZRANK", "big", member]).await,
+            Frame::Integer(rank as i64)
+        );
+    }
+
+    for (start, stop) in [(0, 9), (50, 60), (100, 100)] {
+        let (start_arg, stop_arg) = (start.to_string(), stop.to_string());
+        let expected = &all[start..=stop];
+        assert_eq!(
+            send(&mut connection, &["ZRANGE", "big", &start_arg, &stop_arg]).await,
+            bulks(expected)
+        );
+
+        let reversed: Vec<_> = all.iter().rev().copied().collect();
+        assert_eq!(
+            send(
+                &mut connection,
+                &["ZRANGE", "big", &start_arg, &stop_arg, "REV"]
+            )
+            .await,
+            bulks(&reversed[start..=stop])
+        );
+    }
+
+    let expected: Vec<_> = model
+        .iter()
+        .filter(|(score, _)| (10..20).contains(score))
+        .map(|(_, m)| m.as_str())
+        .collect();
+    assert_eq!(
+        send(&mut connection, &["ZRANGE", "big", "10", "(20", "BYSCORE"]).await,
+        bulks(&expected)
+    );
+}
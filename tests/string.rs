@@ -0,0 +1,362 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::Frame;
+use support::{connect, send, start_server};
+
+fn error(msg: &str) -> Frame {
+    Frame::Error(msg.to_string())
+}
+
+#[tokio::test]
+async fn counters() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["INCR", "n"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["INCRBY", "n", "41"]).await,
+        Frame::Integer(42)
+    );
+    assert_eq!(
+        send(&mut connection, &["DECR", "n"]).await,
+        Frame::Integer(41)
+    );
+    assert_eq!(
+        send(&mut connection, &["DECRBY", "n", "50"]).await,
+        Frame::Integer(-9)
+    );
+    assert_eq!(send(&mut connection, &["GET", "n"]).await, "-9");
+
+    // Counters are plain strings
+    send(&mut connection, &["SET", "n", "100"]).await;
+    assert_eq!(
+        send(&mut connection, &["INCR", "n"]).await,
+        Frame::Integer(101)
+    );
+
+    assert_eq!(
+        send(&mut connection, &["INCRBYFLOAT", "n", "0.5"]).await,
+        "101.5"
+    );
+    assert_eq!(
+        send(&mut connection, &["INCRBYFLOAT", "n", "-1.5"]).await,
+        "100"
+    );
+    assert_eq!(
+        send(&mut connection, &["INCRBYFLOAT", "f", "2.5e3"]).await,
+        "2500"
+    );
+
+    // The float result is an integer again
+    assert_eq!(
+        send(&mut connection, &["INCR", "n"]).await,
+        Frame::Integer(101)
+    );
+}
+
+#[tokio::test]
+async fn counter_errors() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["SET", "text", "hello"]).await;
+    send(&mut connection, &["SET", "float", "1.5"]).await;
+    send(&mut connection, &["SET", "max", &i64::MAX.to_string()]).await;
+    send(&mut connection, &["SET", "min", &i64::MIN.to_string()]).await;
+
+    let not_an_integer = error("ERR value is not an integer or out of range");
+    let overflow = error("ERR increment or decrement would overflow");
+
+    for (args, expected) in [
+        (&["INCR", "text"][..], &not_an_integer),
+        (&["INCR", "float"], &not_an_integer),
+        (&["INCRBY", "n", "1.5"], &not_an_integer),
+        (&["INCRBY", "n", "99999999999999999999"], &not_an_integer),
+        (&["INCR", "max"], &overflow),
+        (&["DECR", "min"], &overflow),
+        (&["INCRBY", "min", "-1"], &overflow),
+        (&["DECRBY", "max", "-1"], &overflow),
+    ] {
+        assert_eq!(send(&mut connection, args).await, *expected, "{:?}", args);
+    }
+
+    assert_eq!(
+        send(&mut connection, &["DECRBY", "n", &i64::MIN.to_string()]).await,
+        error("ERR decrement would overflow")
+    );
+
+    // Failed increments leave the values alone
+    assert_eq!(
+        send(&mut connection, &["GET", "max"]).await,
+        "9223372036854775807"
+    );
+    assert_eq!(send(&mut connection, &["GET", "n"]).await, Frame::Null);
+
+    for args in [
+        &["INCRBYFLOAT", "text", "1"][..],
+        &["INCRBYFLOAT", "n", "one"],
+        &["INCRBYFLOAT", "n", "nan"],
+    ] {
+        assert_eq!(
+            send(&mut connection, args).await,
+            error("ERR value is not a valid float"),
+            "{:?}",
+            args
+        );
+    }
+
+    assert_eq!(
+        send(&mut connection, &["INCRBYFLOAT", "float", "inf"]).await,
+        error("ERR increment would produce NaN or Infinity")
+    );
+    assert_eq!(send(&mut connection, &["GET", "float"]).await, "1.5");
+}
+
+/// Increments from many clients at once are never lost.
+#[tokio::test]
+async fn concurrent_increments() {
+    let addr = start_server().await;
+
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            tokio::spawn(async move {
+                let mut connection = connect(addr).await;
+                for _ in 0..100 {
+                    send(&mut connection, &["INCR", "hits"]).await;
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut connection = connect(addr).await;
+    assert_eq!(send(&mut connection, &["GET", "hits"]).await, "800");
+}
+
+#[tokio::test]
+async fn edit_strings() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["APPEND", "s", "Hello"]).await,
+        Frame::Integer(5)
+    );
+    assert_eq!(
+        send(&mut connection, &["APPEND", "s", " World"]).await,
+        Frame::Integer(11)
+    );
+    assert_eq!(
+        send(&mut connection, &["STRLEN", "s"]).await,
+        Frame::Integer(11)
+    );
+    assert_eq!(
+        send(&mut connection, &["STRLEN", "missing"]).await,
+        Frame::Integer(0)
+    );
+
+    for (start, end, expected) in [
+        ("0", "4", "Hello"),
+        ("-5", "-1", "World"),
+        ("6", "100", "World"),
+        ("-100", "1", "He"),
+        ("5", "2", ""),
+        ("20", "30", ""),
+    ] {
+        assert_eq!(
+            send(&mut connection, &["GETRANGE", "s", start, end]).await,
+            expected,
+            "{} {}",
+            start,
+            end
+        );
+    }
+    assert_eq!(
+        send(&mut connection, &["GETRANGE", "missing", "0", "-1"]).await,
+        ""
+    );
+
+    assert_eq!(
+        send(&mut connection, &["SETRANGE", "s", "6", "Redis"]).await,
+        Frame::Integer(11)
+    );
+    assert_eq!(send(&mut connection, &["GET", "s"]).await, "Hello Redis");
+
+    // Padded with zero bytes
+    assert_eq!(
+        send(&mut connection, &["SETRANGE", "padded", "3", "ab"]).await,
+        Frame::Integer(5)
+    );
+    assert_eq!(
+        send(&mut connection, &["GET", "padded"]).await,
+        Frame::Bulk(Bytes::from_static(b"\0\0\0ab"))
+    );
+
+    // Writing nothing does not create the key
+    assert_eq!(
+        send(&mut connection, &["SETRANGE", "empty", "10", ""]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(send(&mut connection, &["GET", "empty"]).await, Frame::Null);
+
+    assert_eq!(
+        send(&mut connection, &["SETRANGE", "s", "-1", "x"]).await,
+        error("ERR offset is out of range")
+    );
+    assert_eq!(
+        send(&mut connection, &["SETRANGE", "s", "536870912", "x"]).await,
+        error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+    );
+    assert_eq!(send(&mut connection, &["GET", "s"]).await, "Hello Redis");
+}
+
+#[tokio::test]
+async fn get_and_change() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["GETSET", "k", "1"]).await,
+        Frame::Null
+    );
+    send(&mut connection, &["EXPIRE", "k", "100"]).await;
+    assert_eq!(send(&mut connection, &["GETSET", "k", "2"]).await, "1");
+    // The time to live was discarded
+    assert_eq!(
+        send(&mut connection, &["TTL", "k"]).await,
+        Frame::Integer(-1)
+    );
+
+    assert_eq!(
+        send(&mut connection, &["GETEX", "k", "EX", "100"]).await,
+        "2"
+    );
+    assert_eq!(
+        send(&mut connection, &["TTL", "k"]).await,
+        Frame::Integer(100)
+    );
+    assert_eq!(send(&mut connection, &["GETEX", "k"]).await, "2");
+    assert_eq!(
+        send(&mut connection, &["TTL", "k"]).await,
+        Frame::Integer(100)
+    );
+    assert_eq!(send(&mut connection, &["GETEX", "k", "PERSIST"]).await, "2");
+    assert_eq!(
+        send(&mut connection, &["TTL", "k"]).await,
+        Frame::Integer(-1)
+    );
+    assert_eq!(
+        send(&mut connection, &["GETEX", "missing", "PX", "100"]).await,
+        Frame::Null
+    );
+
+    // A Unix time in the past expires the key right away
+    assert_eq!(
+        send(&mut connection, &["GETEX", "k", "EXAT", "1"]).await,
+        "2"
+    );
+    assert_eq!(send(&mut connection, &["GET", "k"]).await, Frame::Null);
+
+    assert_eq!(
+        send(&mut connection, &["GETEX", "k", "EX", "0"]).await,
+        error("ERR invalid expire time in 'getex' command")
+    );
+    assert_eq!(
+        send(&mut connection, &["GETEX", "k", "KEEPTTL"]).await,
+        error("ERR syntax error")
+    );
+
+    send(&mut connection, &["SET", "k", "3"]).await;
+    assert_eq!(send(&mut connection, &["GETDEL", "k"]).await, "3");
+    assert_eq!(send(&mut connection, &["GETDEL", "k"]).await, Frame::Null);
+}
+
+#[tokio::test]
+async fn set_many_keys() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(
+        send(&mut connection, &["SETNX", "a", "1"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(
+        send(&mut connection, &["SETNX", "a", "2"]).await,
+        Frame::Integer(0)
+    );
+
+    send(&mut connection, &["EXPIRE", "a", "100"]).await;
+    assert_eq!(
+        send(&mut connection, &["MSET", "a", "10", "b", "20", "a", "11"]).await,
+        "OK"
+    );
+    assert_eq!(
+        send(&mut connection, &["TTL", "a"]).await,
+        Frame::Integer(-1)
+    );
+
+    send(&mut connection, &["RPUSH", "list", "x"]).await;
+    assert_eq!(
+        send(&mut connection, &["MGET", "a", "b", "c", "list"]).await,
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::from("11")),
+            Frame::Bulk(Bytes::from("20")),
+            Frame::Null,
+            Frame::Null,
+        ])
+    );
+
+    // Nothing is set when any key exists
+    assert_eq!(
+        send(&mut connection, &["MSETNX", "c", "30", "list", "y"]).await,
+        Frame::Integer(0)
+    );
+    assert_eq!(send(&mut connection, &["GET", "c"]).await, Frame::Null);
+    assert_eq!(
+        send(&mut connection, &["MSETNX", "c", "30", "d", "40"]).await,
+        Frame::Integer(1)
+    );
+    assert_eq!(send(&mut connection, &["GET", "d"]).await, "40");
+
+    assert_eq!(
+        send(&mut connection, &["MSET", "a", "1", "b"]).await,
+        error("ERR wrong number of arguments for 'mset' command")
+    );
+}
+
+#[tokio::test]
+async fn wrong_type_errors() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["RPUSH", "list", "x"]).await;
+
+    let wrong_type = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+    for args in [
+        &["INCR", "list"][..],
+        &["INCRBYFLOAT", "list", "1"],
+        &["APPEND", "list", "x"],
+        &["STRLEN", "list"],
+        &["GETRANGE", "list", "0", "1"],
+        &["SETRANGE", "list", "0", "x"],
+        &["SETRANGE", "list", "0", ""],
+        &["GETSET", "list", "x"],
+        &["GETDEL", "list"],
+        &["GETEX", "list"],
+        &["GETEX", "list", "PERSIST"],
+    ] {
+        assert_eq!(send(&mut connection, args).await, wrong_type, "{:?}", args);
+    }
+
+    // Untouched
+    assert_eq!(
+        send(&mut connection, &["LRANGE", "list", "0", "-1"]).await,
+        Frame::Array(vec![Frame::Bulk(Bytes::from("x"))])
+    );
+
+    // Overwritten regardless of the type
+    assert_eq!(send(&mut connection, &["MSET", "list", "x"]).await, "OK");
+    assert_eq!(send(&mut connection, &["GET", "list"]).await, "x");
+}
@@ -0,0 +1,49 @@
+//! Helpers shared by the integration tests.
+
+// Every test crate compiles this module, but not all of them use every helper.
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use my_redis::{server, Connection, Db, Frame};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Start a server on a random local port and return its address.
+pub async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(server::run(
+        listener,
+        Db::new(4),
+        std::future::pending::<()>(),
+    ));
+
+    addr
+}
+
+/// Open a raw connection to the server, for commands the mini-redis client
+/// does not support.
+pub async fn connect(addr: SocketAddr) -> Connection {
+    Connection::new(TcpStream::connect(addr).await.unwrap())
+}
+
+/// Build a command frame out of bulk strings.
+pub fn command(args: &[&str]) -> Frame {
+    Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    )
+}
+
+/// Send a command made of bulk strings and return the response.
+pub async fn send(connection: &mut Connection, args: &[&str]) -> Frame {
+    connection.write_frame(&command(args)).await.unwrap();
+    read(connection).await
+}
+
+/// Read the next frame, failing if the server closed the connection.
+pub async fn read(connection: &mut Connection) -> Frame {
+    connection.read_frame().await.unwrap().unwrap()
+}
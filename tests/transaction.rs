@@ -0,0 +1,265 @@
+mod support;
+
+use bytes::Bytes;
+use my_redis::Frame;
+use support::{connect, send, start_server};
+
+fn error(msg: &str) -> Frame {
+    Frame::Error(msg.to_string())
+}
+
+#[tokio::test]
+async fn exec_runs_queued_commands() {
+    let mut connection = connect(start_server().await).await;
+
+    assert_eq!(send(&mut connection, &["MULTI"]).await, "OK");
+    assert_eq!(send(&mut connection, &["SET", "a", "1"]).await, "QUEUED");
+    assert_eq!(send(&mut connection, &["INCR", "a"]).await, "QUEUED");
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, "QUEUED");
+    // Fails when run, the other commands still are
+    assert_eq!(send(&mut connection, &["LPUSH", "a", "x"]).await, "QUEUED");
+    assert_eq!(send(&mut connection, &["INCR", "a"]).await, "QUEUED");
+
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![
+            Frame::Simple("OK".to_string()),
+            Frame::Integer(2),
+            Frame::Bulk(Bytes::from("2")),
+            error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            Frame::Integer(3),
+        ])
+    );
+
+    // Back to running commands right away
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, "3");
+
+    send(&mut connection, &["MULTI"]).await;
+    assert_eq!(send(&mut connection, &["EXEC"]).await, Frame::Array(vec![]));
+}
+
+#[tokio::test]
+async fn blocking_pops_do_not_wait_in_a_transaction() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["RPUSH", "list", "a", "b"]).await;
+    send(&mut connection, &["SET", "string", "x"]).await;
+
+    assert_eq!(send(&mut connection, &["MULTI"]).await, "OK");
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "empty", "0"]).await,
+        "QUEUED"
+    );
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "empty", "list", "0"]).await,
+        "QUEUED"
+    );
+    assert_eq!(
+        send(&mut connection, &["BRPOP", "list", "0"]).await,
+        "QUEUED"
+    );
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "list", "0"]).await,
+        "QUEUED"
+    );
+    assert_eq!(
+        send(&mut connection, &["BLPOP", "string", "list", "0"]).await,
+        "QUEUED"
+    );
+
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![
+            Frame::Null,
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("list")),
+                Frame::Bulk(Bytes::from("a"))
+            ]),
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from("list")),
+                Frame::Bulk(Bytes::from("b"))
+            ]),
+            // The list was emptied, it is not waited on
+            Frame::Null,
+            error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn discard_drops_queued_commands() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["SET", "a", "1"]).await;
+    assert_eq!(send(&mut connection, &["DISCARD"]).await, "OK");
+
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, Frame::Null);
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        error("ERR EXEC without MULTI")
+    );
+    assert_eq!(
+        send(&mut connection, &["DISCARD"]).await,
+        error("ERR DISCARD without MULTI")
+    );
+}
+
+#[tokio::test]
+async fn queueing_errors() {
+    let mut connection = connect(start_server().await).await;
+
+    send(&mut connection, &["MULTI"]).await;
+    assert_eq!(
+        send(&mut connection, &["MULTI"]).await,
+        error("ERR MULTI calls can not be nested")
+    );
+    assert_eq!(
+        send(&mut connection, &["WATCH", "a"]).await,
+        error("ERR WATCH inside MULTI is not allowed")
+    );
+    // Refused, but the transaction goes on
+    send(&mut connection, &["SET", "a", "1"]).await;
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![Frame::Simple("OK".to_string())])
+    );
+
+    // Commands that can not be queued discard the transaction
+    for args in [&["GET"][..], &["NOPE", "a"]] {
+        send(&mut connection, &["MULTI"]).await;
+        send(&mut connection, &["SET", "a", "2"]).await;
+        assert!(
+            matches!(send(&mut connection, args).await, Frame::Error(_)),
+            "{:?}",
+            args
+        );
+        send(&mut connection, &["SET", "b", "2"]).await;
+        assert_eq!(
+            send(&mut connection, &["EXEC"]).await,
+            error("EXECABORT Transaction discarded because of previous errors.")
+        );
+    }
+
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, "1");
+    assert_eq!(send(&mut connection, &["GET", "b"]).await, Frame::Null);
+}
+
+#[tokio::test]
+async fn watch() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    // Untouched
+    assert_eq!(send(&mut connection, &["WATCH", "a", "b"]).await, "OK");
+    send(&mut other, &["SET", "c", "1"]).await;
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["INCR", "a"]).await;
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![Frame::Integer(1)])
+    );
+
+    // Changed by another client
+    send(&mut connection, &["WATCH", "a"]).await;
+    send(&mut other, &["INCR", "a"]).await;
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["INCR", "a"]).await;
+    assert_eq!(send(&mut connection, &["EXEC"]).await, Frame::Null);
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, "2");
+
+    // EXEC stopped watching the key
+    send(&mut other, &["INCR", "a"]).await;
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["INCR", "a"]).await;
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![Frame::Integer(4)])
+    );
+
+    // Changes made while queueing count too
+    send(&mut connection, &["WATCH", "a"]).await;
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut other, &["GETDEL", "a"]).await;
+    send(&mut connection, &["SET", "a", "1"]).await;
+    assert_eq!(send(&mut connection, &["EXEC"]).await, Frame::Null);
+    assert_eq!(send(&mut connection, &["GET", "a"]).await, Frame::Null);
+
+    // Until UNWATCH
+    send(&mut connection, &["WATCH", "a"]).await;
+    send(&mut other, &["SET", "a", "1"]).await;
+    assert_eq!(send(&mut connection, &["UNWATCH"]).await, "OK");
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["GET", "a"]).await;
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![Frame::Bulk(Bytes::from("1"))])
+    );
+
+    // Or DISCARD
+    send(&mut connection, &["WATCH", "a"]).await;
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["DISCARD"]).await;
+    send(&mut other, &["SET", "a", "2"]).await;
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["GET", "a"]).await;
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![Frame::Bulk(Bytes::from("2"))])
+    );
+}
+
+#[tokio::test]
+async fn watch_ignores_writes_that_change_nothing() {
+    let addr = start_server().await;
+    let mut connection = connect(addr).await;
+    let mut other = connect(addr).await;
+
+    send(&mut other, &["SADD", "set", "a"]).await;
+    send(&mut connection, &["WATCH", "list", "hash", "set", "s"]).await;
+
+    for args in [
+        &["LPOP", "list"][..],
+        &["HDEL", "hash", "f"],
+        &["SREM", "set", "b"],
+        &["SETRANGE", "s", "0", ""],
+        &["GETEX", "s", "EX", "100"],
+    ] {
+        send(&mut other, args).await;
+    }
+
+    send(&mut connection, &["MULTI"]).await;
+    send(&mut connection, &["SADD", "set", "b"]).await;
+    assert_eq!(
+        send(&mut connection, &["EXEC"]).await,
+        Frame::Array(vec![Frame::Integer(1)])
+    );
+}
+
+/// Other clients never see a transaction half done.
+#[tokio::test]
+async fn transactions_are_atomic() {
+    let addr = start_server().await;
+
+    let writer = tokio::spawn(async move {
+        let mut connection = connect(addr).await;
+        for _ in 0..200 {
+            send(&mut connection, &["MULTI"]).await;
+            send(&mut connection, &["INCR", "a"]).await;
+            send(&mut connection, &["INCR", "b"]).await;
+            send(&mut connection, &["EXEC"]).await;
+        }
+    });
+
+    let mut connection = connect(addr).await;
+    while !writer.is_finished() {
+        match send(&mut connection, &["MGET", "a", "b"]).await {
+            Frame::Array(values) => assert_eq!(values[0], values[1]),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+
+    writer.await.unwrap();
+    assert_eq!(send(&mut connection, &["GET", "b"]).await, "200");
+}